@@ -1,9 +1,14 @@
 use std::error::Error;
+use std::path::PathBuf;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    tonic_prost_build::configure().compile_protos(
-        &["proto/bottles.proto", "proto/winebridge.proto"],
-        &["proto/"],
-    )?;
+    // Served through gRPC reflection
+    let descriptors = PathBuf::from(std::env::var("OUT_DIR")?).join("descriptors.bin");
+    tonic_prost_build::configure()
+        .file_descriptor_set_path(descriptors)
+        .compile_protos(
+            &["proto/bottles.proto", "proto/winebridge.proto"],
+            &["proto/"],
+        )?;
     Ok(())
 }
@@ -0,0 +1,13 @@
+#![no_main]
+
+use bottles_core::limits::Limits;
+use bottles_core::lnk::Shortcut;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let limits = Limits {
+        max_size: 1024 * 1024,
+        ..Limits::DEFAULT
+    };
+    let _ = Shortcut::parse_with_limits(data, &limits);
+});
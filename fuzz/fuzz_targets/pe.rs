@@ -0,0 +1,20 @@
+#![no_main]
+
+use bottles_core::limits::Limits;
+use bottles_core::pe::PeFile;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let limits = Limits {
+        max_size: 1024 * 1024,
+        max_depth: 16,
+        max_items: 4096,
+    };
+    if let Some(file) = PeFile::parse_with_limits(data.to_vec(), &limits) {
+        let _ = (file.architecture(), file.subsystem(), file.is_dll());
+        let _ = file.version_info();
+        if let Some(icon) = file.icon() {
+            assert!(icon.data.len() <= 6 + limits.max_size);
+        }
+    }
+});
@@ -0,0 +1,22 @@
+#![no_main]
+
+use bottles_core::limits::Limits;
+use bottles_core::registry::RegistryFile;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    let limits = Limits {
+        max_size: 1024 * 1024,
+        max_depth: 16,
+        max_items: 4096,
+    };
+    if let Some(file) = RegistryFile::parse_with_limits(data, &limits) {
+        assert!(file.keys().count() <= limits.max_items);
+        for key in file.keys() {
+            for (name, value) in &key.values {
+                assert!(key.value(name).is_some());
+                let _ = (value.as_str(), value.as_u64());
+            }
+        }
+    }
+});
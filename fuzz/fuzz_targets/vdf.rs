@@ -0,0 +1,25 @@
+#![no_main]
+
+use bottles_core::limits::Limits;
+use bottles_core::vdf::{self, Value};
+use libfuzzer_sys::fuzz_target;
+
+/// Nesting of `value`, checked against the limits
+fn depth(value: &Value) -> usize {
+    value.entries().map(|(_, child)| 1 + depth(child)).max().unwrap_or(0)
+}
+
+fuzz_target!(|data: &str| {
+    let limits = Limits {
+        max_size: 1024 * 1024,
+        max_depth: 16,
+        max_items: 4096,
+    };
+    if let Some(document) = vdf::parse_with_limits(data, &limits) {
+        assert!(depth(&document) <= limits.max_depth + 1);
+        for (key, child) in document.entries() {
+            assert!(document.get(key).is_some());
+            let _ = child.as_str();
+        }
+    }
+});
@@ -0,0 +1,389 @@
+//! Extraction of runner and component archives
+//!
+//! Runners and components are published as `.tar.gz`, `.tar.xz`, `.tar.zst`
+//! or `.zip` archives. [`extract`] unpacks them while reporting its
+//! progress, keeping executable bits and symbolic links, which Wine builds
+//! depend on.
+//!
+//! Archives come from the network, so their entries are not trusted: entries
+//! with absolute paths or `..` components, symbolic links pointing outside of
+//! the destination or using `..` past anything but a directory extracted
+//! earlier, hard links to symbolic links, and entries or hard link sources
+//! reached through a link extracted earlier are all refused with
+//! [`Error::Integrity`]. Devices and other special files are skipped.
+
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+/// Format of an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Format {
+    TarGz,
+    TarXz,
+    TarZst,
+    Zip,
+}
+
+impl Format {
+    /// The format of the archive at `path`, from its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar.xz") || name.ends_with(".txz") {
+            Some(Self::TarXz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Self::TarZst)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Progress of an extraction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Progress {
+    /// Entry being extracted, relative to the destination
+    pub entry: PathBuf,
+    /// Entries extracted so far
+    pub entries: u64,
+    /// Bytes of the archive read so far
+    pub read: u64,
+    /// Size of the archive, in bytes
+    pub total: u64,
+}
+
+/// Extract the archive at `path` into the directory `destination`
+///
+/// Returns the paths of the extracted entries, relative to `destination`.
+///
+/// # Errors
+///
+/// Returns [`Error::Integrity`] if an entry would be written outside of
+/// `destination`, other errors if the archive is not in a supported format
+/// or can't be read
+pub fn extract(
+    path: &Path,
+    destination: &Path,
+    mut progress: impl FnMut(&Progress),
+) -> Result<Vec<PathBuf>, Error> {
+    let format = Format::from_path(path).ok_or_else(|| {
+        Error::from(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("'{}' is not a supported archive", path.display()),
+        ))
+    })?;
+    fs::create_dir_all(destination).map_err(Error::Io)?;
+    let total = fs::metadata(path).map_err(Error::Io)?.len();
+    let file = File::open(path).map_err(Error::Io)?;
+    let entries = match format {
+        Format::TarGz => extract_tar(file, destination, total, &mut progress, |file| {
+            Ok(Box::new(flate2::read::GzDecoder::new(file)))
+        }),
+        Format::TarXz => extract_tar(file, destination, total, &mut progress, |file| {
+            Ok(Box::new(xz2::read::XzDecoder::new(file)))
+        }),
+        Format::TarZst => extract_tar(file, destination, total, &mut progress, |file| {
+            Ok(Box::new(zstd::stream::read::Decoder::new(file)?))
+        }),
+        Format::Zip => extract_zip(file, destination, total, &mut progress),
+    }?;
+    tracing::info!(
+        "Extracted {} entries of '{}' into '{}'",
+        entries.len(),
+        path.display(),
+        destination.display()
+    );
+    Ok(entries)
+}
+
+/// Extract the archive at `path` as the directory `destination`
+///
+/// The archive is extracted next to `destination` first, then moved in
+/// place, replacing any previous version, so that an interrupted extraction
+/// never leaves a partial runner or component behind. Archives holding a
+/// single directory, as most releases do, are installed from inside it.
+///
+/// # Errors
+///
+/// See [`extract`]
+pub fn install(
+    path: &Path,
+    destination: &Path,
+    progress: impl FnMut(&Progress),
+) -> Result<PathBuf, Error> {
+    let mut name = destination.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    let partial = destination.with_file_name(name);
+    if partial.exists() {
+        fs::remove_dir_all(&partial).map_err(Error::Io)?;
+    }
+    if let Err(e) = extract(path, &partial, progress) {
+        let _ = fs::remove_dir_all(&partial);
+        return Err(e);
+    }
+    let mut children = fs::read_dir(&partial)
+        .map_err(Error::Io)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::Io)?;
+    let root = match children.pop() {
+        Some(child) if children.is_empty() && child.file_type().is_ok_and(|t| t.is_dir()) => {
+            child.path()
+        }
+        _ => partial.clone(),
+    };
+    if destination.exists() {
+        fs::remove_dir_all(destination).map_err(Error::Io)?;
+    }
+    fs::rename(&root, destination).map_err(Error::Io)?;
+    if partial.exists() {
+        fs::remove_dir_all(&partial).map_err(Error::Io)?;
+    }
+    Ok(destination.to_path_buf())
+}
+
+/// A reader counting the bytes read from it
+struct Counted<R> {
+    inner: R,
+    read: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buffer)?;
+        self.read.set(self.read.get() + read as u64);
+        Ok(read)
+    }
+}
+
+fn extract_tar(
+    file: File,
+    destination: &Path,
+    total: u64,
+    progress: &mut impl FnMut(&Progress),
+    decoder: impl FnOnce(Counted<File>) -> io::Result<Box<dyn Read>>,
+) -> Result<Vec<PathBuf>, Error> {
+    // Shared with the reader, which the decoder owns
+    let read = Rc::new(Cell::new(0));
+    let file = Counted {
+        inner: file,
+        read: read.clone(),
+    };
+    let mut archive = tar::Archive::new(decoder(file).map_err(Error::Io)?);
+    let mut extracted = Vec::new();
+    for entry in archive.entries().map_err(Error::Io)? {
+        let mut entry = entry.map_err(Error::Io)?;
+        let relative = sanitize(&entry.path().map_err(Error::Io)?)?;
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        let target = destination.join(&relative);
+        check_parents(destination, &relative)?;
+        let kind = entry.header().entry_type();
+        match kind {
+            tar::EntryType::Directory => fs::create_dir_all(&target).map_err(Error::Io)?,
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let mode = entry.header().mode().ok();
+                write_file(&target, &mut entry, mode)?;
+            }
+            tar::EntryType::Symlink => {
+                let link = entry
+                    .link_name()
+                    .map_err(Error::Io)?
+                    .ok_or_else(|| unsafe_entry(&relative))?;
+                symlink(destination, &relative, &link)?;
+            }
+            tar::EntryType::Link => {
+                let link = entry
+                    .link_name()
+                    .map_err(Error::Io)?
+                    .ok_or_else(|| unsafe_entry(&relative))?;
+                let link = sanitize(&link)?;
+                check_parents(destination, &link)?;
+                let source = destination.join(&link);
+                // Linking to a link would share whatever it points to
+                if fs::symlink_metadata(&source).is_ok_and(|m| m.file_type().is_symlink()) {
+                    return Err(unsafe_entry(&relative));
+                }
+                replace(&target)?;
+                fs::hard_link(source, &target).map_err(Error::Io)?;
+            }
+            _ => {
+                tracing::debug!("Skipping '{}' of type {:?}", relative.display(), kind);
+                continue;
+            }
+        }
+        extracted.push(relative.clone());
+        progress(&Progress {
+            entry: relative,
+            entries: extracted.len() as u64,
+            read: read.get(),
+            total,
+        });
+    }
+    Ok(extracted)
+}
+
+fn extract_zip(
+    file: File,
+    destination: &Path,
+    total: u64,
+    progress: &mut impl FnMut(&Progress),
+) -> Result<Vec<PathBuf>, Error> {
+    let mut archive = zip::ZipArchive::new(file).map_err(zip_error)?;
+    let mut extracted = Vec::new();
+    let mut read = 0;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(zip_error)?;
+        let relative = sanitize(Path::new(entry.name()))?;
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        let target = destination.join(&relative);
+        check_parents(destination, &relative)?;
+        read += entry.compressed_size();
+        let mode = entry.unix_mode();
+        // Links are stored as files holding their target
+        if mode.is_some_and(|mode| mode & 0o170000 == 0o120000) {
+            let mut link = String::new();
+            entry.read_to_string(&mut link).map_err(Error::Io)?;
+            symlink(destination, &relative, Path::new(&link))?;
+        } else if entry.is_dir() {
+            fs::create_dir_all(&target).map_err(Error::Io)?;
+        } else {
+            write_file(&target, &mut entry, mode)?;
+        }
+        extracted.push(relative.clone());
+        progress(&Progress {
+            entry: relative,
+            entries: extracted.len() as u64,
+            read,
+            total,
+        });
+    }
+    Ok(extracted)
+}
+
+/// The path of an entry relative to the destination, refusing paths leaving
+/// it
+fn sanitize(path: &Path) -> Result<PathBuf, Error> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(unsafe_entry(path))
+            }
+        }
+    }
+    Ok(relative)
+}
+
+/// Refuse entries whose directories, as extracted so far, include a link:
+/// the entry would be written wherever the link points
+fn check_parents(destination: &Path, relative: &Path) -> Result<(), Error> {
+    let mut current = destination.to_path_buf();
+    let parents = relative.parent().into_iter().flat_map(Path::components);
+    for component in parents {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(unsafe_entry(relative))
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => return Err(Error::Io(e)),
+        }
+    }
+    Ok(())
+}
+
+fn write_file(target: &Path, content: &mut impl Read, mode: Option<u32>) -> Result<(), Error> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(Error::Io)?;
+    }
+    replace(target)?;
+    let mut file = File::create(target).map_err(Error::Io)?;
+    io::copy(content, &mut file).map_err(Error::Io)?;
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Permission bits only, never setuid or setgid
+        let permissions = fs::Permissions::from_mode(mode & 0o777);
+        fs::set_permissions(target, permissions).map_err(Error::Io)?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(())
+}
+
+/// Create the link `relative` to `link`, which must stay inside
+/// `destination`
+fn symlink(destination: &Path, relative: &Path, link: &Path) -> Result<(), Error> {
+    if link.is_absolute() {
+        return Err(unsafe_entry(relative));
+    }
+    // Where the link points, relative to the destination. `..` only stays
+    // where `resolved` says after directories already extracted: a component
+    // missing so far could later be extracted as a link, and `..` would then
+    // leave wherever that one points. Directories are never replaced by
+    // links, and the parents of the link are created as directories below.
+    let mut resolved: Vec<Component> = relative
+        .parent()
+        .map(|parent| parent.components().collect())
+        .unwrap_or_default();
+    let mut unresolved = false;
+    for component in link.components() {
+        match component {
+            Component::Normal(_) => {
+                resolved.push(component);
+                let current: PathBuf = resolved.iter().collect();
+                unresolved |= !fs::symlink_metadata(destination.join(current))
+                    .is_ok_and(|metadata| metadata.is_dir());
+            }
+            Component::CurDir => {}
+            Component::ParentDir if !unresolved && resolved.pop().is_some() => {}
+            _ => return Err(unsafe_entry(relative)),
+        }
+    }
+
+    let target = destination.join(relative);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(Error::Io)?;
+    }
+    replace(&target)?;
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(link, &target).map_err(Error::Io)?;
+    #[cfg(not(unix))]
+    tracing::warn!("Skipping the link '{}'", relative.display());
+    Ok(())
+}
+
+/// Remove what an earlier entry extracted at `target`, so that a link there
+/// isn't followed
+fn replace(target: &Path) -> Result<(), Error> {
+    match fs::symlink_metadata(target) {
+        Ok(metadata) if !metadata.is_dir() => fs::remove_file(target).map_err(Error::Io),
+        _ => Ok(()),
+    }
+}
+
+fn unsafe_entry(path: &Path) -> Error {
+    Error::Integrity(format!("'{}' points outside of the destination", path.display()))
+}
+
+fn zip_error(e: zip::result::ZipError) -> Error {
+    match e {
+        zip::result::ZipError::Io(e) => Error::Io(e),
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()).into(),
+    }
+}
@@ -0,0 +1,107 @@
+//! Low-latency audio for music production
+//!
+//! Windows DAWs and plugins talk to audio hardware through ASIO, which
+//! wineasio implements on top of JACK. It is installed as a component (see
+//! [`crate::components`]) and reads its settings from the registry, set from
+//! the bottle's [`AudioOptions`] when it is installed and whenever they
+//! change. The Unix side of wineasio must be installed with the runner, as
+//! it is built against a specific Wine version.
+//!
+//! The same buffer size and sample rate are requested from PipeWire, for
+//! programs going through Wine's PulseAudio driver and for wineasio running
+//! on PipeWire's JACK implementation. A JACK server has a single buffer size
+//! for all its clients, which must then be configured in the server itself.
+
+use crate::Error;
+use serde::{Deserialize, Serialize};
+
+/// Registry key holding the settings of wineasio
+pub(crate) const ASIO_KEY: &str = "HKEY_CURRENT_USER\\Software\\Wine\\WineASIO";
+
+/// Sample rate assumed when only a buffer size is set
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// Smallest buffer size accepted by wineasio, in frames
+pub const MIN_BUFFER_SIZE: u32 = 16;
+
+/// Largest buffer size accepted by wineasio, in frames
+pub const MAX_BUFFER_SIZE: u32 = 8192;
+
+/// Audio settings of a bottle
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct AudioOptions {
+    /// Frames per period, a power of two between [`MIN_BUFFER_SIZE`] and
+    /// [`MAX_BUFFER_SIZE`]; the server's default when unset
+    pub buffer_size: Option<u32>,
+    /// Frames per second, 48 kHz when only the buffer size is set
+    pub sample_rate: Option<u32>,
+    /// ASIO input channels offered by wineasio
+    pub inputs: Option<u32>,
+    /// ASIO output channels offered by wineasio
+    pub outputs: Option<u32>,
+}
+
+impl AudioOptions {
+    /// Check that wineasio and PipeWire accept the settings
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the buffer size is not a power of two in range,
+    /// or if the sample rate is zero
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(size) = self.buffer_size {
+            if !size.is_power_of_two() || !(MIN_BUFFER_SIZE..=MAX_BUFFER_SIZE).contains(&size) {
+                return Err(invalid(format!(
+                    "buffer size {} is not a power of two between {} and {}",
+                    size, MIN_BUFFER_SIZE, MAX_BUFFER_SIZE
+                )));
+            }
+        }
+        if self.sample_rate == Some(0) {
+            return Err(invalid("sample rate 0 is not valid".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Variables requesting the buffer size and sample rate
+    ///
+    /// `PIPEWIRE_LATENCY` is read by PipeWire's PulseAudio and JACK
+    /// implementations, `PULSE_LATENCY_MSEC` by a PulseAudio server.
+    pub fn environment(&self) -> Vec<(&'static str, String)> {
+        let Some(size) = self.buffer_size else {
+            return Vec::new();
+        };
+        let rate = self.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE).max(1);
+        let milliseconds = (u64::from(size) * 1000).div_ceil(u64::from(rate));
+        vec![
+            ("PIPEWIRE_LATENCY", format!("{}/{}", size, rate)),
+            ("PULSE_LATENCY_MSEC", milliseconds.to_string()),
+        ]
+    }
+
+    /// Values of [`ASIO_KEY`] applying the settings, all `REG_DWORD`s
+    ///
+    /// A buffer size is fixed, so hosts can't pick another one.
+    pub(crate) fn asio_settings(&self) -> Vec<(&'static str, u32)> {
+        let mut settings = Vec::new();
+        match self.buffer_size {
+            Some(size) => {
+                settings.extend([("Preferred buffersize", size), ("Fixed buffersize", 1)])
+            }
+            None => settings.push(("Fixed buffersize", 0)),
+        }
+        if let Some(inputs) = self.inputs {
+            settings.push(("Number of inputs", inputs));
+        }
+        if let Some(outputs) = self.outputs {
+            settings.push(("Number of outputs", outputs));
+        }
+        settings
+    }
+}
+
+fn invalid(message: String) -> Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message).into()
+}
@@ -1,8 +1,33 @@
+use crate::audio::AudioOptions;
+use crate::disk::{self, DiskUsage};
+use crate::drives::{self, Drive, DriveKind};
+use crate::gpu::GpuPreference;
+use crate::health::{self, HealthReport, RepairReport};
+use crate::installers::setup::{self, InstallerOptions, InstallerReport};
+use crate::kerberos::KerberosOptions;
+use crate::launch::gamescope::GamescopeOptions;
+use crate::launch::steam_runtime::SteamRuntimeMode;
+use crate::launch::upscaling::FsrOptions;
+use crate::manager::Manager;
+use crate::peripherals::PeripheralOptions;
+use crate::persistence::migrate::SchemaVersion;
+use crate::playtime::Playtime;
+use crate::ports::{self, SerialPort, UsbOptions};
+use crate::programs::{self, InstalledProgram, Program};
+use crate::runner::{PassThroughKind, PrefixArch};
+use crate::saves::SaveBackupOptions;
+use crate::session::Session;
+use crate::sync::SyncMode;
+use crate::thumbnail::ThumbnailOptions;
+use crate::winecfg::WineSettings;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum BottleType {
     Gaming,
     Software,
@@ -15,32 +40,483 @@ impl Default for BottleType {
     }
 }
 
+impl fmt::Display for BottleType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Gaming => "Gaming",
+            Self::Software => "Software",
+            Self::Custom => "Custom",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for BottleType {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gaming" => Ok(Self::Gaming),
+            "software" => Ok(Self::Software),
+            "custom" => Ok(Self::Custom),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{}' is not a bottle type", s),
+            )
+            .into()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
 pub struct BottleConfig {
+    pub version: SchemaVersion,
     pub runner: Option<String>,
+    /// Launch programs with DOSBox or ScummVM instead of [`Self::runner`],
+    /// see [`crate::runner::PassThrough`]
+    pub passthrough: Option<PassThroughKind>,
     pub dxvk_version: Option<String>,
     pub vkd3d_version: Option<String>,
+    /// Architecture of the prefix, recorded when it is created, see
+    /// [`crate::health`]
+    pub arch: PrefixArch,
+    /// Requested synchronization primitive, see [`crate::sync`]
+    pub sync: SyncMode,
+    /// GPU programs render on, see [`crate::gpu`]
+    pub gpu: GpuPreference,
+    /// Run Proton inside a Steam Linux Runtime container, see
+    /// [`crate::launch::steam_runtime`]
+    pub steam_runtime: SteamRuntimeMode,
+    /// Run programs inside gamescope, see [`crate::launch`]
+    pub gamescope: Option<GamescopeOptions>,
+    /// Upscale fullscreen programs with Wine's built-in FSR, see
+    /// [`crate::launch::upscaling`]
+    pub fsr: Option<FsrOptions>,
+    /// Run programs through Feral GameMode's `gamemoderun`
+    pub gamemode: bool,
+    /// Show the MangoHud performance overlay
+    pub mangohud: bool,
+    /// MangoHud configuration file, instead of the user's default one
+    pub mangohud_config: Option<PathBuf>,
+    /// Capture Vulkan and OpenGL output for OBS with obs-vkcapture
+    pub obs_vkcapture: bool,
+    /// Keep a history of the performance of every session, see
+    /// [`crate::resources::history`]
+    pub performance_history: bool,
+    /// Let frontends capture previews of running programs, see
+    /// [`crate::thumbnail`]
+    pub thumbnails: ThumbnailOptions,
+    /// Audio latency and wineasio settings, see [`crate::audio`]
+    pub audio: AudioOptions,
+    /// Back up the saves of programs once they exit, see [`crate::saves`]
+    pub save_backup: SaveBackupOptions,
+    /// Host printers and scanners shown to programs, see
+    /// [`crate::peripherals`]
+    pub peripherals: PeripheralOptions,
+    /// Host HID devices shown to programs, see [`crate::ports`]
+    pub usb: UsbOptions,
+    /// Let programs use the host's smart card readers, see
+    /// [`crate::smartcard`]
+    pub smart_cards: bool,
+    /// Pass the host's Kerberos tickets to programs, see [`crate::kerberos`]
+    pub kerberos: KerberosOptions,
+    /// Virtual desktop, DPI, audio driver and mouse capture, see
+    /// [`crate::winecfg`]
+    pub wine_settings: WineSettings,
     pub environment: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Bottle {
+    #[serde(default)]
+    pub version: SchemaVersion,
     pub name: String,
     pub path: PathBuf,
     pub kind: BottleType,
     pub config: BottleConfig,
+    /// The prefix belongs to another application (e.g. a Steam game prefix):
+    /// it can be used, but operations that modify or delete it are refused
+    #[serde(default)]
+    pub read_only: bool,
+    /// The library of programs launched from the bottle, see
+    /// [`crate::programs`]
+    #[serde(default)]
+    pub programs: Vec<Program>,
+    /// Time spent running programs of the bottle
+    #[serde(default)]
+    pub playtime: Playtime,
     #[serde(skip)]
     pub active: bool, // Runtime state, not persisted
+    /// What the bottle is doing, see [`Manager::bottle_state`]
+    #[serde(skip)]
+    pub state: BottleState, // Runtime state, not persisted
+}
+
+/// What a list of bottles shows of each, read from the index alone
+///
+/// Building one never reads the prefix or starts a runner, see
+/// [`Manager::list_bottle_summaries`](crate::manager::Manager::list_bottle_summaries).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BottleSummary {
+    pub name: String,
+    pub kind: BottleType,
+    #[serde(default)]
+    pub path: PathBuf,
+    /// Name of the bottle's runner, if it has one
+    #[serde(default)]
+    pub runner: Option<String>,
+    #[serde(default)]
+    pub read_only: bool,
+    /// Whether a program of the bottle is running
+    pub active: bool,
+    #[serde(default)]
+    pub state: BottleState,
+}
+
+impl From<&Bottle> for BottleSummary {
+    fn from(bottle: &Bottle) -> Self {
+        Self {
+            name: bottle.name.clone(),
+            kind: bottle.kind.clone(),
+            path: bottle.path.clone(),
+            runner: bottle.config.runner.clone(),
+            read_only: bottle.read_only,
+            active: bottle.active,
+            state: bottle.state.clone(),
+        }
+    }
+}
+
+/// What a bottle is doing, maintained by the manager, see
+/// [`Manager::bottle_state`]
+///
+/// Operations changing a bottle only start from some states, see
+/// [`BottleState::allows`]: a bottle being updated can't be launched, and a
+/// broken one has to be repaired first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum BottleState {
+    /// The prefix is being created
+    Creating,
+    /// Nothing runs in the bottle
+    #[default]
+    Ready,
+    /// Programs launched by the manager run in the bottle
+    Running { pids: Vec<u32> },
+    /// The prefix is being changed, e.g. updated or given a component
+    Updating,
+    /// The prefix was found broken, see [`crate::health`]
+    Broken,
+    /// Another process, e.g. another manager, is changing the bottle
+    Locked,
+}
+
+impl BottleState {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::Creating => "creating",
+            Self::Ready => "ready",
+            Self::Running { .. } => "running",
+            Self::Updating => "updating",
+            Self::Broken => "broken",
+            Self::Locked => "locked",
+        }
+    }
+
+    /// The state of id `id`, as returned by [`BottleState::id`], `pids` being
+    /// the processes of a running bottle
+    pub fn from_id(id: &str, pids: Vec<u32>) -> Option<Self> {
+        Some(match id {
+            "creating" => Self::Creating,
+            "ready" => Self::Ready,
+            "running" => Self::Running { pids },
+            "updating" => Self::Updating,
+            "broken" => Self::Broken,
+            "locked" => Self::Locked,
+            _ => return None,
+        })
+    }
+
+    /// Whether a bottle in this state can become `next`
+    ///
+    /// Only ready bottles start an operation; programs can be launched
+    /// alongside running ones, and broken bottles can be updated to repair
+    /// them. Operations going back to [`BottleState::Ready`] once done are
+    /// not transitions.
+    pub fn allows(&self, next: &Self) -> bool {
+        matches!(
+            (self, next),
+            (Self::Ready, _)
+                | (Self::Running { .. }, Self::Running { .. })
+                | (Self::Broken, Self::Updating)
+        )
+    }
+}
+
+impl fmt::Display for BottleState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+/// Which bottles a listing shows, every bottle by default
+///
+/// Filters only look at the index, like [`BottleSummary`], so backends apply
+/// them while reading it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BottleFilter {
+    /// Part of the name of the bottles, ignoring case
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub kind: Option<BottleType>,
+    /// Name of the runner of the bottles
+    #[serde(default)]
+    pub runner: Option<String>,
+}
+
+impl BottleFilter {
+    pub fn matches(&self, summary: &BottleSummary) -> bool {
+        let name = self
+            .name
+            .as_ref()
+            .is_none_or(|name| summary.name.to_lowercase().contains(&name.to_lowercase()));
+        let kind = self.kind.as_ref().is_none_or(|kind| *kind == summary.kind);
+        let runner = self
+            .runner
+            .as_ref()
+            .is_none_or(|runner| summary.runner.as_ref() == Some(runner));
+        name && kind && runner
+    }
 }
 
 impl Bottle {
     pub fn new(name: String, path: impl Into<PathBuf>, kind: BottleType) -> Self {
         Self {
+            version: SchemaVersion::default(),
             name,
             path: path.into(),
             kind,
             config: BottleConfig::default(),
+            read_only: false,
+            programs: Vec::new(),
+            playtime: Playtime::default(),
             active: false,
+            state: BottleState::default(),
+        }
+    }
+
+    /// A program of the library by id
+    pub fn program(&self, id: u64) -> Option<&Program> {
+        self.programs.iter().find(|program| program.id == id)
+    }
+
+    /// The programs installed in the bottle's prefix, see [`crate::programs`]
+    pub fn installed_programs(&self) -> Result<Vec<InstalledProgram>, crate::Error> {
+        programs::installed(&self.path)
+    }
+
+    /// The drive letters of the bottle's prefix, see [`crate::drives`]
+    pub fn drives(&self) -> Result<Vec<Drive>, crate::Error> {
+        drives::list(&self.path)
+    }
+
+    /// Map drive `letter` to the host directory `host_path`, as a hard disk
+    pub fn add_drive(
+        &self,
+        manager: &Manager,
+        letter: char,
+        host_path: impl AsRef<Path>,
+    ) -> Result<Drive, crate::Error> {
+        self.check_writable()?;
+        let runner = manager.runner_for(self)?;
+        drives::add(runner.as_ref(), &self.path, letter, host_path.as_ref(), DriveKind::HardDisk)
+    }
+
+    /// Unmap drive `letter`
+    pub fn remove_drive(&self, manager: &Manager, letter: char) -> Result<(), crate::Error> {
+        self.check_writable()?;
+        let runner = manager.runner_for(self)?;
+        drives::remove(runner.as_ref(), &self.path, letter)
+    }
+
+    /// The COM ports of the bottle's prefix, see [`crate::ports`]
+    pub fn serial_ports(&self) -> Result<Vec<SerialPort>, crate::Error> {
+        ports::serial_ports(&self.path)
+    }
+
+    /// Map `COM<number>` to the host serial device `device`, e.g.
+    /// `/dev/ttyUSB0`
+    pub fn add_serial_port(
+        &self,
+        number: u16,
+        device: impl AsRef<Path>,
+    ) -> Result<SerialPort, crate::Error> {
+        self.check_writable()?;
+        ports::add_serial_port(&self.path, number, device.as_ref())
+    }
+
+    /// Unmap `COM<number>`
+    pub fn remove_serial_port(&self, number: u16) -> Result<(), crate::Error> {
+        self.check_writable()?;
+        ports::remove_serial_port(&self.path, number)
+    }
+
+    /// Run the setup executable or MSI package `path` and wait for
+    /// everything it started to exit, see [`crate::installers::setup`]
+    ///
+    /// MSI packages are installed with `msiexec`, which must reach them
+    /// through a drive of the bottle.
+    ///
+    /// # Returns
+    ///
+    /// The programs the installer added to the prefix, among others
+    pub fn run_installer(
+        &self,
+        manager: &Manager,
+        path: impl AsRef<Path>,
+        options: &InstallerOptions,
+    ) -> Result<InstallerReport, crate::Error> {
+        self.check_writable()?;
+        setup::run(manager, self, path.as_ref(), options)
+    }
+
+    /// The space used by the bottle, see [`crate::disk`]
+    pub fn disk_usage(&self) -> Result<DiskUsage, crate::Error> {
+        disk::usage(self)
+    }
+
+    /// Check the prefix for common breakage, see [`crate::health`]
+    pub fn verify(&self, manager: &Manager) -> Result<HealthReport, crate::Error> {
+        health::verify(manager, self)
+    }
+
+    /// Fix what [`Bottle::verify`] finds, see [`crate::health::repair`]
+    ///
+    /// # Returns
+    ///
+    /// The problems fixed and those remaining
+    pub fn repair(&self, manager: &Manager) -> Result<RepairReport, crate::Error> {
+        self.check_writable()?;
+        health::repair(manager, self)
+    }
+
+    /// Refuse to modify the prefix of a [read-only](Self::read_only) bottle
+    fn check_writable(&self) -> Result<(), crate::Error> {
+        if self.read_only {
+            return Err(crate::Error::NotAuthorized(format!("'{}' is read-only", self.name)));
+        }
+        Ok(())
+    }
+
+    /// Start one of Wine's built-in tools in the bottle
+    ///
+    /// The tool runs like any program launched with
+    /// [`Manager::launch_program`]: with the bottle's runner, environment and
+    /// launch wrappers, tracked as a [`Session`].
+    pub fn run_tool(&self, manager: &Manager, tool: Tool) -> Result<Session, crate::Error> {
+        let args: Vec<String> = tool.args().iter().map(|arg| arg.to_string()).collect();
+        manager.launch_program(&self.name, Path::new(tool.program()), &args)
+    }
+}
+
+/// A utility shipped with Wine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Tool {
+    /// Wine's configuration dialog
+    Winecfg,
+    /// Registry editor
+    Regedit,
+    /// Task manager, listing the processes of the bottle
+    Taskmgr,
+    /// Command prompt, in its own console window
+    Cmd,
+    /// Control panel
+    Control,
+    /// File manager
+    Explorer,
+    /// Add/remove programs
+    Uninstaller,
+}
+
+impl Tool {
+    pub const ALL: [Self; 7] = [
+        Self::Winecfg,
+        Self::Regedit,
+        Self::Taskmgr,
+        Self::Cmd,
+        Self::Control,
+        Self::Explorer,
+        Self::Uninstaller,
+    ];
+
+    /// Human-readable name, as shown to users
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Winecfg => "Wine configuration",
+            Self::Regedit => "Registry editor",
+            Self::Taskmgr => "Task manager",
+            Self::Cmd => "Command prompt",
+            Self::Control => "Control panel",
+            Self::Explorer => "File manager",
+            Self::Uninstaller => "Uninstaller",
+        }
+    }
+
+    /// Executable run by the runner, looked up by Wine in `system32`
+    pub fn program(self) -> &'static str {
+        match self {
+            Self::Winecfg => "winecfg",
+            Self::Regedit => "regedit",
+            Self::Taskmgr => "taskmgr",
+            // The daemon has no terminal, so the prompt gets a console window
+            Self::Cmd => "start",
+            Self::Control => "control",
+            Self::Explorer => "explorer",
+            Self::Uninstaller => "uninstaller",
         }
     }
+
+    fn args(self) -> &'static [&'static str] {
+        match self {
+            Self::Cmd => &["cmd"],
+            _ => &[],
+        }
+    }
+
+    /// Identifier of the tool, e.g. `winecfg`
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Cmd => "cmd",
+            _ => self.program(),
+        }
+    }
+}
+
+impl fmt::Display for Tool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+impl FromStr for Tool {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let wanted = s.to_ascii_lowercase();
+        let wanted = wanted.trim_end_matches(".exe");
+        Self::ALL
+            .into_iter()
+            .find(|tool| tool.id() == wanted)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("'{}' is not a Wine tool", s),
+                )
+                .into()
+            })
+    }
 }
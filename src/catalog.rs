@@ -0,0 +1,345 @@
+//! The catalog of runners, components and installers available for download
+//!
+//! A catalog repository publishes an [`Index`] as `index.json` at its URL,
+//! listing runner builds, component releases, dependencies and installer
+//! recipes. [`Catalog::fetch`] downloads it and keeps a copy in the cache
+//! directory, which is used instead when the repository can't be reached, so
+//! frontends can still show what is available while offline.
+//!
+//! When the catalog has a public key, the index must be signed with minisign,
+//! the signature being published as `index.json.minisig` next to it. Indexes
+//! whose signature doesn't match are rejected, cached or not, see
+//! [`crate::integrity`]. The releases of a signed index are then trusted
+//! through their checksums.
+//!
+//! [`Manager::catalog`](crate::manager::Manager::catalog) adds the recipes of
+//! the registered [`crate::extensions::CatalogExtension`]s to the index.
+
+use crate::components::ComponentKind;
+use crate::installers::Recipe;
+use crate::integrity::{self, Check};
+use crate::jobs::CancelToken;
+use crate::net::{Downloader, Request};
+use crate::runner::RunnerInfo;
+use crate::version::{self, Version};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// File name of the index in repositories and in the cache
+const INDEX_FILE: &str = "index.json";
+
+/// File name of the signature of the index
+const SIGNATURE_FILE: &str = "index.json.minisig";
+
+/// Version of the index format read by this crate
+pub const INDEX_VERSION: u32 = 1;
+
+/// What a catalog repository offers
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Index {
+    pub version: u32,
+    pub runners: Vec<RunnerRelease>,
+    pub components: Vec<ComponentRelease>,
+    /// Recipes installing libraries programs need, e.g. the Visual C++
+    /// runtime
+    pub dependencies: Vec<Recipe>,
+    /// Recipes installing programs
+    pub installers: Vec<Recipe>,
+}
+
+/// A runner build available for download
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunnerRelease {
+    /// Name of the runner once installed, e.g. `soda-9.0-1`
+    pub name: String,
+    /// Family of the runner, e.g. `soda` or `ge-proton`
+    pub family: String,
+    pub version: String,
+    pub url: String,
+    /// Hex SHA-256 of the archive
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Size of the archive, in bytes
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// Checks the archive must pass besides its SHA-256
+    #[serde(default)]
+    pub checks: Vec<Check>,
+}
+
+/// A component release available for download
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComponentRelease {
+    pub component: ComponentKind,
+    pub version: String,
+    pub url: String,
+    /// Hex SHA-256 of the archive
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Size of the archive, in bytes
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// Checks the archive must pass besides its SHA-256
+    #[serde(default)]
+    pub checks: Vec<Check>,
+}
+
+impl RunnerRelease {
+    /// The version of the release parsed for comparisons, from its name,
+    /// e.g. `GE-Proton9-20`, so its flavor matches the installed runner's
+    pub fn parsed_version(&self) -> Option<Version> {
+        Version::parse(&self.name)
+    }
+
+    /// Download the archive of the release into `directory` and verify it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Integrity`] if the archive doesn't pass its checks,
+    /// in which case it is deleted, [`Error::Cancelled`] once `token` is
+    /// cancelled
+    pub fn download(
+        &self,
+        downloader: &Downloader,
+        directory: &Path,
+        token: Option<&CancelToken>,
+    ) -> Result<PathBuf, Error> {
+        let request = request(&self.url, self.sha256.as_deref(), directory).cancellable(token);
+        download(downloader, &request, &self.checks)
+    }
+}
+
+impl ComponentRelease {
+    /// The version of the release parsed for comparisons
+    pub fn parsed_version(&self) -> Option<Version> {
+        Version::parse(&self.version)
+    }
+
+    /// Download the archive of the release into `directory` and verify it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Integrity`] if the archive doesn't pass its checks,
+    /// in which case it is deleted, [`Error::Cancelled`] once `token` is
+    /// cancelled
+    pub fn download(
+        &self,
+        downloader: &Downloader,
+        directory: &Path,
+        token: Option<&CancelToken>,
+    ) -> Result<PathBuf, Error> {
+        let request = request(&self.url, self.sha256.as_deref(), directory).cancellable(token);
+        download(downloader, &request, &self.checks)
+    }
+}
+
+impl Index {
+    /// The releases of `family`, in the order of the index
+    pub fn runner_family<'a>(&'a self, family: &'a str) -> impl Iterator<Item = &'a RunnerRelease> {
+        self.runners.iter().filter(move |runner| runner.family == family)
+    }
+
+    /// The releases of `kind`, in the order of the index
+    pub fn component_releases(
+        &self,
+        kind: ComponentKind,
+    ) -> impl Iterator<Item = &ComponentRelease> {
+        self.components.iter().filter(move |release| release.component == kind)
+    }
+
+    /// The newest release of the flavor of `runner`, if it is newer than
+    /// `runner`, see [`crate::version`]
+    pub fn runner_update(&self, runner: &RunnerInfo) -> Option<&RunnerRelease> {
+        update(&self.runners, &runner.parsed_version()?, RunnerRelease::parsed_version)
+    }
+
+    /// The newest release of `kind`, if it is newer than the version
+    /// `installed`
+    pub fn component_update(
+        &self,
+        kind: ComponentKind,
+        installed: &str,
+    ) -> Option<&ComponentRelease> {
+        let installed = Version::parse(installed)?;
+        update(self.component_releases(kind), &installed, ComponentRelease::parsed_version)
+    }
+
+    /// Find a dependency or installer recipe by name
+    pub fn recipe(&self, name: &str) -> Option<&Recipe> {
+        self.dependencies
+            .iter()
+            .chain(&self.installers)
+            .find(|recipe| recipe.name == name)
+    }
+}
+
+/// An index, as fetched or read from the cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub index: Index,
+    /// When the index was downloaded
+    pub fetched: SystemTime,
+    /// Whether the repository couldn't be reached and the index comes from
+    /// the cache
+    pub offline: bool,
+}
+
+/// A catalog repository and its cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Catalog {
+    url: String,
+    cache: PathBuf,
+    public_key: Option<String>,
+}
+
+impl Catalog {
+    /// A catalog published at `url`, cached in the directory `cache`
+    pub fn new(url: impl Into<String>, cache: impl Into<PathBuf>) -> Self {
+        Self {
+            url: url.into().trim_end_matches('/').to_string(),
+            cache: cache.into(),
+            public_key: None,
+        }
+    }
+
+    /// Require the index to be signed with the minisign key `public_key`,
+    /// given as the base64 line of the key file
+    pub fn with_public_key(mut self, public_key: impl Into<String>) -> Self {
+        self.public_key = Some(public_key.into());
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Download the index with `downloader`, falling back to the cached one
+    /// when the repository can't be reached
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index can't be downloaded and none is cached,
+    /// or if its signature doesn't match
+    pub fn fetch(&self, downloader: &Downloader) -> Result<Snapshot, Error> {
+        match self.download(downloader) {
+            Ok(snapshot) => Ok(snapshot),
+            Err(e) => match self.cached()? {
+                Some(snapshot) => {
+                    tracing::warn!(
+                        "Cannot update the catalog {}, using its cache: {}",
+                        self.url,
+                        e
+                    );
+                    Ok(snapshot)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    /// The cached index, without downloading it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cached index can't be read or its signature
+    /// doesn't match
+    pub fn cached(&self) -> Result<Option<Snapshot>, Error> {
+        let path = self.cache.join(INDEX_FILE);
+        let Ok(metadata) = fs::metadata(&path) else {
+            return Ok(None);
+        };
+        self.verify(&path, &self.cache.join(SIGNATURE_FILE))?;
+        Ok(Some(Snapshot {
+            index: read_index(&path)?,
+            fetched: metadata.modified().map_err(Error::Io)?,
+            offline: true,
+        }))
+    }
+
+    fn download(&self, downloader: &Downloader) -> Result<Snapshot, Error> {
+        let index = self.cache.join(format!("{}.new", INDEX_FILE));
+        let signature = self.cache.join(format!("{}.new", SIGNATURE_FILE));
+        let url = format!("{}/{}", self.url, INDEX_FILE);
+        downloader.download(&Request::new(url, &index), |_| {})?;
+        if self.public_key.is_some() {
+            let url = format!("{}/{}", self.url, SIGNATURE_FILE);
+            downloader.download(&Request::new(url, &signature), |_| {})?;
+        }
+        let checked = self
+            .verify(&index, &signature)
+            .and_then(|()| read_index(&index));
+        let parsed = match checked {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                let _ = fs::remove_file(&index);
+                let _ = fs::remove_file(&signature);
+                return Err(e);
+            }
+        };
+        // Only replaced once verified, so a bad download keeps the last
+        // good index
+        fs::rename(&index, self.cache.join(INDEX_FILE)).map_err(Error::Io)?;
+        if self.public_key.is_some() {
+            fs::rename(&signature, self.cache.join(SIGNATURE_FILE)).map_err(Error::Io)?;
+        }
+        tracing::info!("Updated the catalog {}", self.url);
+        Ok(Snapshot {
+            index: parsed,
+            fetched: SystemTime::now(),
+            offline: false,
+        })
+    }
+
+    /// Check the signature of the index at `path`, when the catalog has a
+    /// public key
+    fn verify(&self, path: &Path, signature: &Path) -> Result<(), Error> {
+        let Some(public_key) = &self.public_key else {
+            return Ok(());
+        };
+        integrity::minisign(path, signature, public_key)
+    }
+}
+
+fn read_index(path: &Path) -> Result<Index, Error> {
+    let index: Index = serde_json::from_str(&fs::read_to_string(path).map_err(Error::Io)?)?;
+    if index.version > INDEX_VERSION {
+        return Err(Error::UnsupportedVersion(index.version));
+    }
+    Ok(index)
+}
+
+/// The newest of `releases` of the flavor of `installed`, if it is newer
+fn update<'a, T>(
+    releases: impl IntoIterator<Item = &'a T>,
+    installed: &Version,
+    version: impl Fn(&T) -> Option<Version>,
+) -> Option<&'a T> {
+    let newest = version::newest(releases, installed.flavor(), |release| version(*release))?;
+    version(newest)?.is_newer_than(installed).then_some(newest)
+}
+
+/// The download of the archive at `url` into `directory`
+fn request(url: &str, sha256: Option<&str>, directory: &Path) -> Request {
+    let name = url.split(['?', '#']).next().unwrap_or(url);
+    let name = name.rsplit('/').find(|part| !part.is_empty()).unwrap_or(name);
+    let request = Request::new(url, directory.join(name));
+    match sha256 {
+        Some(sha256) => request.sha256(sha256),
+        None => request,
+    }
+}
+
+/// Download an archive and verify it
+fn download(
+    downloader: &Downloader,
+    request: &Request,
+    checks: &[Check],
+) -> Result<PathBuf, Error> {
+    let path = downloader.download(request, |_| {})?;
+    integrity::verify_all(downloader, &path, checks)?;
+    Ok(path)
+}
@@ -0,0 +1,893 @@
+//! Components installed into prefixes
+//!
+//! DXVK and VKD3D-Proton are installed by overwriting Wine's builtin Direct3D
+//! DLLs in `system32` and `syswow64` and overriding them as native. Every
+//! replaced file is copied aside first and recorded, with the overrides it had
+//! before, in `.components/installed.json` inside the prefix, so that
+//! [`uninstall`] puts the prefix back the way it was. When a copy is missing,
+//! the DLL of the bottle's runner is restored instead.
+//!
+//! The copies come from the runner the bottle used at installation time, so
+//! they are replaced with the new runner's DLLs by [`refresh_originals`] when
+//! the bottle switches runners or its runner is updated. Updating the prefix
+//! to the new Wine version may put Wine's DLLs back, so the components are
+//! then checked with [`clobbered`] and reinstalled (see
+//! [`Manager::update_prefix`]).
+//!
+//! LatencyFleX and obs-vkcapture are Vulkan layers living outside the prefix.
+//! Their release only has to be recorded in the bottle, [`environment`] then
+//! adds the layer to the search path of the Vulkan loader and enables it for
+//! the bottle's programs. LatencyFleX additionally ships Wine DLLs, installed
+//! like DXVK's but without overriding anything.
+//!
+//! Media Foundation is installed like DXVK, for games whose cutscenes don't
+//! play with Wine's own implementation. The registry files at the root of its
+//! release are imported and its COM servers registered, and those entries
+//! are left in place by [`uninstall`]: they then point back to Wine's
+//! builtin DLLs. Proton ships its own Media Foundation support, so the
+//! component is not installed into bottles using it (see
+//! [`ComponentKind::is_bundled_with`]).
+//!
+//! wineasio is installed like LatencyFleX's DLLs, its COM server registered
+//! and its settings written from the bottle's
+//! [`AudioOptions`](crate::audio::AudioOptions), see [`crate::audio`].
+//!
+//! Components are taken from [`Manager::components_path`], which holds the
+//! extracted releases as `<id>/<version>/{x64,x32}/*.dll` (`x86` instead of
+//! `x32` for VKD3D-Proton), and the layer manifests of Vulkan layers in
+//! `<id>/<version>/implicit_layer.d`. Versions missing from it are fetched
+//! there from the [`ComponentSource`]s registered by the embedder, see
+//! [`crate::extensions`].
+
+use crate::audio::{self, AudioOptions};
+use crate::bottle::{Bottle, BottleState};
+use crate::extensions::ComponentSource;
+use crate::manager::Manager;
+use crate::registry::{Hive, RegistryFile};
+use crate::runner::Runner;
+use crate::templates::LATEST;
+use crate::transaction::Transaction;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Directory of the prefix holding the record and the replaced files
+const RECORD_DIR: &str = ".components";
+
+/// Directory of a release holding its Vulkan layer manifests
+const LAYER_DIR: &str = "implicit_layer.d";
+
+/// Registry key holding the DLL overrides of a prefix
+const OVERRIDES_KEY: &str = "HKEY_CURRENT_USER\\Software\\Wine\\DllOverrides";
+
+/// Directories of a release holding the 64-bit DLLs
+const X64: &[&str] = &["x64"];
+
+/// Directories of a release holding the 32-bit DLLs
+const X32: &[&str] = &["x32", "x86"];
+
+/// Directories of a runner holding Wine's 64-bit PE DLLs, newest layout first
+const RUNNER_DLL_DIRS_64: &[&str] = &[
+    "lib/wine/x86_64-windows",
+    "lib64/wine/x86_64-windows",
+    "files/lib/wine/x86_64-windows",
+    "files/lib64/wine/x86_64-windows",
+    "lib64/wine",
+    "files/lib64/wine",
+    "lib/x86_64-linux-gnu/wine/x86_64-windows",
+];
+
+/// Directories of a runner holding Wine's 32-bit PE DLLs, newest layout first
+const RUNNER_DLL_DIRS_32: &[&str] = &[
+    "lib/wine/i386-windows",
+    "lib32/wine/i386-windows",
+    "files/lib/wine/i386-windows",
+    "lib/wine",
+    "files/lib/wine",
+    "lib/i386-linux-gnu/wine/i386-windows",
+];
+
+/// A component that can be installed into a bottle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ComponentKind {
+    /// Direct3D 8 to 11 through Vulkan
+    Dxvk,
+    /// Direct3D 12 through Vulkan
+    Vkd3d,
+    /// Input latency reduction layer
+    LatencyFlex,
+    /// Capture layer feeding OBS's game capture source
+    ObsVkcapture,
+    /// Windows' Media Foundation, for videos and cutscenes
+    MediaFoundation,
+    /// ASIO driver for DAWs and audio plugins, on top of JACK
+    WineAsio,
+}
+
+impl ComponentKind {
+    pub const ALL: [Self; 6] = [
+        Self::Dxvk,
+        Self::Vkd3d,
+        Self::LatencyFlex,
+        Self::ObsVkcapture,
+        Self::MediaFoundation,
+        Self::WineAsio,
+    ];
+
+    /// Identifier of the component, as used in [`Manager::components_path`]
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Dxvk => "dxvk",
+            Self::Vkd3d => "vkd3d-proton",
+            Self::LatencyFlex => "latencyflex",
+            Self::ObsVkcapture => "obs-vkcapture",
+            Self::MediaFoundation => "mf",
+            Self::WineAsio => "wineasio",
+        }
+    }
+
+    /// Human-readable name, as shown to users
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Dxvk => "DXVK",
+            Self::Vkd3d => "VKD3D-Proton",
+            Self::LatencyFlex => "LatencyFleX",
+            Self::ObsVkcapture => "OBS Vulkan capture",
+            Self::MediaFoundation => "Media Foundation",
+            Self::WineAsio => "wineasio",
+        }
+    }
+
+    /// DLLs the component installs into the prefix, without extension
+    pub fn dlls(self) -> &'static [&'static str] {
+        match self {
+            Self::Dxvk => &["d3d8", "d3d9", "d3d10core", "d3d11", "dxgi"],
+            Self::Vkd3d => &["d3d12", "d3d12core"],
+            Self::LatencyFlex => &["latencyflex_layer", "latencyflex_wine"],
+            Self::ObsVkcapture => &[],
+            Self::MediaFoundation => &[
+                "colorcnv",
+                "mf",
+                "mferror",
+                "mfplat",
+                "mfplay",
+                "mfreadwrite",
+                "msmpeg2adec",
+                "msmpeg2vdec",
+                "sqmapi",
+            ],
+            // Named after their architecture, each release directory ships one
+            Self::WineAsio => &["wineasio32", "wineasio64"],
+        }
+    }
+
+    /// DLLs registered with `regsvr32` once installed
+    pub fn servers(self) -> &'static [&'static str] {
+        match self {
+            Self::MediaFoundation => &["colorcnv", "msmpeg2adec", "msmpeg2vdec"],
+            Self::WineAsio => &["wineasio32", "wineasio64"],
+            _ => &[],
+        }
+    }
+
+    /// Whether the DLLs replace Wine's builtin ones, and have to be
+    /// overridden as native
+    pub fn replaces_builtins(self) -> bool {
+        matches!(self, Self::Dxvk | Self::Vkd3d | Self::MediaFoundation)
+    }
+
+    /// Whether `runner` already provides what the component does, so it must
+    /// not be installed over it
+    pub fn is_bundled_with(self, runner: &dyn Runner) -> bool {
+        match self {
+            Self::MediaFoundation => is_proton(runner),
+            _ => false,
+        }
+    }
+
+    /// Variable enabling the component's Vulkan layer, for layer components
+    pub fn layer_variable(self) -> Option<&'static str> {
+        match self {
+            Self::LatencyFlex => Some("LFX"),
+            Self::ObsVkcapture => Some("OBS_VKCAPTURE"),
+            Self::Dxvk | Self::Vkd3d | Self::MediaFoundation | Self::WineAsio => None,
+        }
+    }
+}
+
+impl fmt::Display for ComponentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl FromStr for ComponentKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dxvk" => Ok(Self::Dxvk),
+            "vkd3d" | "vkd3d-proton" => Ok(Self::Vkd3d),
+            "latencyflex" | "lfx" => Ok(Self::LatencyFlex),
+            "obs-vkcapture" | "obs_vkcapture" | "vkcapture" => Ok(Self::ObsVkcapture),
+            "mf" | "media-foundation" | "mediafoundation" => Ok(Self::MediaFoundation),
+            "wineasio" | "asio" => Ok(Self::WineAsio),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{}' is not a component", s),
+            )
+            .into()),
+        }
+    }
+}
+
+/// A prefix file overwritten by a component
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplacedFile {
+    /// Path relative to the prefix, e.g. `drive_c/windows/system32/d3d11.dll`
+    pub path: PathBuf,
+    /// Copy of the original file relative to the prefix, `None` if the
+    /// component added the file
+    pub backup: Option<PathBuf>,
+}
+
+/// A DLL override set by a component
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplacedOverride {
+    pub dll: String,
+    /// The override the DLL had before, `None` if it had none
+    pub previous: Option<String>,
+}
+
+/// A component installed in a prefix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstalledComponent {
+    pub kind: ComponentKind,
+    pub version: String,
+    pub installed_at: SystemTime,
+    pub files: Vec<ReplacedFile>,
+    pub overrides: Vec<ReplacedOverride>,
+    /// Directory holding the manifest of the component's Vulkan layer
+    #[serde(default)]
+    pub layer: Option<PathBuf>,
+}
+
+/// The components installed in a prefix, as recorded in the prefix
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InstalledComponents {
+    /// Name and version of the runner the originals come from
+    pub runner: Option<String>,
+    pub components: Vec<InstalledComponent>,
+}
+
+impl InstalledComponents {
+    /// Read the record of `prefix`, empty if nothing was installed
+    pub fn load(prefix: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(record_path(prefix)) {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, prefix: &Path) -> Result<(), Error> {
+        let path = record_path(prefix);
+        fs::create_dir_all(prefix.join(RECORD_DIR)).map_err(Error::Io)?;
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(self)?).map_err(Error::Io)?;
+        fs::rename(&temporary, &path).map_err(Error::Io)
+    }
+
+    pub fn get(&self, kind: ComponentKind) -> Option<&InstalledComponent> {
+        self.components.iter().find(|component| component.kind == kind)
+    }
+
+    fn take(&mut self, kind: ComponentKind) -> Option<InstalledComponent> {
+        let index = self.components.iter().position(|component| component.kind == kind)?;
+        Some(self.components.remove(index))
+    }
+
+    /// Whether the originals come from another runner, or another version of
+    /// it, than `runner`
+    pub fn is_outdated(&self, runner: &dyn Runner) -> bool {
+        !self.components.is_empty() && self.runner.as_deref() != Some(runner_id(runner).as_str())
+    }
+}
+
+/// Install a component into a bottle
+///
+/// A `version` of [`LATEST`] picks the newest one available. Installing a
+/// component again, e.g. another version, keeps the originals recorded by the
+/// first installation. A failed installation puts back the files and
+/// overrides it replaced, see [`crate::transaction`].
+///
+/// # Errors
+///
+/// Returns an error if the version is neither available in
+/// [`Manager::components_path`] nor from a [`ComponentSource`], if the bottle
+/// has no runner, if the runner already bundles the component or if the
+/// prefix cannot be written, or [`Error::BottleBusy`] while programs run in
+/// the bottle
+pub fn install(
+    manager: &Manager,
+    bottle_name: &str,
+    kind: ComponentKind,
+    version: &str,
+) -> Result<InstalledComponent, Error> {
+    let _operation = manager.begin(bottle_name, BottleState::Updating)?;
+    let mut bottle = manager.get_bottle(bottle_name)?;
+    let runner = manager.runner_for(&bottle)?;
+    if kind.is_bundled_with(runner.as_ref()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("{} is bundled with {}", kind, runner.info().name()),
+        )
+        .into());
+    }
+    let directory = manager.components_path().join(kind.id());
+    let sources = manager.extensions().component_sources();
+    let version = if version == LATEST {
+        newest_version(&directory)
+            .or_else(|| sources.iter().find_map(|source| source.versions(kind).into_iter().next()))
+            .ok_or_else(|| not_available(kind, version))?
+    } else {
+        version.to_string()
+    };
+    let source = directory.join(&version);
+    if !source.is_dir() {
+        fetch(&sources, kind, &version, &source)?;
+    }
+    let layer = match kind.layer_variable() {
+        Some(_) if source.join(LAYER_DIR).is_dir() => Some(source.join(LAYER_DIR)),
+        Some(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} {} has no {} directory", kind, version, LAYER_DIR),
+            )
+            .into())
+        }
+        None => None,
+    };
+
+    let prefix = bottle.path.clone();
+    let original = bottle.clone();
+    let mut transaction = Transaction::new(format!("install {} in '{}'", kind, bottle.name));
+    let mut record = InstalledComponents::load(&prefix)?;
+    if record.runner.is_none() {
+        record.runner = Some(runner_id(runner.as_ref()));
+    }
+    let previous = record.take(kind);
+    let mut files = previous
+        .as_ref()
+        .map(|component| component.files.clone())
+        .unwrap_or_default();
+
+    let mut installed = Vec::new();
+    for (architecture, system) in targets(&prefix) {
+        let dlls = architecture
+            .iter()
+            .map(|dir| source.join(dir))
+            .find(|dlls| dlls.is_dir());
+        let Some(dlls) = dlls else {
+            continue;
+        };
+        for dll in kind.dlls() {
+            let file = format!("{}.dll", dll);
+            let from = dlls.join(&file);
+            if !from.is_file() {
+                continue;
+            }
+            let relative = Path::new("drive_c/windows").join(system).join(&file);
+            let target = prefix.join(&relative);
+            if !files.iter().any(|replaced| replaced.path == relative) {
+                let backup = if target.exists() {
+                    let backup = Path::new(RECORD_DIR).join(kind.id()).join(system);
+                    transaction.create_dir_all(&prefix.join(&backup))?;
+                    let backup = backup.join(&file);
+                    transaction.preserve_file(&prefix.join(&backup))?;
+                    fs::copy(&target, prefix.join(&backup)).map_err(Error::Io)?;
+                    Some(backup)
+                } else {
+                    None
+                };
+                files.push(ReplacedFile {
+                    path: relative,
+                    backup,
+                });
+            }
+            // Wine may have linked the file to the runner's copy, which must
+            // not be overwritten
+            transaction.preserve_file(&target)?;
+            if target.symlink_metadata().is_ok() {
+                fs::remove_file(&target).map_err(Error::Io)?;
+            }
+            fs::copy(&from, &target).map_err(Error::Io)?;
+            if !installed.contains(dll) {
+                installed.push(*dll);
+            }
+        }
+    }
+
+    // Only the DLLs the release ships are overridden, Wine fails to load the
+    // others when told to use native ones only
+    let mut overrides = previous
+        .map(|component| component.overrides)
+        .unwrap_or_default();
+    let registry = RegistryFile::load_hive(&prefix, Hive::CurrentUser).unwrap_or_default();
+    let current = registry.key("Software\\Wine\\DllOverrides");
+    if kind.replaces_builtins() {
+        for dll in installed {
+            if overrides.iter().any(|replaced| replaced.dll == dll) {
+                continue;
+            }
+            overrides.push(ReplacedOverride {
+                dll: dll.to_string(),
+                previous: current
+                    .and_then(|key| key.value(dll))
+                    .and_then(|value| value.as_str())
+                    .map(str::to_string),
+            });
+        }
+    }
+    for replaced in &overrides {
+        let (runner, prefix) = (runner.as_ref(), &prefix);
+        let (dll, previous) = (replaced.dll.clone(), replaced.previous.clone());
+        transaction.step(
+            format!("override {}", dll),
+            || set_override(runner, prefix, &replaced.dll, Some("native")),
+            move || set_override(runner, prefix, &dll, previous.as_deref()),
+        )?;
+    }
+    register(runner.as_ref(), &prefix, kind, &source)?;
+    if kind == ComponentKind::WineAsio {
+        set_asio_settings(runner.as_ref(), &prefix, &bottle.config.audio)?;
+    }
+
+    let component = InstalledComponent {
+        kind,
+        version: version.clone(),
+        installed_at: SystemTime::now(),
+        files,
+        overrides,
+        layer,
+    };
+    record.components.push(component.clone());
+    transaction.preserve_file(&record_path(&prefix))?;
+    record.save(&prefix)?;
+
+    set_version(&mut bottle, kind, Some(version));
+    transaction.step(
+        "update the index",
+        || manager.persistence().update_bottle(&bottle),
+        || manager.persistence().update_bottle(&original),
+    )?;
+    transaction.commit();
+    tracing::info!("Installed {} {} in '{}'", kind, component.version, bottle.name);
+    Ok(component)
+}
+
+/// Remove a component from a bottle, restoring the files and overrides it
+/// replaced
+///
+/// # Errors
+///
+/// Returns an error if the component is not installed or if the prefix
+/// cannot be written
+pub fn uninstall(manager: &Manager, bottle_name: &str, kind: ComponentKind) -> Result<(), Error> {
+    let _operation = manager.begin(bottle_name, BottleState::Updating)?;
+    let mut bottle = manager.get_bottle(bottle_name)?;
+    let prefix = bottle.path.clone();
+    let mut record = InstalledComponents::load(&prefix)?;
+    let component = record.take(kind).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} is not installed in '{}'", kind, bottle.name),
+        )
+    })?;
+    let runner = manager.runner_for(&bottle).ok();
+    let win64 = is_win64(&prefix);
+
+    for replaced in &component.files {
+        let target = prefix.join(&replaced.path);
+        if target.symlink_metadata().is_ok() {
+            fs::remove_file(&target).map_err(Error::Io)?;
+        }
+        let Some(backup) = &replaced.backup else {
+            continue;
+        };
+        let backup = prefix.join(backup);
+        if backup.is_file() {
+            fs::rename(&backup, &target).map_err(Error::Io)?;
+            continue;
+        }
+        let original = runner
+            .as_deref()
+            .and_then(|runner| runner_dll(runner, is_64_bit(&replaced.path, win64), &target));
+        match original {
+            Some(original) => {
+                fs::copy(&original, &target).map_err(Error::Io)?;
+            }
+            None => tracing::warn!(
+                "No original left for '{}', Wine will recreate it on the next prefix update",
+                replaced.path.display()
+            ),
+        }
+    }
+
+    match &runner {
+        Some(runner) => {
+            for replaced in &component.overrides {
+                let previous = replaced.previous.as_deref();
+                set_override(runner.as_ref(), &prefix, &replaced.dll, previous)?;
+            }
+        }
+        None => tracing::warn!(
+            "'{}' has no runner, the overrides of {} are left in place",
+            bottle.name,
+            kind
+        ),
+    }
+
+    let backups = prefix.join(RECORD_DIR).join(kind.id());
+    if backups.exists() {
+        fs::remove_dir_all(&backups).map_err(Error::Io)?;
+    }
+    record.save(&prefix)?;
+
+    set_version(&mut bottle, kind, None);
+    manager.persistence().update_bottle(&bottle)?;
+    tracing::info!("Uninstalled {} from '{}'", kind, bottle.name);
+    Ok(())
+}
+
+/// Replace the originals recorded in `prefix` with the DLLs of `runner`
+///
+/// Called when a bottle switches runners, so that uninstalling a component
+/// afterwards restores the DLLs of the runner now in use rather than those of
+/// the previous one. Originals the runner has no copy of are kept.
+pub fn refresh_originals(prefix: &Path, runner: &dyn Runner) -> Result<(), Error> {
+    let mut record = InstalledComponents::load(prefix)?;
+    if record.components.is_empty() {
+        return Ok(());
+    }
+    let win64 = is_win64(prefix);
+    let replaced_files = record.components.iter().flat_map(|component| &component.files);
+    for replaced in replaced_files {
+        let Some(backup) = &replaced.backup else {
+            continue;
+        };
+        let original = runner_dll(runner, is_64_bit(&replaced.path, win64), &replaced.path);
+        if let Some(original) = original {
+            fs::copy(&original, prefix.join(backup)).map_err(Error::Io)?;
+        }
+    }
+    record.runner = Some(runner_id(runner));
+    record.save(prefix)
+}
+
+/// The components of `prefix` whose DLLs no longer match their release
+///
+/// Components whose release is no longer in [`Manager::components_path`]
+/// can't be checked and are not returned.
+pub fn clobbered(manager: &Manager, prefix: &Path) -> Result<Vec<InstalledComponent>, Error> {
+    let record = InstalledComponents::load(prefix)?;
+    let win64 = is_win64(prefix);
+    let components = record.components.into_iter().filter(|component| {
+        let release = manager
+            .components_path()
+            .join(component.kind.id())
+            .join(&component.version);
+        release.is_dir()
+            && component.files.iter().any(|replaced| {
+                let architecture = if is_64_bit(&replaced.path, win64) {
+                    X64
+                } else {
+                    X32
+                };
+                let Some(name) = replaced.path.file_name() else {
+                    return false;
+                };
+                let shipped = architecture
+                    .iter()
+                    .map(|dir| release.join(dir).join(name))
+                    .find(|path| path.is_file());
+                match shipped {
+                    Some(shipped) => !same_content(&shipped, &prefix.join(&replaced.path)),
+                    None => false,
+                }
+            })
+    });
+    Ok(components.collect())
+}
+
+/// Reinstall the recorded version of the components of a bottle whose DLLs
+/// were replaced, e.g. by a prefix update
+///
+/// # Returns
+///
+/// The reinstalled components
+pub fn repair(manager: &Manager, bottle_name: &str) -> Result<Vec<InstalledComponent>, Error> {
+    let _operation = manager.begin(bottle_name, BottleState::Updating)?;
+    let bottle = manager.get_bottle(bottle_name)?;
+    let runner = manager.runner_for(&bottle)?;
+    let mut reinstalled = Vec::new();
+    for component in clobbered(manager, &bottle.path)? {
+        if component.kind.is_bundled_with(runner.as_ref()) {
+            tracing::info!(
+                "{} bundles {}, not reinstalling it in '{}'",
+                runner.info().name(),
+                component.kind,
+                bottle.name
+            );
+            continue;
+        }
+        tracing::info!(
+            "The DLLs of {} in '{}' were replaced, reinstalling {}",
+            component.kind,
+            bottle.name,
+            component.version
+        );
+        reinstalled.push(install(manager, bottle_name, component.kind, &component.version)?);
+    }
+    Ok(reinstalled)
+}
+
+/// Variables enabling the Vulkan layers installed in `prefix`
+///
+/// The layers are added to the loader's search path with
+/// `VK_ADD_LAYER_PATH`, so the host's own layers stay available.
+pub fn environment(prefix: &Path) -> Vec<(&'static str, String)> {
+    let Ok(record) = InstalledComponents::load(prefix) else {
+        return Vec::new();
+    };
+    let mut layers = Vec::new();
+    let mut environment = Vec::new();
+    for component in &record.components {
+        let (Some(variable), Some(layer)) = (component.kind.layer_variable(), &component.layer)
+        else {
+            continue;
+        };
+        layers.push(layer.display().to_string());
+        environment.push((variable, "1".to_string()));
+    }
+    if !layers.is_empty() {
+        environment.push(("VK_ADD_LAYER_PATH", layers.join(":")));
+    }
+    environment
+}
+
+/// Name and version of a runner, as recorded in [`InstalledComponents::runner`]
+fn runner_id(runner: &dyn Runner) -> String {
+    format!("{} {}", runner.info().name(), runner.info().version().trim())
+}
+
+fn same_content(a: &Path, b: &Path) -> bool {
+    let same_size =
+        matches!((fs::metadata(a), fs::metadata(b)), (Ok(a), Ok(b)) if a.len() == b.len());
+    same_size && matches!((fs::read(a), fs::read(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+/// The architecture directories of a release and the system directory they
+/// are installed to, for the architectures of `prefix`
+fn targets(prefix: &Path) -> Vec<(&'static [&'static str], &'static str)> {
+    if is_win64(prefix) {
+        vec![(X64, "system32"), (X32, "syswow64")]
+    } else {
+        vec![(X32, "system32")]
+    }
+}
+
+fn is_win64(prefix: &Path) -> bool {
+    prefix.join("drive_c/windows/syswow64").is_dir()
+}
+
+/// Whether a DLL of the prefix is a 64-bit one
+fn is_64_bit(relative: &Path, win64: bool) -> bool {
+    win64 && !relative.components().any(|c| c.as_os_str().eq_ignore_ascii_case("syswow64"))
+}
+
+/// The runner's own copy of a DLL of the prefix
+pub(crate) fn runner_dll(runner: &dyn Runner, is_64_bit: bool, dll: &Path) -> Option<PathBuf> {
+    let name = dll.file_name()?;
+    let directory = runner.wine().root();
+    let candidates = if is_64_bit {
+        RUNNER_DLL_DIRS_64
+    } else {
+        RUNNER_DLL_DIRS_32
+    };
+    candidates
+        .iter()
+        .map(|candidate| directory.join(candidate).join(name))
+        .find(|path| path.is_file())
+}
+
+/// Whether `runner` is Proton, or UMU running Proton
+fn is_proton(runner: &dyn Runner) -> bool {
+    runner.info().directory().join("proton").is_file()
+        || runner.info().executable_path().ends_with("umu-run")
+}
+
+/// Import the registry files at the root of a release, and register the COM
+/// servers of the component
+fn register(
+    runner: &dyn Runner,
+    prefix: &Path,
+    kind: ComponentKind,
+    source: &Path,
+) -> Result<(), Error> {
+    let mut files: Vec<PathBuf> = fs::read_dir(source)
+        .map_err(Error::Io)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e.eq_ignore_ascii_case("reg")))
+        .collect();
+    files.sort();
+    for file in files {
+        // Wine maps the host's root directory to Z:
+        let file = format!("Z:{}", file.display()).replace('/', "\\");
+        run(runner, prefix, "regedit", &["/S", &file])?;
+    }
+    for (_, system) in targets(prefix) {
+        let regsvr32 = format!("C:\\windows\\{}\\regsvr32.exe", system);
+        for server in kind.servers() {
+            let file = format!("{}.dll", server);
+            // Not every architecture ships every server
+            if !prefix.join("drive_c/windows").join(system).join(&file).is_file() {
+                continue;
+            }
+            run(runner, prefix, &regsvr32, &["/s", &file])?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the settings of wineasio to the registry of `prefix`
+pub(crate) fn set_asio_settings(
+    runner: &dyn Runner,
+    prefix: &Path,
+    options: &AudioOptions,
+) -> Result<(), Error> {
+    for (name, value) in options.asio_settings() {
+        let value = value.to_string();
+        let args = [
+            "add",
+            audio::ASIO_KEY,
+            "/v",
+            name,
+            "/t",
+            "REG_DWORD",
+            "/d",
+            value.as_str(),
+            "/f",
+        ];
+        run(runner, prefix, "reg", &args)?;
+    }
+    Ok(())
+}
+
+/// Run a program in the prefix, failing if it does
+pub(crate) fn run(
+    runner: &dyn Runner,
+    prefix: &Path,
+    program: &str,
+    args: &[&str],
+) -> Result<(), Error> {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    let status = runner
+        .command(Path::new(program), &args, prefix, &HashMap::new())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(Error::Io)?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!("'{}' failed: {}", program, status)).into());
+    }
+    Ok(())
+}
+
+/// Set or remove (with `None`) the override of a DLL with `reg.exe`
+pub(crate) fn set_override(
+    runner: &dyn Runner,
+    prefix: &Path,
+    dll: &str,
+    mode: Option<&str>,
+) -> Result<(), Error> {
+    let action = if mode.is_some() { "add" } else { "delete" };
+    let mut args = vec![action, OVERRIDES_KEY, "/v", dll];
+    if let Some(mode) = mode {
+        args.extend(["/d", mode]);
+    }
+    args.push("/f");
+    let args: Vec<String> = args.into_iter().map(str::to_string).collect();
+
+    let status = runner
+        .command(Path::new("reg"), &args, prefix, &HashMap::new())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(Error::Io)?;
+    // Deleting an override that doesn't exist fails, which is fine
+    if !status.success() && mode.is_some() {
+        return Err(std::io::Error::other(format!("cannot override '{}': {}", dll, status)).into());
+    }
+    Ok(())
+}
+
+fn set_version(bottle: &mut Bottle, kind: ComponentKind, version: Option<String>) {
+    match kind {
+        ComponentKind::Dxvk => bottle.config.dxvk_version = version,
+        ComponentKind::Vkd3d => bottle.config.vkd3d_version = version,
+        // Only recorded in the prefix
+        ComponentKind::LatencyFlex
+        | ComponentKind::ObsVkcapture
+        | ComponentKind::MediaFoundation
+        | ComponentKind::WineAsio => {}
+    }
+}
+
+/// Fetch `version` of `kind` into `destination` from the first source
+/// providing it, see [`crate::extensions::ComponentSource`]
+fn fetch(
+    sources: &[Arc<dyn ComponentSource>],
+    kind: ComponentKind,
+    version: &str,
+    destination: &Path,
+) -> Result<(), Error> {
+    let source = sources
+        .iter()
+        .find(|source| source.versions(kind).iter().any(|other| other == version))
+        .ok_or_else(|| not_available(kind, version))?;
+    // Fetched next to the destination first, so failed fetches don't leave
+    // a partial release behind
+    let mut name = destination.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    let partial = destination.with_file_name(name);
+    if partial.exists() {
+        fs::remove_dir_all(&partial).map_err(Error::Io)?;
+    }
+    fs::create_dir_all(&partial).map_err(Error::Io)?;
+    if let Err(e) = source.fetch(kind, version, &partial) {
+        let _ = fs::remove_dir_all(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, destination).map_err(Error::Io)?;
+    tracing::info!("Fetched {} {} into '{}'", kind, version, destination.display());
+    Ok(())
+}
+
+/// The newest version directory in `directory`, comparing the numbers in
+/// the names, leaving out fetches in progress
+fn newest_version(directory: &Path) -> Option<String> {
+    fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| !name.ends_with(".part"))
+        .max_by_key(|name| {
+            name.split(|c: char| !c.is_ascii_digit())
+                .filter_map(|number| number.parse::<u64>().ok())
+                .collect::<Vec<_>>()
+        })
+}
+
+fn record_path(prefix: &Path) -> PathBuf {
+    prefix.join(RECORD_DIR).join("installed.json")
+}
+
+fn not_available(kind: ComponentKind, version: &str) -> Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{} {} is not available", kind, version),
+    )
+    .into()
+}
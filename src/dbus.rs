@@ -0,0 +1,160 @@
+use crate::bottle::BottleFilter;
+use crate::events::Event;
+use crate::manager::Manager;
+use crate::Error;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use zbus::connection::WeakConnection;
+use zbus::fdo;
+use zbus::object_server::SignalEmitter;
+
+/// Well-known bus name claimed by the service
+pub const BUS_NAME: &str = "org.bottles.Next";
+/// Object path the interface is exported at
+pub const OBJECT_PATH: &str = "/org/bottles/Next";
+
+/// D-Bus interface exposing basic bottle operations on the session bus
+///
+/// Meant for desktop integration (search providers, launchers, shell scripts)
+/// that shouldn't need gRPC bindings; the gRPC API remains the complete one.
+pub struct Interface {
+    manager: Arc<Manager>,
+}
+
+impl Interface {
+    pub fn new(manager: Arc<Manager>) -> Self {
+        Self { manager }
+    }
+}
+
+fn failed(error: Error) -> fdo::Error {
+    fdo::Error::Failed(error.to_string())
+}
+
+#[zbus::interface(name = "org.bottles.Next")]
+impl Interface {
+    /// Names of all the bottles in the index
+    fn list_bottles(&self) -> fdo::Result<Vec<String>> {
+        self.manager
+            .bottle_summaries(BottleFilter::default())
+            .map(|summary| summary.map(|b| b.name))
+            .collect::<Result<_, _>>()
+            .map_err(failed)
+    }
+
+    /// Launch a program in a bottle, returning the id of the new session
+    fn launch_program(&self, bottle: &str, program: &str, args: Vec<String>) -> fdo::Result<u64> {
+        let session = self
+            .manager
+            .launch_program(bottle, Path::new(program), &args)
+            .map_err(failed)?;
+        Ok(session.id)
+    }
+
+    /// Stop a running session
+    fn stop_session(&self, id: u64) -> fdo::Result<()> {
+        self.manager.stop_session(id).map_err(failed)?;
+        Ok(())
+    }
+
+    /// Emitted when a program starts running in a bottle
+    #[zbus(signal)]
+    async fn session_started(
+        emitter: &SignalEmitter<'_>,
+        id: u64,
+        bottle: &str,
+        program: &str,
+    ) -> zbus::Result<()>;
+
+    /// Emitted when a program running in a bottle exits or is stopped
+    #[zbus(signal)]
+    async fn session_stopped(
+        emitter: &SignalEmitter<'_>,
+        id: u64,
+        bottle: &str,
+        program: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// Display name of a program, without its directory
+fn program_name(program: &Path) -> String {
+    program
+        .file_name()
+        .unwrap_or(program.as_os_str())
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Emit `SessionStarted`/`SessionStopped` as the manager starts programs and
+/// sees them exit, see [`Event::ProcessStarted`] and [`Event::ProcessExited`]
+///
+/// The task only keeps a weak reference to the connection: it ends at the
+/// first event after the connection is dropped, or with the manager.
+async fn watch_sessions(connection: WeakConnection, mut events: Receiver<Event>) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                tracing::warn!("Missed {} events, session signals were not emitted", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let (started, session, bottle, program) = match &event {
+            Event::ProcessStarted {
+                session,
+                bottle,
+                program,
+                ..
+            } => (true, *session, bottle, program),
+            Event::ProcessExited {
+                session,
+                bottle,
+                program,
+                ..
+            } => (false, *session, bottle, program),
+            _ => continue,
+        };
+        let Some(connection) = connection.upgrade() else {
+            break;
+        };
+        let emitter = match SignalEmitter::new(&connection, OBJECT_PATH) {
+            Ok(emitter) => emitter,
+            Err(e) => {
+                tracing::error!("Cannot emit session signals: {}", e);
+                break;
+            }
+        };
+        let program = program_name(program);
+        let result = if started {
+            Interface::session_started(&emitter, session, bottle, &program).await
+        } else {
+            Interface::session_stopped(&emitter, session, bottle, &program).await
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to emit a session signal: {}", e);
+        }
+    }
+}
+
+/// Export the interface on the session bus under [`BUS_NAME`]
+///
+/// The interface stays available for as long as the returned connection lives.
+/// `SessionStarted` and `SessionStopped` signals are emitted from a background
+/// task, so tools like do-not-disturb toggles or performance profiles can react
+/// to programs being launched.
+///
+/// # Errors
+///
+/// Returns an error if the session bus is unreachable or the name is already taken
+pub async fn serve(manager: Arc<Manager>) -> Result<zbus::Connection, Error> {
+    let connection = zbus::connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Interface::new(manager.clone()))?
+        .build()
+        .await?;
+    tokio::spawn(watch_sessions(connection.downgrade(), manager.events().subscribe()));
+    Ok(connection)
+}
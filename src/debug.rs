@@ -0,0 +1,212 @@
+//! Guided collection of Wine debug output
+//!
+//! Wine's debug channels are powerful but hard to pick without knowing Wine's
+//! internals. [`DebugPreset`] names the channel sets useful for common kinds
+//! of problems, and [`capture`] relaunches the last program of a bottle with a
+//! preset and bundles everything a bug report needs into a single file,
+//! including what the program changed in the prefix.
+
+use crate::bottle::Bottle;
+use crate::export::{self, HostInfo};
+use crate::manager::Manager;
+use crate::prefix::{self, PrefixDiff, Snapshot};
+use crate::session::Launch;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often a capture checks whether the program exited
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Named set of Wine debug channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DebugPreset {
+    /// Crashes, black screens and rendering glitches
+    D3dIssues,
+    /// Missing, crackling or delayed sound
+    AudioIssues,
+    /// Programs that fail to start or complain about missing DLLs
+    LoaderIssues,
+}
+
+impl DebugPreset {
+    pub const ALL: [Self; 3] = [Self::D3dIssues, Self::AudioIssues, Self::LoaderIssues];
+
+    /// Human-readable name, as shown to users
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::D3dIssues => "d3d issues",
+            Self::AudioIssues => "audio issues",
+            Self::LoaderIssues => "loader issues",
+        }
+    }
+
+    /// Value of `WINEDEBUG` for the preset
+    pub fn channels(self) -> &'static str {
+        match self {
+            Self::D3dIssues => "err+all,warn+d3d,+d3d11,+d3d12,+dxgi,+vulkan,+wgl",
+            Self::AudioIssues => "err+all,+mmdevapi,+winepulse,+winealsa,+dsound,+xaudio2",
+            Self::LoaderIssues => "err+all,warn+all,+loaddll,+module",
+        }
+    }
+
+    /// All the variables the preset sets, including `WINEDEBUG`
+    pub fn environment(self) -> Vec<(&'static str, &'static str)> {
+        let mut environment = vec![("WINEDEBUG", self.channels())];
+        if self == Self::D3dIssues {
+            environment.push(("DXVK_LOG_LEVEL", "info"));
+            environment.push(("VKD3D_DEBUG", "warn"));
+        }
+        environment
+    }
+}
+
+impl fmt::Display for DebugPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl FromStr for DebugPreset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let wanted = s.to_ascii_lowercase().replace(['-', '_'], " ");
+        Self::ALL
+            .into_iter()
+            .find(|preset| {
+                let label = preset.label();
+                label == wanted || label.trim_end_matches(" issues") == wanted
+            })
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("'{}' is not a debug preset", s),
+                )
+                .into()
+            })
+    }
+}
+
+/// Everything collected by a [`capture`], meant to be attached to bug reports
+///
+/// Paths and environment values are redacted like in
+/// [`CompatibilityReport`](crate::export::CompatibilityReport).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureBundle {
+    pub preset: DebugPreset,
+    pub bottle: Bottle,
+    pub launch: Launch,
+    pub host: HostInfo,
+    pub started_at: SystemTime,
+    /// The program was still running when the capture timed out and was stopped
+    pub timed_out: bool,
+    /// Output of the program with the preset's channels enabled
+    pub log: String,
+    /// Files and registry keys the program changed, `None` if the prefix
+    /// couldn't be read
+    pub changes: Option<PrefixDiff>,
+}
+
+impl CaptureBundle {
+    /// Serialize the bundle as pretty-printed JSON
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Relaunch the last program of a bottle with a debug preset and collect its
+/// output
+///
+/// Blocks until the program exits or `timeout` elapses, in which case it is
+/// stopped. The bundle is also written next to the session logs.
+///
+/// # Returns
+///
+/// The bundle and the path it was written to
+///
+/// # Errors
+///
+/// Returns an error if nothing was launched in the bottle since the manager
+/// started, or if the program cannot be launched again
+pub fn capture(
+    manager: &Manager,
+    bottle_name: &str,
+    preset: DebugPreset,
+    timeout: Duration,
+) -> Result<(CaptureBundle, PathBuf), Error> {
+    let bottle = manager.get_bottle(bottle_name)?;
+    let launch = manager.last_launch(bottle_name).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("nothing was launched in '{}' yet", bottle_name),
+        )
+    })?;
+
+    let mut overrides = launch.overrides.clone();
+    for (key, value) in preset.environment() {
+        overrides.insert(key.to_string(), value.to_string());
+    }
+    let before = Snapshot::capture(&bottle.path)
+        .map_err(|e| tracing::warn!("Cannot snapshot '{}': {}", bottle_name, e))
+        .ok();
+    let session =
+        manager.launch_program_with_env(bottle_name, &launch.program, &launch.args, &overrides)?;
+    tracing::info!(
+        "Capturing '{}' in '{}' with the {} preset",
+        launch.program.display(),
+        bottle_name,
+        preset
+    );
+
+    let deadline = Instant::now() + timeout;
+    let mut timed_out = false;
+    while manager.sessions().iter().any(|s| s.id == session.id) {
+        if Instant::now() >= deadline {
+            timed_out = true;
+            manager.stop_session(session.id)?;
+            break;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    let changes = before.and_then(|before| {
+        prefix::flush_registry(manager, &bottle);
+        let after = Snapshot::capture(&bottle.path).ok()?;
+        Some(prefix::diff(&before, &after))
+    });
+
+    let log = match &session.log {
+        // Programs don't always write UTF-8
+        Some(path) => String::from_utf8_lossy(&fs::read(path).map_err(Error::Io)?).into_owned(),
+        None => String::new(),
+    };
+    let log = match std::env::var("HOME") {
+        Ok(home) if !home.is_empty() => log.replace(&home, "~"),
+        _ => log,
+    };
+
+    let mut launch = launch;
+    launch.program = PathBuf::from(export::redact_path(&launch.program));
+    export::redact_args(&mut launch.args);
+    export::redact_environment(&mut launch.overrides);
+    let bundle = CaptureBundle {
+        preset,
+        bottle: export::redact_bottle(bottle),
+        launch,
+        host: HostInfo::current(),
+        started_at: session.started_at,
+        timed_out,
+        log,
+        changes,
+    };
+    let path = manager.capture_path(bottle_name, session.id);
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory).map_err(Error::Io)?;
+    }
+    fs::write(&path, bundle.to_json()?).map_err(Error::Io)?;
+    Ok((bundle, path))
+}
@@ -0,0 +1,174 @@
+//! Disk space used by bottles
+//!
+//! [`usage`] measures a prefix, broken down by the top-level directories of
+//! `drive_c` and by installed component, along with the save backups of the
+//! bottle (see [`crate::saves`]), which are kept outside the prefix.
+//!
+//! Prefixes hold tens of thousands of files, so the size of the files of each
+//! directory is remembered with the directory's modification time, which
+//! changes whenever an entry is added, removed or renamed in it. Measuring a
+//! prefix again only reads the directories that changed. A file growing in
+//! place, without being replaced, isn't noticed until its directory changes.
+//!
+//! Symbolic links are not followed: `dosdevices/z:` links to the host's root.
+
+use crate::bottle::Bottle;
+use crate::components::{ComponentKind, InstalledComponents};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::SystemTime;
+
+/// Space used by a bottle, in bytes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskUsage {
+    /// The whole prefix
+    pub total: u64,
+    /// Entries of `drive_c`, largest first
+    pub drive_c: Vec<EntryUsage>,
+    /// Files installed by components, and the originals they replaced
+    pub components: Vec<ComponentUsage>,
+    /// Save backups of the bottle, outside the prefix
+    pub backups: u64,
+}
+
+/// Space used by a file or directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryUsage {
+    pub name: String,
+    pub bytes: u64,
+}
+
+/// Space used by an installed component
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComponentUsage {
+    pub kind: ComponentKind,
+    pub version: String,
+    pub bytes: u64,
+}
+
+/// What is remembered of a directory
+#[derive(Debug, Clone)]
+struct Cached {
+    modified: SystemTime,
+    /// Size of the files directly in the directory
+    files: u64,
+    subdirectories: Vec<PathBuf>,
+}
+
+/// Measure the space used by `bottle`, see the [module documentation](self)
+///
+/// # Errors
+///
+/// Returns an error if the prefix can't be read
+pub fn usage(bottle: &Bottle) -> Result<DiskUsage, Error> {
+    let _span = tracing::debug_span!("disk_usage", bottle = %bottle.name).entered();
+    let prefix = &bottle.path;
+    let mut usage = DiskUsage {
+        total: directory_size(prefix)?,
+        ..DiskUsage::default()
+    };
+
+    let drive_c = prefix.join("drive_c");
+    if drive_c.is_dir() {
+        for entry in fs::read_dir(&drive_c).map_err(Error::Io)? {
+            let entry = entry.map_err(Error::Io)?;
+            let file_type = entry.file_type().map_err(Error::Io)?;
+            let bytes = if file_type.is_dir() {
+                directory_size(&entry.path())?
+            } else if file_type.is_file() {
+                entry.metadata().map_err(Error::Io)?.len()
+            } else {
+                continue;
+            };
+            let name = entry.file_name().to_string_lossy().into_owned();
+            usage.drive_c.push(EntryUsage { name, bytes });
+        }
+        usage.drive_c.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+    }
+
+    let size = |relative: &Path| fs::metadata(prefix.join(relative)).map_or(0, |m| m.len());
+    for component in InstalledComponents::load(prefix)?.components {
+        let bytes = component
+            .files
+            .iter()
+            .map(|file| size(file.path.as_path()) + file.backup.as_deref().map_or(0, size))
+            .sum();
+        usage.components.push(ComponentUsage {
+            kind: component.kind,
+            version: component.version,
+            bytes,
+        });
+    }
+
+    if let Some(destination) = &bottle.config.save_backup.destination {
+        let backups = destination.join(&bottle.name);
+        if backups.is_dir() {
+            usage.backups = directory_size(&backups)?;
+        }
+    }
+    Ok(usage)
+}
+
+/// Forget what is remembered of the directories under `path`, e.g. once a
+/// bottle is deleted
+pub fn forget(path: &Path) {
+    cache().retain(|directory, _| !directory.starts_with(path));
+}
+
+/// Size of the files under `directory`, from the cache where it is current
+fn directory_size(directory: &Path) -> Result<u64, Error> {
+    let mut total = 0;
+    let mut pending = vec![directory.to_path_buf()];
+    while let Some(directory) = pending.pop() {
+        let cached = match read(&directory) {
+            Ok(cached) => cached,
+            // Removed while being measured
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        total += cached.files;
+        pending.extend(cached.subdirectories);
+    }
+    Ok(total)
+}
+
+/// What `directory` holds, read again if it changed since it was cached
+fn read(directory: &Path) -> Result<Cached, Error> {
+    let modified = fs::symlink_metadata(directory)
+        .and_then(|metadata| metadata.modified())
+        .map_err(Error::Io)?;
+    if let Some(cached) = cache().get(directory) {
+        if cached.modified == modified {
+            return Ok(cached.clone());
+        }
+    }
+
+    let mut cached = Cached {
+        modified,
+        files: 0,
+        subdirectories: Vec::new(),
+    };
+    for entry in fs::read_dir(directory).map_err(Error::Io)? {
+        let entry = entry.map_err(Error::Io)?;
+        let file_type = entry.file_type().map_err(Error::Io)?;
+        if file_type.is_dir() {
+            cached.subdirectories.push(entry.path());
+        } else if file_type.is_file() {
+            cached.files += entry.metadata().map_or(0, |metadata| metadata.len());
+        }
+    }
+    cache().insert(directory.to_path_buf(), cached.clone());
+    Ok(cached)
+}
+
+fn cache() -> MutexGuard<'static, HashMap<PathBuf, Cached>> {
+    static CACHE: OnceLock<Mutex<HashMap<PathBuf, Cached>>> = OnceLock::new();
+    CACHE
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
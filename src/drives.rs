@@ -0,0 +1,228 @@
+//! Windows drive letters of a prefix
+//!
+//! Wine maps a drive letter to a host directory with a symbolic link named
+//! after the letter in the prefix's `dosdevices` directory, e.g. `d:` linking
+//! to `/mnt/games`. The type of the drive, which some installers check, is
+//! stored under `HKLM\Software\Wine\Drives`; drives without one are typed
+//! after the file system they are on.
+//!
+//! `C:` is the prefix's own `drive_c` and can't be changed here. `Z:` maps the
+//! host's root directory by default and can be removed, to keep programs out
+//! of the rest of the host.
+
+use crate::components;
+use crate::registry::{Hive, RegistryFile};
+use crate::runner::Runner;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Registry key holding the types of the drives
+const DRIVES_KEY: &str = "Software\\Wine\\Drives";
+
+/// How Windows programs see a drive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DriveKind {
+    #[default]
+    HardDisk,
+    Network,
+    /// An optical drive, for games checking for their disc
+    CdRom,
+    Floppy,
+}
+
+impl DriveKind {
+    /// Name of the type in the registry
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::HardDisk => "hd",
+            Self::Network => "network",
+            Self::CdRom => "cdrom",
+            Self::Floppy => "floppy",
+        }
+    }
+}
+
+impl fmt::Display for DriveKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+impl FromStr for DriveKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hd" | "harddisk" => Ok(Self::HardDisk),
+            "network" => Ok(Self::Network),
+            "cdrom" => Ok(Self::CdRom),
+            "floppy" => Ok(Self::Floppy),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{}' is not a drive type", s),
+            )
+            .into()),
+        }
+    }
+}
+
+/// A drive letter of a prefix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Drive {
+    /// Uppercase letter of the drive
+    pub letter: char,
+    /// Directory the drive maps to, on the host
+    pub path: PathBuf,
+    /// Type set in the registry, `None` when Wine picks it
+    pub kind: Option<DriveKind>,
+}
+
+/// The drives of `prefix`, sorted by letter
+pub fn list(prefix: &Path) -> Result<Vec<Drive>, Error> {
+    let dosdevices = prefix.join("dosdevices");
+    let registry = RegistryFile::load_hive(prefix, Hive::LocalMachine).unwrap_or_default();
+    let types = registry.key(DRIVES_KEY);
+    let mut drives = Vec::new();
+    for entry in fs::read_dir(&dosdevices).map_err(Error::Io)? {
+        let entry = entry.map_err(Error::Io)?;
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        let Some(letter) = letter_of(&name) else {
+            continue;
+        };
+        let Ok(target) = fs::read_link(entry.path()) else {
+            continue;
+        };
+        let kind = types
+            .and_then(|key| key.value(&name))
+            .and_then(|value| value.as_str())
+            .and_then(|kind| kind.parse().ok());
+        drives.push(Drive {
+            letter,
+            // Wine links drive_c relatively
+            path: dosdevices.join(target),
+            kind,
+        });
+    }
+    drives.sort_by_key(|drive| drive.letter);
+    Ok(drives)
+}
+
+/// Map drive `letter` of `prefix` to the host directory `path`
+///
+/// # Errors
+///
+/// Returns an error if the letter is not one of `A:` to `Z:`, is `C:` or is
+/// already mapped, or if `path` is not an existing directory
+pub fn add(
+    runner: &dyn Runner,
+    prefix: &Path,
+    letter: char,
+    path: &Path,
+    kind: DriveKind,
+) -> Result<Drive, Error> {
+    let link = link(prefix, letter)?;
+    if link.symlink_metadata().is_ok() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("drive {}: is already mapped", letter.to_ascii_uppercase()),
+        )
+        .into());
+    }
+    if !path.is_absolute() || !path.is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("'{}' is not a directory", path.display()),
+        )
+        .into());
+    }
+    std::os::unix::fs::symlink(path, &link).map_err(Error::Io)?;
+    let value = drive_value(letter);
+    let key = format!("{}\\{}", Hive::LocalMachine.root_name(), DRIVES_KEY);
+    let args = ["add", key.as_str(), "/v", value.as_str(), "/d", kind.id(), "/f"];
+    if let Err(e) = components::run(runner, prefix, "reg", &args) {
+        fs::remove_file(&link).map_err(Error::Io)?;
+        return Err(e);
+    }
+    tracing::info!("Mapped {}: to '{}'", letter.to_ascii_uppercase(), path.display());
+    Ok(Drive {
+        letter: letter.to_ascii_uppercase(),
+        path: path.to_path_buf(),
+        kind: Some(kind),
+    })
+}
+
+/// Unmap drive `letter` of `prefix`
+///
+/// # Errors
+///
+/// Returns an error if the letter is `C:` or is not mapped
+pub fn remove(runner: &dyn Runner, prefix: &Path, letter: char) -> Result<(), Error> {
+    let link = link(prefix, letter)?;
+    fs::remove_file(&link).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("drive {}: is not mapped", letter.to_ascii_uppercase()),
+        )
+        .into(),
+        _ => Error::Io(e),
+    })?;
+    let value = drive_value(letter);
+    let key = format!("{}\\{}", Hive::LocalMachine.root_name(), DRIVES_KEY);
+    // Drives without a type have no value to delete
+    let args = ["delete", key.as_str(), "/v", value.as_str(), "/f"];
+    if components::run(runner, prefix, "reg", &args).is_err() {
+        tracing::debug!("{}: had no type in the registry", letter.to_ascii_uppercase());
+    }
+    Ok(())
+}
+
+/// Windows path of the host file `path` in `prefix`, through the drive
+/// mapping the closest directory above it, e.g. `Z:\home\user\setup.msi`
+///
+/// Returns `None` if no drive maps a directory above `path`.
+pub fn windows_path(prefix: &Path, path: &Path) -> Option<String> {
+    let path = fs::canonicalize(path).ok()?;
+    let (drive, rest) = list(prefix)
+        .ok()?
+        .into_iter()
+        .filter_map(|drive| {
+            let root = fs::canonicalize(&drive.path).ok()?;
+            let rest = path.strip_prefix(&root).ok()?.to_path_buf();
+            Some((drive, rest))
+        })
+        .min_by_key(|(_, rest)| rest.components().count())?;
+    let rest: Vec<String> = rest
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    Some(format!("{}:\\{}", drive.letter, rest.join("\\")))
+}
+
+/// Link of drive `letter` in `dosdevices`, checking the letter can be changed
+fn link(prefix: &Path, letter: char) -> Result<PathBuf, Error> {
+    if !letter.is_ascii_alphabetic() || letter.eq_ignore_ascii_case(&'c') {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{}: is not a drive letter that can be changed", letter),
+        )
+        .into());
+    }
+    Ok(prefix.join("dosdevices").join(drive_value(letter)))
+}
+
+/// Name of a drive in `dosdevices` and in the registry, e.g. `d:`
+fn drive_value(letter: char) -> String {
+    format!("{}:", letter.to_ascii_lowercase())
+}
+
+/// The drive letter named by a `dosdevices` entry, e.g. `d:`
+fn letter_of(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    let (letter, colon) = (chars.next()?, chars.next()?);
+    (letter.is_ascii_alphabetic() && colon == ':' && chars.next().is_none())
+        .then(|| letter.to_ascii_uppercase())
+}
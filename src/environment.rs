@@ -0,0 +1,188 @@
+//! Layered environment resolution for programs launched in a bottle
+//!
+//! The environment is built from five layers, each overriding the previous:
+//!
+//! 1. crate defaults, applied to every bottle
+//! 2. the template of the bottle type (see [`BottleType`])
+//! 3. the variables enabling the bottle's typed settings: the sync mode,
+//!    downgraded to what the host supports (see [`crate::sync`]), the GPU
+//!    selection (see [`crate::gpu`]), Wine's FSR upscaling (see
+//!    [`crate::launch::upscaling`]), the audio latency (see [`crate::audio`]),
+//!    the display of the graphics driver (see [`crate::winecfg`]),
+//!    hidden printers (see [`crate::peripherals`]), the pcscd socket (see
+//!    [`crate::smartcard`]), the Kerberos credential cache (see
+//!    [`crate::kerberos`]), the host paths shared with the Steam Linux
+//!    Runtime container and the Vulkan layers installed as components (see
+//!    [`crate::components`])
+//! 4. [`BottleConfig::environment`](crate::bottle::BottleConfig::environment)
+//! 5. per-launch overrides
+//!
+//! Values may reference other variables as `$NAME` or `${NAME}`. References
+//! are resolved against the layers below the value's own layer, then the
+//! built-in variables (`$WINEPREFIX`, `$BOTTLE_PATH`, `$BOTTLE_NAME`), then the
+//! daemon's own environment, so `PATH=$PATH:/extra` works as in a shell.
+//! Unknown references are kept verbatim and `$$` produces a literal `$`.
+
+use crate::bottle::{Bottle, BottleType};
+use crate::components;
+use crate::gpu;
+use crate::launch::steam_runtime;
+use crate::smartcard;
+use crate::sync::SyncSupport;
+use std::collections::HashMap;
+
+/// Variables set for every bottle
+const DEFAULTS: &[(&str, &str)] = &[
+    // Wine's debug output is noise for users and slows down games
+    ("WINEDEBUG", "-all"),
+    ("DXVK_LOG_LEVEL", "warn"),
+    ("VKD3D_DEBUG", "none"),
+];
+
+/// Variables set for gaming bottles
+const GAMING: &[(&str, &str)] = &[
+    // Keep shader caches next to the prefix so they go away with the bottle
+    ("DXVK_STATE_CACHE_PATH", "$BOTTLE_PATH"),
+    ("__GL_SHADER_DISK_CACHE", "1"),
+    ("__GL_SHADER_DISK_CACHE_PATH", "$BOTTLE_PATH"),
+    ("WINE_LARGE_ADDRESS_AWARE", "1"),
+];
+
+/// The template layer of a bottle type
+pub fn template(kind: &BottleType) -> &'static [(&'static str, &'static str)] {
+    match kind {
+        BottleType::Gaming => GAMING,
+        BottleType::Software | BottleType::Custom => &[],
+    }
+}
+
+/// Resolve the environment a program in `bottle` is launched with
+///
+/// # Arguments
+///
+/// * `bottle` - The bottle the program runs in
+/// * `overrides` - Variables set for this launch only
+///
+/// # Returns
+///
+/// The fully expanded variables, including `WINEPREFIX`. The daemon's own
+/// environment is not part of the result; it is inherited by the process.
+pub fn resolve(bottle: &Bottle, overrides: &HashMap<String, String>) -> HashMap<String, String> {
+    let builtins = [
+        ("WINEPREFIX", bottle.path.display().to_string()),
+        ("BOTTLE_PATH", bottle.path.display().to_string()),
+        ("BOTTLE_NAME", bottle.name.clone()),
+    ];
+
+    let mut resolved: HashMap<String, String> = HashMap::new();
+    let mut apply = |layer: Vec<(&str, &str)>| {
+        let expanded: Vec<(String, String)> = layer
+            .into_iter()
+            .map(|(key, value)| {
+                let value = expand(value, |name| {
+                    resolved
+                        .get(name)
+                        .cloned()
+                        .or_else(|| {
+                            builtins
+                                .iter()
+                                .find(|(builtin, _)| *builtin == name)
+                                .map(|(_, value)| value.clone())
+                        })
+                        .or_else(|| std::env::var(name).ok())
+                });
+                (key.to_string(), value)
+            })
+            .collect();
+        resolved.extend(expanded);
+    };
+
+    apply(DEFAULTS.to_vec());
+    apply(template(&bottle.kind).to_vec());
+
+    let requested = bottle.config.sync;
+    let sync = requested.effective(&SyncSupport::detect());
+    if sync != requested {
+        tracing::warn!(
+            "{:?} is not supported on this host, '{}' uses {:?} instead",
+            requested,
+            bottle.name,
+            sync
+        );
+    }
+    apply(sync.environment());
+    let mut typed = gpu::environment(&bottle.config.gpu);
+    if let Some(fsr) = &bottle.config.fsr {
+        typed.extend(fsr.environment());
+    }
+    typed.extend(bottle.config.audio.environment());
+    typed.extend(bottle.config.wine_settings.environment());
+    typed.extend(bottle.config.peripherals.environment());
+    typed.extend(bottle.config.kerberos.environment());
+    let mut shared = bottle.config.kerberos.shared_paths();
+    if bottle.config.smart_cards {
+        typed.extend(smartcard::environment());
+        shared.extend(smartcard::shared_paths());
+    }
+    typed.extend(steam_runtime::shared_paths(&shared));
+    typed.extend(components::environment(&bottle.path));
+    apply(typed.iter().map(|(key, value)| (*key, value.as_str())).collect());
+    apply(sorted(&bottle.config.environment));
+    apply(sorted(overrides));
+
+    resolved.insert("WINEPREFIX".to_string(), bottle.path.display().to_string());
+    resolved
+}
+
+/// Layer entries in a stable order, so expansion does not depend on hashing
+fn sorted(layer: &HashMap<String, String>) -> Vec<(&str, &str)> {
+    let mut entries: Vec<(&str, &str)> = layer
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    entries.sort();
+    entries
+}
+
+/// Expand `$NAME` and `${NAME}` references in `value`
+///
+/// `lookup` returns the value of a variable, or `None` to keep the reference
+/// as written.
+pub fn expand(value: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(index) = rest.find('$') {
+        output.push_str(&rest[..index]);
+        let after = &rest[index + 1..];
+
+        if let Some(after) = after.strip_prefix('$') {
+            output.push('$');
+            rest = after;
+            continue;
+        }
+
+        let (name, reference_len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+
+        match (!name.is_empty()).then(|| lookup(name)).flatten() {
+            Some(resolved) => output.push_str(&resolved),
+            None => {
+                output.push('$');
+                output.push_str(&after[..reference_len]);
+            }
+        }
+        rest = &after[reference_len..];
+    }
+    output.push_str(rest);
+    output
+}
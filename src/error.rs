@@ -1,3 +1,4 @@
+use crate::bottle::BottleState;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -6,4 +7,52 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("Serde: {0}")]
     Serde(#[from] serde_json::Error),
+    #[error("YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(feature = "dbus")]
+    #[error("D-Bus: {0}")]
+    DBus(#[from] zbus::Error),
+    #[error("Transport: {0}")]
+    Transport(#[from] tonic::transport::Error),
+    /// A call to another manager or to a bridge over gRPC failed, see
+    /// [`crate::target`] and [`crate::winebridge`]
+    #[error("Remote: {}", .0.message())]
+    Remote(Box<tonic::Status>),
+    #[error("Unsupported format version: {0}")]
+    UnsupportedVersion(u32),
+    #[error("Bottle already exists: {0}")]
+    BottleExists(String),
+    #[error("Bottle not found: {0}")]
+    BottleNotFound(String),
+    /// Another operation holds the bottle, see [`crate::lock`]
+    #[error("Bottle is busy: {0}")]
+    BottleBusy(String),
+    /// The bottle can't do what was asked in its state, see
+    /// [`BottleState::allows`]
+    #[error("Bottle {0} is {1}")]
+    InvalidState(String, BottleState),
+    #[error("Runner not found: {0}")]
+    RunnerNotFound(String),
+    #[error("Session not found: {0}")]
+    SessionNotFound(u64),
+    #[error("Job not found: {0}")]
+    JobNotFound(u64),
+    /// The operation was cancelled, see [`crate::jobs::CancelToken`]
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("Not authorized to {0}")]
+    NotAuthorized(String),
+    /// A download doesn't match its checksum or signature, see
+    /// [`crate::integrity`]
+    #[error("Integrity check failed: {0}")]
+    Integrity(String),
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        Self::Remote(Box::new(status))
+    }
 }
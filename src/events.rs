@@ -0,0 +1,103 @@
+//! Changes of bottles, programs and jobs, as they happen
+//!
+//! The manager emits an [`Event`] when a bottle is created or deleted, when
+//! a program it launched starts or exits and when a job progresses, so
+//! frontends follow them without polling, see [`Manager::events`]. Every
+//! subscriber gets the events emitted after it subscribed; one falling more
+//! than [`CAPACITY`] events behind misses the oldest of them, and its
+//! receiver reports how many with [`broadcast::error::RecvError::Lagged`].
+//!
+//! Exits are noticed within a second, as sessions are checked for them, see
+//! [`crate::session::Sessions`].
+//!
+//! [`Manager::events`]: crate::manager::Manager::events
+
+use crate::jobs::Job;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::sync::broadcast;
+
+/// Events kept for subscribers falling behind
+pub const CAPACITY: usize = 256;
+
+/// Something that changed in a manager
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    BottleCreated { bottle: String },
+    BottleDeleted { bottle: String },
+    /// A program was launched, see [`crate::session::Session`]
+    ProcessStarted {
+        bottle: String,
+        session: u64,
+        pid: u32,
+        program: PathBuf,
+    },
+    /// A launched program exited or was stopped
+    ProcessExited {
+        bottle: String,
+        session: u64,
+        pid: u32,
+        program: PathBuf,
+    },
+    /// A job changed status or reported progress, see [`crate::jobs`]
+    JobProgress { job: Job },
+}
+
+impl Event {
+    /// Identifier of the kind of event, as serialized
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::BottleCreated { .. } => "bottle_created",
+            Self::BottleDeleted { .. } => "bottle_deleted",
+            Self::ProcessStarted { .. } => "process_started",
+            Self::ProcessExited { .. } => "process_exited",
+            Self::JobProgress { .. } => "job_progress",
+        }
+    }
+
+    /// Name of the bottle the event is about, `None` for jobs
+    pub fn bottle(&self) -> Option<&str> {
+        match self {
+            Self::BottleCreated { bottle }
+            | Self::BottleDeleted { bottle }
+            | Self::ProcessStarted { bottle, .. }
+            | Self::ProcessExited { bottle, .. } => Some(bottle),
+            Self::JobProgress { .. } => None,
+        }
+    }
+}
+
+/// Channel the events of a manager are broadcast on
+///
+/// Clones emit on the same channel.
+#[derive(Debug, Clone)]
+pub struct Events {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new(CAPACITY)
+    }
+}
+
+impl Events {
+    /// A channel keeping `capacity` events for subscribers falling behind
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self { sender }
+    }
+
+    /// Send `event` to every subscriber
+    pub fn emit(&self, event: Event) {
+        tracing::trace!("Event {:?}", event);
+        // Fails only when nobody is subscribed
+        let _ = self.sender.send(event);
+    }
+
+    /// Receive the events emitted from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,206 @@
+use crate::bottle::Bottle;
+use crate::components::{ComponentKind, InstalledComponents};
+use crate::manager::Manager;
+use crate::runner::Capabilities;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Placeholder written in place of redacted values
+pub const REDACTED: &str = "<redacted>";
+
+/// Environment variable name fragments that mark a value as secret
+const SECRET_MARKERS: &[&str] = &[
+    "TOKEN",
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "KEY",
+    "AUTH",
+    "CREDENTIAL",
+    "COOKIE",
+    "SESSION",
+];
+
+/// Snapshot of the whole installation, meant to be attached to bug reports
+///
+/// Every path is rewritten relative to the user's home directory (`~/...`),
+/// environment values and program arguments that look like credentials are
+/// replaced with [`REDACTED`], so the document can be shared publicly as-is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CompatibilityReport {
+    /// Version of this crate that produced the report
+    pub core_version: String,
+    pub host: HostInfo,
+    pub runners: Vec<RunnerEntry>,
+    pub bottles: Vec<Bottle>,
+    /// Components installed in the bottles, as recorded in their prefixes
+    pub components: Vec<ComponentEntry>,
+}
+
+/// Basic facts about the machine the report was generated on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct HostInfo {
+    pub os: String,
+    pub arch: String,
+    /// Kernel release, where it can be determined
+    pub kernel: Option<String>,
+    /// Value of `XDG_SESSION_TYPE` (`wayland`, `x11`, ...)
+    pub session_type: Option<String>,
+}
+
+/// An installed runner as seen by the report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RunnerEntry {
+    pub name: String,
+    pub version: String,
+    pub directory: String,
+    pub available: bool,
+    pub capabilities: Capabilities,
+}
+
+/// A component installed in a bottle, as seen by the report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ComponentEntry {
+    pub bottle: String,
+    pub kind: ComponentKind,
+    pub version: String,
+}
+
+impl CompatibilityReport {
+    /// The JSON schema of compatibility reports, see [`crate::schema`]
+    #[cfg(feature = "schema")]
+    pub fn schema() -> serde_json::Value {
+        crate::schema::schema(crate::schema::Document::CompatibilityReport)
+    }
+
+    /// Collect the current state of `manager`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bottle index cannot be read
+    pub fn collect(manager: &Manager) -> Result<Self, Error> {
+        let runners = manager
+            .runners()
+            .iter()
+            .map(|runner| RunnerEntry {
+                name: runner.info().name().to_string(),
+                version: runner.info().version().trim().to_string(),
+                directory: redact_path(runner.info().directory()),
+                available: runner.is_available(),
+                capabilities: runner.capabilities(),
+            })
+            .collect();
+
+        let bottles = manager.list_bottles()?;
+        let mut components = Vec::new();
+        for bottle in &bottles {
+            // A broken record only hides the bottle's components
+            let installed = InstalledComponents::load(&bottle.path).unwrap_or_else(|e| {
+                tracing::warn!("Failed to read the components of '{}': {}", bottle.name, e);
+                InstalledComponents::default()
+            });
+            for component in installed.components {
+                components.push(ComponentEntry {
+                    bottle: bottle.name.clone(),
+                    kind: component.kind,
+                    version: component.version,
+                });
+            }
+        }
+
+        Ok(Self {
+            core_version: env!("CARGO_PKG_VERSION").to_string(),
+            host: HostInfo::current(),
+            runners,
+            bottles: bottles.into_iter().map(redact_bottle).collect(),
+            components,
+        })
+    }
+
+    /// Serialize the report as pretty-printed JSON
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+impl HostInfo {
+    pub fn current() -> Self {
+        let kernel = std::fs::read_to_string("/proc/sys/kernel/osrelease")
+            .ok()
+            .map(|release| release.trim().to_string());
+        Self {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            kernel,
+            session_type: std::env::var("XDG_SESSION_TYPE").ok(),
+        }
+    }
+}
+
+/// Whether an environment variable name looks like it holds a secret
+pub fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SECRET_MARKERS.iter().any(|marker| name.contains(marker))
+}
+
+pub(crate) fn redact_bottle(mut bottle: Bottle) -> Bottle {
+    bottle.path = redact_path(&bottle.path).into();
+    for program in bottle.programs.iter_mut() {
+        program.executable = redact_path(&program.executable).into();
+        program.working_dir = program
+            .working_dir
+            .as_deref()
+            .map(|directory| redact_path(directory).into());
+        redact_args(&mut program.args);
+        redact_environment(&mut program.environment);
+    }
+    redact_environment(&mut bottle.config.environment);
+    bottle
+}
+
+/// Replace the values of the variables that look like secrets
+pub(crate) fn redact_environment(environment: &mut HashMap<String, String>) {
+    for (name, value) in environment.iter_mut() {
+        if is_secret(name) {
+            *value = REDACTED.to_string();
+        }
+    }
+}
+
+/// Replace the values of arguments named like secrets, given as
+/// `--token=<value>` or `--token <value>`, and redact absolute paths
+pub(crate) fn redact_args(args: &mut [String]) {
+    let mut value_of_secret = false;
+    for arg in args.iter_mut() {
+        if value_of_secret {
+            *arg = REDACTED.to_string();
+            value_of_secret = false;
+            continue;
+        }
+        match arg.split_once('=') {
+            Some((name, _)) if is_secret(name) => *arg = format!("{}={}", name, REDACTED),
+            Some(_) => {}
+            None if arg.starts_with('-') => value_of_secret = is_secret(arg),
+            None if Path::new(arg.as_str()).is_absolute() => {
+                *arg = redact_path(Path::new(arg.as_str()));
+            }
+            None => {}
+        }
+    }
+}
+
+/// Rewrite a path relative to the home directory so user names don't leak
+pub(crate) fn redact_path(path: &Path) -> String {
+    if let Some(home) = std::env::var_os("HOME") {
+        if let Ok(relative) = path.strip_prefix(&home) {
+            return Path::new("~").join(relative).display().to_string();
+        }
+    }
+    path.display().to_string()
+}
@@ -0,0 +1,150 @@
+//! Hooks for embedders to extend the core without forking it
+//!
+//! Distributions and frontends register trait objects in the [`Extensions`]
+//! of a [`Manager`](crate::manager::Manager), see
+//! [`Manager::extensions`](crate::manager::Manager::extensions):
+//!
+//! - [`LaunchHook`]s run before a program starts and may change its
+//!   environment or refuse the launch, e.g. to mount a network share first.
+//! - [`CreationHook`]s run once a bottle was created, e.g. to install the
+//!   distribution's fonts.
+//! - [`CatalogExtension`]s add recipes to the installer catalog, e.g. the
+//!   internal programs of a company.
+//! - [`ComponentSource`]s provide component versions missing from
+//!   [`Manager::components_path`](crate::manager::Manager::components_path),
+//!   e.g. DXVK as packaged by the distribution.
+//!
+//! Extensions are called in the order they were registered.
+
+use crate::bottle::Bottle;
+use crate::components::ComponentKind;
+use crate::installers::Recipe;
+use crate::programs::Program;
+use crate::Error;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+/// Called before a program of a bottle is launched
+pub trait LaunchHook: Send + Sync {
+    /// Change the variables `program` is launched with
+    ///
+    /// # Errors
+    ///
+    /// An error cancels the launch and is returned to the caller
+    fn before_launch(
+        &self,
+        bottle: &Bottle,
+        program: &Program,
+        environment: &mut HashMap<String, String>,
+    ) -> Result<(), Error>;
+}
+
+/// Called once a bottle was created and added to the index
+pub trait CreationHook: Send + Sync {
+    /// Set up the new `bottle`
+    ///
+    /// # Errors
+    ///
+    /// The bottle exists either way, so errors are only logged
+    fn after_create(&self, bottle: &Bottle) -> Result<(), Error>;
+}
+
+/// Source of recipes for the installer catalog
+pub trait CatalogExtension: Send + Sync {
+    /// The recipes the extension provides
+    fn recipes(&self) -> Vec<Recipe>;
+}
+
+/// Source of component versions, see [`crate::components`]
+pub trait ComponentSource: Send + Sync {
+    /// The versions of `kind` the source provides, newest first
+    fn versions(&self, kind: ComponentKind) -> Vec<String>;
+
+    /// Put the release `version` of `kind` in `destination`, laid out like
+    /// the directories of [`crate::components`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the release cannot be fetched
+    fn fetch(&self, kind: ComponentKind, version: &str, destination: &Path) -> Result<(), Error>;
+}
+
+/// The extensions registered with a manager
+#[derive(Default)]
+pub struct Extensions {
+    launch_hooks: RwLock<Vec<Arc<dyn LaunchHook>>>,
+    creation_hooks: RwLock<Vec<Arc<dyn CreationHook>>>,
+    catalogs: RwLock<Vec<Arc<dyn CatalogExtension>>>,
+    component_sources: RwLock<Vec<Arc<dyn ComponentSource>>>,
+}
+
+impl Extensions {
+    pub fn add_launch_hook(&self, hook: Arc<dyn LaunchHook>) {
+        add(&self.launch_hooks, hook);
+    }
+
+    pub fn add_creation_hook(&self, hook: Arc<dyn CreationHook>) {
+        add(&self.creation_hooks, hook);
+    }
+
+    pub fn add_catalog(&self, catalog: Arc<dyn CatalogExtension>) {
+        add(&self.catalogs, catalog);
+    }
+
+    pub fn add_component_source(&self, source: Arc<dyn ComponentSource>) {
+        add(&self.component_sources, source);
+    }
+
+    /// The recipes of every catalog extension
+    pub fn recipes(&self) -> Vec<Recipe> {
+        list(&self.catalogs)
+            .iter()
+            .flat_map(|catalog| catalog.recipes())
+            .collect()
+    }
+
+    /// Run the launch hooks, stopping at the first error
+    pub(crate) fn before_launch(
+        &self,
+        bottle: &Bottle,
+        program: &Program,
+        environment: &mut HashMap<String, String>,
+    ) -> Result<(), Error> {
+        for hook in list(&self.launch_hooks) {
+            hook.before_launch(bottle, program, environment)?;
+        }
+        Ok(())
+    }
+
+    /// Run the creation hooks, logging their errors
+    pub(crate) fn after_create(&self, bottle: &Bottle) {
+        for hook in list(&self.creation_hooks) {
+            if let Err(e) = hook.after_create(bottle) {
+                tracing::warn!("Cannot set up the new bottle '{}': {}", bottle.name, e);
+            }
+        }
+    }
+
+    /// The component sources, in the order they were registered
+    pub(crate) fn component_sources(&self) -> Vec<Arc<dyn ComponentSource>> {
+        list(&self.component_sources)
+    }
+}
+
+// Extensions can't leave the lists half-updated, so poisoned locks are still
+// usable
+fn add<T: ?Sized>(extensions: &RwLock<Vec<Arc<T>>>, extension: Arc<T>) {
+    extensions
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push(extension);
+}
+
+/// A copy of `extensions`, so they can register others while called
+fn list<T: ?Sized>(extensions: &RwLock<Vec<Arc<T>>>) -> Vec<Arc<T>> {
+    extensions
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
@@ -0,0 +1,212 @@
+//! Running inside a Flatpak sandbox
+//!
+//! Inside a Flatpak, only the programs shipped with the application and its
+//! runtime are visible. Tools installed on the host, like gamescope, Steam's
+//! runtimes or a system Wine, have to be started with `flatpak-spawn --host`,
+//! which needs the `org.freedesktop.Flatpak` bus name to be allowed. [`adapt`]
+//! rewrites a [`Command`] that way when its program is only found on the
+//! host, so runners and launch wrappers build their commands as usual.
+//!
+//! Host paths mounted in the sandbox under `/run/host` are translated back to
+//! the paths the host knows them by.
+
+use crate::launch;
+use serde::{Deserialize, Serialize};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// File present at the root of every Flatpak sandbox
+pub const INFO_FILE: &str = "/.flatpak-info";
+
+/// Where the host's file system is mounted in the sandbox, if shared
+const HOST_ROOT: &str = "/run/host";
+
+/// Bus name `flatpak-spawn --host` talks to
+const DEVELOPMENT_BUS: &str = "org.freedesktop.Flatpak";
+
+/// The sandbox the daemon runs in, read from [`INFO_FILE`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sandbox {
+    /// Application id, e.g. `com.usebottles.bottles`
+    pub app_id: Option<String>,
+    /// Devices the sandbox can access, e.g. `dri` or `all`
+    pub devices: Vec<String>,
+    /// Whether programs can be started on the host
+    pub host_access: bool,
+}
+
+impl Sandbox {
+    /// Parse the contents of [`INFO_FILE`]
+    pub fn parse(content: &str) -> Self {
+        let mut sandbox = Self::default();
+        let mut section = "";
+        for line in content.lines().map(str::trim) {
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name;
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match (section, key.trim()) {
+                ("Application", "name") => sandbox.app_id = Some(value.trim().to_string()),
+                ("Context", "devices") => {
+                    sandbox.devices = value
+                        .split(';')
+                        .filter(|device| !device.is_empty())
+                        .map(str::to_string)
+                        .collect();
+                }
+                ("Session Bus Policy", DEVELOPMENT_BUS) => {
+                    sandbox.host_access = matches!(value.trim(), "talk" | "own")
+                }
+                _ => {}
+            }
+        }
+        sandbox
+    }
+
+    /// Whether the sandbox can access the GPU
+    pub fn gpu_access(&self) -> bool {
+        self.devices.iter().any(|device| device == "dri" || device == "all")
+    }
+}
+
+/// The sandbox the daemon runs in, `None` outside of Flatpak
+pub fn sandbox() -> Option<&'static Sandbox> {
+    static SANDBOX: OnceLock<Option<Sandbox>> = OnceLock::new();
+    SANDBOX
+        .get_or_init(|| fs::read_to_string(INFO_FILE).ok().map(|info| Sandbox::parse(&info)))
+        .as_ref()
+}
+
+/// Whether the daemon runs inside a Flatpak sandbox
+pub fn is_sandboxed() -> bool {
+    sandbox().is_some()
+}
+
+/// The path the host knows a file by, e.g. `/run/host/usr/bin/gamescope` →
+/// `/usr/bin/gamescope`
+pub fn host_path(path: &Path) -> PathBuf {
+    match path.strip_prefix(HOST_ROOT) {
+        Ok(rest) => Path::new("/").join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// Whether `program` can only run on the host
+///
+/// Always `false` outside of Flatpak.
+pub fn is_host_only(program: &OsStr) -> bool {
+    if !is_sandboxed() {
+        return false;
+    }
+    let path = Path::new(program);
+    if path.starts_with(HOST_ROOT) {
+        return true;
+    }
+    if path.is_absolute() {
+        return !path.exists();
+    }
+    path.components().count() == 1 && launch::search_path(&program.to_string_lossy()).is_none()
+}
+
+/// Locate an executable in the host's `PATH`
+///
+/// Returns `None` outside of Flatpak, or if the sandbox can't start programs
+/// on the host.
+pub fn find_on_host(name: &str) -> Option<PathBuf> {
+    if !sandbox()?.host_access {
+        return None;
+    }
+    let output = Command::new("flatpak-spawn")
+        .args(["--host", "sh", "-c", "command -v \"$1\"", "sh", name])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+/// Run `command` on the host if its program is only available there
+///
+/// The variables and working directory set on `command` are passed along.
+/// The host program is stopped along with the `flatpak-spawn` process, so
+/// sessions can be stopped as usual. Commands that can run in the sandbox are
+/// returned unchanged.
+pub fn adapt(command: Command) -> Command {
+    if !is_host_only(command.get_program()) || is_host_command(&command) {
+        return command;
+    }
+    if !sandbox().is_some_and(|sandbox| sandbox.host_access) {
+        tracing::warn!(
+            "'{}' is only available on the host, but the sandbox can't start host programs",
+            command.get_program().to_string_lossy()
+        );
+        return command;
+    }
+
+    let mut host = Command::new("flatpak-spawn");
+    host.args(["--host", "--watch-bus"]);
+    for (key, value) in command.get_envs() {
+        let mut option = OsString::new();
+        match value {
+            Some(value) => {
+                option.push("--env=");
+                option.push(key);
+                option.push("=");
+                option.push(value);
+            }
+            None => {
+                option.push("--unset-env=");
+                option.push(key);
+            }
+        }
+        host.arg(option);
+    }
+    if let Some(directory) = command.get_current_dir() {
+        let mut option = OsString::from("--directory=");
+        option.push(host_path(directory));
+        host.arg(option);
+    }
+    host.arg(host_path(Path::new(command.get_program())));
+    host.args(command.get_args().map(|arg| host_path(Path::new(arg))));
+    host
+}
+
+/// Set the working directory of `command`, which [`adapt`] may have made
+/// run on the host
+pub fn with_directory(mut command: Command, directory: &Path) -> Command {
+    if !is_host_command(&command) {
+        command.current_dir(directory);
+        return command;
+    }
+    // flatpak-spawn --host [options] program args
+    let options: Vec<&OsStr> = command
+        .get_args()
+        .take_while(|arg| arg.to_string_lossy().starts_with("--"))
+        .collect();
+    let rest = command.get_args().skip(options.len());
+    let mut option = OsString::from("--directory=");
+    option.push(host_path(directory));
+    let mut host = Command::new(command.get_program());
+    host.args(
+        options
+            .iter()
+            .filter(|option| !option.to_string_lossy().starts_with("--directory=")),
+    )
+    .arg(option)
+    .args(rest);
+    host
+}
+
+/// Whether `command` was made to run on the host by [`adapt`]
+pub fn is_host_command(command: &Command) -> bool {
+    command.get_program() == "flatpak-spawn"
+        && command.get_args().next().is_some_and(|arg| arg == "--host")
+}
@@ -0,0 +1,321 @@
+//! GPU enumeration and per-bottle GPU selection
+//!
+//! On hybrid graphics laptops and multi-GPU desktops, programs render on the
+//! primary GPU unless told otherwise, and every driver stack has its own way
+//! of being told. [`GpuPreference`] is stored in the bottle config and turned
+//! into the right variables for the selected GPU's driver by [`environment`]:
+//! `DRI_PRIME` and `MESA_VK_DEVICE_SELECT` for Mesa drivers, the PRIME render
+//! offload variables for NVIDIA's driver, and `VK_ICD_FILENAMES` to hide the
+//! Vulkan drivers of the other GPUs.
+//!
+//! GPUs are found through the DRM devices in sysfs; names and types come from
+//! `vulkaninfo` when it is installed.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const DRM_DIR: &str = "/sys/class/drm";
+
+/// Directories holding Vulkan driver (ICD) manifests
+const ICD_DIRS: &[&str] = &["/usr/share/vulkan/icd.d", "/etc/vulkan/icd.d"];
+
+/// GPU vendor, from the PCI vendor id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Vendor {
+    Amd,
+    Intel,
+    Nvidia,
+    Other(u16),
+}
+
+impl From<u16> for Vendor {
+    fn from(id: u16) -> Self {
+        match id {
+            0x1002 => Self::Amd,
+            0x8086 => Self::Intel,
+            0x10de => Self::Nvidia,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// Whether a GPU is built into the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GpuKind {
+    Integrated,
+    Discrete,
+    Unknown,
+}
+
+/// A GPU found on the host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Gpu {
+    /// PCI address, e.g. `0000:01:00.0`
+    pub pci_slot: String,
+    pub vendor: Vendor,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    /// Kernel driver, e.g. `amdgpu` or `nvidia`
+    pub driver: Option<String>,
+    /// Marketing name, as reported by Vulkan
+    pub name: Option<String>,
+    pub kind: GpuKind,
+    /// The GPU the firmware initialized, which programs use by default
+    pub primary: bool,
+}
+
+impl Gpu {
+    /// Whether the GPU runs NVIDIA's proprietary driver
+    pub fn is_nvidia_proprietary(&self) -> bool {
+        self.driver.as_deref() == Some("nvidia")
+    }
+
+    /// Prefixes of the Vulkan driver manifests for this GPU's driver
+    fn icd_prefixes(&self) -> &'static [&'static str] {
+        match self.driver.as_deref() {
+            Some("nvidia") => &["nvidia_icd"],
+            Some("amdgpu") | Some("radeon") => &["radeon_icd", "amd_icd"],
+            Some("i915") | Some("xe") => &["intel_icd", "intel_hasvk_icd"],
+            Some("nouveau") | Some("nova") => &["nouveau_icd", "nvk_icd"],
+            _ => &[],
+        }
+    }
+}
+
+/// Which GPU a bottle renders on
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum GpuPreference {
+    /// Whatever the host picks, usually the primary GPU
+    #[default]
+    Default,
+    /// The first discrete GPU, if any
+    Discrete,
+    /// The first integrated GPU, if any
+    Integrated,
+    /// The GPU at this PCI address, see [`Gpu::pci_slot`]
+    Pci(String),
+}
+
+impl GpuPreference {
+    /// The GPU matching the preference among `gpus`
+    pub fn select<'a>(&self, gpus: &'a [Gpu]) -> Option<&'a Gpu> {
+        match self {
+            Self::Default => None,
+            Self::Discrete => gpus.iter().find(|gpu| gpu.kind == GpuKind::Discrete),
+            Self::Integrated => gpus.iter().find(|gpu| gpu.kind == GpuKind::Integrated),
+            Self::Pci(slot) => gpus
+                .iter()
+                .find(|gpu| gpu.pci_slot.eq_ignore_ascii_case(slot)),
+        }
+    }
+}
+
+/// List the GPUs of the host, the primary one first
+pub fn list() -> Vec<Gpu> {
+    let Ok(entries) = fs::read_dir(DRM_DIR) else {
+        return Vec::new();
+    };
+    let mut gpus: Vec<Gpu> = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        // card0, card1, ... but not the connectors like card0-DP-1
+        if !name.starts_with("card") || name.contains('-') {
+            continue;
+        }
+        if let Some(gpu) = from_sysfs(&entry.path().join("device")) {
+            if !gpus.iter().any(|known| known.pci_slot == gpu.pci_slot) {
+                gpus.push(gpu);
+            }
+        }
+    }
+
+    for device in vulkan_devices().unwrap_or_default() {
+        if let Some(gpu) = gpus
+            .iter_mut()
+            .find(|gpu| gpu.vendor_id == device.vendor_id && gpu.device_id == device.device_id)
+        {
+            gpu.name = Some(device.name);
+            if device.kind != GpuKind::Unknown {
+                gpu.kind = device.kind;
+            }
+        }
+    }
+    gpus.sort_by_key(|gpu| (!gpu.primary, gpu.pci_slot.clone()));
+    gpus
+}
+
+/// Variables making programs render on the GPU selected by `preference`
+///
+/// Nothing is set when the preference is the default or matches no GPU, in
+/// which case a warning is logged.
+pub fn environment(preference: &GpuPreference) -> Vec<(&'static str, String)> {
+    if *preference == GpuPreference::Default {
+        return Vec::new();
+    }
+    let gpus = list();
+    let Some(gpu) = preference.select(&gpus) else {
+        tracing::warn!("No GPU matches {:?}, using the default one", preference);
+        return Vec::new();
+    };
+
+    let mut environment = Vec::new();
+    if gpu.is_nvidia_proprietary() {
+        environment.push(("__NV_PRIME_RENDER_OFFLOAD", "1".to_string()));
+        environment.push(("__VK_LAYER_NV_optimus", "NVIDIA_only".to_string()));
+        environment.push(("__GLX_VENDOR_LIBRARY_NAME", "nvidia".to_string()));
+    } else {
+        // Mesa's format for PCI addresses: pci-0000_01_00_0
+        let slot = gpu.pci_slot.replace([':', '.'], "_");
+        environment.push(("DRI_PRIME", format!("pci-{}", slot)));
+        environment.push((
+            "MESA_VK_DEVICE_SELECT",
+            format!("{:04x}:{:04x}", gpu.vendor_id, gpu.device_id),
+        ));
+        environment.push(("__GLX_VENDOR_LIBRARY_NAME", "mesa".to_string()));
+    }
+
+    // Other drivers' Vulkan devices are hidden only when they would be
+    // enumerated first, as the selection variables can't reach across drivers
+    let others_use_other_drivers = gpus
+        .iter()
+        .filter(|other| other.pci_slot != gpu.pci_slot)
+        .any(|other| other.icd_prefixes() != gpu.icd_prefixes());
+    if others_use_other_drivers {
+        let icds = icd_files(gpu.icd_prefixes());
+        if !icds.is_empty() {
+            let icds: Vec<String> = icds.iter().map(|p| p.display().to_string()).collect();
+            environment.push(("VK_ICD_FILENAMES", icds.join(":")));
+        }
+    }
+    environment
+}
+
+fn from_sysfs(device: &Path) -> Option<Gpu> {
+    let read_id = |file: &str| {
+        let value = fs::read_to_string(device.join(file)).ok()?;
+        u16::from_str_radix(value.trim().trim_start_matches("0x"), 16).ok()
+    };
+    let vendor_id = read_id("vendor")?;
+    let device_id = read_id("device")?;
+    let pci_slot = fs::canonicalize(device)
+        .ok()?
+        .file_name()?
+        .to_string_lossy()
+        .to_string();
+    let driver = fs::read_link(device.join("driver"))
+        .ok()
+        .and_then(|link| link.file_name().map(|name| name.to_string_lossy().to_string()));
+    let primary = fs::read_to_string(device.join("boot_vga"))
+        .map(|value| value.trim() == "1")
+        .unwrap_or(false);
+
+    let vendor = Vendor::from(vendor_id);
+    let kind = match vendor {
+        Vendor::Nvidia => GpuKind::Discrete,
+        Vendor::Intel if driver.as_deref() == Some("i915") => GpuKind::Integrated,
+        _ => GpuKind::Unknown,
+    };
+    Some(Gpu {
+        pci_slot,
+        vendor,
+        vendor_id,
+        device_id,
+        driver,
+        name: None,
+        kind,
+        primary,
+    })
+}
+
+/// A device as seen by the Vulkan loader
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VulkanDevice {
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub name: String,
+    pub kind: GpuKind,
+    /// Whether the device renders on the CPU, e.g. llvmpipe
+    #[serde(default)]
+    pub software: bool,
+    /// Vulkan version supported by the driver, e.g. `1.3.278`
+    pub api_version: Option<String>,
+    /// Driver name, e.g. `radv` or `NVIDIA`
+    pub driver_name: Option<String>,
+    /// Driver version string, e.g. `Mesa 24.1.2`
+    pub driver_info: Option<String>,
+}
+
+/// The devices listed by `vulkaninfo --summary`
+///
+/// Returns `None` if `vulkaninfo` is not installed or fails, which usually
+/// means no Vulkan driver is installed at all.
+pub fn vulkan_devices() -> Option<Vec<VulkanDevice>> {
+    let output = Command::new("vulkaninfo").arg("--summary").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let summary = String::from_utf8_lossy(&output.stdout);
+
+    // Every device is a block of `key = value` lines starting with apiVersion
+    let mut devices = Vec::new();
+    let mut current: Option<VulkanDevice> = None;
+    for line in summary.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if key == "apiVersion" {
+            devices.extend(current.take());
+            current = Some(VulkanDevice {
+                vendor_id: 0,
+                device_id: 0,
+                name: String::new(),
+                kind: GpuKind::Unknown,
+                software: false,
+                api_version: None,
+                driver_name: None,
+                driver_info: None,
+            });
+        }
+        let Some(device) = current.as_mut() else {
+            continue;
+        };
+        let id = || u16::from_str_radix(value.trim_start_matches("0x"), 16).unwrap_or(0);
+        match key {
+            "vendorID" => device.vendor_id = id(),
+            "deviceID" => device.device_id = id(),
+            "deviceName" => device.name = value.to_string(),
+            "apiVersion" => device.api_version = Some(value.to_string()),
+            "driverName" => device.driver_name = Some(value.to_string()),
+            "driverInfo" => device.driver_info = Some(value.to_string()),
+            "deviceType" if value.contains("INTEGRATED_GPU") => device.kind = GpuKind::Integrated,
+            "deviceType" if value.contains("DISCRETE_GPU") => device.kind = GpuKind::Discrete,
+            "deviceType" if value.contains("TYPE_CPU") => device.software = true,
+            _ => {}
+        }
+    }
+    devices.extend(current);
+    Some(devices)
+}
+
+/// Vulkan driver manifests whose file name starts with one of `prefixes`
+pub(crate) fn icd_files(prefixes: &[&str]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = ICD_DIRS
+        .iter()
+        .filter_map(|directory| fs::read_dir(directory).ok())
+        .flat_map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name.ends_with(".json")
+                        && prefixes.iter().any(|prefix| name.starts_with(prefix))
+                })
+        })
+        .collect();
+    files.sort();
+    files
+}
@@ -0,0 +1,265 @@
+//! Finding and fixing common breakage of prefixes
+//!
+//! Prefixes break in a few recurring ways: a sync tool or an interrupted
+//! deletion leaves them without `system.reg`, a backup restores `dosdevices`
+//! without its links, the runner is replaced by another Wine version. [`verify`]
+//! looks for these [`Problem`]s without starting anything, and [`repair`]
+//! fixes what can be fixed: it restores the links of the prefix and runs
+//! `wineboot -u`, see [`Manager::update_prefix`].
+//!
+//! Bottles with problems keeping programs from running are
+//! [broken](BottleState::Broken) until verified again, see
+//! [`Problem::is_breaking`].
+//!
+//! Drives linking to host directories that are gone are only reported, as the
+//! directory may just be unmounted; unmap them with
+//! [`Bottle::remove_drive`](crate::bottle::Bottle::remove_drive). A prefix of
+//! the wrong architecture has to be created again.
+
+use crate::bottle::{Bottle, BottleState};
+use crate::manager::Manager;
+use crate::runner::{PrefixArch, Runner};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Lines of `system.reg` searched for the architecture of the prefix
+const HEADER_LINES: usize = 8;
+
+/// Link of `C:` in `dosdevices`, as Wine creates it
+const DRIVE_C_TARGET: &str = "../drive_c";
+
+/// Something wrong with a prefix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// `system.reg` is missing: the prefix was never initialized, or lost
+    /// its registry
+    MissingRegistry,
+    /// The prefix is of another architecture than its bottle's config
+    WrongArch {
+        expected: PrefixArch,
+        found: PrefixArch,
+    },
+    /// `dosdevices/c:` doesn't lead to `drive_c`
+    MissingDriveC,
+    /// A link of `dosdevices` leads nowhere
+    BrokenLink { name: String, target: PathBuf },
+    /// The prefix was last updated for another Wine version than the one of
+    /// its runner, so Wine updates it on the next start
+    StaleUpdate,
+    /// The runner of the bottle isn't installed
+    RunnerNotFound { runner: String },
+}
+
+impl Problem {
+    /// What is wrong, for frontends to show
+    pub fn description(&self) -> String {
+        match self {
+            Self::MissingRegistry => "The registry of the prefix is missing".to_string(),
+            Self::WrongArch { expected, found } => format!(
+                "The prefix is {} while the bottle expects {}",
+                found.id(),
+                expected.id()
+            ),
+            Self::MissingDriveC => "Drive C: is not mapped to drive_c".to_string(),
+            Self::BrokenLink { name, target } => {
+                format!("'{}' links to '{}', which doesn't exist", name, target.display())
+            }
+            Self::StaleUpdate => "The prefix was updated for another Wine version".to_string(),
+            Self::RunnerNotFound { runner } => format!("The runner {} is not installed", runner),
+        }
+    }
+
+    /// Whether the problem keeps programs from running in the prefix, making
+    /// the bottle [broken](BottleState::Broken)
+    ///
+    /// Wine updates stale prefixes itself, and drives may just be unmounted.
+    pub fn is_breaking(&self) -> bool {
+        !matches!(self, Self::StaleUpdate | Self::BrokenLink { .. })
+    }
+
+    /// Whether [`repair`] can fix the problem
+    pub fn is_repairable(&self) -> bool {
+        match self {
+            Self::MissingRegistry | Self::MissingDriveC | Self::StaleUpdate => true,
+            Self::BrokenLink { target, .. } => target.is_relative(),
+            Self::WrongArch { .. } | Self::RunnerNotFound { .. } => false,
+        }
+    }
+}
+
+/// What [`verify`] found in a prefix
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub problems: Vec<Problem>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// What [`repair`] did to a prefix
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepairReport {
+    /// Problems found before the repair, and gone after
+    pub fixed: Vec<Problem>,
+    /// Problems still there, see [`Problem::is_repairable`]
+    pub remaining: Vec<Problem>,
+}
+
+/// Check the prefix of `bottle` for common breakage, see the
+/// [module documentation](self)
+///
+/// # Errors
+///
+/// Returns an error if the prefix can't be read
+pub fn verify(manager: &Manager, bottle: &Bottle) -> Result<HealthReport, Error> {
+    let prefix = &bottle.path;
+    let mut problems = Vec::new();
+    match prefix_arch(prefix)? {
+        None => problems.push(Problem::MissingRegistry),
+        Some(found) if found != bottle.config.arch => problems.push(Problem::WrongArch {
+            expected: bottle.config.arch,
+            found,
+        }),
+        Some(_) => {}
+    }
+    problems.extend(link_problems(prefix)?);
+    if let Some(name) = &bottle.config.runner {
+        match manager.find_runner(name) {
+            Some(runner) => {
+                if is_stale(prefix, runner.as_ref()) {
+                    problems.push(Problem::StaleUpdate);
+                }
+            }
+            None => problems.push(Problem::RunnerNotFound {
+                runner: name.clone(),
+            }),
+        }
+    }
+    let broken = problems.iter().any(Problem::is_breaking);
+    manager.states().set_broken(&bottle.name, broken);
+    Ok(HealthReport { problems })
+}
+
+/// Fix what [`verify`] finds in the prefix of `bottle`, see the
+/// [module documentation](self)
+///
+/// # Errors
+///
+/// Returns an error if the prefix can't be read or written, or if
+/// `wineboot -u` fails
+pub fn repair(manager: &Manager, bottle: &Bottle) -> Result<RepairReport, Error> {
+    let _span = tracing::info_span!("repair_prefix", bottle = %bottle.name).entered();
+    let _operation = manager.begin(&bottle.name, BottleState::Updating)?;
+    let before = verify(manager, bottle)?;
+    let dosdevices = bottle.path.join("dosdevices");
+    for problem in &before.problems {
+        match problem {
+            Problem::MissingDriveC => {
+                fs::create_dir_all(&dosdevices).map_err(Error::Io)?;
+                let link = dosdevices.join("c:");
+                if link.symlink_metadata().is_ok() {
+                    fs::remove_file(&link).map_err(Error::Io)?;
+                }
+                std::os::unix::fs::symlink(DRIVE_C_TARGET, &link).map_err(Error::Io)?;
+            }
+            Problem::BrokenLink { name, .. } if problem.is_repairable() => {
+                fs::remove_file(dosdevices.join(name)).map_err(Error::Io)?;
+            }
+            _ => {}
+        }
+    }
+    let runner_found = !before
+        .problems
+        .iter()
+        .any(|problem| matches!(problem, Problem::RunnerNotFound { .. }));
+    if runner_found && bottle.config.runner.is_some() {
+        manager.update_prefix(&bottle.name)?;
+    }
+
+    let after = verify(manager, bottle)?;
+    let fixed = before
+        .problems
+        .into_iter()
+        .filter(|problem| !after.problems.contains(problem))
+        .collect();
+    for problem in &after.problems {
+        tracing::warn!("'{}' still has a problem: {}", bottle.name, problem.description());
+    }
+    Ok(RepairReport {
+        fixed,
+        remaining: after.problems,
+    })
+}
+
+/// The architecture recorded in the registry of `prefix`, `None` if it has
+/// no `system.reg`
+///
+/// Prefixes recording no architecture are from before Wine 1.5 and 32-bit.
+pub fn prefix_arch(prefix: &Path) -> Result<Option<PrefixArch>, Error> {
+    let file = match fs::File::open(prefix.join("system.reg")) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::Io(e)),
+    };
+    for line in BufReader::new(file).lines().take(HEADER_LINES) {
+        match line.map_err(Error::Io)?.trim() {
+            "#arch=win64" => return Ok(Some(PrefixArch::Win64)),
+            "#arch=win32" => return Ok(Some(PrefixArch::Win32)),
+            _ => {}
+        }
+    }
+    Ok(Some(PrefixArch::Win32))
+}
+
+/// Problems of the links of `dosdevices`
+fn link_problems(prefix: &Path) -> Result<Vec<Problem>, Error> {
+    let dosdevices = prefix.join("dosdevices");
+    if !dosdevices.join("c:").join("windows").is_dir() {
+        return Ok(vec![Problem::MissingDriveC]);
+    }
+    let mut problems = Vec::new();
+    for entry in fs::read_dir(&dosdevices).map_err(Error::Io)? {
+        let entry = entry.map_err(Error::Io)?;
+        let Ok(target) = fs::read_link(entry.path()) else {
+            continue;
+        };
+        // Links to devices, e.g. `d::`, only exist while the device does
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.ends_with("::") && !entry.path().exists() {
+            problems.push(Problem::BrokenLink { name, target });
+        }
+    }
+    problems.sort_by(|a, b| a.description().cmp(&b.description()));
+    Ok(problems)
+}
+
+/// Whether `prefix` was last updated for another Wine than the one of
+/// `runner`
+///
+/// Wine records the modification time of its `wine.inf` in
+/// `.update-timestamp`, or `disable` when updates are turned off.
+fn is_stale(prefix: &Path, runner: &dyn Runner) -> bool {
+    let inf = runner.wine().root().join("share/wine/wine.inf");
+    let Some(modified) = fs::metadata(inf)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+    else {
+        return false;
+    };
+    match fs::read_to_string(prefix.join(".update-timestamp")) {
+        Ok(stamp) => {
+            let stamp = stamp.trim();
+            stamp != "disable" && stamp.parse() != Ok(modified.as_secs())
+        }
+        Err(_) => true,
+    }
+}
@@ -0,0 +1,405 @@
+//! Installer recipes
+//!
+//! A [`Recipe`] describes how to set up a program in a fresh bottle as a list
+//! of [`Step`]s. Recipes are plain YAML or JSON files so they can be reviewed
+//! and contributed like any other text; [`recorder`] drafts one from a manual
+//! installer run. [`setup`] runs a single installer and waits for it to
+//! finish.
+//!
+//! [`Recipe::install`] runs the steps of a recipe in order, creating the
+//! bottle first when it doesn't exist, and reports its progress before each
+//! step. Installers given by URL are downloaded into
+//! [`Manager::downloads_path`], in a directory named after their URL. Those
+//! with a `sha256` are kept there, so installing a recipe again doesn't
+//! download them again; the others are downloaded every time, nothing telling
+//! a file left there from the installer.
+//!
+//! Recipes needing logic of their own run it as a sandboxed WebAssembly
+//! plugin, see the `wasm` module, available with the `wasm` feature.
+
+pub mod recorder;
+pub mod setup;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use crate::bottle::{Bottle, BottleType};
+use crate::components::{self, ComponentKind, LATEST};
+use crate::jobs::CancelToken;
+use crate::manager::Manager;
+use crate::manifest::BottleManifest;
+use crate::net::Request;
+use crate::programs::Program;
+use crate::registry::RegistryValue;
+use crate::transaction::Transaction;
+use crate::{integrity, Error};
+use serde::{Deserialize, Serialize};
+use setup::{InstallerOptions, InstallerReport};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Instructions for installing a program into a bottle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Recipe {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Type of the bottle the program is installed into
+    #[serde(default)]
+    pub bottle_type: BottleType,
+    pub steps: Vec<Step>,
+}
+
+/// A single action of a [`Recipe`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Step {
+    /// Run an installer executable
+    RunInstaller {
+        /// File name of the installer, or the URL it is downloaded from
+        file: String,
+        #[serde(default)]
+        arguments: Vec<String>,
+        /// Hex SHA-256 of the installer, checked before it runs
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+    },
+    /// Run a WebAssembly module queuing steps itself
+    ///
+    /// Needs the `wasm` feature, see the `wasm` module.
+    RunPlugin {
+        /// File name of the module, or the URL it is downloaded from
+        module: String,
+        /// Hex SHA-256 of the module, checked before it runs
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+    },
+    /// Install a component, such as DXVK, see [`crate::components`]
+    InstallComponent {
+        component: ComponentKind,
+        /// Version to install, the newest available when unset
+        #[serde(default)]
+        version: Option<String>,
+    },
+    /// Set a registry value; `key` is a full path such as `HKCU\Software\Wine`
+    SetRegistry {
+        key: String,
+        name: String,
+        value: RegistryValue,
+    },
+    /// Override how Wine loads a DLL, e.g. `native,builtin`
+    SetDllOverride { dll: String, mode: String },
+    /// Make an installed executable known to frontends
+    RegisterProgram {
+        name: String,
+        /// Windows path of the executable, e.g. `C:\Program Files\App\app.exe`
+        path: String,
+    },
+}
+
+impl Recipe {
+    /// The JSON schema of recipes, see [`crate::schema`]
+    #[cfg(feature = "schema")]
+    pub fn schema() -> serde_json::Value {
+        crate::schema::schema(crate::schema::Document::Recipe)
+    }
+
+    /// Load a recipe, as YAML or JSON depending on the file extension
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path).map_err(Error::Io)?;
+        if path.extension().is_some_and(|ext| ext == "json") {
+            Ok(serde_json::from_str(&content)?)
+        } else {
+            Ok(serde_yaml::from_str(&content)?)
+        }
+    }
+
+    /// Serialize the recipe as YAML, the format recipes are contributed in
+    pub fn to_yaml(&self) -> Result<String, Error> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+
+    /// Install the recipe into the bottle `bottle_name`, creating it if it
+    /// doesn't exist
+    ///
+    /// `progress` is called before each step and once all are done.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bottle can't be created or if a step fails,
+    /// in which case the following steps are not run, [`Error::Cancelled`]
+    /// once [`RecipeOptions::token`] is cancelled. A bottle the recipe
+    /// created is deleted again either way.
+    pub fn install(
+        &self,
+        manager: &Manager,
+        bottle_name: &str,
+        options: &RecipeOptions,
+        mut progress: impl FnMut(&RecipeProgress),
+    ) -> Result<RecipeReport, Error> {
+        let mut transaction = Transaction::new(format!("install '{}'", self.name))
+            .with_token(options.token.as_ref());
+        let bottle = match manager.get_bottle(bottle_name) {
+            Ok(bottle) => bottle,
+            Err(Error::BottleNotFound(_)) => {
+                let bottle = self.create_bottle(manager, bottle_name, options)?;
+                let name = bottle.name.clone();
+                transaction.on_rollback(format!("create '{}'", name), move || {
+                    manager.delete_bottle(&name).map(drop)
+                });
+                bottle
+            }
+            Err(e) => return Err(e),
+        };
+        let mut report = RecipeReport {
+            bottle: bottle.name.clone(),
+            installers: Vec::new(),
+            programs: Vec::new(),
+        };
+
+        let total = self.steps.len();
+        for (index, step) in self.steps.iter().enumerate() {
+            progress(&RecipeProgress {
+                step: index,
+                total,
+                message: step.description(),
+            });
+            transaction.check()?;
+            let bottle = manager.get_bottle(&bottle.name)?;
+            self.run_step(manager, &bottle, step, options, &mut report)?;
+        }
+        transaction.commit();
+        progress(&RecipeProgress {
+            step: total,
+            total,
+            message: format!("Installed {}", self.name),
+        });
+        tracing::info!("Installed recipe '{}' in '{}'", self.name, report.bottle);
+        Ok(report)
+    }
+
+    fn create_bottle(
+        &self,
+        manager: &Manager,
+        bottle_name: &str,
+        options: &RecipeOptions,
+    ) -> Result<Bottle, Error> {
+        let runner = match &options.runner {
+            Some(name) => manager.find_runner(name),
+            None => manager.runners().into_iter().next(),
+        }
+        .ok_or_else(|| Error::RunnerNotFound(options.runner.clone().unwrap_or_default()))?;
+        let manifest = BottleManifest::new(bottle_name, self.bottle_type.clone());
+        let token = options.token.clone().unwrap_or_default();
+        Ok(manager.create_bottle_cancellable(&manifest, runner.as_ref(), &token)?.bottle)
+    }
+
+    fn run_step(
+        &self,
+        manager: &Manager,
+        bottle: &Bottle,
+        step: &Step,
+        options: &RecipeOptions,
+        report: &mut RecipeReport,
+    ) -> Result<(), Error> {
+        match step {
+            Step::RunInstaller {
+                file,
+                arguments,
+                sha256,
+            } => {
+                let path = installer_file(manager, file, sha256.as_deref(), options)?;
+                let installer = InstallerOptions {
+                    arguments: arguments.clone(),
+                    ..InstallerOptions::default()
+                };
+                report.installers.push(bottle.run_installer(manager, &path, &installer)?);
+            }
+            Step::RunPlugin { module, sha256 } => {
+                let path = installer_file(manager, module, sha256.as_deref(), options)?;
+                // Plugins may only download what the recipe lists, checked
+                // like the recipe checks it
+                let listed: HashMap<&str, &Option<String>> = self
+                    .steps
+                    .iter()
+                    .filter_map(|step| match step {
+                        Step::RunInstaller { file, sha256, .. } if is_url(file) => {
+                            Some((file.as_str(), sha256))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let urls = listed.keys().map(|url| url.to_string()).collect();
+                for mut step in plugin_steps(&path, options.sources.as_deref(), urls)? {
+                    if let Step::RunInstaller { file, sha256, .. } = &mut step {
+                        if let Some(listed) = listed.get(file.as_str()) {
+                            sha256.clone_from(listed);
+                        }
+                    }
+                    self.run_step(manager, bottle, &step, options, report)?;
+                }
+            }
+            Step::InstallComponent { component, version } => {
+                let version = version.as_deref().unwrap_or(LATEST);
+                components::install(manager, &bottle.name, *component, version)?;
+            }
+            Step::SetRegistry { key, name, value } => {
+                let runner = manager.runner_for(bottle)?;
+                let (kind, data) = reg_data(value)?;
+                let args = ["add", key, "/v", name, "/t", kind, "/d", data.as_str(), "/f"];
+                components::run(runner.as_ref(), &bottle.path, "reg", &args)?;
+            }
+            Step::SetDllOverride { dll, mode } => {
+                let runner = manager.runner_for(bottle)?;
+                components::set_override(runner.as_ref(), &bottle.path, dll, Some(mode.as_str()))?;
+            }
+            Step::RegisterProgram { name, path } => {
+                let mut program = Program::new(path);
+                program.name = name.clone();
+                report.programs.push(manager.add_program(&bottle.name, program)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Step {
+    /// What the step does, as shown while it runs
+    pub fn description(&self) -> String {
+        match self {
+            Self::RunInstaller { file, .. } => format!("Running {}", file_name(file)),
+            Self::RunPlugin { module, .. } => format!("Running {}", file_name(module)),
+            Self::InstallComponent { component, .. } => format!("Installing {}", component),
+            Self::SetRegistry { key, name, .. } => format!("Setting {}\\{}", key, name),
+            Self::SetDllOverride { dll, mode } => format!("Loading {} as {}", dll, mode),
+            Self::RegisterProgram { name, .. } => format!("Adding {} to the library", name),
+        }
+    }
+}
+
+/// How a recipe is installed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecipeOptions {
+    /// Runner of the bottle when the recipe creates it, the first installed
+    /// one when unset
+    pub runner: Option<String>,
+    /// Directory holding the installers a recipe names by file name, usually
+    /// the recipe's own directory
+    pub sources: Option<PathBuf>,
+    /// Stops the installation between steps and during downloads once
+    /// cancelled
+    #[serde(skip)]
+    pub token: Option<CancelToken>,
+}
+
+/// Progress of a recipe being installed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecipeProgress {
+    /// Index of the step about to run, `total` once done
+    pub step: usize,
+    pub total: usize,
+    pub message: String,
+}
+
+/// What installing a recipe did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecipeReport {
+    pub bottle: String,
+    /// Runs of the recipe's installers, in order
+    pub installers: Vec<InstallerReport>,
+    /// Programs added to the library of the bottle
+    pub programs: Vec<Program>,
+}
+
+/// The installer `file` of a recipe on disk, downloading it if it is a URL,
+/// checked against `sha256` if set
+fn installer_file(
+    manager: &Manager,
+    file: &str,
+    sha256: Option<&str>,
+    options: &RecipeOptions,
+) -> Result<PathBuf, Error> {
+    if !is_url(file) {
+        let path = Path::new(file);
+        let path = match &options.sources {
+            Some(sources) if path.is_relative() => sources.join(path),
+            _ => path.to_path_buf(),
+        };
+        if let Some(sha256) = sha256 {
+            integrity::sha256(&path, sha256)?;
+        }
+        return Ok(path);
+    }
+
+    // Installers of different URLs often share a file name, e.g. setup.exe
+    let directory: String = Sha256::digest(file.as_bytes())
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let target = manager.downloads_path().join(directory).join(file_name(file));
+    let mut request = Request::new(file, target).cancellable(options.token.as_ref());
+    // The downloader reuses a file with the checksum, and only such a file
+    if let Some(sha256) = sha256 {
+        request = request.sha256(sha256);
+    }
+    manager.downloader().download(&request, |_| {})
+}
+
+#[cfg(feature = "wasm")]
+fn plugin_steps(
+    path: &Path,
+    sources: Option<&Path>,
+    urls: Vec<String>,
+) -> Result<Vec<Step>, Error> {
+    wasm::steps(path, sources, urls)
+}
+
+#[cfg(not(feature = "wasm"))]
+fn plugin_steps(
+    path: &Path,
+    _sources: Option<&Path>,
+    _urls: Vec<String>,
+) -> Result<Vec<Step>, Error> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("running '{}' needs the wasm feature", path.display()),
+    )
+    .into())
+}
+
+fn is_url(file: &str) -> bool {
+    file.starts_with("https://") || file.starts_with("http://")
+}
+
+/// Last part of a path or URL, without the query
+fn file_name(file: &str) -> &str {
+    let file = file.split(['?', '#']).next().unwrap_or(file);
+    file.rsplit('/').find(|part| !part.is_empty()).unwrap_or(file)
+}
+
+/// Type and data of a value as given to `reg add`
+fn reg_data(value: &RegistryValue) -> Result<(&'static str, String), Error> {
+    Ok(match value {
+        RegistryValue::String(data) => ("REG_SZ", data.clone()),
+        RegistryValue::ExpandString(data) => ("REG_EXPAND_SZ", data.clone()),
+        // reg separates the strings with a literal \0
+        RegistryValue::MultiString(data) => ("REG_MULTI_SZ", data.join("\\0")),
+        RegistryValue::Dword(data) => ("REG_DWORD", data.to_string()),
+        RegistryValue::Qword(data) => ("REG_QWORD", data.to_string()),
+        RegistryValue::Binary(data) => {
+            ("REG_BINARY", data.iter().map(|byte| format!("{:02x}", byte)).collect())
+        }
+        RegistryValue::Raw { kind, .. } => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("registry values of type {} can't be set", kind),
+            )
+            .into())
+        }
+    })
+}
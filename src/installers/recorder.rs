@@ -0,0 +1,189 @@
+//! Drafting recipes from manual installer runs
+//!
+//! Writing a recipe by hand means knowing every file and setting an installer
+//! needs. A [`Recording`] instead snapshots the bottle, lets the user run the
+//! installer (and tools like `winecfg`) as usual, then compares the prefix with
+//! the snapshot using [`prefix::diff`] and turns what changed into a draft
+//! [`Recipe`]. The draft is a
+//! starting point to review: installer file names have to be replaced with
+//! download URLs, and only the executables that look like programs are
+//! registered.
+
+use super::{Recipe, Step};
+use crate::bottle::Bottle;
+use crate::manager::Manager;
+use crate::prefix::{self, PrefixDiff, Snapshot};
+use crate::registry::Hive;
+use crate::session::Launch;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// How often [`Recording::finish`] checks whether the recorded programs exited
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Executables installed alongside programs that aren't worth registering
+const NOT_PROGRAMS: &[&str] = &[
+    "unins", "setup", "install", "update", "crash", "redist", "helper", "report",
+];
+
+/// Everything a recording observed, with the recipe drafted from it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRun {
+    pub bottle: String,
+    pub started_at: SystemTime,
+    pub launches: Vec<Launch>,
+    pub changes: PrefixDiff,
+    pub recipe: Recipe,
+}
+
+/// A manual installer run being recorded
+pub struct Recording {
+    bottle: Bottle,
+    before: Snapshot,
+    /// Launched programs, with the id of their session
+    launches: Vec<(u64, Launch)>,
+}
+
+impl Recording {
+    /// Snapshot a bottle to start recording the changes made to it
+    pub fn start(manager: &Manager, bottle_name: &str) -> Result<Self, Error> {
+        let bottle = manager.get_bottle(bottle_name)?;
+        let before = Snapshot::capture(&bottle.path)?;
+        tracing::info!("Recording changes to '{}'", bottle.name);
+        Ok(Self {
+            bottle,
+            before,
+            launches: Vec::new(),
+        })
+    }
+
+    /// Launch a program as part of the recording, usually the installer
+    pub fn launch(
+        &mut self,
+        manager: &Manager,
+        program: &Path,
+        args: &[String],
+    ) -> Result<(), Error> {
+        let session = manager.launch_program(&self.bottle.name, program, args)?;
+        self.launches.push((
+            session.id,
+            Launch {
+                program: program.to_path_buf(),
+                args: args.to_vec(),
+                overrides: HashMap::new(),
+            },
+        ));
+        Ok(())
+    }
+
+    /// Wait for the launched programs to exit and draft a recipe from the
+    /// changes made to the bottle
+    pub fn finish(self, manager: &Manager) -> Result<RecordedRun, Error> {
+        while manager
+            .sessions()
+            .iter()
+            .any(|session| self.launches.iter().any(|(id, _)| *id == session.id))
+        {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        prefix::flush_registry(manager, &self.bottle);
+        let after = Snapshot::capture(&self.bottle.path)?;
+
+        let changes = prefix::diff(&self.before, &after);
+        let launches: Vec<Launch> = self.launches.into_iter().map(|(_, launch)| launch).collect();
+        let recipe = draft(&self.bottle, &launches, &changes);
+
+        Ok(RecordedRun {
+            bottle: self.bottle.name,
+            started_at: self.before.taken_at,
+            launches,
+            changes,
+            recipe,
+        })
+    }
+}
+
+fn draft(
+    bottle: &Bottle,
+    launches: &[Launch],
+    changes: &PrefixDiff,
+) -> Recipe {
+    let mut steps = Vec::new();
+
+    // Wine's own tools (winecfg, regedit) are launched by name, their effects
+    // are recorded as registry steps below
+    for launch in launches.iter().filter(|launch| launch.program.components().count() > 1) {
+        let file = launch
+            .program
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        steps.push(Step::RunInstaller {
+            file,
+            arguments: launch.args.clone(),
+            sha256: None,
+        });
+    }
+
+    // Installers write many keys themselves when the recipe runs them again,
+    // only Wine's configuration is set by the user
+    let wine_key = format!("{}\\Software\\Wine", Hive::CurrentUser.root_name());
+    let overrides_key = format!("{}\\DllOverrides", wine_key);
+    for (change, value) in changes.written_values() {
+        if change.key.eq_ignore_ascii_case(&overrides_key) {
+            if let Some(mode) = value.as_str() {
+                steps.push(Step::SetDllOverride {
+                    dll: change.name.clone(),
+                    mode: mode.to_string(),
+                });
+            }
+        } else if change.key.to_lowercase().starts_with(&wine_key.to_lowercase()) {
+            steps.push(Step::SetRegistry {
+                key: change.key.clone(),
+                name: change.name.clone(),
+                value: value.clone(),
+            });
+        }
+    }
+
+    for path in changes.added_files.iter().filter(|path| is_program(path)) {
+        let Some(windows_path) = prefix::windows_path(path) else {
+            continue;
+        };
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        steps.push(Step::RegisterProgram {
+            name,
+            path: windows_path,
+        });
+    }
+
+    let name = launches
+        .iter()
+        .find_map(|launch| launch.program.file_stem())
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| bottle.name.clone());
+    Recipe {
+        name,
+        description: None,
+        bottle_type: bottle.kind.clone(),
+        steps,
+    }
+}
+
+/// Whether an installed file looks like the executable of a program
+fn is_program(path: &Path) -> bool {
+    let Some(name) = path.file_name().map(|name| name.to_string_lossy().to_lowercase()) else {
+        return false;
+    };
+    let in_programs = path.components().any(|component| {
+        let component = component.as_os_str().to_string_lossy().to_lowercase();
+        component.starts_with("program files") || component == "appdata"
+    });
+    name.ends_with(".exe") && in_programs && !NOT_PROGRAMS.iter().any(|word| name.contains(word))
+}
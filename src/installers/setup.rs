@@ -0,0 +1,183 @@
+//! Running setup programs and MSI packages
+//!
+//! The process an installer is started as is often not the one doing the
+//! work: launchers extract the real installer, start it and exit, MSI
+//! packages run their custom actions in separate `msiexec` processes, and
+//! bootstrappers install redistributables one after the other. [`run`]
+//! waits for every process started in the prefix while the installer runs,
+//! not only the one it launched, then reports the programs it installed.
+//!
+//! Processes are found through `/proc`, so when the runner is started on the
+//! host from Flatpak, only the end of the installer's own process and of the
+//! wineserver are waited for.
+
+use crate::bottle::Bottle;
+use crate::drives;
+use crate::manager::Manager;
+use crate::prefix;
+use crate::programs::{self, InstalledProgram};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How often the processes of an installer are checked for
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Processes Wine keeps running in a prefix, whoever started them
+const SYSTEM_PROCESSES: &[&str] = &[
+    "wineserver",
+    "services.exe",
+    "winedevice.exe",
+    "plugplay.exe",
+    "explorer.exe",
+    "rpcss.exe",
+    "svchost.exe",
+    "conhost.exe",
+];
+
+/// How an installer is run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InstallerOptions {
+    /// Install an MSI package without its interface and without restarting
+    ///
+    /// Setup executables have their own switches, e.g. `/S` for NSIS or
+    /// `/VERYSILENT` for Inno Setup, which go in `arguments`.
+    pub quiet: bool,
+    /// Arguments passed to the installer, after the ones of msiexec for MSI
+    /// packages, e.g. `TARGETDIR=C:\App`
+    pub arguments: Vec<String>,
+    /// Stop waiting for the installer after this long, e.g. when it starts
+    /// the installed program once done
+    pub timeout: Option<Duration>,
+}
+
+/// What an installer run did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct InstallerReport {
+    /// Session the installer was launched as
+    pub session: u64,
+    /// Whether every process of the installer exited before the timeout
+    pub completed: bool,
+    /// How long the installer ran
+    pub duration: Duration,
+    /// Programs found in the prefix that weren't there before
+    pub installed: Vec<InstalledProgram>,
+}
+
+impl InstallerReport {
+    /// The JSON schema of installer reports, see [`crate::schema`]
+    #[cfg(feature = "schema")]
+    pub fn schema() -> serde_json::Value {
+        crate::schema::schema(crate::schema::Document::InstallerReport)
+    }
+}
+
+/// Run the setup executable or MSI package `path` in `bottle` and wait for
+/// it to finish, see [`crate::bottle::Bottle::run_installer`]
+pub(crate) fn run(
+    manager: &Manager,
+    bottle: &Bottle,
+    path: &Path,
+    options: &InstallerOptions,
+) -> Result<InstallerReport, Error> {
+    if !path.is_file() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("'{}' is not an installer", path.display()),
+        )
+        .into());
+    }
+    let (program, args) = if is_msi(path) {
+        let package = drives::windows_path(&bottle.path, path).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no drive of '{}' maps '{}'", bottle.name, path.display()),
+            )
+        })?;
+        let mut args = vec!["/i".to_string(), package];
+        if options.quiet {
+            args.extend(["/qn".to_string(), "/norestart".to_string()]);
+        }
+        args.extend(options.arguments.iter().cloned());
+        (Path::new("msiexec"), args)
+    } else {
+        (path, options.arguments.clone())
+    };
+
+    let before = programs::installed(&bottle.path)?;
+    let running: HashSet<u32> = processes(&bottle.path).into_iter().map(|(pid, _)| pid).collect();
+    let started = Instant::now();
+    let session = manager.launch_program(&bottle.name, program, &args)?;
+    tracing::info!("Running installer '{}' in '{}'", path.display(), bottle.name);
+
+    let is_system = |name: &str| SYSTEM_PROCESSES.contains(&name);
+    let mut completed = true;
+    loop {
+        let launched = manager.sessions().iter().any(|other| other.id == session.id);
+        let spawned = processes(&bottle.path)
+            .into_iter()
+            .any(|(pid, name)| !running.contains(&pid) && !is_system(&name));
+        if !launched && !spawned {
+            break;
+        }
+        if options.timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            tracing::warn!(
+                "Installer '{}' is still running after {:?}, not waiting for it",
+                path.display(),
+                started.elapsed()
+            );
+            completed = false;
+            break;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    // The registry is written to the hive files when the wineserver exits,
+    // which other programs running in the bottle would delay
+    let others = processes(&bottle.path).into_iter().any(|(_, name)| !is_system(&name));
+    if completed && !others {
+        prefix::flush_registry(manager, bottle);
+    }
+    let installed = programs::installed(&bottle.path)?
+        .into_iter()
+        .filter(|program| !before.contains(program))
+        .collect();
+
+    Ok(InstallerReport {
+        session: session.id,
+        completed,
+        duration: started.elapsed(),
+        installed,
+    })
+}
+
+fn is_msi(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("msi"))
+}
+
+/// The processes running in `prefix`, with their names, found by their
+/// `WINEPREFIX`
+fn processes(prefix: &Path) -> Vec<(u32, String)> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let wanted = format!("WINEPREFIX={}", prefix.display());
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| {
+            fs::read(format!("/proc/{}/environ", pid)).is_ok_and(|environ| {
+                environ.split(|byte| *byte == 0).any(|variable| variable == wanted.as_bytes())
+            })
+        })
+        .filter_map(|pid| {
+            let name = fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+            Some((pid, name.trim().to_string()))
+        })
+        .collect()
+}
@@ -0,0 +1,222 @@
+//! Recipe logic compiled to WebAssembly
+//!
+//! Community recipes and fixes sometimes need more than a fixed list of
+//! steps. A [`Step::RunPlugin`] runs a WebAssembly module which decides the
+//! steps itself, through the functions of the `bottles` import module:
+//!
+//! - `log(message, len)` writes a message to the log
+//! - `set_registry_string(key, key_len, name, name_len, value, value_len)`
+//!   and `set_registry_dword(key, key_len, name, name_len, value)` set a
+//!   registry value
+//! - `set_dll_override(dll, dll_len, mode, mode_len)` overrides a DLL
+//! - `run_installer(file, file_len)` runs an installer of the recipe, a
+//!   plain file name found in its sources or a URL the recipe lists itself
+//!
+//! Strings are UTF-8, passed as a pointer into the exported `memory` and a
+//! length. Functions return 0 on success, -1 for arguments that aren't
+//! strings or installers the plugin may not run and -2 once the plugin
+//! queued [`MAX_STEPS`] steps. The module exports `run`, taking nothing and
+//! returning 0 on success.
+//!
+//! Plugins don't run anything themselves: the functions queue steps, run
+//! like those of the recipe once `run` returned. They get no other access to
+//! the host, no files, network or clock, and are stopped once they used up
+//! their fuel or memory.
+
+use super::{is_url, Step};
+use crate::registry::RegistryValue;
+use crate::Error;
+use std::path::{Component, Path, PathBuf};
+use wasmtime::{Caller, Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// Name of the import module of the host functions
+const HOST_MODULE: &str = "bottles";
+
+/// Function run by the host
+const ENTRY_POINT: &str = "run";
+
+/// Fuel given to a plugin, roughly the number of instructions it may run
+const FUEL: u64 = 1_000_000_000;
+
+/// Largest memory a plugin may use, in bytes
+const MEMORY_LIMIT: usize = 64 << 20;
+
+/// Most steps a plugin may queue
+pub const MAX_STEPS: usize = 256;
+
+struct State {
+    steps: Vec<Step>,
+    limits: StoreLimits,
+    /// Where the installers named by file name are, none may run if unset
+    sources: Option<PathBuf>,
+    /// URLs of the installers listed by the recipe running the plugin
+    urls: Vec<String>,
+}
+
+impl State {
+    /// Whether the plugin may run the installer `file`
+    fn allows(&self, file: &str) -> bool {
+        if is_url(file) {
+            return self.urls.iter().any(|url| url == file);
+        }
+        let mut components = Path::new(file).components();
+        let plain = matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        );
+        match &self.sources {
+            // A symlink in the sources could still lead elsewhere
+            Some(sources) if plain => sources
+                .join(file)
+                .canonicalize()
+                .is_ok_and(|path| path.starts_with(sources)),
+            _ => false,
+        }
+    }
+}
+
+/// Run the plugin at `path` and return the steps it queued
+///
+/// The installers the plugin runs must be in `sources` or among `urls`.
+pub(crate) fn steps(
+    path: &Path,
+    sources: Option<&Path>,
+    urls: Vec<String>,
+) -> Result<Vec<Step>, Error> {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config).map_err(plugin_error)?;
+    let module = Module::from_file(&engine, path).map_err(plugin_error)?;
+    let mut linker = Linker::new(&engine);
+    define(&mut linker).map_err(plugin_error)?;
+
+    let state = State {
+        steps: Vec::new(),
+        limits: StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).build(),
+        sources: sources.and_then(|sources| sources.canonicalize().ok()),
+        urls,
+    };
+    let mut store = Store::new(&engine, state);
+    store.limiter(|state| &mut state.limits);
+    store.set_fuel(FUEL).map_err(plugin_error)?;
+    let instance = linker.instantiate(&mut store, &module).map_err(plugin_error)?;
+    let run = instance
+        .get_typed_func::<(), i32>(&mut store, ENTRY_POINT)
+        .map_err(plugin_error)?;
+    let status = run.call(&mut store, ()).map_err(plugin_error)?;
+    if status != 0 {
+        return Err(std::io::Error::other(format!(
+            "plugin '{}' failed with status {}",
+            path.display(),
+            status
+        ))
+        .into());
+    }
+    let steps = store.into_data().steps;
+    tracing::debug!("Plugin '{}' queued {} step(s)", path.display(), steps.len());
+    Ok(steps)
+}
+
+fn define(linker: &mut Linker<State>) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "log",
+        |mut caller: Caller<'_, State>, message: u32, len: u32| {
+            if let Some(message) = string(&mut caller, message, len) {
+                tracing::info!("Plugin: {}", message);
+            }
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "set_registry_string",
+        |mut caller: Caller<'_, State>,
+         key: u32,
+         key_len: u32,
+         name: u32,
+         name_len: u32,
+         value: u32,
+         value_len: u32| {
+            let key = string(&mut caller, key, key_len);
+            let name = string(&mut caller, name, name_len);
+            let value = string(&mut caller, value, value_len);
+            let (Some(key), Some(name), Some(value)) = (key, name, value) else {
+                return -1;
+            };
+            let value = RegistryValue::String(value);
+            queue(&mut caller, Step::SetRegistry { key, name, value })
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "set_registry_dword",
+        |mut caller: Caller<'_, State>,
+         key: u32,
+         key_len: u32,
+         name: u32,
+         name_len: u32,
+         value: u32| {
+            let key = string(&mut caller, key, key_len);
+            let name = string(&mut caller, name, name_len);
+            let (Some(key), Some(name)) = (key, name) else {
+                return -1;
+            };
+            let value = RegistryValue::Dword(value);
+            queue(&mut caller, Step::SetRegistry { key, name, value })
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "set_dll_override",
+        |mut caller: Caller<'_, State>, dll: u32, dll_len: u32, mode: u32, mode_len: u32| {
+            let dll = string(&mut caller, dll, dll_len);
+            let mode = string(&mut caller, mode, mode_len);
+            let (Some(dll), Some(mode)) = (dll, mode) else {
+                return -1;
+            };
+            queue(&mut caller, Step::SetDllOverride { dll, mode })
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "run_installer",
+        |mut caller: Caller<'_, State>, file: u32, file_len: u32| {
+            let Some(file) = string(&mut caller, file, file_len) else {
+                return -1;
+            };
+            if !caller.data().allows(&file) {
+                tracing::warn!("Plugin tried to run '{}', outside of its recipe", file);
+                return -1;
+            }
+            let step = Step::RunInstaller {
+                file,
+                arguments: Vec::new(),
+                sha256: None,
+            };
+            queue(&mut caller, step)
+        },
+    )?;
+    Ok(())
+}
+
+fn queue(caller: &mut Caller<'_, State>, step: Step) -> i32 {
+    let steps = &mut caller.data_mut().steps;
+    if steps.len() >= MAX_STEPS {
+        return -2;
+    }
+    steps.push(step);
+    0
+}
+
+/// The string at `ptr` in the memory of the plugin
+fn string(caller: &mut Caller<'_, State>, ptr: u32, len: u32) -> Option<String> {
+    let memory = caller.get_export("memory")?.into_memory()?;
+    let start = usize::try_from(ptr).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    let bytes = memory.data(&*caller).get(start..end)?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+fn plugin_error(e: wasmtime::Error) -> Error {
+    std::io::Error::other(format!("plugin error: {:#}", e)).into()
+}
@@ -0,0 +1,4 @@
+//! Integrations with tools working on bottles from the outside
+
+pub mod sunshine;
+pub mod yabridge;
@@ -0,0 +1,180 @@
+//! Streaming sessions with Sunshine and Moonlight
+//!
+//! Sunshine streams a machine to Moonlight clients, and lists the
+//! applications clients may start in its `apps.json`. When a frontend
+//! launches a program on a remote target (see [`crate::target::Remote`]), the
+//! host can register it there, so that the user picks it up right away from
+//! Moonlight, and starts it again from there later.
+//!
+//! Entries are launched back through the daemon, by opening the program's
+//! [`shortcuts::uri`], so the program gets the bottle's runner and settings.
+//! Only entries added here, recognizable by their [`ENTRY_PREFIX`], are ever
+//! replaced or removed; the rest of the file is kept as is. Sunshine reads the
+//! file when it starts and when its applications are edited from its web UI.
+
+use crate::flatpak;
+use crate::session::Session;
+use crate::shortcuts;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the names of the applications added to Sunshine
+pub const ENTRY_PREFIX: &str = "Bottles: ";
+
+/// Application id of Sunshine's Flatpak
+const FLATPAK_ID: &str = "dev.lizardbyte.app.Sunshine";
+
+/// A session registered with Sunshine
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handoff {
+    /// Name of the application, as listed by Moonlight
+    pub app: String,
+    pub bottle: String,
+    /// Session the application was registered for
+    pub session: u64,
+    /// Command Sunshine runs to start the application
+    pub command: String,
+}
+
+/// The applications of a Sunshine installation
+#[derive(Debug, Clone)]
+pub struct Sunshine {
+    apps: PathBuf,
+}
+
+impl Sunshine {
+    /// Find the `apps.json` of the user's Sunshine, native or Flatpak
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if Sunshine was never run by the user
+    pub fn find() -> Result<Self, Error> {
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|_| !flatpak::is_sandboxed())
+            .map(PathBuf::from)
+            .or_else(|| Some(home.clone()?.join(".config")));
+        let candidates = [
+            config.map(|config| config.join("sunshine")),
+            home.map(|home| home.join(".var/app").join(FLATPAK_ID).join("config/sunshine")),
+        ];
+        candidates
+            .into_iter()
+            .flatten()
+            .map(|directory| directory.join("apps.json"))
+            .find(|apps| apps.is_file())
+            .map(Self::at)
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::NotFound, "Sunshine is not set up")
+                    .into()
+            })
+    }
+
+    /// The applications listed in the `apps.json` at `apps`
+    pub fn at(apps: impl Into<PathBuf>) -> Self {
+        Self { apps: apps.into() }
+    }
+
+    pub fn apps_path(&self) -> &Path {
+        &self.apps
+    }
+
+    /// Add an application starting the program of `session` with `args`,
+    /// replacing the one of a previous session of the same program
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `apps.json` can't be read or written
+    pub fn register(&self, session: &Session, args: &[String]) -> Result<Handoff, Error> {
+        let program = session
+            .program
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| session.program.to_string_lossy().into_owned());
+        let handoff = Handoff {
+            app: format!("{}{} ({})", ENTRY_PREFIX, program, session.bottle),
+            bottle: session.bottle.clone(),
+            session: session.id,
+            command: format!(
+                "xdg-open \"{}\"",
+                shortcuts::uri(&session.bottle, &session.program, args)
+            ),
+        };
+        let mut apps = self.load()?;
+        entries(&mut apps)?.retain(|app| name(app) != Some(handoff.app.as_str()));
+        entries(&mut apps)?.push(json!({
+            "name": handoff.app,
+            "cmd": handoff.command,
+            "auto-detach": "true",
+        }));
+        self.save(&apps)?;
+        tracing::info!("Registered '{}' with Sunshine", handoff.app);
+        Ok(handoff)
+    }
+
+    /// Remove the application `app` added by [`Sunshine::register`]
+    pub fn unregister(&self, app: &str) -> Result<(), Error> {
+        if !app.starts_with(ENTRY_PREFIX) {
+            return Err(not_ours(app));
+        }
+        let mut apps = self.load()?;
+        entries(&mut apps)?.retain(|entry| name(entry) != Some(app));
+        self.save(&apps)
+    }
+
+    /// Names of the applications added by [`Sunshine::register`]
+    pub fn registered(&self) -> Result<Vec<String>, Error> {
+        let mut apps = self.load()?;
+        Ok(entries(&mut apps)?
+            .iter()
+            .filter_map(name)
+            .filter(|name| name.starts_with(ENTRY_PREFIX))
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn load(&self) -> Result<Value, Error> {
+        match fs::read_to_string(&self.apps) {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(json!({ "apps": [] })),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    fn save(&self, apps: &Value) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(apps)?;
+        let temporary = self.apps.with_extension("json.new");
+        fs::write(&temporary, content).map_err(Error::Io)?;
+        fs::rename(&temporary, &self.apps).map_err(Error::Io)
+    }
+}
+
+/// The list of applications of `apps.json`
+fn entries(apps: &mut Value) -> Result<&mut Vec<Value>, Error> {
+    let invalid = || {
+        Error::from(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the applications of Sunshine are not a list",
+        ))
+    };
+    let apps = apps.as_object_mut().ok_or_else(invalid)?;
+    apps.entry("apps")
+        .or_insert_with(|| Value::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or_else(invalid)
+}
+
+fn name(app: &Value) -> Option<&str> {
+    app.get("name").and_then(Value::as_str)
+}
+
+fn not_ours(app: &str) -> Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("'{}' was not added by bottles", app),
+    )
+    .into()
+}
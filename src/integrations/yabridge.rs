@@ -0,0 +1,200 @@
+//! Windows VST and CLAP plugins in Linux DAWs through yabridge
+//!
+//! yabridge bridges the plugins of a set of Windows plugin directories,
+//! managed with `yabridgectl`: `yabridgectl sync` creates a Linux plugin for
+//! every plugin found, which runs the Windows one with Wine. The prefix of a
+//! plugin is the one its directory is in, so directories of a bottle run in
+//! that bottle. Only directories inside bottles are handled here, the others
+//! of yabridgectl's configuration are left alone.
+//!
+//! yabridge runs plugins with the `wine` of the DAW's `PATH`. Syncing puts
+//! the bottle's runner first in `PATH`, so the checks yabridgectl makes after
+//! a sync use the Wine plugins of that bottle will run with.
+
+use crate::bottle::Bottle;
+use crate::manager::Manager;
+use crate::{flatpak, launch, Error};
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Where yabridge's release archive installs `yabridgectl`, relative to the
+/// home directory
+const USER_INSTALL: &str = ".local/share/yabridge/yabridgectl";
+
+/// Directories installers put plugins in, relative to `drive_c`
+pub const PLUGIN_DIRS: &[&str] = &[
+    "Program Files/Common Files/VST3",
+    "Program Files/Common Files/CLAP",
+    "Program Files/VSTPlugins",
+    "Program Files/Steinberg/VstPlugins",
+    "Program Files (x86)/Common Files/VST3",
+    "Program Files (x86)/VSTPlugins",
+    "Program Files (x86)/Steinberg/VstPlugins",
+];
+
+/// The plugin directories of [`PLUGIN_DIRS`] present in `bottle`
+pub fn plugin_dirs(bottle: &Bottle) -> Vec<PathBuf> {
+    PLUGIN_DIRS
+        .iter()
+        .map(|directory| bottle.path.join("drive_c").join(directory))
+        .filter(|directory| directory.is_dir())
+        .collect()
+}
+
+/// An installed `yabridgectl`
+#[derive(Debug, Clone)]
+pub struct Yabridge {
+    yabridgectl: PathBuf,
+}
+
+impl Yabridge {
+    /// Find `yabridgectl` in `PATH` or in yabridge's user installation
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if yabridge is not installed
+    pub fn find() -> Result<Self, Error> {
+        launch::find_in_path("yabridgectl")
+            .or_else(|| {
+                let home = std::env::var_os("HOME")?;
+                let path = Path::new(&home).join(USER_INSTALL);
+                path.is_file().then_some(path)
+            })
+            .map(|yabridgectl| Self { yabridgectl })
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::NotFound, "yabridge is not installed")
+                    .into()
+            })
+    }
+
+    pub fn executable(&self) -> &Path {
+        &self.yabridgectl
+    }
+
+    /// Version of yabridge, e.g. `5.1.0`
+    pub fn version(&self) -> Option<String> {
+        let output = self.run(&["--version"], Vec::new()).ok()?;
+        // `yabridgectl 5.1.0`
+        let version = output.split_whitespace().last()?;
+        Some(version.to_string())
+    }
+
+    /// The plugin directories of `bottle` known to yabridge
+    pub fn associated(&self, manager: &Manager, bottle_name: &str) -> Result<Vec<PathBuf>, Error> {
+        let bottle = manager.get_bottle(bottle_name)?;
+        let output = self.run(&["list"], Vec::new())?;
+        let directories = output
+            .lines()
+            .map(|line| PathBuf::from(line.trim()))
+            .filter(|directory| directory.starts_with(&bottle.path))
+            .collect();
+        Ok(directories)
+    }
+
+    /// Let yabridge bridge the plugins of `directory`, a directory of the
+    /// bottle's prefix
+    ///
+    /// Relative paths are relative to the prefix's `drive_c`, like
+    /// [`PLUGIN_DIRS`]. The plugins are bridged by the next [`Yabridge::sync`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `directory` is not a directory of the bottle, or
+    /// if yabridgectl fails
+    pub fn add(
+        &self,
+        manager: &Manager,
+        bottle_name: &str,
+        directory: &Path,
+    ) -> Result<PathBuf, Error> {
+        let bottle = manager.get_bottle(bottle_name)?;
+        let directory = plugin_dir(&bottle, directory)?;
+        if !directory.is_dir() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("'{}' does not exist", directory.display()),
+            )
+            .into());
+        }
+        let mut args = vec![OsString::from("add")];
+        args.push(directory.clone().into_os_string());
+        self.run(&args, Vec::new())?;
+        tracing::info!("Added '{}' of '{}' to yabridge", directory.display(), bottle.name);
+        Ok(directory)
+    }
+
+    /// Stop bridging the plugins of a directory added with [`Yabridge::add`]
+    ///
+    /// The bridged plugins are removed by the next [`Yabridge::sync`].
+    pub fn remove(
+        &self,
+        manager: &Manager,
+        bottle_name: &str,
+        directory: &Path,
+    ) -> Result<(), Error> {
+        let bottle = manager.get_bottle(bottle_name)?;
+        let directory = plugin_dir(&bottle, directory)?;
+        let mut args = vec![OsString::from("rm")];
+        args.push(directory.into_os_string());
+        self.run(&args, Vec::new())?;
+        Ok(())
+    }
+
+    /// Bridge the plugins of every directory, and remove the bridges of
+    /// plugins that are gone, with the Wine of the bottle's runner
+    ///
+    /// # Returns
+    ///
+    /// The output of `yabridgectl sync`, summarizing the changes
+    pub fn sync(&self, manager: &Manager, bottle_name: &str) -> Result<String, Error> {
+        let bottle = manager.get_bottle(bottle_name)?;
+        let runner = manager.runner_for(&bottle)?;
+        let wine = runner.wine().info().executable_path();
+        let mut path = OsString::new();
+        if let Some(bin) = wine.parent() {
+            path.push(flatpak::host_path(bin));
+            path.push(":");
+        }
+        path.push(std::env::var_os("PATH").unwrap_or_default());
+        let env = vec![
+            ("WINEPREFIX", flatpak::host_path(&bottle.path).into_os_string()),
+            ("WINELOADER", flatpak::host_path(&wine).into_os_string()),
+            ("PATH", path),
+        ];
+        let output = self.run(&["sync", "--prune"], env)?;
+        tracing::info!("Synced yabridge with the runner of '{}'", bottle.name);
+        Ok(output)
+    }
+
+    /// Run yabridgectl, returning its output
+    fn run<S: AsRef<std::ffi::OsStr>>(
+        &self,
+        args: &[S],
+        env: Vec<(&str, OsString)>,
+    ) -> Result<String, Error> {
+        let mut command = Command::new(&self.yabridgectl);
+        command.args(args).envs(env).stdin(Stdio::null());
+        let output = flatpak::adapt(command).output().map_err(Error::Io)?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = format!("yabridgectl failed: {}", stderr.trim());
+            return Err(std::io::Error::other(message).into());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// `directory` as a host path, checking it is inside the prefix of `bottle`
+fn plugin_dir(bottle: &Bottle, directory: &Path) -> Result<PathBuf, Error> {
+    let directory = bottle.path.join("drive_c").join(directory);
+    let escapes = directory.components().any(|c| c == Component::ParentDir);
+    if escapes || !directory.starts_with(&bottle.path) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("'{}' is not in '{}'", directory.display(), bottle.name),
+        )
+        .into());
+    }
+    Ok(directory)
+}
@@ -0,0 +1,202 @@
+//! Verification of downloaded runners and components
+//!
+//! Runner and component archives contain programs run on the user's
+//! machine, so a compromised mirror must not be able to replace them. Each
+//! [`Check`] of a download is verified by [`verify`] once it is downloaded and
+//! before it is extracted; any failure is an [`Error::Integrity`] and the
+//! archive is deleted.
+//!
+//! Checksums only protect against corrupted downloads unless they come from a
+//! trusted source, like a signed catalog index (see [`crate::catalog`]) or
+//! GitHub's own digest of a release asset. Signatures are checked with the
+//! host's `minisign` or `gpgv`.
+
+use crate::net::{self, Downloader, Request};
+use crate::{flatpak, launch, Error};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A check a downloaded file must pass
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Check {
+    /// The file has this hex SHA-256
+    Sha256 { digest: String },
+    /// The minisign signature at `signature` matches the file and the key
+    Minisign {
+        /// Base64 line of the public key file
+        public_key: String,
+        /// URL of the `.minisig` file
+        signature: String,
+    },
+    /// The detached OpenPGP signature at `signature` matches the file and a
+    /// key of `keyring`
+    Gpg {
+        keyring: PathBuf,
+        /// URL of the `.sig` or `.asc` file
+        signature: String,
+    },
+    /// GitHub's SHA-256 digest of the asset `asset` of the release `tag` of
+    /// `repository` matches the file
+    GitHubRelease {
+        /// Owner and name of the repository, e.g. `GloriousEggroll/proton-ge-custom`
+        repository: String,
+        tag: String,
+        asset: String,
+    },
+}
+
+/// Verify that the file at `path` passes `check`
+///
+/// # Errors
+///
+/// Returns [`Error::Integrity`] if the file doesn't pass the check, other
+/// errors if the check couldn't be made, e.g. without network
+pub fn verify(downloader: &Downloader, path: &Path, check: &Check) -> Result<(), Error> {
+    match check {
+        Check::Sha256 { digest } => sha256(path, digest),
+        Check::Minisign {
+            public_key,
+            signature,
+        } => {
+            let file = with_suffix(path, ".minisig");
+            downloader.download(&Request::new(signature.clone(), &file), |_| {})?;
+            let result = minisign(path, &file, public_key);
+            let _ = std::fs::remove_file(&file);
+            result
+        }
+        Check::Gpg { keyring, signature } => {
+            let file = with_suffix(path, ".sig");
+            downloader.download(&Request::new(signature.clone(), &file), |_| {})?;
+            let result = gpg(path, &file, keyring);
+            let _ = std::fs::remove_file(&file);
+            result
+        }
+        Check::GitHubRelease {
+            repository,
+            tag,
+            asset,
+        } => {
+            let digest = github_digest(downloader, repository, tag, asset)?;
+            sha256(path, &digest)
+        }
+    }
+}
+
+/// Verify that the file at `path` passes every check of `checks`, deleting
+/// it if it doesn't
+///
+/// # Errors
+///
+/// See [`verify`]
+pub fn verify_all(downloader: &Downloader, path: &Path, checks: &[Check]) -> Result<(), Error> {
+    for check in checks {
+        if let Err(e) = verify(downloader, path, check) {
+            if matches!(e, Error::Integrity(_)) {
+                tracing::warn!("Deleting '{}': {}", path.display(), e);
+                let _ = std::fs::remove_file(path);
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Check that the file at `path` has the hex SHA-256 `expected`
+pub(crate) fn sha256(path: &Path, expected: &str) -> Result<(), Error> {
+    let expected = expected.trim();
+    let expected = expected.strip_prefix("sha256:").unwrap_or(expected);
+    let actual = net::sha256(path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(Error::Integrity(format!(
+            "'{}' has SHA-256 {}, not {}",
+            path.display(),
+            actual,
+            expected
+        )));
+    }
+    Ok(())
+}
+
+/// Check the minisign `signature` of the file at `path`
+pub(crate) fn minisign(path: &Path, signature: &Path, public_key: &str) -> Result<(), Error> {
+    let mut command = tool("minisign", path)?;
+    command
+        .args(["-V", "-q", "-P", public_key, "-m"])
+        .arg(path)
+        .arg("-x")
+        .arg(signature);
+    run(command, path, "is not signed by the expected minisign key")
+}
+
+/// Check the detached OpenPGP `signature` of the file at `path`
+fn gpg(path: &Path, signature: &Path, keyring: &Path) -> Result<(), Error> {
+    let mut command = tool("gpgv", path)?;
+    command
+        .arg("--keyring")
+        .arg(keyring)
+        .arg(signature)
+        .arg(path);
+    run(command, path, "is not signed by a key of the keyring")
+}
+
+/// The digest GitHub computed for a release asset
+fn github_digest(
+    downloader: &Downloader,
+    repository: &str,
+    tag: &str,
+    asset: &str,
+) -> Result<String, Error> {
+    #[derive(Deserialize)]
+    struct Release {
+        assets: Vec<Asset>,
+    }
+    #[derive(Deserialize)]
+    struct Asset {
+        name: String,
+        digest: Option<String>,
+    }
+
+    let url = format!("https://api.github.com/repos/{}/releases/tags/{}", repository, tag);
+    let release: Release = serde_json::from_str(&downloader.get(&url)?)?;
+    release
+        .assets
+        .into_iter()
+        .find(|candidate| candidate.name == asset)
+        .and_then(|asset| asset.digest)
+        .ok_or_else(|| {
+            Error::Integrity(format!(
+                "release {} of {} has no digest of '{}'",
+                tag, repository, asset
+            ))
+        })
+}
+
+fn tool(name: &str, path: &Path) -> Result<Command, Error> {
+    if launch::find_in_path(name).is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("verifying '{}' needs {}", path.display(), name),
+        )
+        .into());
+    }
+    let mut command = Command::new(name);
+    command.stdin(Stdio::null());
+    Ok(command)
+}
+
+fn run(command: Command, path: &Path, failure: &str) -> Result<(), Error> {
+    let output = flatpak::adapt(command).output().map_err(Error::Io)?;
+    if !output.status.success() {
+        return Err(Error::Integrity(format!("'{}' {}", path.display(), failure)));
+    }
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name: OsString = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
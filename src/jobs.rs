@@ -0,0 +1,417 @@
+//! Long operations running in the background
+//!
+//! Creating a bottle, installing a component or downloading a runner takes
+//! from seconds to minutes. Queued through [`Jobs::submit`] (or the `queue_*`
+//! methods of [`Manager`](crate::manager::Manager)), they run on their own
+//! thread and are tracked as [`Job`]s, so frontends follow the progress of
+//! every operation the same way and can cancel them.
+//!
+//! At most [`Jobs::concurrency`] jobs run at the same time, the others wait
+//! in the order they were queued. Cancelling a queued job drops it; a running
+//! job stops at its next [`JobContext::check`]. Finished jobs are kept for a
+//! while so frontends can show their outcome.
+
+use crate::events::{Event, Events};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+/// Jobs running at the same time by default
+pub const DEFAULT_CONCURRENCY: usize = 2;
+
+/// Finished jobs kept, the oldest are forgotten first
+const HISTORY: usize = 100;
+
+/// What a job does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    CreateBottle,
+    InstallComponent,
+    InstallRunner,
+    InstallRecipe,
+    WarmUp,
+    Other,
+}
+
+impl JobKind {
+    /// Identifier of the kind, as serialized
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::CreateBottle => "create_bottle",
+            Self::InstallComponent => "install_component",
+            Self::InstallRunner => "install_runner",
+            Self::InstallRecipe => "install_recipe",
+            Self::WarmUp => "warm_up",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Where a job is in its life
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting for another job to finish
+    Queued,
+    Running,
+    Succeeded,
+    Failed { error: String },
+    Cancelled,
+}
+
+impl JobStatus {
+    /// Identifier of the state, as serialized
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Succeeded => "succeeded",
+            Self::Failed { .. } => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, Self::Queued | Self::Running)
+    }
+}
+
+/// Progress reported by a running job
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobProgress {
+    /// Work done so far, in units of the job, e.g. bytes or steps
+    pub current: u64,
+    /// Work to do in total, when known
+    pub total: Option<u64>,
+    /// What the job is doing
+    pub message: String,
+}
+
+/// A job queued with [`Jobs::submit`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Job {
+    /// Identifier of the job, unique for the lifetime of the queue
+    pub id: u64,
+    pub kind: JobKind,
+    /// What the job does, for frontends to show, e.g. `Create 'Games'`
+    pub description: String,
+    pub status: JobStatus,
+    /// Last progress reported, if any
+    pub progress: Option<JobProgress>,
+    pub queued_at: SystemTime,
+    pub started_at: Option<SystemTime>,
+    pub finished_at: Option<SystemTime>,
+}
+
+/// Flag telling an operation to stop, shared with whoever may cancel it
+///
+/// Clones share the flag: cancelling one cancels them all. Two tokens are
+/// equal when they share their flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl PartialEq for CancelToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CancelToken {}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Return [`Error::Cancelled`] once cancelled, for operations to stop
+    /// with `?`
+    pub fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// What a running job is given to report its progress and notice its
+/// cancellation
+pub struct JobContext<'a> {
+    id: u64,
+    token: CancelToken,
+    jobs: &'a Jobs,
+}
+
+impl JobContext<'_> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The token cancelled with the job, for operations taking one
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+
+    /// See [`CancelToken::check`]
+    pub fn check(&self) -> Result<(), Error> {
+        self.token.check()
+    }
+
+    /// Report the progress of the job
+    pub fn progress(&self, current: u64, total: Option<u64>, message: impl Into<String>) {
+        let progress = JobProgress {
+            current,
+            total,
+            message: message.into(),
+        };
+        if let Some(entry) = self.jobs.state().jobs.get_mut(&self.id) {
+            entry.job.progress = Some(progress);
+            self.jobs.emit(&entry.job);
+        }
+    }
+}
+
+/// Queue of the jobs of a manager
+pub struct Jobs {
+    state: Mutex<State>,
+    /// Notified when a job finishes or is cancelled
+    changed: Condvar,
+    events: Events,
+}
+
+struct State {
+    next_id: u64,
+    concurrency: usize,
+    running: usize,
+    jobs: BTreeMap<u64, Entry>,
+}
+
+impl State {
+    /// The oldest job still waiting to start
+    fn next_queued(&self) -> Option<u64> {
+        self.jobs
+            .values()
+            .find(|entry| entry.job.status == JobStatus::Queued && !entry.token.is_cancelled())
+            .map(|entry| entry.job.id)
+    }
+}
+
+struct Entry {
+    job: Job,
+    token: CancelToken,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self::new(DEFAULT_CONCURRENCY)
+    }
+}
+
+impl Jobs {
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                next_id: 0,
+                concurrency,
+                running: 0,
+                jobs: BTreeMap::new(),
+            }),
+            changed: Condvar::new(),
+            events: Events::default(),
+        }
+    }
+
+    /// Emit [`Event::JobProgress`] on `events` as jobs change
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.state().concurrency
+    }
+
+    /// Change how many jobs may run at the same time, running jobs finish
+    /// either way
+    pub fn set_concurrency(&self, concurrency: usize) {
+        self.state().concurrency = concurrency;
+        self.changed.notify_all();
+    }
+
+    /// Queue `work`, run on its own thread once a slot is free
+    ///
+    /// Returns the id of the job. An error returned by `work` fails the job,
+    /// [`Error::Cancelled`] cancels it.
+    pub fn submit<F>(
+        self: &Arc<Self>,
+        kind: JobKind,
+        description: impl Into<String>,
+        work: F,
+    ) -> u64
+    where
+        F: FnOnce(&JobContext) -> Result<(), Error> + Send + 'static,
+    {
+        let token = CancelToken::default();
+        let id = {
+            let mut state = self.state();
+            state.next_id += 1;
+            let id = state.next_id;
+            let job = Job {
+                id,
+                kind,
+                description: description.into(),
+                status: JobStatus::Queued,
+                progress: None,
+                queued_at: SystemTime::now(),
+                started_at: None,
+                finished_at: None,
+            };
+            tracing::debug!("Queued job {}: {}", id, job.description);
+            self.emit(&job);
+            let entry = Entry {
+                job,
+                token: token.clone(),
+            };
+            state.jobs.insert(id, entry);
+            id
+        };
+        let jobs = Arc::clone(self);
+        std::thread::spawn(move || jobs.run(id, token, work));
+        id
+    }
+
+    /// The jobs queued, running and recently finished, oldest first
+    pub fn list(&self) -> Vec<Job> {
+        self.state().jobs.values().map(|entry| entry.job.clone()).collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Job> {
+        self.state().jobs.get(&id).map(|entry| entry.job.clone())
+    }
+
+    /// Cancel a queued or running job
+    ///
+    /// Returns the job as it is now: a running job is only cancelled once it
+    /// notices, see [`JobContext::check`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::JobNotFound`] if no job has that id
+    pub fn cancel(&self, id: u64) -> Result<Job, Error> {
+        let job = {
+            let state = self.state();
+            let entry = state.jobs.get(&id).ok_or(Error::JobNotFound(id))?;
+            if !entry.job.status.is_finished() {
+                entry.token.cancel();
+                tracing::info!("Cancelling job {}: {}", id, entry.job.description);
+            }
+            entry.job.clone()
+        };
+        self.changed.notify_all();
+        Ok(job)
+    }
+
+    /// Wait for a job to finish, returning how it finished
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::JobNotFound`] if no job has that id
+    pub fn wait(&self, id: u64) -> Result<Job, Error> {
+        let mut state = self.state();
+        loop {
+            let job = &state.jobs.get(&id).ok_or(Error::JobNotFound(id))?.job;
+            if job.status.is_finished() {
+                return Ok(job.clone());
+            }
+            state = self
+                .changed
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    fn run<F>(&self, id: u64, token: CancelToken, work: F)
+    where
+        F: FnOnce(&JobContext) -> Result<(), Error>,
+    {
+        {
+            let mut state = self.state();
+            // Jobs start in the order they were queued
+            while (state.running >= state.concurrency.max(1) || state.next_queued() != Some(id))
+                && !token.is_cancelled()
+            {
+                state = self
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+            if token.is_cancelled() {
+                self.finish(state, id, JobStatus::Cancelled);
+                return;
+            }
+            state.running += 1;
+            if let Some(entry) = state.jobs.get_mut(&id) {
+                entry.job.status = JobStatus::Running;
+                entry.job.started_at = Some(SystemTime::now());
+                self.emit(&entry.job);
+            }
+        }
+        // The next queued job may start too
+        self.changed.notify_all();
+
+        let context = JobContext {
+            id,
+            token: token.clone(),
+            jobs: self,
+        };
+        let status = match work(&context) {
+            Ok(()) => JobStatus::Succeeded,
+            Err(Error::Cancelled) => JobStatus::Cancelled,
+            Err(e) => {
+                tracing::warn!("Job {} failed: {}", id, e);
+                JobStatus::Failed {
+                    error: e.to_string(),
+                }
+            }
+        };
+        let mut state = self.state();
+        state.running -= 1;
+        self.finish(state, id, status);
+    }
+
+    /// Record how a job finished and forget the oldest finished jobs
+    fn finish(&self, mut state: MutexGuard<'_, State>, id: u64, status: JobStatus) {
+        if let Some(entry) = state.jobs.get_mut(&id) {
+            tracing::debug!("Job {} is {}", id, status.id());
+            entry.job.status = status;
+            entry.job.finished_at = Some(SystemTime::now());
+            self.emit(&entry.job);
+        }
+        let finished: Vec<u64> = state
+            .jobs
+            .values()
+            .filter(|entry| entry.job.status.is_finished())
+            .map(|entry| entry.job.id)
+            .collect();
+        for id in finished.iter().take(finished.len().saturating_sub(HISTORY)) {
+            state.jobs.remove(id);
+        }
+        drop(state);
+        self.changed.notify_all();
+    }
+
+    fn emit(&self, job: &Job) {
+        self.events.emit(Event::JobProgress { job: job.clone() });
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,257 @@
+//! Kerberos single sign-on for enterprise programs
+//!
+//! Windows line-of-business programs signing in to Active Directory go
+//! through Wine's `kerberos.dll`, which gets its tickets from the host's
+//! libkrb5, so a user signed in with `kinit` or through SSSD is signed in in
+//! the bottle too. Launches don't always inherit the host's Kerberos
+//! variables though: the daemon may be started outside of the login
+//! session, and commands run on the host from Flatpak only get the variables
+//! set on them. With [`KerberosOptions::enabled`], the credential cache and
+//! the Kerberos variables of the daemon are passed to launches explicitly,
+//! and the cache is shared with the Steam Linux Runtime container.
+//!
+//! In Flatpak, file caches and the KCM socket need file system permissions,
+//! and kernel keyring caches can't be used at all, see [`diagnose`].
+
+use crate::flatpak;
+use crate::system::diagnostics::{self, Check, CheckStatus, Feature};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Variable naming the credential cache
+const CCACHE_VARIABLE: &str = "KRB5CCNAME";
+
+/// Variables of libkrb5 passed to launches when set for the daemon
+const PASSTHROUGH: &[&str] = &["KRB5_CONFIG", "KRB5_KTNAME", "KRB5_CLIENT_KTNAME", "KRB5_TRACE"];
+
+/// Configuration of libkrb5 when `KRB5_CONFIG` is unset
+const DEFAULT_CONFIG: &str = "/etc/krb5.conf";
+
+/// Socket of the KCM credential cache server, e.g. sssd-kcm
+const KCM_SOCKET: &str = "/var/run/.heim_org.h5l.kcm-socket";
+
+/// Kerberos settings of a bottle
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct KerberosOptions {
+    /// Pass the host's credential cache and Kerberos variables to launches
+    pub enabled: bool,
+    /// Credential cache to use instead of the host's default, e.g.
+    /// `FILE:/run/user/1000/krb5cc_corp`
+    pub ccache: Option<String>,
+    /// krb5.conf to use instead of the host's
+    pub config: Option<PathBuf>,
+}
+
+impl KerberosOptions {
+    /// The credential cache programs use
+    pub fn credential_cache(&self) -> CredentialCache {
+        match &self.ccache {
+            Some(name) => CredentialCache::parse(name),
+            None => host_ccache(),
+        }
+    }
+
+    /// Variables passing the credential cache and the Kerberos settings of
+    /// the daemon, empty when disabled
+    pub fn environment(&self) -> Vec<(&'static str, String)> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let mut environment = vec![(CCACHE_VARIABLE, self.credential_cache().name)];
+        for variable in PASSTHROUGH {
+            if let Some(value) = std::env::var(variable).ok().filter(|value| !value.is_empty()) {
+                environment.push((*variable, value));
+            }
+        }
+        if let Some(config) = &self.config {
+            environment.retain(|(variable, _)| *variable != "KRB5_CONFIG");
+            environment.push(("KRB5_CONFIG", config.display().to_string()));
+        }
+        environment
+    }
+
+    /// Host paths the Steam Linux Runtime container needs to see for
+    /// Kerberos to work, empty when disabled
+    pub fn shared_paths(&self) -> Vec<PathBuf> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let mut paths: Vec<PathBuf> = self.credential_cache().shared_path().into_iter().collect();
+        paths.extend(self.config.clone());
+        paths
+    }
+}
+
+/// Kind of a credential cache, from the prefix of its name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CacheKind {
+    /// A single file, `FILE:<path>` or a bare path
+    File(PathBuf),
+    /// A directory of caches, `DIR:<path>` or `DIR::<path of a cache>`
+    Directory(PathBuf),
+    /// The KCM server, `KCM:`
+    Kcm,
+    /// The kernel keyring, `KEYRING:`
+    Keyring,
+    /// Caches that only live in a process, like `MEMORY:`
+    Other,
+}
+
+/// A credential cache, as named in `KRB5CCNAME`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialCache {
+    pub name: String,
+    pub kind: CacheKind,
+}
+
+impl CredentialCache {
+    /// Parse the name of a credential cache
+    pub fn parse(name: &str) -> Self {
+        let kind = match name.split_once(':') {
+            None => CacheKind::File(PathBuf::from(name)),
+            Some(("FILE", path)) => CacheKind::File(PathBuf::from(path)),
+            Some(("DIR", path)) => match path.strip_prefix(':') {
+                // A cache of the collection
+                Some(cache) => CacheKind::Directory(
+                    Path::new(cache).parent().map(Path::to_path_buf).unwrap_or_default(),
+                ),
+                None => CacheKind::Directory(PathBuf::from(path)),
+            },
+            Some(("KCM", _)) => CacheKind::Kcm,
+            Some(("KEYRING", _)) => CacheKind::Keyring,
+            Some(_) => CacheKind::Other,
+        };
+        Self {
+            name: name.to_string(),
+            kind,
+        }
+    }
+
+    /// Path to share for programs to reach the cache
+    ///
+    /// File caches are replaced when tickets are renewed, so their directory
+    /// is shared rather than the file.
+    pub fn shared_path(&self) -> Option<PathBuf> {
+        match &self.kind {
+            CacheKind::File(path) => path.parent().map(Path::to_path_buf),
+            CacheKind::Directory(path) => Some(path.clone()),
+            CacheKind::Kcm => Some(PathBuf::from(KCM_SOCKET)),
+            CacheKind::Keyring | CacheKind::Other => None,
+        }
+    }
+}
+
+/// The default credential cache of the host
+///
+/// `KRB5CCNAME` when set, then the `default_ccache_name` of krb5.conf, then
+/// libkrb5's built-in `FILE:/tmp/krb5cc_<uid>`.
+pub fn host_ccache() -> CredentialCache {
+    if let Some(name) = std::env::var(CCACHE_VARIABLE).ok().filter(|name| !name.is_empty()) {
+        return CredentialCache::parse(&name);
+    }
+    let config = std::env::var("KRB5_CONFIG")
+        .ok()
+        .and_then(|paths| paths.split(':').next().map(str::to_string))
+        .filter(|path| !path.is_empty())
+        .unwrap_or_else(|| DEFAULT_CONFIG.to_string());
+    let name = fs::read_to_string(config)
+        .ok()
+        .and_then(|content| default_ccache_name(&content))
+        .unwrap_or_else(|| "FILE:/tmp/krb5cc_%{uid}".to_string());
+    CredentialCache::parse(&expand(&name))
+}
+
+/// Check whether Kerberos sign-on can work in bottles with `options`
+pub fn diagnose(options: &KerberosOptions) -> Vec<Check> {
+    let mut checks = diagnostics::library_checks(
+        "kerberos.libkrb5",
+        "Kerberos library",
+        "libkrb5.so.3",
+        "krb5-libs or libkrb5-3",
+        Feature::Kerberos,
+    );
+    checks.push(ccache_check(&options.credential_cache()));
+    checks
+}
+
+fn ccache_check(cache: &CredentialCache) -> Check {
+    const ID: &str = "kerberos.ccache";
+    const TITLE: &str = "Credential cache";
+
+    let sandbox = flatpak::sandbox();
+    let hidden = || {
+        let path = cache.shared_path().unwrap_or_default();
+        let hint = match sandbox {
+            Some(sandbox) => format!(
+                "Log in with kinit, or allow access to the cache with: \
+                 flatpak override --user --filesystem={} {}",
+                path.display(),
+                sandbox.app_id.as_deref().unwrap_or("<application id>")
+            ),
+            None => "Log in with kinit".to_string(),
+        };
+        Check::new(
+            ID,
+            TITLE,
+            CheckStatus::Warning,
+            format!("{} holds no tickets", cache.name),
+        )
+        .hint(hint)
+        .affects(&[Feature::Kerberos])
+    };
+    match &cache.kind {
+        CacheKind::Keyring if sandbox.is_some() => Check::new(
+            ID,
+            TITLE,
+            CheckStatus::Error,
+            format!("{} is in the kernel keyring, which Flatpak blocks", cache.name),
+        )
+        .hint("Set a file credential cache for the bottle and log in to it with kinit -c")
+        .affects(&[Feature::Kerberos]),
+        CacheKind::File(path) | CacheKind::Directory(path) if !path.exists() => hidden(),
+        CacheKind::Kcm if !Path::new(KCM_SOCKET).exists() => hidden(),
+        CacheKind::Other => Check::new(
+            ID,
+            TITLE,
+            CheckStatus::Warning,
+            format!("{} can't be shared with programs", cache.name),
+        )
+        .hint("Set a file credential cache for the bottle")
+        .affects(&[Feature::Kerberos]),
+        _ => Check::new(ID, TITLE, CheckStatus::Ok, format!("using {}", cache.name)),
+    }
+}
+
+/// The `default_ccache_name` of the `[libdefaults]` section of a krb5.conf
+fn default_ccache_name(content: &str) -> Option<String> {
+    let mut section = "";
+    for line in content.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim();
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if section == "libdefaults" && key.trim() == "default_ccache_name" {
+            return Some(value.trim().to_string());
+        }
+    }
+    None
+}
+
+/// Expand the user and temporary directory tokens of a cache name
+fn expand(name: &str) -> String {
+    use std::os::unix::fs::MetadataExt;
+
+    // /proc/self belongs to the user running the process
+    let uid = fs::metadata("/proc/self").map(|metadata| metadata.uid()).unwrap_or_default();
+    let temp = std::env::var("TMPDIR").unwrap_or_else(|_| "/tmp".to_string());
+    name.replace("%{uid}", &uid.to_string())
+        .replace("%{euid}", &uid.to_string())
+        .replace("%{USERID}", &uid.to_string())
+        .replace("%{TEMP}", &temp)
+}
@@ -0,0 +1,50 @@
+use super::{find_in_path, prepend};
+use crate::flatpak;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Whether Feral GameMode can be used on this host
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GamemodeStatus {
+    /// The `gamemoderun` wrapper
+    pub wrapper: Option<PathBuf>,
+    /// `gamemoded -s` could reach the daemon
+    pub daemon: bool,
+}
+
+impl GamemodeStatus {
+    /// Whether enabling GameMode would have any effect
+    pub fn is_available(&self) -> bool {
+        self.wrapper.is_some() && self.daemon
+    }
+}
+
+/// Check whether GameMode is installed and its daemon is reachable
+pub fn probe() -> GamemodeStatus {
+    let daemon = find_in_path("gamemoded").is_some_and(|gamemoded| {
+        let mut command = Command::new(gamemoded);
+        command.arg("-s");
+        flatpak::adapt(command)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    });
+    GamemodeStatus {
+        wrapper: find_in_path("gamemoderun"),
+        daemon,
+    }
+}
+
+/// Run `command` through `gamemoderun`, if GameMode is available
+pub fn wrap(command: Command) -> Command {
+    let status = probe();
+    match status.wrapper {
+        Some(wrapper) if status.daemon => prepend(&command, wrapper, Vec::new()),
+        _ => {
+            tracing::warn!("GameMode is enabled but not available, launching without it");
+            command
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use super::upscaling::Upscaler;
+use super::{find_in_path, prepend};
+use crate::flatpak;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Strongest sharpness accepted by gamescope; lower values are sharper
+pub const MAX_SHARPNESS: u8 = 20;
+
+/// How the gamescope window is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum WindowMode {
+    #[default]
+    Windowed,
+    Fullscreen,
+    Borderless,
+}
+
+/// Settings of the gamescope session a program runs in
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct GamescopeOptions {
+    /// Size of the gamescope window, `(width, height)`
+    pub resolution: Option<(u32, u32)>,
+    /// Resolution the program renders at, upscaled to `resolution`
+    pub internal_resolution: Option<(u32, u32)>,
+    /// Filter used to upscale from `internal_resolution`, gamescope picks one
+    /// when unset
+    pub upscaler: Option<Upscaler>,
+    /// Sharpness of the FSR and NIS upscalers, from 0 (sharpest) to
+    /// [`MAX_SHARPNESS`]
+    pub sharpness: Option<u8>,
+    /// Frame rate limit
+    pub fps_limit: Option<u32>,
+    pub window_mode: WindowMode,
+    /// Enable HDR output, if the installed gamescope supports it
+    pub hdr: bool,
+}
+
+/// What the installed gamescope can do
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GamescopeCapabilities {
+    pub path: PathBuf,
+    /// Version reported by `gamescope --version`, if any
+    pub version: Option<String>,
+    pub hdr: bool,
+    /// Upscalers are selected with `--filter`; older releases have one flag per
+    /// upscaler instead
+    pub filter: bool,
+}
+
+/// Check whether gamescope is installed and which features it supports
+///
+/// Features are detected from the options listed in `gamescope --help`, since
+/// distributions ship builds with different feature sets.
+pub fn probe() -> Option<GamescopeCapabilities> {
+    let path = find_in_path("gamescope")?;
+    let output = |arg: &str| {
+        let mut command = Command::new(&path);
+        command.arg(arg);
+        flatpak::adapt(command).output().ok().map(|output| {
+            // Gamescope prints its help and version to stderr
+            let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+            text.push_str(&String::from_utf8_lossy(&output.stderr));
+            text
+        })
+    };
+
+    let version = output("--version").and_then(|text| {
+        let line = text.lines().find(|line| line.contains("gamescope"))?;
+        line.split_whitespace()
+            .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+            .map(str::to_string)
+    });
+    let help = output("--help").unwrap_or_default();
+    Some(GamescopeCapabilities {
+        hdr: help.contains("--hdr-enabled"),
+        filter: help.contains("--filter"),
+        path,
+        version,
+    })
+}
+
+/// Run `command` inside gamescope
+pub fn wrap(
+    command: Command,
+    options: &GamescopeOptions,
+    capabilities: &GamescopeCapabilities,
+) -> Command {
+    prepend(&command, &capabilities.path, arguments(options, capabilities))
+}
+
+/// Gamescope arguments for `options`, ending with the `--` separator
+fn arguments(options: &GamescopeOptions, capabilities: &GamescopeCapabilities) -> Vec<OsString> {
+    let mut args: Vec<String> = Vec::new();
+    if let Some((width, height)) = options.resolution {
+        args.extend(["-W".into(), width.to_string(), "-H".into(), height.to_string()]);
+    }
+    if let Some((width, height)) = options.internal_resolution {
+        args.extend(["-w".into(), width.to_string(), "-h".into(), height.to_string()]);
+    }
+    if let Some(upscaler) = options.upscaler {
+        if capabilities.filter {
+            let filter = match upscaler {
+                Upscaler::Linear => "linear",
+                Upscaler::Nearest => "nearest",
+                Upscaler::Fsr => "fsr",
+                Upscaler::Nis => "nis",
+            };
+            args.extend(["-F".into(), filter.into()]);
+        } else {
+            match upscaler {
+                Upscaler::Linear => {}
+                Upscaler::Nearest => args.push("-n".into()),
+                Upscaler::Fsr => args.push("-U".into()),
+                Upscaler::Nis => args.push("-Y".into()),
+            }
+        }
+    }
+    if let Some(sharpness) = options.sharpness {
+        // Older releases only know the FSR specific name, newer ones keep it
+        // as an alias
+        args.extend([
+            "--fsr-sharpness".into(),
+            sharpness.min(MAX_SHARPNESS).to_string(),
+        ]);
+    }
+    if let Some(fps) = options.fps_limit {
+        args.extend(["-r".into(), fps.to_string()]);
+    }
+    match options.window_mode {
+        WindowMode::Windowed => {}
+        WindowMode::Fullscreen => args.push("-f".into()),
+        WindowMode::Borderless => args.push("-b".into()),
+    }
+    if options.hdr {
+        if capabilities.hdr {
+            args.push("--hdr-enabled".into());
+        } else {
+            tracing::warn!("The installed gamescope does not support HDR, launching without it");
+        }
+    }
+    args.push("--".into());
+    args.into_iter().map(OsString::from).collect()
+}
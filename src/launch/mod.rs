@@ -0,0 +1,124 @@
+//! Wrappers applied around the runner command when launching programs
+//!
+//! Tools like gamescope are started in place of the program and run it
+//! themselves, overlays are injected into it (see [`overlays`]). [`wrap`]
+//! applies the wrappers enabled in a [`BottleConfig`] to
+//! a fully configured runner command; wrappers whose tool is not installed are
+//! skipped with a warning, so a bottle configured on another machine still
+//! launches. Proton itself can be run inside the Steam Linux Runtime, see
+//! [`steam_runtime`].
+//!
+//! Inside a Flatpak, wrappers only installed on the host are found there and
+//! run through `flatpak-spawn --host` (see [`crate::flatpak`]).
+
+pub mod gamemode;
+pub mod gamescope;
+pub mod overlays;
+pub mod rendering;
+pub mod steam_runtime;
+pub mod upscaling;
+
+use crate::bottle::BottleConfig;
+use crate::flatpak;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Variables of systemd's socket activation protocol, meant for the daemon
+/// only
+const LISTEN_VARIABLES: &[&str] = &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"];
+
+/// Apply the launch wrappers enabled in `config` to `command`
+///
+/// The variables of systemd's socket activation are removed from its
+/// environment, see [`isolate`].
+pub fn wrap(command: Command, config: &BottleConfig) -> Command {
+    // Overlays go inside gamescope, so they hook the program and not the compositor
+    let mut command = overlays::wrap(command, config);
+    if let Some(options) = &config.gamescope {
+        match gamescope::probe() {
+            Some(capabilities) => command = gamescope::wrap(command, options, &capabilities),
+            None => tracing::warn!("Gamescope is enabled but not installed, launching without it"),
+        }
+    }
+    // GameMode applies to the whole process tree, so it wraps everything else
+    if config.gamemode {
+        command = gamemode::wrap(command);
+    }
+    isolate(&mut command);
+    command
+}
+
+/// Keep the variables the daemon was socket activated with out of the
+/// environment of `command`, which runs a program in a bottle
+pub fn isolate(command: &mut Command) -> &mut Command {
+    for variable in LISTEN_VARIABLES {
+        command.env_remove(variable);
+    }
+    command
+}
+
+/// Re-run `command` through `program`, placing `args` before the original
+/// command line
+///
+/// The environment and working directory of the original command are kept, so
+/// the program sees the same environment. Stdio settings are not, so configure
+/// them on the wrapped command.
+///
+/// When `command` runs on the host through `flatpak-spawn`, `program` is run
+/// on the host as well, in place of the original program.
+pub fn prepend(command: &Command, program: impl Into<OsString>, args: Vec<OsString>) -> Command {
+    let program = program.into();
+    if flatpak::is_host_command(command) {
+        // flatpak-spawn --host [options] program args
+        let options: Vec<&OsStr> = command
+            .get_args()
+            .take_while(|arg| arg.to_string_lossy().starts_with("--"))
+            .collect();
+        let rest = command.get_args().skip(options.len());
+        let mut wrapped = Command::new(command.get_program());
+        wrapped
+            .args(&options)
+            .arg(flatpak::host_path(Path::new(&program)))
+            .args(args)
+            .args(rest);
+        if let Some(directory) = command.get_current_dir() {
+            wrapped.current_dir(directory);
+        }
+        return wrapped;
+    }
+
+    if flatpak::is_host_only(&program) {
+        tracing::warn!(
+            "'{}' is only available on the host, where '{}' may not exist",
+            program.to_string_lossy(),
+            command.get_program().to_string_lossy()
+        );
+    }
+    let mut wrapped = Command::new(program);
+    wrapped.args(args).arg(command.get_program()).args(command.get_args());
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => wrapped.env(key, value),
+            None => wrapped.env_remove(key),
+        };
+    }
+    if let Some(directory) = command.get_current_dir() {
+        wrapped.current_dir(directory);
+    }
+    flatpak::adapt(wrapped)
+}
+
+/// Locate an executable in `PATH`, or in the host's `PATH` when running
+/// inside a Flatpak
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    search_path(name).or_else(|| flatpak::find_on_host(name))
+}
+
+/// Locate an executable in the daemon's own `PATH`
+pub(crate) fn search_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|directory| directory.join(name))
+        .find(|candidate| candidate.is_file())
+}
@@ -0,0 +1,123 @@
+//! Performance overlays and capture layers injected into launched programs
+//!
+//! MangoHud and OBS's vkcapture both ship a Vulkan layer, enabled through an
+//! environment variable, and a wrapper script that additionally hooks OpenGL.
+//! The wrapper is preferred when installed; with only the layer present,
+//! Vulkan programs (including everything running on DXVK or VKD3D) are still
+//! covered. A layer installed in the bottle as a component (see
+//! [`crate::components`]) has already been enabled in the command's
+//! environment.
+
+use super::{find_in_path, prepend};
+use crate::bottle::BottleConfig;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// How an overlay can be enabled on this host
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OverlaySupport {
+    /// Wrapper executable, e.g. `mangohud`
+    pub wrapper: Option<PathBuf>,
+    /// Whether a Vulkan layer manifest was found
+    pub vulkan_layer: bool,
+}
+
+impl OverlaySupport {
+    pub fn is_available(&self) -> bool {
+        self.wrapper.is_some() || self.vulkan_layer
+    }
+}
+
+/// Availability of every supported overlay
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Overlays {
+    pub mangohud: OverlaySupport,
+    pub obs_vkcapture: OverlaySupport,
+}
+
+/// Check which overlays are installed
+pub fn probe() -> Overlays {
+    let layers = vulkan_layers();
+    let has_layer = |marker: &str| layers.iter().any(|layer| layer.contains(marker));
+    Overlays {
+        mangohud: OverlaySupport {
+            wrapper: find_in_path("mangohud"),
+            vulkan_layer: has_layer("mangohud"),
+        },
+        obs_vkcapture: OverlaySupport {
+            wrapper: find_in_path("obs-gamecapture"),
+            vulkan_layer: has_layer("obs_vkcapture"),
+        },
+    }
+}
+
+/// Apply the overlays enabled in `config` to `command`
+pub fn wrap(command: Command, config: &BottleConfig) -> Command {
+    if !config.mangohud && !config.obs_vkcapture {
+        return command;
+    }
+    let overlays = probe();
+    let mut command = command;
+
+    if config.obs_vkcapture {
+        command = enable(command, "OBS vkcapture", &overlays.obs_vkcapture, "OBS_VKCAPTURE");
+    }
+    if config.mangohud {
+        if let Some(path) = &config.mangohud_config {
+            command.env("MANGOHUD_CONFIGFILE", path);
+        }
+        command = enable(command, "MangoHud", &overlays.mangohud, "MANGOHUD");
+    }
+    command
+}
+
+fn enable(command: Command, name: &str, support: &OverlaySupport, variable: &str) -> Command {
+    let mut command = match &support.wrapper {
+        Some(wrapper) => prepend(&command, wrapper, Vec::new()),
+        None => command,
+    };
+    let enabled = command
+        .get_envs()
+        .any(|(key, value)| key == variable && value.is_some());
+    if support.is_available() {
+        command.env(variable, "1");
+    } else if enabled {
+        tracing::debug!("{} is enabled by a component of the bottle", name);
+    } else {
+        tracing::warn!("{} is enabled but not installed, launching without it", name);
+    }
+    command
+}
+
+/// File names of the implicit Vulkan layer manifests, lowercased
+fn vulkan_layers() -> Vec<String> {
+    let mut directories: Vec<PathBuf> = [
+        "/usr/share/vulkan/implicit_layer.d",
+        "/usr/local/share/vulkan/implicit_layer.d",
+        "/etc/vulkan/implicit_layer.d",
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+    if let Some(data_home) = data_home {
+        directories.push(data_home.join("vulkan/implicit_layer.d"));
+    }
+    // Flatpak ships layers as extensions of the runtime
+    if let Ok(extensions) = fs::read_dir("/usr/lib/extensions/vulkan") {
+        for extension in extensions.filter_map(|e| e.ok()) {
+            directories.push(extension.path().join("share/vulkan/implicit_layer.d"));
+        }
+    }
+
+    directories
+        .iter()
+        .filter_map(|directory| fs::read_dir(directory).ok())
+        .flat_map(|entries| entries.filter_map(|e| e.ok()))
+        .map(|entry| entry.file_name().to_string_lossy().to_ascii_lowercase())
+        .collect()
+}
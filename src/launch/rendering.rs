@@ -0,0 +1,117 @@
+//! Detection of programs silently falling back to software rendering
+//!
+//! When no usable GPU driver is found, DXVK and VKD3D-Proton still work on top
+//! of a CPU Vulkan implementation (llvmpipe), and Direct3D 12 may use WARP.
+//! The program starts but runs at a few frames per second, which users rarely
+//! connect to a driver problem. [`watch`] checks a session shortly after it
+//! starts and attaches a [`SessionWarning`] with remediation hints.
+
+use crate::gpu;
+use crate::logs::dxvk::{Layer, TranslationLog};
+use crate::session::{SessionWarning, Sessions};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Delays after the start of a session at which its output is checked
+///
+/// Translation layers pick their device when the program creates its first
+/// window, so the first check may come too early for slow starting programs.
+const CHECKS: &[Duration] = &[Duration::from_secs(10), Duration::from_secs(45)];
+
+/// Names of CPU renderers, as reported by drivers and Direct3D
+const SOFTWARE_RENDERERS: &[&str] = &[
+    "llvmpipe",
+    "lavapipe",
+    "softpipe",
+    "swiftshader",
+    "Microsoft Basic Render Driver",
+];
+
+/// Check a freshly started session for software rendering in the background
+///
+/// The session log is checked first; if it doesn't say which device was
+/// picked, the host's Vulkan devices are queried with `vulkaninfo` instead.
+pub fn watch(sessions: Arc<Sessions>, id: u64, log: PathBuf) {
+    std::thread::spawn(move || {
+        let mut elapsed = Duration::ZERO;
+        for &check in CHECKS {
+            std::thread::sleep(check - elapsed);
+            elapsed = check;
+            if sessions.get(id).is_none() {
+                return;
+            }
+
+            let content = fs::read_to_string(&log).unwrap_or_default();
+            let renderer = match software_renderer_in_log(&content) {
+                Some(renderer) => Some(renderer),
+                // The device is known, and it is not a software one
+                None if mentions_device(&content) => return,
+                None if check == CHECKS[CHECKS.len() - 1] => software_vulkan_only(),
+                None => continue,
+            };
+            if let Some(renderer) = renderer {
+                let warning = warning(&renderer);
+                tracing::warn!("Session {}: {}", id, warning.message);
+                sessions.warn(id, warning);
+            }
+            return;
+        }
+    });
+}
+
+/// The software renderer a program ended up with, according to its output
+pub fn software_renderer_in_log(content: &str) -> Option<String> {
+    let log = TranslationLog::parse(Layer::Dxvk, content);
+    if log.software_rendering() {
+        return log.adapters.first().map(|adapter| adapter.name.clone());
+    }
+    content
+        .lines()
+        .filter(|line| line.to_ascii_lowercase().contains("using"))
+        .find_map(|line| {
+            SOFTWARE_RENDERERS
+                .iter()
+                .find(|renderer| line.contains(**renderer))
+                .map(|renderer| renderer.to_string())
+        })
+}
+
+/// Whether the output says which device rendering happens on
+fn mentions_device(content: &str) -> bool {
+    !TranslationLog::parse(Layer::Dxvk, content).adapters.is_empty()
+}
+
+/// The CPU Vulkan device, if it is the only Vulkan device on the host
+///
+/// Returns `None` when a GPU is available or `vulkaninfo` is not installed.
+pub fn software_vulkan_only() -> Option<String> {
+    let devices = gpu::vulkan_devices()?;
+    if !devices.iter().all(|device| device.software) {
+        return None;
+    }
+    devices.into_iter().next().map(|device| device.name)
+}
+
+fn warning(renderer: &str) -> SessionWarning {
+    let mut hints = vec![
+        "Install the Vulkan driver for your GPU (Mesa's RADV or ANV, or the NVIDIA driver)"
+            .to_string(),
+        "Install the 32-bit variant of the driver as well, 32-bit programs need it".to_string(),
+        "Make sure VK_ICD_FILENAMES or VK_DRIVER_FILES is not set to a software driver".to_string(),
+    ];
+    if crate::flatpak::is_sandboxed() {
+        hints.push(
+            "Update the Flatpak runtime so its GL extensions match your host driver (flatpak update)"
+                .to_string(),
+        );
+    }
+    SessionWarning {
+        message: format!(
+            "The program is rendering on the CPU ({}), expect very low performance",
+            renderer
+        ),
+        hints,
+    }
+}
@@ -0,0 +1,125 @@
+//! Running Proton inside the Steam Linux Runtime
+//!
+//! Steam runs Proton inside a Steam Linux Runtime container (pressure-vessel),
+//! which provides the libraries Proton was built against regardless of the
+//! host distribution. Proton declares the runtime it needs in its
+//! `toolmanifest.vdf`. [`wrap`] starts the Proton command through the
+//! runtime's `_v2-entry-point` when a bottle asks for it, so programs run in
+//! the same environment as under Steam.
+
+use super::prepend;
+use crate::persistence::import::{default_steam_root, steam_libraries};
+use crate::runner::ProtonVersion;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Variable listing the host paths pressure-vessel shares with the container,
+/// besides the ones it shares by default
+const SHARED_PATHS: &str = "PRESSURE_VESSEL_FILESYSTEMS_RW";
+
+/// A Steam Linux Runtime container
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum SteamRuntime {
+    /// Steam Linux Runtime 3.0, used by Proton 8 and newer
+    Sniper,
+    /// Steam Linux Runtime 2.0, used by Proton 5.13 to 7
+    Soldier,
+}
+
+impl SteamRuntime {
+    /// Steam app id of the runtime, as used in `toolmanifest.vdf`
+    pub fn app_id(self) -> u32 {
+        match self {
+            Self::Sniper => 1628350,
+            Self::Soldier => 1391110,
+        }
+    }
+
+    /// Name of the runtime's directory in `steamapps/common`
+    pub fn directory_name(self) -> &'static str {
+        match self {
+            Self::Sniper => "SteamLinuxRuntime_sniper",
+            Self::Soldier => "SteamLinuxRuntime_soldier",
+        }
+    }
+
+    fn from_app_id(app_id: u32) -> Option<Self> {
+        [Self::Sniper, Self::Soldier]
+            .into_iter()
+            .find(|runtime| runtime.app_id() == app_id)
+    }
+}
+
+/// Whether a bottle runs Proton inside a Steam Linux Runtime
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum SteamRuntimeMode {
+    /// Run Proton directly on the host
+    #[default]
+    Off,
+    /// Use the runtime Proton asks for in its `toolmanifest.vdf`
+    Auto,
+    /// Use this runtime
+    Runtime(SteamRuntime),
+}
+
+/// The runtime `proton`'s `toolmanifest.vdf` asks for, if any
+pub fn required(proton: &Path) -> Option<SteamRuntime> {
+    SteamRuntime::from_app_id(ProtonVersion::read(proton).require_tool_appid?)
+}
+
+/// Find an installed runtime in the Steam libraries of the user
+pub fn locate(runtime: SteamRuntime) -> Option<PathBuf> {
+    let root = default_steam_root()?;
+    steam_libraries(&root)
+        .into_iter()
+        .map(|library| {
+            library
+                .join("steamapps")
+                .join("common")
+                .join(runtime.directory_name())
+        })
+        .find(|directory| directory.join("_v2-entry-point").is_file())
+}
+
+/// Run the Proton `command` of `proton` inside the runtime selected by `mode`
+///
+/// The command is returned unchanged when `mode` is [`SteamRuntimeMode::Off`],
+/// when `proton` is not a Proton build, or when the runtime is not installed,
+/// in which case a warning is logged.
+pub fn wrap(mut command: Command, mode: SteamRuntimeMode, proton: &Path) -> Command {
+    let runtime = match mode {
+        SteamRuntimeMode::Off => return command,
+        _ if !proton.join("proton").is_file() => {
+            tracing::warn!(
+                "'{}' is not a Proton build, running it without the Steam Runtime",
+                proton.display()
+            );
+            return command;
+        }
+        SteamRuntimeMode::Auto => match required(proton) {
+            Some(runtime) => runtime,
+            None => return command,
+        },
+        SteamRuntimeMode::Runtime(runtime) => runtime,
+    };
+    let Some(directory) = locate(runtime) else {
+        tracing::warn!("{} is not installed, running Proton without it", runtime.directory_name());
+        return command;
+    };
+
+    // pressure-vessel only shares the tools it is told about with the container
+    command.env("STEAM_COMPAT_TOOL_PATHS", proton);
+    let entry_point = directory.join("_v2-entry-point");
+    let args: Vec<OsString> = vec!["--verb=run".into(), "--".into()];
+    prepend(&command, entry_point, args)
+}
+
+/// The variable sharing `paths` with the container, `None` without paths
+pub(crate) fn shared_paths(paths: &[PathBuf]) -> Option<(&'static str, String)> {
+    let paths: Vec<String> = paths.iter().map(|path| path.display().to_string()).collect();
+    (!paths.is_empty()).then(|| (SHARED_PATHS, paths.join(":")))
+}
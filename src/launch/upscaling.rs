@@ -0,0 +1,65 @@
+//! Resolution scaling of fullscreen programs
+//!
+//! Two upscalers are supported: the FSR 1 implementation built into Wine-GE
+//! and Proton builds, enabled per bottle with [`FsrOptions`], and the filters
+//! of gamescope, configured through
+//! [`GamescopeOptions`](super::gamescope::GamescopeOptions). Wine's FSR only
+//! applies to programs that switch to a lower fullscreen resolution, while
+//! gamescope scales anything it runs.
+
+use serde::{Deserialize, Serialize};
+
+/// Strongest sharpening accepted by Wine; lower values are sharper
+pub const MAX_STRENGTH: u8 = 5;
+
+/// Settings of Wine's fullscreen FSR upscaling
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct FsrOptions {
+    /// Sharpening strength, from 0 (sharpest) to [`MAX_STRENGTH`]
+    pub strength: Option<u8>,
+    /// Extra fullscreen mode offered to programs, `(width, height)`, for
+    /// rendering at a resolution other than the predefined FSR ones
+    pub resolution: Option<(u32, u32)>,
+}
+
+impl FsrOptions {
+    /// Variables enabling FSR with these settings
+    pub fn environment(&self) -> Vec<(&'static str, String)> {
+        let mut environment = vec![("WINE_FULLSCREEN_FSR", "1".to_string())];
+        if let Some(strength) = self.strength {
+            if strength > MAX_STRENGTH {
+                tracing::warn!(
+                    "FSR strength {} is out of range, using {}",
+                    strength,
+                    MAX_STRENGTH
+                );
+            }
+            environment.push((
+                "WINE_FULLSCREEN_FSR_STRENGTH",
+                strength.min(MAX_STRENGTH).to_string(),
+            ));
+        }
+        if let Some((width, height)) = self.resolution {
+            environment.push((
+                "WINE_FULLSCREEN_FSR_CUSTOM_MODE",
+                format!("{}x{}", width, height),
+            ));
+        }
+        environment
+    }
+}
+
+/// Filter gamescope scales the program's output with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Upscaler {
+    Linear,
+    /// Nearest neighbour, for pixel art
+    Nearest,
+    /// AMD FidelityFX Super Resolution 1
+    Fsr,
+    /// NVIDIA Image Scaling
+    Nis,
+}
@@ -1,10 +1,103 @@
+//! Core library of Bottles: bottles, their runners and the daemon serving them
+//!
+//! # API tiers
+//!
+//! - [`prelude`] re-exports what most frontends need, to start with
+//!   `use bottles_core::prelude::*`.
+//! - Every other public module is stable: it only breaks in major releases.
+//! - Subsystems still iterating are only public with the `unstable` feature:
+//!   `lockfile`, `target` and `warmup`. They may break in any release, pin
+//!   an exact version of the crate when enabling it. So are the parts of the
+//!   stable API naming them, the `lockfile` of a
+//!   [`CreationReport`](manager::CreationReport) and
+//!   `Manager::queue_warm_up`. Without the feature the manager still uses
+//!   them internally, e.g. to record lockfiles.
+
+/// Declare modules that are only public with the `unstable` feature
+macro_rules! unstable {
+    ($($module:ident),* $(,)?) => {$(
+        #[cfg(feature = "unstable")]
+        pub mod $module;
+        #[cfg(not(feature = "unstable"))]
+        #[allow(dead_code)]
+        mod $module;
+    )*};
+}
+
 mod error;
+pub mod prelude;
 pub mod runner;
+pub mod archive;
+pub mod audio;
 pub mod bottle;
+pub mod catalog;
+pub mod components;
+pub mod debug;
+pub mod disk;
+pub mod drives;
+pub mod environment;
+pub mod events;
+pub mod extensions;
+pub mod flatpak;
+pub mod gpu;
+pub mod health;
+pub mod installers;
+pub mod jobs;
+pub mod integrity;
+pub mod integrations;
+pub mod kerberos;
+pub mod limits;
+pub mod lock;
+pub mod lnk;
+pub mod pe;
+pub mod peripherals;
 pub mod persistence;
+pub mod playtime;
+pub mod ports;
+pub mod prefix;
+pub mod programs;
+pub mod registry;
+mod relocate;
+pub mod saves;
+unstable!(lockfile);
+pub mod manifest;
+pub mod net;
+pub mod launch;
+pub mod logs;
+pub mod manager;
+pub mod export;
+pub mod service;
+pub mod session;
+pub mod shortcuts;
+pub mod smartcard;
+mod state;
+pub mod sync;
+pub mod system;
+unstable!(target);
+pub mod templates;
+pub mod thumbnail;
+pub mod transaction;
+pub mod vdf;
+pub mod version;
+unstable!(warmup);
+pub mod winebridge;
+pub mod winecfg;
+#[cfg(unix)]
+pub mod privileged;
+#[cfg(target_os = "linux")]
+pub mod resources;
+#[cfg(target_os = "linux")]
+pub mod systemd;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+#[cfg(feature = "schema")]
+pub mod schema;
 pub use error::Error;
 
 pub mod proto {
+    /// Descriptors of the protocol files, served through gRPC reflection
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("descriptors");
+
     pub mod bottles {
          tonic::include_proto!("bottles");
     }
@@ -0,0 +1,41 @@
+//! Bounds on the parsers of untrusted data
+//!
+//! Registry files, VDF documents, shortcuts and executables come from
+//! prefixes and downloads anyone may have written. Their parsers
+//! ([`crate::registry`], [`crate::vdf`], [`crate::lnk`], [`crate::pe`]) never
+//! panic on malformed input, and stop at the [`Limits`] they are given instead
+//! of allocating or recursing in proportion to what a crafted file claims.
+//! The default limits are far above what real files need.
+//!
+//! The parsers are fuzzed by the targets of the `fuzz` directory, run with
+//! `cargo fuzz run <target>`.
+
+/// What a parser may read and build from a single input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Size of the input, in bytes
+    pub max_size: usize,
+    /// Nesting of objects, for formats that nest
+    pub max_depth: usize,
+    /// Keys, values, entries or strings built from the input
+    pub max_items: usize,
+}
+
+impl Limits {
+    pub const DEFAULT: Self = Self {
+        max_size: 1024 * 1024 * 1024,
+        max_depth: 64,
+        max_items: 4 * 1024 * 1024,
+    };
+
+    /// Whether an input of `size` bytes may be parsed
+    pub fn allows_size(&self, size: usize) -> bool {
+        size <= self.max_size
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
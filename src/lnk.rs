@@ -0,0 +1,219 @@
+//! Reading Windows shortcuts (`.lnk`)
+//!
+//! Installers put shortcuts to what they install on the desktop and in the
+//! start menu of the prefix. [`Shortcut`] reads where one points to, with
+//! which arguments and icon, from the Shell Link format, without running
+//! anything. Paths are Windows paths, see [`crate::prefix::host_path`] to find
+//! them in a prefix.
+
+use crate::limits::Limits;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+const HEADER_SIZE: u32 = 0x4c;
+/// `00021401-0000-0000-C000-000000000046`, as stored
+const LINK_CLSID: [u8; 16] = [
+    0x01, 0x14, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46,
+];
+
+const HAS_TARGET_ID_LIST: u32 = 1 << 0;
+const HAS_LINK_INFO: u32 = 1 << 1;
+const HAS_NAME: u32 = 1 << 2;
+const HAS_RELATIVE_PATH: u32 = 1 << 3;
+const HAS_WORKING_DIR: u32 = 1 << 4;
+const HAS_ARGUMENTS: u32 = 1 << 5;
+const HAS_ICON_LOCATION: u32 = 1 << 6;
+const IS_UNICODE: u32 = 1 << 7;
+
+/// Link info flag of a target on a local volume
+const VOLUME_ID_AND_LOCAL_BASE_PATH: u32 = 1 << 0;
+/// Link info flag of a target on a network share
+const COMMON_NETWORK_RELATIVE_LINK: u32 = 1 << 1;
+
+/// How the window of the target is shown when started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShowCommand {
+    #[default]
+    Normal,
+    Maximized,
+    Minimized,
+}
+
+impl ShowCommand {
+    fn from_value(value: u32) -> Self {
+        match value {
+            3 => Self::Maximized,
+            7 => Self::Minimized,
+            _ => Self::Normal,
+        }
+    }
+}
+
+/// A parsed shortcut
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shortcut {
+    /// Windows path of the target, e.g. `C:\Games\Game\game.exe`, when the
+    /// shortcut records one
+    pub target: Option<String>,
+    /// Path of the target relative to the shortcut
+    pub relative_path: Option<String>,
+    pub working_directory: Option<String>,
+    pub arguments: Option<String>,
+    /// Comment of the shortcut, shown as its tooltip
+    pub description: Option<String>,
+    /// File holding the icon, the target's own icon if unset
+    pub icon_location: Option<String>,
+    /// Index of the icon in [`Shortcut::icon_location`]
+    pub icon_index: i32,
+    pub show_command: ShowCommand,
+}
+
+impl Shortcut {
+    /// Read and parse the shortcut at `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, exceeds the
+    /// [default limits](Limits) or is not a shortcut
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let invalid = |reason: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("'{}' {}", path.display(), reason),
+            )
+        };
+        let size = fs::metadata(path).map_err(Error::Io)?.len();
+        if !usize::try_from(size).is_ok_and(|size| Limits::DEFAULT.allows_size(size)) {
+            return Err(invalid("is too large to be read").into());
+        }
+        let data = fs::read(path).map_err(Error::Io)?;
+        Self::parse(&data).ok_or_else(|| invalid("is not a Windows shortcut").into())
+    }
+
+    /// Parse the contents of a shortcut, `None` if it isn't one
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        Self::parse_with_limits(data, &Limits::DEFAULT)
+    }
+
+    /// Parse the contents of a shortcut like [`Shortcut::parse`], within
+    /// `limits`
+    pub fn parse_with_limits(data: &[u8], limits: &Limits) -> Option<Self> {
+        if !limits.allows_size(data.len())
+            || u32_at(data, 0)? != HEADER_SIZE
+            || data.get(4..20)? != LINK_CLSID
+        {
+            return None;
+        }
+        let flags = u32_at(data, 20)?;
+        let mut shortcut = Self {
+            icon_index: u32_at(data, 56)? as i32,
+            show_command: ShowCommand::from_value(u32_at(data, 60)?),
+            ..Self::default()
+        };
+
+        let mut offset = HEADER_SIZE as usize;
+        if flags & HAS_TARGET_ID_LIST != 0 {
+            offset = offset.checked_add(2 + u16_at(data, offset)? as usize)?;
+        }
+        if flags & HAS_LINK_INFO != 0 {
+            let size = u32_at(data, offset)? as usize;
+            let info = data.get(offset..offset.checked_add(size)?)?;
+            shortcut.target = link_target(info);
+            offset += size;
+        }
+
+        let unicode = flags & IS_UNICODE != 0;
+        let fields = [
+            (HAS_NAME, &mut shortcut.description),
+            (HAS_RELATIVE_PATH, &mut shortcut.relative_path),
+            (HAS_WORKING_DIR, &mut shortcut.working_directory),
+            (HAS_ARGUMENTS, &mut shortcut.arguments),
+            (HAS_ICON_LOCATION, &mut shortcut.icon_location),
+        ];
+        for (flag, field) in fields {
+            if flags & flag == 0 {
+                continue;
+            }
+            let (value, next) = string_data(data, offset, unicode)?;
+            *field = Some(value).filter(|value| !value.is_empty());
+            offset = next;
+        }
+        Some(shortcut)
+    }
+}
+
+/// The path of the target recorded in the link info structure `info`
+fn link_target(info: &[u8]) -> Option<String> {
+    let header_size = u32_at(info, 4)?;
+    let flags = u32_at(info, 8)?;
+    let suffix = match (header_size >= 0x24).then(|| u32_at(info, 32)).flatten() {
+        Some(offset) if offset != 0 => unicode_string(info, offset as usize)?,
+        _ => ansi_string(info, u32_at(info, 24)? as usize)?,
+    };
+    let base = if flags & VOLUME_ID_AND_LOCAL_BASE_PATH != 0 {
+        match (header_size >= 0x24).then(|| u32_at(info, 28)).flatten() {
+            Some(offset) if offset != 0 => unicode_string(info, offset as usize)?,
+            _ => ansi_string(info, u32_at(info, 16)? as usize)?,
+        }
+    } else if flags & COMMON_NETWORK_RELATIVE_LINK != 0 {
+        let network = u32_at(info, 20)? as usize;
+        let name = u32_at(info, network.checked_add(8)?)? as usize;
+        ansi_string(info, network.checked_add(name)?)?
+    } else {
+        return None;
+    };
+    if suffix.is_empty() || base.ends_with('\\') {
+        Some(base + &suffix)
+    } else {
+        Some(format!("{}\\{}", base, suffix))
+    }
+}
+
+/// A counted string of the string data section at `offset`, and the offset
+/// following it
+fn string_data(data: &[u8], offset: usize, unicode: bool) -> Option<(String, usize)> {
+    let count = u16_at(data, offset)? as usize;
+    let start = offset + 2;
+    if unicode {
+        let end = start.checked_add(count * 2)?;
+        let units: Vec<u16> = data
+            .get(start..end)?
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect();
+        Some((String::from_utf16_lossy(&units), end))
+    } else {
+        let end = start.checked_add(count)?;
+        Some((String::from_utf8_lossy(data.get(start..end)?).into_owned(), end))
+    }
+}
+
+/// The NUL-terminated string at `offset`, in the system code page
+fn ansi_string(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..)?;
+    let end = bytes.iter().position(|byte| *byte == 0)?;
+    Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+/// The NUL-terminated UTF-16 string at `offset`
+fn unicode_string(data: &[u8], offset: usize) -> Option<String> {
+    let units: Vec<u16> = data
+        .get(offset..)?
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|unit| *unit != 0)
+        .collect();
+    Some(String::from_utf16_lossy(&units))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
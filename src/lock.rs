@@ -0,0 +1,129 @@
+//! Keeping operations on a bottle from racing
+//!
+//! Installing a component while a program runs, or deleting a prefix while
+//! it is initialized, leaves the prefix broken. Operations lock the bottle
+//! first, see [`Manager::lock_bottle`]: programs and reads of the prefix
+//! share the lock, changes to the prefix hold it exclusively. Locks are
+//! `flock(2)` locks on files of [`Manager::locks_path`], so managers of
+//! different processes sharing a directory are kept apart too, and a lock is
+//! released when its holder exits, even on a crash.
+//!
+//! Locks are taken without waiting: one held by another operation fails with
+//! [`Error::BottleBusy`]. An operation holding a bottle exclusively may run
+//! others on it from the same thread, e.g. the creation of a bottle
+//! installing its components. A shared lock can't be made exclusive.
+//!
+//! [`Manager::lock_bottle`]: crate::manager::Manager::lock_bottle
+//! [`Manager::locks_path`]: crate::manager::Manager::locks_path
+
+use crate::Error;
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+thread_local! {
+    /// Lock files held exclusively by the current thread
+    static EXCLUSIVE: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+/// How a bottle is locked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Running programs or reading the prefix, alongside other holders
+    Shared,
+    /// Changing the prefix, alone
+    Exclusive,
+}
+
+/// A lock on a bottle, released when dropped
+///
+/// An exclusive lock has to be dropped by the thread that took it.
+#[derive(Debug)]
+pub struct BottleLock {
+    mode: LockMode,
+    path: PathBuf,
+    /// `None` when the thread already held the bottle exclusively
+    file: Option<File>,
+}
+
+impl BottleLock {
+    /// Lock the bottle `bottle` through its lock file `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::BottleBusy`] if another operation holds the bottle
+    /// in a conflicting mode, or an error if `path` can't be opened
+    pub fn acquire(path: &Path, bottle: &str, mode: LockMode) -> Result<Self, Error> {
+        let path = path.to_path_buf();
+        if EXCLUSIVE.with(|held| held.borrow().contains(&path)) {
+            return Ok(Self {
+                mode,
+                path,
+                file: None,
+            });
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(Error::Io)?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(Error::Io)?;
+        let operation = match mode {
+            LockMode::Shared => libc::LOCK_SH,
+            LockMode::Exclusive => libc::LOCK_EX,
+        };
+        if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } != 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::WouldBlock {
+                return Err(Error::BottleBusy(bottle.to_string()));
+            }
+            return Err(Error::Io(e));
+        }
+        if mode == LockMode::Exclusive {
+            EXCLUSIVE.with(|held| held.borrow_mut().push(path.clone()));
+        }
+        tracing::debug!("Locked '{}' ({:?})", bottle, mode);
+        Ok(Self {
+            mode,
+            path,
+            file: Some(file),
+        })
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// Whether the lock was taken by a thread already holding the bottle
+    /// exclusively
+    pub fn is_nested(&self) -> bool {
+        self.file.is_none()
+    }
+
+    /// Whether an operation of another thread or process holds the bottle
+    /// of the lock file `path` exclusively
+    pub fn is_held(path: &Path) -> bool {
+        if EXCLUSIVE.with(|held| held.borrow().iter().any(|held| held == path)) {
+            return false;
+        }
+        let Ok(file) = File::open(path) else {
+            return false;
+        };
+        // The probing lock is released as the file is closed
+        let locked = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) };
+        locked != 0 && std::io::Error::last_os_error().kind() == std::io::ErrorKind::WouldBlock
+    }
+}
+
+impl Drop for BottleLock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock itself
+        if self.file.is_some() && self.mode == LockMode::Exclusive {
+            let _ = EXCLUSIVE.try_with(|held| held.borrow_mut().retain(|path| path != &self.path));
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Reproducible bottle builds
+//!
+//! A [strict](crate::manifest::BottleManifest::strict) manifest pins the
+//! SHA-256 of the runner and of every component it installs, and creating a
+//! bottle from it fails on any other artifact. The bottle then records a
+//! [`Lockfile`] of what it was built from, in its prefix. QA teams shipping
+//! preconfigured bottles keep the lockfile, rebuild with
+//! [`Lockfile::pin`] and check any bottle against it with
+//! [`Lockfile::verify`].
+//!
+//! Artifacts are pinned by [`artifact_sha256`], a digest of every file of
+//! their directory and of their relative paths. Runners outside
+//! [`Manager::runners_path`], like the system's Wine, are pinned by their
+//! executable only.
+
+use crate::bottle::{Bottle, BottleConfig, BottleType};
+use crate::components::{ComponentKind, InstalledComponents};
+use crate::manager::Manager;
+use crate::manifest::{BottleManifest, ComponentPin, VerificationReport};
+use crate::net;
+use crate::runner::Runner;
+use crate::templates::LATEST;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Version of the lockfile format
+pub const LOCKFILE_VERSION: u32 = 1;
+
+/// Name of the lockfile in the prefix of a bottle
+pub const LOCKFILE_NAME: &str = "bottle.lock";
+
+/// A runner or component a bottle was built from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LockedRunner {
+    pub name: String,
+    /// See [`artifact_sha256`]
+    pub sha256: String,
+}
+
+/// A component installed in a bottle, as locked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LockedComponent {
+    pub kind: ComponentKind,
+    pub version: String,
+    /// See [`artifact_sha256`]
+    pub sha256: String,
+}
+
+/// Everything a bottle was built from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Lockfile {
+    pub version: u32,
+    pub bottle: String,
+    pub kind: BottleType,
+    pub runner: LockedRunner,
+    pub components: Vec<LockedComponent>,
+    /// Settings of the bottle once created
+    pub config: BottleConfig,
+}
+
+impl Lockfile {
+    /// The JSON schema of lockfiles, see [`crate::schema`]
+    #[cfg(feature = "schema")]
+    pub fn schema() -> serde_json::Value {
+        crate::schema::schema(crate::schema::Document::Lockfile)
+    }
+
+    /// Lock what `bottle` is built from right now
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bottle has no runner, or if its runner or
+    /// components can't be read
+    pub fn capture(manager: &Manager, bottle: &Bottle) -> Result<Self, Error> {
+        let runner = manager.runner_for(bottle)?;
+        let components = InstalledComponents::load(&bottle.path)?
+            .components
+            .into_iter()
+            .map(|component| {
+                let directory = component_path(manager, component.kind, &component.version);
+                Ok(LockedComponent {
+                    kind: component.kind,
+                    sha256: artifact_sha256(&directory)?,
+                    version: component.version,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self {
+            version: LOCKFILE_VERSION,
+            bottle: bottle.name.clone(),
+            kind: bottle.kind.clone(),
+            runner: LockedRunner {
+                name: runner.info().name().to_string(),
+                sha256: runner_sha256(manager, runner.as_ref())?,
+            },
+            components,
+            config: bottle.config.clone(),
+        })
+    }
+
+    /// Load a lockfile, e.g. [`Lockfile::path`] of a bottle
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path).map_err(Error::Io)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let temporary = path.with_extension("lock.new");
+        fs::write(&temporary, serde_json::to_string_pretty(self)?).map_err(Error::Io)?;
+        fs::rename(&temporary, path).map_err(Error::Io)
+    }
+
+    /// Where the lockfile of the bottle at `prefix` is recorded
+    pub fn path(prefix: &Path) -> PathBuf {
+        prefix.join(LOCKFILE_NAME)
+    }
+
+    /// Make `manifest` strict and pin it to the artifacts of the lockfile,
+    /// to build the same bottle again
+    ///
+    /// The runner to create the bottle with is still chosen by the caller,
+    /// creation fails unless it is [`Lockfile::runner`] as locked.
+    pub fn pin(&self, manifest: &mut BottleManifest) {
+        manifest.strict = true;
+        manifest.runner_sha256 = Some(self.runner.sha256.clone());
+        manifest.components = self
+            .components
+            .iter()
+            .map(|component| ComponentPin {
+                kind: component.kind,
+                version: component.version.clone(),
+                sha256: Some(component.sha256.clone()),
+            })
+            .collect();
+    }
+
+    /// Check that the bottle `bottle_name` is built from this lockfile
+    ///
+    /// Every difference is a failed check of the report: another runner or
+    /// component, a component missing or added, other settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bottle doesn't exist or can't be locked, see
+    /// [`Lockfile::capture`]
+    pub fn verify(
+        &self,
+        manager: &Manager,
+        bottle_name: &str,
+    ) -> Result<VerificationReport, Error> {
+        let bottle = manager.get_bottle(bottle_name)?;
+        let current = Self::capture(manager, &bottle)?;
+        let mut report = VerificationReport::default();
+
+        let detail = |locked: &str, found: &str| format!("locked {}, found {}", locked, found);
+        let runner = if current.runner.name != self.runner.name {
+            Some(detail(&self.runner.name, &current.runner.name))
+        } else if current.runner.sha256 != self.runner.sha256 {
+            Some(detail(&self.runner.sha256, &current.runner.sha256))
+        } else {
+            None
+        };
+        report.push("lock:runner".to_string(), runner.is_none(), runner);
+
+        for locked in &self.components {
+            let found = current.components.iter().find(|found| found.kind == locked.kind);
+            let failure = match found {
+                None => Some("not installed".to_string()),
+                Some(found) if found.version != locked.version => {
+                    Some(detail(&locked.version, &found.version))
+                }
+                Some(found) if found.sha256 != locked.sha256 => {
+                    Some(detail(&locked.sha256, &found.sha256))
+                }
+                Some(_) => None,
+            };
+            let name = format!("lock:component:{}", locked.kind.id());
+            report.push(name, failure.is_none(), failure);
+        }
+        for found in &current.components {
+            if !self.components.iter().any(|locked| locked.kind == found.kind) {
+                let name = format!("lock:component:{}", found.kind.id());
+                let failure = format!("{} is installed but not locked", found.version);
+                report.push(name, false, Some(failure));
+            }
+        }
+
+        let config = serde_json::to_value(&self.config)? == serde_json::to_value(&current.config)?;
+        let failure = (!config).then(|| "the settings changed".to_string());
+        report.push("lock:config".to_string(), config, failure);
+
+        Ok(report)
+    }
+}
+
+/// Digest of the artifact at `path`, a file or a directory
+///
+/// The digest of a directory covers the relative path and content of each of
+/// its files, and the target of each symbolic link, in a fixed order, so it
+/// only depends on what the directory holds.
+///
+/// # Errors
+///
+/// Returns an error if the artifact can't be read
+pub fn artifact_sha256(path: &Path) -> Result<String, Error> {
+    if !path.is_dir() {
+        return net::sha256(path);
+    }
+    let mut hasher = Sha256::new();
+    digest_directory(path, Path::new(""), &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Check that a strict manifest pins the runner and every component
+///
+/// # Errors
+///
+/// Returns an error naming the first artifact left unpinned
+pub(crate) fn check_pinned(manifest: &BottleManifest) -> Result<(), Error> {
+    if !manifest.strict {
+        return Ok(());
+    }
+    let unpinned = |what: String| {
+        Error::from(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("a strict manifest must pin {}", what),
+        ))
+    };
+    if manifest.runner_sha256.is_none() {
+        return Err(unpinned("the digest of the runner".to_string()));
+    }
+    for component in &manifest.components {
+        if component.version == LATEST {
+            return Err(unpinned(format!("the version of {}", component.kind)));
+        }
+        if component.sha256.is_none() {
+            return Err(unpinned(format!("the digest of {}", component.kind)));
+        }
+    }
+    Ok(())
+}
+
+/// Check the digest `found` of the artifact `what` against the pinned one
+///
+/// # Errors
+///
+/// Returns [`Error::Integrity`] if the digests differ
+pub(crate) fn check_digest(what: &str, found: &str, pinned: &str) -> Result<(), Error> {
+    if !found.eq_ignore_ascii_case(pinned) {
+        return Err(Error::Integrity(format!(
+            "{} is pinned to {}, found {}",
+            what, pinned, found
+        )));
+    }
+    Ok(())
+}
+
+/// Digest of the runner, see the [module documentation](self)
+pub(crate) fn runner_sha256(manager: &Manager, runner: &dyn Runner) -> Result<String, Error> {
+    let info = runner.info();
+    if info.directory().starts_with(manager.runners_path()) {
+        artifact_sha256(info.directory())
+    } else {
+        artifact_sha256(&info.executable_path())
+    }
+}
+
+/// Where the version `version` of the component `kind` is installed
+pub(crate) fn component_path(manager: &Manager, kind: ComponentKind, version: &str) -> PathBuf {
+    manager.components_path().join(kind.id()).join(version)
+}
+
+fn digest_directory(root: &Path, relative: &Path, hasher: &mut Sha256) -> Result<(), Error> {
+    let mut entries = fs::read_dir(root.join(relative))
+        .map_err(Error::Io)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::Io)?;
+    entries.sort();
+    for name in entries {
+        let relative = relative.join(name);
+        let path = root.join(&relative);
+        let metadata = path.symlink_metadata().map_err(Error::Io)?;
+        let key = relative.to_string_lossy();
+        if metadata.is_symlink() {
+            let target = fs::read_link(&path).map_err(Error::Io)?;
+            hasher.update(format!("link {} {}\n", key, target.to_string_lossy()));
+        } else if metadata.is_dir() {
+            digest_directory(root, &relative, hasher)?;
+        } else {
+            hasher.update(format!("file {} {}\n", key, net::sha256(&path)?));
+        }
+    }
+    Ok(())
+}
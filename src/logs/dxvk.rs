@@ -0,0 +1,280 @@
+//! Structured data from DXVK and VKD3D-Proton logs
+//!
+//! Both layers log which Vulkan devices they found and which Direct3D feature
+//! level they ended up with. That is usually enough to explain a slow or
+//! broken game: a software Vulkan driver (llvmpipe) being picked, a driver too
+//! old for the required extensions, or a feature level below what the game
+//! needs.
+
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Vulkan drivers that render on the CPU
+const SOFTWARE_DRIVERS: &[&str] = &["llvmpipe", "lavapipe", "swiftshader", "softpipe"];
+
+/// Endings of the log file names DXVK uses, one per API
+const LOG_SUFFIXES: &[&str] = &[
+    "_d3d8.log",
+    "_d3d9.log",
+    "_d3d10core.log",
+    "_d3d11.log",
+    "_dxgi.log",
+    "_d3d12.log",
+];
+
+/// Translation layer that wrote a log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Layer {
+    Dxvk,
+    Vkd3d,
+}
+
+/// A Vulkan device listed in a log
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Adapter {
+    pub name: String,
+    pub driver: Option<String>,
+    pub vulkan: Option<String>,
+}
+
+impl Adapter {
+    /// Whether the device renders on the CPU
+    pub fn is_software(&self) -> bool {
+        let name = self.name.to_ascii_lowercase();
+        SOFTWARE_DRIVERS.iter().any(|driver| name.contains(driver))
+    }
+}
+
+/// What a DXVK or VKD3D-Proton log says about the rendering setup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranslationLog {
+    pub layer: Layer,
+    pub version: Option<String>,
+    /// Executable the log was written for
+    pub game: Option<String>,
+    /// Devices found, in the order they were listed
+    pub adapters: Vec<Adapter>,
+    /// Highest feature level the device supports, e.g. `D3D_FEATURE_LEVEL_12_1`
+    pub max_feature_level: Option<String>,
+    /// Feature level the device was created with
+    pub feature_level: Option<String>,
+    /// Vulkan extensions reported as required but unsupported
+    pub missing_extensions: Vec<String>,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+impl TranslationLog {
+    /// Parse the content of a log written by `layer`
+    pub fn parse(layer: Layer, content: &str) -> Self {
+        let mut log = Self {
+            layer,
+            version: None,
+            game: None,
+            adapters: Vec::new(),
+            max_feature_level: None,
+            feature_level: None,
+            missing_extensions: Vec::new(),
+            warnings: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        for raw in content.lines() {
+            let Some((class, message)) = split_class(raw) else {
+                continue;
+            };
+            let trimmed = message.trim();
+
+            // DXVK lists device properties indented below the device name
+            if message.starts_with("    ") || message.starts_with('\t') {
+                if let (Some(adapter), Some((key, value))) =
+                    (log.adapters.last_mut(), trimmed.split_once(':'))
+                {
+                    match key.trim() {
+                        "Driver" => adapter.driver = Some(value.trim().to_string()),
+                        "Vulkan" => adapter.vulkan = Some(value.trim().to_string()),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+
+            if let Some(version) = trimmed
+                .strip_prefix("DXVK: ")
+                .or_else(|| trimmed.strip_prefix("vkd3d-proton - "))
+            {
+                log.version.get_or_insert_with(|| {
+                    version.split_whitespace().next().unwrap_or(version).to_string()
+                });
+            } else if let Some(game) = trimmed.strip_prefix("Game: ") {
+                log.game = Some(game.to_string());
+            } else if let Some(level) = feature_level(trimmed, "Maximum supported feature level") {
+                log.max_feature_level = Some(level);
+            } else if let Some(level) = feature_level(trimmed, "Using feature level") {
+                log.feature_level = Some(level);
+            } else if class == "info" && trimmed.ends_with(':') && is_device_header(trimmed) {
+                log.adapters.push(Adapter {
+                    name: trimmed.trim_end_matches(':').to_string(),
+                    ..Adapter::default()
+                });
+            }
+
+            if let Some(extension) = missing_extension(trimmed) {
+                if !log.missing_extensions.contains(&extension) {
+                    log.missing_extensions.push(extension);
+                }
+            }
+            match class {
+                "warn" => log.warnings.push(trimmed.to_string()),
+                "err" => log.errors.push(trimmed.to_string()),
+                _ => {}
+            }
+        }
+        log
+    }
+
+    /// Parse a log file, guessing the layer from its name
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path).map_err(Error::Io)?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let layer = if name.contains("vkd3d") || name.ends_with("_d3d12.log") {
+            Layer::Vkd3d
+        } else {
+            Layer::Dxvk
+        };
+        Ok(Self::parse(layer, &content))
+    }
+
+    /// Whether only CPU renderers were available
+    pub fn software_rendering(&self) -> bool {
+        !self.adapters.is_empty() && self.adapters.iter().all(Adapter::is_software)
+    }
+
+    /// Human-readable explanations of the problems found in the log
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.software_rendering() {
+            problems.push(format!(
+                "Only a software Vulkan driver ({}) was found, so rendering runs on the CPU. \
+                 Install the Vulkan driver for your GPU (and its 32-bit variant for 32-bit games).",
+                self.adapters[0].name
+            ));
+        } else if self.adapters.is_empty() && !self.errors.is_empty() {
+            problems.push("No Vulkan device could be used".to_string());
+        }
+        if !self.missing_extensions.is_empty() {
+            problems.push(format!(
+                "The Vulkan driver lacks required extensions: {}. Updating the driver usually fixes this.",
+                self.missing_extensions.join(", ")
+            ));
+        }
+        if let Some(max) = &self.max_feature_level {
+            if level_number(max).is_some_and(|level| level < (11, 0)) {
+                problems.push(format!(
+                    "The device only supports {}, most games need at least D3D_FEATURE_LEVEL_11_0",
+                    max
+                ));
+            }
+        }
+        problems
+    }
+}
+
+/// Find the DXVK and VKD3D-Proton logs in a directory
+///
+/// DXVK writes `<executable>_<api>.log` next to the executable, or into
+/// `DXVK_LOG_PATH` if set.
+pub fn find_logs(directory: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut logs: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.to_ascii_lowercase())
+                .is_some_and(|name| {
+                    LOG_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+                        || (name.contains("vkd3d") && name.ends_with(".log"))
+                })
+        })
+        .collect();
+    logs.sort();
+    logs
+}
+
+/// Split `info:  message` (DXVK) or `info:vkd3d-proton:function: message`
+/// (VKD3D-Proton) into the class and the message
+fn split_class(line: &str) -> Option<(&str, &str)> {
+    let (class, rest) = line.split_once(':')?;
+    if !matches!(class, "trace" | "debug" | "info" | "warn" | "err" | "fixme") {
+        return None;
+    }
+    let message = match rest.strip_prefix("vkd3d-proton:") {
+        Some(rest) => rest.split_once(':').map(|(_, message)| message).unwrap_or(rest),
+        // DXVK pads the class to a fixed width with spaces
+        None => rest.strip_prefix("  ").or_else(|| rest.strip_prefix(' ')).unwrap_or(rest),
+    };
+    Some((class, message))
+}
+
+fn feature_level(message: &str, marker: &str) -> Option<String> {
+    if !message.contains(marker) {
+        return None;
+    }
+    message
+        .split_whitespace()
+        .find(|word| word.starts_with("D3D_FEATURE_LEVEL_"))
+        .map(|word| word.trim_end_matches([',', '.']).to_string())
+}
+
+/// `D3D_FEATURE_LEVEL_11_1` → `(11, 1)`
+fn level_number(level: &str) -> Option<(u32, u32)> {
+    let (major, minor) = level.strip_prefix("D3D_FEATURE_LEVEL_")?.split_once('_')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Device headers are the only `name:` lines that aren't section titles
+fn is_device_header(message: &str) -> bool {
+    let lowered = message.to_ascii_lowercase();
+    !["extension", "providers", "features", "memory", "heap", "options", "config"]
+        .iter()
+        .any(|word| lowered.contains(word))
+}
+
+fn missing_extension(message: &str) -> Option<String> {
+    let lowered = message.to_ascii_lowercase();
+    let missing = ["not supported", "unsupported", "missing", "required"]
+        .iter()
+        .any(|marker| lowered.contains(marker));
+    if !missing || !lowered.contains("extension") {
+        return None;
+    }
+    message
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .find(|word| word.starts_with("VK_"))
+        .map(str::to_string)
+}
+
+/// Parse every log found by [`find_logs`] in `directory`
+///
+/// Logs that cannot be read are skipped with a warning.
+pub fn collect(directory: &Path) -> Vec<(PathBuf, TranslationLog)> {
+    find_logs(directory)
+        .into_iter()
+        .filter_map(|path| match TranslationLog::from_file(&path) {
+            Ok(log) => Some((path, log)),
+            Err(e) => {
+                tracing::warn!("Skipping '{}': {}", path.display(), e);
+                None
+            }
+        })
+        .collect()
+}
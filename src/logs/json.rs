@@ -0,0 +1,48 @@
+//! JSON logs of the daemon
+//!
+//! The crate reports what it does through `tracing`, with spans around
+//! bottle creations, prefix initializations, program launches, downloads and
+//! index operations. Their fields say which bottle, runner, session and
+//! process (`pid`) an event is about. [`layer`] writes every event as one JSON
+//! object per line, with the fields of the spans it happened in, so log
+//! collectors can filter the logs of the daemon by bottle or process.
+//!
+//! Daemons opt in by calling [`init`], or by adding [`layer`] to their own
+//! subscriber.
+
+use crate::Error;
+use tracing::Subscriber;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Filter used when `RUST_LOG` is not set
+const DEFAULT_FILTER: &str = "info";
+
+/// A layer writing events as JSON lines to standard error
+pub fn layer<S>() -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    tracing_subscriber::fmt::layer()
+        .json()
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_writer(std::io::stderr)
+}
+
+/// Install a global subscriber writing JSON lines, filtered by `RUST_LOG`
+///
+/// # Errors
+///
+/// Returns an error if a global subscriber is already installed
+pub fn init() -> Result<(), Error> {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer())
+        .try_init()
+        .map_err(|e| std::io::Error::other(e.to_string()).into())
+}
@@ -0,0 +1,206 @@
+//! Parsing and following the output of launched programs
+//!
+//! The output of every session is written to a log file (see
+//! [`Manager::session_log_path`]). [`tail`] follows such a file and yields
+//! parsed [`LogLine`]s, so the CLI and frontend consoles can show live output
+//! filtered by source and level. The log files DXVK and VKD3D-Proton write
+//! themselves are parsed by [`dxvk`].
+//!
+//! The logs of the daemon itself are written to machine-parsable JSON by
+//! [`json`], with the `json-logs` feature.
+
+pub mod dxvk;
+#[cfg(feature = "json-logs")]
+pub mod json;
+
+use crate::manager::Manager;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// How often a log that stopped growing is checked for new output
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Lines buffered between the reader task and the consumer
+const BUFFER: usize = 256;
+
+/// Component that produced a log line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Source {
+    Wine,
+    Dxvk,
+    Proton,
+    Hooks,
+    /// Output of the program itself, or anything not recognized
+    Program,
+}
+
+/// Severity of a log line, from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// A parsed line of program output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogLine {
+    pub source: Source,
+    pub level: Level,
+    /// The line without its source and level prefix
+    pub message: String,
+    /// The line as written by the program
+    pub raw: String,
+}
+
+/// Which lines [`tail`] yields
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogFilter {
+    /// Skip lines less severe than this
+    pub min_level: Option<Level>,
+    /// Only yield lines from these sources; all sources if empty
+    pub sources: Vec<Source>,
+    /// Only yield lines containing this text
+    pub contains: Option<String>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, line: &LogLine) -> bool {
+        self.min_level.is_none_or(|level| line.level >= level)
+            && (self.sources.is_empty() || self.sources.contains(&line.source))
+            && self
+                .contains
+                .as_deref()
+                .is_none_or(|text| line.raw.contains(text))
+    }
+}
+
+/// Parse a line of program output
+///
+/// Recognizes Wine's debug channel format (`0024:err:module:...`), the
+/// `level:  message` format used by DXVK and VKD3D-Proton, lines printed by
+/// the Proton script and lines prefixed with `hook:` by launch hooks.
+pub fn parse_line(raw: &str) -> LogLine {
+    let raw = raw.trim_end_matches(['\r', '\n']);
+    let line = |source, level, message: &str| LogLine {
+        source,
+        level,
+        message: message.trim().to_string(),
+        raw: raw.to_string(),
+    };
+
+    if let Some(message) = raw.strip_prefix("hook:") {
+        return line(Source::Hooks, Level::Info, message);
+    }
+    if raw.starts_with("Proton:") || raw.starts_with("ProtonFixes") {
+        return line(Source::Proton, Level::Info, raw);
+    }
+
+    // Wine: an optional hexadecimal thread id, then the debug class
+    let wine = match raw.split_once(':') {
+        Some((tid, rest)) if !tid.is_empty() && tid.chars().all(|c| c.is_ascii_hexdigit()) => rest,
+        _ => raw,
+    };
+    if let Some((class, rest)) = wine.split_once(':') {
+        let level = match class {
+            "err" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "fixme" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        };
+        // DXVK's prefixes are followed by spaces, Wine's by the channel name
+        if let Some(level) = level.filter(|_| !rest.starts_with(' ')) {
+            return line(Source::Wine, level, rest);
+        }
+    }
+
+    if let Some((class, message)) = raw.split_once(':') {
+        if message.starts_with(' ') {
+            let level = match class {
+                "err" => Some(Level::Error),
+                "warn" => Some(Level::Warn),
+                "info" => Some(Level::Info),
+                "debug" => Some(Level::Debug),
+                "trace" => Some(Level::Trace),
+                _ => None,
+            };
+            if let Some(level) = level {
+                return line(Source::Dxvk, level, message);
+            }
+        }
+    }
+
+    line(Source::Program, Level::Info, raw)
+}
+
+/// Follow the output of a session
+///
+/// Yields the lines already in the log, then new lines as they are written.
+/// The stream ends once the session is no longer running and the log has been
+/// read to the end, or when it is dropped.
+///
+/// # Errors
+///
+/// Returns [`Error::SessionNotFound`] if the session never wrote a log
+pub async fn tail(
+    manager: Arc<Manager>,
+    bottle: &str,
+    session: u64,
+    filter: LogFilter,
+) -> Result<ReceiverStream<LogLine>, Error> {
+    let path = manager.session_log_path(bottle, session);
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::SessionNotFound(session))
+        }
+        Err(e) => return Err(Error::Io(e)),
+    };
+
+    let (sender, receiver) = mpsc::channel(BUFFER);
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(file);
+        let mut pending = String::new();
+        loop {
+            match reader.read_line(&mut pending).await {
+                Ok(0) => {
+                    let running = manager.sessions().iter().any(|s| s.id == session);
+                    if !running || sender.is_closed() {
+                        break;
+                    }
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+                // A line without its newline is still being written
+                Ok(_) if !pending.ends_with('\n') => continue,
+                Ok(_) => {
+                    let line = parse_line(&pending);
+                    pending.clear();
+                    if filter.matches(&line) && sender.send(line).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to read the log of session {}: {}", session, e);
+                    break;
+                }
+            }
+        }
+        // Output flushed right before the process exited
+        if !pending.is_empty() {
+            let line = parse_line(&pending);
+            if filter.matches(&line) {
+                let _ = sender.send(line).await;
+            }
+        }
+    });
+    Ok(ReceiverStream::new(receiver))
+}
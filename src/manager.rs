@@ -0,0 +1,1824 @@
+use crate::archive;
+use crate::audio::AudioOptions;
+use crate::bottle::{Bottle, BottleFilter, BottleState, BottleSummary, BottleType};
+use crate::catalog::{Catalog, ComponentRelease, RunnerRelease, Snapshot};
+use crate::components::{self, ComponentKind, InstalledComponent, InstalledComponents};
+use crate::disk;
+use crate::environment;
+use crate::events::{Event, Events};
+use crate::extensions::Extensions;
+use crate::flatpak;
+use crate::health;
+use crate::installers::{Recipe, RecipeOptions, Step};
+use crate::jobs::{CancelToken, JobKind, Jobs};
+use crate::kerberos;
+use crate::launch;
+use crate::lock::{BottleLock, LockMode};
+use crate::lockfile::{self, Lockfile};
+use crate::manifest::{BottleManifest, VerificationReport};
+use crate::net::Downloader;
+use crate::peripherals::{self, PeripheralOptions};
+use crate::persistence::{Backend, Persistence};
+use crate::playtime::{self, Stats};
+use crate::ports::{self, UsbOptions};
+use crate::prefix;
+use crate::programs::{self, Program, ProgramKind};
+use crate::relocate;
+#[cfg(target_os = "linux")]
+use crate::resources::history::{self, History};
+#[cfg(target_os = "linux")]
+use crate::resources::{self, ResourceUsage};
+use crate::runner::{self, PassThrough, PassThroughKind, Runner, Wine, SYSTEM_WINE};
+use crate::saves::{self, SaveLocation};
+use crate::session::{Launch, Session, Sessions};
+use crate::smartcard;
+use crate::state::{Operation, States};
+use crate::system::diagnostics::{Check, CheckStatus};
+use crate::templates::Template;
+use crate::thumbnail::{Thumbnail, Thumbnails};
+use crate::transaction::Transaction;
+use crate::version;
+#[cfg(feature = "unstable")]
+use crate::warmup;
+use crate::winebridge::{self, Bridge, Bridges, Token};
+use crate::winecfg::{self, GraphicsDriver, WineSettings};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Entry point for managing the bottles stored under a base directory
+///
+/// The base directory holds the bottle index (see [`Persistence`]) and a
+/// `bottles` directory containing one prefix per bottle.
+pub struct Manager {
+    base_path: PathBuf,
+    persistence: Arc<dyn Backend>,
+    sessions: Arc<Sessions>,
+    states: States,
+    /// Most recent launch of every bottle
+    last_launches: Mutex<HashMap<String, Launch>>,
+    #[cfg(target_os = "linux")]
+    usage: Mutex<resources::Sampler>,
+    thumbnails: Thumbnails,
+    extensions: Extensions,
+    downloader: Downloader,
+    jobs: Arc<Jobs>,
+    events: Events,
+    bridges: Arc<Bridges>,
+    /// Directory of every runner found so far, by name
+    runner_directories: Mutex<HashMap<String, PathBuf>>,
+    /// Last index of every catalog read, by URL
+    catalog_snapshots: Mutex<HashMap<String, Snapshot>>,
+    /// When the manager was created, in milliseconds since the epoch, telling
+    /// its session ids apart from those of earlier runs
+    started_at: u128,
+}
+
+/// Bottles read at a time by [`Manager::bottle_summaries`]
+pub const BOTTLE_PAGE_SIZE: usize = 100;
+
+/// Iterator over the summaries of the bottles, see
+/// [`Manager::bottle_summaries`]
+pub struct BottleSummaries<'a> {
+    manager: &'a Manager,
+    filter: BottleFilter,
+    offset: usize,
+    page: std::vec::IntoIter<BottleSummary>,
+    done: bool,
+}
+
+impl Iterator for BottleSummaries<'_> {
+    type Item = Result<BottleSummary, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(summary) = self.page.next() {
+            return Some(Ok(summary));
+        }
+        if self.done {
+            return None;
+        }
+        let page = self
+            .manager
+            .list_bottle_summaries_page(&self.filter, self.offset, BOTTLE_PAGE_SIZE);
+        match page {
+            Ok(page) => {
+                self.done = page.len() < BOTTLE_PAGE_SIZE;
+                self.offset += page.len();
+                self.page = page.into_iter();
+                self.page.next().map(Ok)
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// What runs a program of a bottle
+enum BottleRunner {
+    Wine(Box<dyn Runner>),
+    PassThrough(PassThrough),
+    /// Native programs are started directly
+    Native,
+}
+
+/// Outcome of a bottle creation
+///
+/// Automation should check [`VerificationReport::passed`] before relying on the
+/// bottle: creation succeeds as long as the prefix could be initialized, while
+/// the verification describes whether it matches what the manifest expected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreationReport {
+    pub bottle: Bottle,
+    pub verification: VerificationReport,
+    /// What the bottle was built from, recorded for
+    /// [strict](BottleManifest::strict) manifests
+    #[cfg(feature = "unstable")]
+    #[serde(default)]
+    pub lockfile: Option<Lockfile>,
+}
+
+impl Manager {
+    pub fn new(base_path: impl Into<PathBuf>) -> Self {
+        let base_path = base_path.into();
+        let persistence = Box::new(Persistence::new(&base_path));
+        Self::with_backend(base_path, persistence)
+    }
+
+    /// Create a manager storing the bottle index in a custom backend
+    pub fn with_backend(base_path: impl Into<PathBuf>, persistence: Box<dyn Backend>) -> Self {
+        let events = Events::default();
+        Self {
+            base_path: base_path.into(),
+            persistence: Arc::from(persistence),
+            sessions: Arc::new(Sessions::default().with_events(events.clone())),
+            states: States::default(),
+            last_launches: Mutex::default(),
+            #[cfg(target_os = "linux")]
+            usage: Mutex::default(),
+            thumbnails: Thumbnails::default(),
+            extensions: Extensions::default(),
+            downloader: Downloader::default(),
+            jobs: Arc::new(Jobs::default().with_events(events.clone())),
+            events,
+            bridges: Arc::default(),
+            runner_directories: Mutex::default(),
+            catalog_snapshots: Mutex::default(),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_millis())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Base directory managed by this instance
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+
+    /// Directory containing the bottle prefixes
+    pub fn bottles_path(&self) -> PathBuf {
+        self.base_path.join("bottles")
+    }
+
+    /// Directory containing the output of launched programs, one directory
+    /// per bottle
+    pub fn logs_path(&self) -> PathBuf {
+        self.base_path.join("logs")
+    }
+
+    /// File receiving the output of a session, see [`crate::logs::tail`]
+    ///
+    /// Session ids start over with every manager, so the name also carries
+    /// the time the manager was created and logs of earlier runs are kept.
+    pub fn session_log_path(&self, bottle_name: &str, id: u64) -> PathBuf {
+        self.logs_path()
+            .join(bottle_name)
+            .join(format!("{}-{}.log", self.started_at, id))
+    }
+
+    /// File receiving the capture of a session, see [`crate::debug::capture`]
+    pub fn capture_path(&self, bottle_name: &str, id: u64) -> PathBuf {
+        self.logs_path()
+            .join(bottle_name)
+            .join(format!("capture-{}-{}.json", self.started_at, id))
+    }
+
+    /// File holding the performance history of a bottle, see
+    /// [`crate::resources::history`]
+    pub fn history_path(&self, bottle_name: &str) -> PathBuf {
+        self.base_path.join("history").join(format!("{}.json", bottle_name))
+    }
+
+    /// Directory holding the lock files of bottles, see [`crate::lock`]
+    pub fn locks_path(&self) -> PathBuf {
+        self.base_path.join("locks")
+    }
+
+    /// Directory containing the installed runners
+    pub fn runners_path(&self) -> PathBuf {
+        self.base_path.join("runners")
+    }
+
+    /// Directory containing the extracted component releases, see
+    /// [`crate::components`]
+    pub fn components_path(&self) -> PathBuf {
+        self.base_path.join("components")
+    }
+
+    /// Directory keeping the installers downloaded by recipes, see
+    /// [`crate::installers`]
+    pub fn downloads_path(&self) -> PathBuf {
+        self.base_path.join("downloads")
+    }
+
+    /// Directory caching the indexes of catalogs, one directory per catalog
+    pub fn catalogs_path(&self) -> PathBuf {
+        self.base_path.join("catalogs")
+    }
+
+    /// The WineBridge executable deployed into prefixes, see
+    /// [`crate::winebridge`]
+    pub fn bridge_path(&self) -> PathBuf {
+        self.base_path.join("winebridge").join(winebridge::EXECUTABLE)
+    }
+
+    /// The catalog published at `url`, cached under [`Manager::catalogs_path`]
+    pub fn catalog_at(&self, url: &str) -> Catalog {
+        let directory: String = url
+            .trim_end_matches('/')
+            .split("://")
+            .last()
+            .unwrap_or(url)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+            .collect();
+        Catalog::new(url, self.catalogs_path().join(directory))
+    }
+
+    /// Fetch the index of `catalog`, adding the recipes of the registered
+    /// [`crate::extensions::CatalogExtension`]s to its installers
+    ///
+    /// # Errors
+    ///
+    /// See [`Catalog::fetch`]
+    pub fn catalog(&self, catalog: &Catalog) -> Result<Snapshot, Error> {
+        let mut snapshot = catalog.fetch(&self.downloader)?;
+        self.remember_catalog(catalog, &snapshot);
+        snapshot.index.installers.extend(self.extensions.recipes());
+        Ok(snapshot)
+    }
+
+    /// The last index of `catalog` read by this manager or found in its
+    /// cache, without downloading it
+    ///
+    /// Frontends show it right away while [`Manager::catalog`] updates it,
+    /// see [`crate::warmup`] to read it ahead of time.
+    ///
+    /// # Errors
+    ///
+    /// See [`Catalog::cached`]
+    pub fn cached_catalog(&self, catalog: &Catalog) -> Result<Option<Snapshot>, Error> {
+        let known = self
+            .catalog_snapshots
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(catalog.url())
+            .cloned();
+        let snapshot = match known {
+            Some(snapshot) => Some(snapshot),
+            None => catalog.cached()?,
+        };
+        Ok(snapshot.map(|mut snapshot| {
+            self.remember_catalog(catalog, &snapshot);
+            snapshot.index.installers.extend(self.extensions.recipes());
+            snapshot
+        }))
+    }
+
+    fn remember_catalog(&self, catalog: &Catalog, snapshot: &Snapshot) {
+        self.catalog_snapshots
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(catalog.url().to_string(), snapshot.clone());
+    }
+
+    /// Download, verify and extract `release` into [`Manager::runners_path`]
+    ///
+    /// Returns the directory of the runner, replacing a previous installation
+    /// of the same name.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Integrity`] if the archive doesn't pass its checks or
+    /// holds unsafe entries, see [`crate::archive`], [`Error::Cancelled`] if
+    /// `token` is cancelled before the archive is extracted
+    pub fn install_runner(
+        &self,
+        release: &RunnerRelease,
+        token: Option<&CancelToken>,
+        progress: impl FnMut(&archive::Progress),
+    ) -> Result<PathBuf, Error> {
+        // Catalogs are remote, the installation replaces what the name points to
+        validate_file_name("runner", &release.name)?;
+        let path = release.download(&self.downloader, &self.downloads_path(), token)?;
+        token.map_or(Ok(()), CancelToken::check)?;
+        let destination = self.runners_path().join(&release.name);
+        fs::create_dir_all(self.runners_path()).map_err(Error::Io)?;
+        let installed = archive::install(&path, &destination, progress)?;
+        let _ = fs::remove_file(&path);
+        tracing::info!("Installed runner '{}'", release.name);
+        Ok(installed)
+    }
+
+    /// Download, verify and extract `release` into
+    /// [`Manager::components_path`], where bottles can install it from
+    ///
+    /// # Errors
+    ///
+    /// See [`Manager::install_runner`]
+    pub fn install_component_release(
+        &self,
+        release: &ComponentRelease,
+        token: Option<&CancelToken>,
+        progress: impl FnMut(&archive::Progress),
+    ) -> Result<PathBuf, Error> {
+        validate_file_name("component version", &release.version)?;
+        let path = release.download(&self.downloader, &self.downloads_path(), token)?;
+        token.map_or(Ok(()), CancelToken::check)?;
+        let directory = self.components_path().join(release.component.id());
+        fs::create_dir_all(&directory).map_err(Error::Io)?;
+        let installed = archive::install(&path, &directory.join(&release.version), progress)?;
+        let _ = fs::remove_file(&path);
+        tracing::info!("Installed {} {}", release.component, release.version);
+        Ok(installed)
+    }
+
+    /// List the runners installed in [`Manager::runners_path`], followed by the
+    /// system-wide ones and the Wine of the distribution, see [`Wine::system`]
+    pub fn runners(&self) -> Vec<Box<dyn Runner>> {
+        let runners: Vec<_> = self
+            .runner_roots()
+            .iter()
+            .flat_map(|root| runner::discover(root))
+            .chain(Wine::system().map(|wine| Box::new(wine) as Box<dyn Runner>))
+            .collect();
+        self.remember_runners(
+            runners
+                .iter()
+                .map(|runner| (runner.info().name(), runner.info().directory())),
+        );
+        runners
+    }
+
+    /// List the runners like [`Manager::runners`], without starting any of
+    /// them, see [`runner::discover_lazy`]
+    pub fn runners_lazy(&self) -> Vec<Box<dyn Runner>> {
+        self.runner_roots()
+            .iter()
+            .flat_map(|root| runner::discover_lazy(root))
+            .chain(Wine::system_lazy().map(|wine| Box::new(wine) as Box<dyn Runner>))
+            .collect()
+    }
+
+    /// Directories holding the runners, in the order they are listed
+    pub(crate) fn runner_roots(&self) -> Vec<PathBuf> {
+        #[allow(unused_mut)]
+        let mut roots = vec![self.runners_path()];
+        #[cfg(unix)]
+        roots.push(PathBuf::from(crate::privileged::SYSTEM_RUNNERS_DIR));
+        roots
+    }
+
+    /// Find an installed runner by name
+    ///
+    /// The directory a runner was last found in is tried first, every runner
+    /// is listed otherwise. [`SYSTEM_WINE`] is the Wine installed by the
+    /// distribution, see [`Wine::system`].
+    pub fn find_runner(&self, name: &str) -> Option<Box<dyn Runner>> {
+        if name == SYSTEM_WINE {
+            return Wine::system().map(|wine| Box::new(wine) as Box<dyn Runner>);
+        }
+        let known = self
+            .runner_directories
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(name)
+            .cloned();
+        let runner = known.and_then(|directory| runner::open(&directory, false));
+        if let Some(runner) = runner.filter(|r| r.info().name() == name) {
+            return Some(runner);
+        }
+        self.runners().into_iter().find(|r| r.info().name() == name)
+    }
+
+    /// The newest installed runner of `flavor`, e.g. `ge-proton` or `wine`,
+    /// see [`crate::version`]
+    pub fn newest_runner(&self, flavor: &str) -> Option<Box<dyn Runner>> {
+        version::newest(self.runners_lazy(), flavor, |runner| runner.info().parsed_version())
+    }
+
+    /// Replace the directories tried first by [`Manager::find_runner`] with
+    /// those of the runners listed, by name, the first of a name winning
+    pub(crate) fn remember_runners<'a>(
+        &self,
+        runners: impl IntoIterator<Item = (&'a str, &'a Path)>,
+    ) {
+        let mut directories = self
+            .runner_directories
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        directories.clear();
+        for (name, directory) in runners {
+            directories
+                .entry(name.to_string())
+                .or_insert_with(|| directory.to_path_buf());
+        }
+    }
+
+    pub fn persistence(&self) -> &dyn Backend {
+        self.persistence.as_ref()
+    }
+
+    /// The downloader shared by the downloads of the manager, see
+    /// [`crate::net`]
+    pub fn downloader(&self) -> &Downloader {
+        &self.downloader
+    }
+
+    /// The queue of the background operations of the manager, see
+    /// [`crate::jobs`]
+    pub fn jobs(&self) -> &Jobs {
+        &self.jobs
+    }
+
+    /// The changes of the manager's bottles, programs and jobs, see
+    /// [`crate::events`]
+    pub fn events(&self) -> &Events {
+        &self.events
+    }
+
+    /// Create a bottle as a job, with `runner` or the first available one
+    ///
+    /// Returns the id of the job, see [`Manager::create_bottle`] for how it
+    /// may fail.
+    pub fn queue_create_bottle(
+        self: &Arc<Self>,
+        manifest: BottleManifest,
+        runner: Option<String>,
+    ) -> u64 {
+        let manager = Arc::clone(self);
+        let description = format!("Create '{}'", manifest.name);
+        self.jobs.submit(JobKind::CreateBottle, description, move |job| {
+            let runner = match &runner {
+                Some(name) => manager.find_runner(name),
+                None => manager.runners().into_iter().find(|r| r.is_available()),
+            }
+            .ok_or_else(|| Error::RunnerNotFound(runner.clone().unwrap_or_default()))?;
+            job.check()?;
+            job.progress(0, None, format!("Initializing the prefix with {}", runner.info().name()));
+            manager.create_bottle_cancellable(&manifest, runner.as_ref(), job.token())?;
+            Ok(())
+        })
+    }
+
+    /// Install a component into a bottle as a job, see
+    /// [`components::install`]
+    pub fn queue_component_install(
+        self: &Arc<Self>,
+        bottle_name: &str,
+        kind: ComponentKind,
+        version: &str,
+    ) -> u64 {
+        let manager = Arc::clone(self);
+        let (bottle_name, version) = (bottle_name.to_string(), version.to_string());
+        let description = format!("Install {} {} in '{}'", kind, version, bottle_name);
+        self.jobs.submit(JobKind::InstallComponent, description, move |job| {
+            job.check()?;
+            components::install(&manager, &bottle_name, kind, &version)?;
+            Ok(())
+        })
+    }
+
+    /// Warm up the caches of the manager as a job, see [`warmup::warm_up`]
+    ///
+    /// Returns the id of the job, whose progress is the current
+    /// [`warmup::Stage`] and how far it is.
+    #[cfg(feature = "unstable")]
+    pub fn queue_warm_up(self: &Arc<Self>, catalogs: Vec<Catalog>) -> u64 {
+        let manager = Arc::clone(self);
+        self.jobs.submit(JobKind::WarmUp, "Warm up", move |job| {
+            warmup::warm_up(&manager, &catalogs, job.token(), |readiness| {
+                let description = readiness.stage.description();
+                job.progress(readiness.current, readiness.total, description);
+            })?;
+            Ok(())
+        })
+    }
+
+    /// Download and install a runner as a job, see [`Manager::install_runner`]
+    pub fn queue_runner_install(self: &Arc<Self>, release: RunnerRelease) -> u64 {
+        let manager = Arc::clone(self);
+        let description = format!("Install {}", release.name);
+        self.jobs.submit(JobKind::InstallRunner, description, move |job| {
+            job.check()?;
+            manager.install_runner(&release, Some(job.token()), |progress| {
+                let message = format!("Extracting {}", progress.entry.display());
+                job.progress(progress.read, Some(progress.total), message);
+            })?;
+            Ok(())
+        })
+    }
+
+    /// Install a recipe as a job, see [`Recipe::install`]
+    ///
+    /// The token of `options` is replaced by the one of the job.
+    pub fn queue_recipe_install(
+        self: &Arc<Self>,
+        recipe: Recipe,
+        bottle_name: &str,
+        mut options: RecipeOptions,
+    ) -> u64 {
+        let manager = Arc::clone(self);
+        let bottle_name = bottle_name.to_string();
+        let description = format!("Install '{}' in '{}'", recipe.name, bottle_name);
+        self.jobs.submit(JobKind::InstallRecipe, description, move |job| {
+            options.token = Some(job.token().clone());
+            recipe.install(&manager, &bottle_name, &options, |progress| {
+                let (step, total) = (progress.step as u64, Some(progress.total as u64));
+                job.progress(step, total, progress.message.clone());
+            })?;
+            Ok(())
+        })
+    }
+
+    /// The hooks registered by the embedder, see [`crate::extensions`]
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn list_bottles(&self) -> Result<Vec<Bottle>, Error> {
+        self.persistence.load_bottles()
+    }
+
+    /// Summarize every bottle, for frontends to show the list of bottles
+    ///
+    /// Only the index and the sessions in memory are read: no process is
+    /// started and no prefix is touched, so this is fast even on a cold
+    /// start. [`BottleSummary::active`] tells whether a program launched by
+    /// this manager is running in the bottle, [`BottleSummary::state`] what
+    /// the bottle is doing.
+    pub fn list_bottle_summaries(&self) -> Result<Vec<BottleSummary>, Error> {
+        let started = std::time::Instant::now();
+        let mut summaries = self.persistence.load_summaries()?;
+        self.mark_states(&mut summaries);
+        tracing::debug!("Listed {} bottles in {:?}", summaries.len(), started.elapsed());
+        Ok(summaries)
+    }
+
+    /// Summarize the bottles matching `filter` like
+    /// [`Manager::list_bottle_summaries`], skipping the first `offset` of
+    /// them and returning at most `limit`
+    pub fn list_bottle_summaries_page(
+        &self,
+        filter: &BottleFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<BottleSummary>, Error> {
+        let mut summaries = self.persistence.load_summaries_page(filter, offset, limit)?;
+        self.mark_states(&mut summaries);
+        Ok(summaries)
+    }
+
+    /// Iterate over the summaries of the bottles matching `filter`, reading
+    /// the index [`BOTTLE_PAGE_SIZE`] bottles at a time
+    ///
+    /// Large libraries are listed without building every summary up front,
+    /// see [`Manager::list_bottle_summaries_page`]. The iteration stops after
+    /// the first error.
+    pub fn bottle_summaries(&self, filter: BottleFilter) -> BottleSummaries<'_> {
+        BottleSummaries {
+            manager: self,
+            filter,
+            offset: 0,
+            page: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+
+    fn mark_states(&self, summaries: &mut [BottleSummary]) {
+        let sessions = self.sessions.list();
+        for summary in summaries {
+            summary.state = self.state_with(&summary.name, &sessions);
+            summary.active |= matches!(summary.state, BottleState::Running { .. });
+        }
+    }
+
+    /// What the bottle `name` is doing, see [`BottleState`]
+    ///
+    /// Operations in progress come first, then the programs launched by the
+    /// manager. Bottles are broken from when [`crate::health::verify`] finds
+    /// their prefix broken until it doesn't.
+    pub fn bottle_state(&self, name: &str) -> BottleState {
+        self.state_with(name, &self.sessions.list())
+    }
+
+    fn state_with(&self, name: &str, sessions: &[Session]) -> BottleState {
+        if let Some(state) = self.states.operation(name) {
+            return state;
+        }
+        let pids: Vec<u32> = sessions
+            .iter()
+            .filter(|session| session.bottle == name)
+            .map(|session| session.pid)
+            .collect();
+        if !pids.is_empty() {
+            BottleState::Running { pids }
+        } else if self.states.is_broken(name) {
+            BottleState::Broken
+        } else if self.lock_path(name).is_ok_and(|path| BottleLock::is_held(&path)) {
+            BottleState::Locked
+        } else {
+            BottleState::Ready
+        }
+    }
+
+    pub(crate) fn states(&self) -> &States {
+        &self.states
+    }
+
+    /// Start an operation changing the bottle `name`, which is in `state`
+    /// until the operation is dropped
+    ///
+    /// The bottle is locked exclusively; operations run by another one on
+    /// the same bottle leave its state alone.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::BottleBusy`] if another operation holds the bottle,
+    /// or [`Error::InvalidState`] if its state doesn't allow `state`
+    pub(crate) fn begin(&self, name: &str, state: BottleState) -> Result<Operation<'_>, Error> {
+        let lock = self.lock_bottle(name, LockMode::Exclusive)?;
+        if lock.is_nested() {
+            return Ok(Operation::nested(name, lock));
+        }
+        let current = self.bottle_state(name);
+        if !current.allows(&state) {
+            return Err(Error::InvalidState(name.to_string(), current));
+        }
+        Ok(self.states.start(name, state, lock))
+    }
+
+    pub fn get_bottle(&self, name: &str) -> Result<Bottle, Error> {
+        self.persistence
+            .get_bottle(name)?
+            .ok_or_else(|| Error::BottleNotFound(name.to_string()))
+    }
+
+    /// Lock the bottle `name` for an operation, see [`crate::lock`]
+    ///
+    /// Programs launched by the manager share the lock of their bottle until
+    /// they exit; sessions that ended are forgotten first.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::BottleBusy`] if another operation holds the bottle
+    pub fn lock_bottle(&self, name: &str, mode: LockMode) -> Result<BottleLock, Error> {
+        self.sessions.list();
+        BottleLock::acquire(&self.lock_path(name)?, name, mode)
+    }
+
+    fn lock_path(&self, name: &str) -> Result<PathBuf, Error> {
+        validate_name(name)?;
+        Ok(self.locks_path().join(format!("{}.lock", name)))
+    }
+
+    /// Create a bottle from a manifest and verify the result
+    ///
+    /// The prefix is created under [`Manager::bottles_path`], initialized with
+    /// `runner`, set up according to the manifest's [`Template`] and
+    /// registered in the index. The manifest's components are installed and
+    /// the template's steps run, then the result is checked against the
+    /// manifest's [`crate::manifest::Verification`]. A
+    /// [strict](BottleManifest::strict) manifest also records the bottle's
+    /// [`Lockfile`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a bottle with the same name already exists, if the
+    /// name cannot be used as a directory name, if the prefix cannot be
+    /// initialized or a component installed, or [`Error::Integrity`] if the
+    /// runner or a component doesn't match its pinned digest. The prefix is
+    /// removed again on failure.
+    pub fn create_bottle(
+        &self,
+        manifest: &BottleManifest,
+        runner: &dyn Runner,
+    ) -> Result<CreationReport, Error> {
+        self.create_bottle_cancellable(manifest, runner, &CancelToken::default())
+    }
+
+    /// Create a bottle like [`Manager::create_bottle`], stopping once `token`
+    /// is cancelled
+    ///
+    /// A cancelled creation returns [`Error::Cancelled`] and removes what it
+    /// created, the prefix and the index entry.
+    pub fn create_bottle_cancellable(
+        &self,
+        manifest: &BottleManifest,
+        runner: &dyn Runner,
+        token: &CancelToken,
+    ) -> Result<CreationReport, Error> {
+        let _span = tracing::info_span!(
+            "create_bottle",
+            bottle = %manifest.name,
+            runner = runner.info().name()
+        )
+        .entered();
+        validate_name(&manifest.name)?;
+        if self.persistence.get_bottle(&manifest.name)?.is_some() {
+            return Err(Error::BottleExists(manifest.name.clone()));
+        }
+        lockfile::check_pinned(manifest)?;
+        // Held until the transaction is undone, on failure
+        let _operation = self.begin(&manifest.name, BottleState::Creating)?;
+        if let Some(sha256) = &manifest.runner_sha256 {
+            let name = runner.info().name();
+            let found = lockfile::runner_sha256(self, runner)?;
+            lockfile::check_digest(&format!("runner {}", name), &found, sha256)?;
+        }
+
+        let template = manifest
+            .template
+            .clone()
+            .unwrap_or_else(|| Template::builtin(manifest.kind.clone()));
+
+        let mut transaction =
+            Transaction::new(format!("create '{}'", manifest.name)).with_token(Some(token));
+        let path = self.bottles_path().join(&manifest.name);
+        transaction.create_dir_all(&path)?;
+        runner.initialize_cancellable(&path, token)?;
+        template.prepare_prefix(&path)?;
+        self.deploy_bridge(&path);
+
+        let mut bottle = Bottle::new(manifest.name.clone(), &path, manifest.kind.clone());
+        bottle.config = manifest.config.clone();
+        template.apply(&mut bottle.config);
+        // The runner picks the architecture, the bottle records it
+        if let Some(arch) = health::prefix_arch(&path)? {
+            if arch != bottle.config.arch {
+                tracing::warn!("'{}' was created {} by its runner", bottle.name, arch.id());
+            }
+            bottle.config.arch = arch;
+        }
+        if bottle.config.runner.is_none() {
+            bottle.config.runner = Some(runner.info().name().to_string());
+        }
+        self.add_to_index(&mut transaction, &bottle)?;
+
+        // Installed into the indexed bottle, undone with the whole prefix
+        for pin in &manifest.components {
+            transaction.check()?;
+            let component = components::install(self, &bottle.name, pin.kind, &pin.version)?;
+            if let Some(sha256) = &pin.sha256 {
+                let directory = lockfile::component_path(self, pin.kind, &component.version);
+                let found = lockfile::artifact_sha256(&directory)?;
+                let what = format!("{} {}", pin.kind, component.version);
+                lockfile::check_digest(&what, &found, sha256)?;
+            }
+        }
+        // Components pinned by the manifest aren't installed again
+        let mut recipe = template.recipe();
+        recipe.steps.retain(|step| match step {
+            Step::InstallComponent { component, .. } => {
+                !manifest.components.iter().any(|pin| pin.kind == *component)
+            }
+            _ => true,
+        });
+        if !recipe.steps.is_empty() {
+            transaction.check()?;
+            let options = RecipeOptions {
+                token: Some(token.clone()),
+                ..RecipeOptions::default()
+            };
+            recipe.install(self, &bottle.name, &options, |_| {})?;
+        }
+        if !manifest.components.is_empty() || !recipe.steps.is_empty() {
+            bottle = self.get_bottle(&bottle.name)?;
+        }
+
+        let verification = manifest.verify.run(runner, &path);
+        if !verification.passed() {
+            tracing::warn!(
+                "Bottle '{}' failed {} verification check(s)",
+                bottle.name,
+                verification.failures().count()
+            );
+        }
+
+        // Saved with the bottle either way, only reported with `unstable`
+        #[cfg_attr(not(feature = "unstable"), allow(unused_variables))]
+        let lockfile = if manifest.strict {
+            let lockfile = Lockfile::capture(self, &bottle)?;
+            lockfile.save(&Lockfile::path(&path))?;
+            Some(lockfile)
+        } else {
+            None
+        };
+
+        transaction.commit();
+        self.extensions.after_create(&bottle);
+        self.events.emit(Event::BottleCreated {
+            bottle: bottle.name.clone(),
+        });
+
+        Ok(CreationReport {
+            bottle,
+            verification,
+            #[cfg(feature = "unstable")]
+            lockfile,
+        })
+    }
+
+    /// Create a bottle whose programs run with DOSBox or ScummVM
+    ///
+    /// The bottle's directory is created under [`Manager::bottles_path`] and
+    /// holds the games instead of a prefix, see [`PassThrough`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a bottle with the same name already exists, if the
+    /// name cannot be used as a directory name, or if the runner is not
+    /// installed
+    pub fn create_passthrough_bottle(
+        &self,
+        name: &str,
+        kind: PassThroughKind,
+    ) -> Result<Bottle, Error> {
+        validate_name(name)?;
+        if self.persistence.get_bottle(name)?.is_some() {
+            return Err(Error::BottleExists(name.to_string()));
+        }
+        PassThrough::find(kind)?;
+
+        let mut transaction = Transaction::new(format!("create '{}'", name));
+        let path = self.bottles_path().join(name);
+        transaction.create_dir_all(&path)?;
+        let mut bottle = Bottle::new(name.to_string(), &path, BottleType::Gaming);
+        bottle.config.passthrough = Some(kind);
+        self.add_to_index(&mut transaction, &bottle)?;
+        transaction.commit();
+        self.extensions.after_create(&bottle);
+        self.events.emit(Event::BottleCreated {
+            bottle: bottle.name.clone(),
+        });
+        Ok(bottle)
+    }
+
+    /// Delete the prefix of a bottle and remove it from the index
+    ///
+    /// The prefix of a [read-only](Bottle::read_only) bottle is left in place,
+    /// since it belongs to another application. Programs it still runs that
+    /// the manager didn't launch are killed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::BottleBusy`] while the manager runs programs in the
+    /// bottle, or another operation holds it
+    pub fn delete_bottle(&self, name: &str) -> Result<Bottle, Error> {
+        let lock = self.lock_bottle(name, LockMode::Exclusive)?;
+        let bottle = self.get_bottle(name)?;
+        self.bridges.stop(name);
+        if !bottle.read_only && bottle.path.exists() {
+            // Programs still running would write into the removed prefix
+            if let Ok(runner) = self.runner_for(&bottle) {
+                if let Err(e) = runner.wine().wineserver_kill(&bottle.path) {
+                    tracing::warn!("Cannot stop the programs of '{}': {}", bottle.name, e);
+                }
+            }
+            fs::remove_dir_all(&bottle.path).map_err(Error::Io)?;
+        }
+        // Removed last, a prefix that can't be removed stays in the index
+        let bottle = self.persistence.remove_bottle(name)?;
+        disk::forget(&bottle.path);
+        self.states.forget(name);
+        drop(lock);
+        let _ = fs::remove_file(self.lock_path(name)?);
+        self.events.emit(Event::BottleDeleted {
+            bottle: bottle.name.clone(),
+        });
+        Ok(bottle)
+    }
+
+    /// Rename a bottle
+    ///
+    /// A prefix in [`Manager::bottles_path`] is moved along to the new name,
+    /// see [`Manager::move_bottle`]; one elsewhere stays where it is. The
+    /// logs and performance history of the bottle follow it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::BottleExists`] if a bottle is named `new_name`, or an
+    /// error if the bottle runs programs or its prefix can't be moved
+    pub fn rename_bottle(&self, name: &str, new_name: &str) -> Result<Bottle, Error> {
+        validate_name(new_name)?;
+        if self.persistence.get_bottle(new_name)?.is_some() {
+            return Err(Error::BottleExists(new_name.to_string()));
+        }
+        let bottle = self.get_bottle(name)?;
+        let path = if bottle.path == self.bottles_path().join(name) && !bottle.read_only {
+            self.bottles_path().join(new_name)
+        } else {
+            bottle.path.clone()
+        };
+        let renamed = self.relocate(bottle, new_name, path)?;
+
+        let moves = [
+            (self.logs_path().join(name), self.logs_path().join(new_name)),
+            (self.history_path(name), self.history_path(new_name)),
+        ];
+        for (from, to) in moves.iter().filter(|(from, _)| from.exists()) {
+            if let Err(e) = fs::rename(from, to) {
+                tracing::warn!("Cannot move '{}': {}", from.display(), e);
+            }
+        }
+        Ok(renamed)
+    }
+
+    /// Move the prefix of a bottle to `new_path`, which must not exist
+    ///
+    /// The prefix is copied when `new_path` is on another file system. Host
+    /// paths under the previous location, in the registry and in the config
+    /// of the bottle, are rewritten. A move that fails half-way is undone.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotAuthorized`] for [read-only](Bottle::read_only)
+    /// bottles, or an error if the bottle runs programs, `new_path` exists or
+    /// the prefix can't be moved
+    pub fn move_bottle(&self, name: &str, new_path: &Path) -> Result<Bottle, Error> {
+        let bottle = self.get_bottle(name)?;
+        if bottle.read_only {
+            return Err(Error::NotAuthorized(format!("move '{}', it is read-only", name)));
+        }
+        if !new_path.is_absolute() || new_path.starts_with(&bottle.path) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{}' is not a place to move '{}' to", new_path.display(), name),
+            )
+            .into());
+        }
+        self.relocate(bottle, name, new_path.to_path_buf())
+    }
+
+    /// Rename `bottle` to `name` and move its prefix to `path`
+    fn relocate(&self, bottle: Bottle, name: &str, path: PathBuf) -> Result<Bottle, Error> {
+        let _span = tracing::info_span!("relocate_bottle", bottle = %bottle.name).entered();
+        let lock = self.lock_bottle(&bottle.name, LockMode::Exclusive)?;
+        self.bridges.stop(&bottle.name);
+        let mut transaction = Transaction::new(format!("move '{}'", bottle.name));
+        let from = bottle.path.clone();
+        if path != from {
+            if path.symlink_metadata().is_ok() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!("'{}' already exists", path.display()),
+                )
+                .into());
+            }
+            // Wine writes the registry back when its wineserver exits
+            prefix::flush_registry(self, &bottle);
+            if let Some(parent) = path.parent() {
+                transaction.create_dir_all(parent)?;
+            }
+            let (back_from, back_to) = (path.clone(), from.clone());
+            transaction.step(
+                format!("move '{}'", from.display()),
+                || relocate::move_dir(&from, &path),
+                move || relocate::move_dir(&back_from, &back_to),
+            )?;
+            for file in relocate::REGISTRY_FILES {
+                let file = path.join(file);
+                transaction.preserve_file(&file)?;
+                relocate::rewrite_registry(&file, &from, &path)?;
+            }
+        }
+
+        let mut moved = relocate::rewrite_bottle(&bottle, &from, &path)?;
+        moved.name = name.to_string();
+        if moved.name == bottle.name {
+            transaction.step(
+                "update the index",
+                || self.persistence.update_bottle(&moved),
+                || self.persistence.update_bottle(&bottle),
+            )?;
+        } else {
+            transaction.step(
+                "remove from the index",
+                || self.persistence.remove_bottle(&bottle.name).map(drop),
+                || self.persistence.add_bottle(&bottle),
+            )?;
+            self.add_to_index(&mut transaction, &moved)?;
+        }
+        transaction.commit();
+        disk::forget(&from);
+        if moved.name != bottle.name {
+            self.states.rename(&bottle.name, &moved.name);
+            drop(lock);
+            let _ = fs::remove_file(self.lock_path(&bottle.name)?);
+        }
+        tracing::info!("Moved '{}' to '{}'", bottle.name, moved.path.display());
+        Ok(moved)
+    }
+
+    /// Resolve the runner configured for `bottle`
+    pub fn runner_for(&self, bottle: &Bottle) -> Result<Box<dyn Runner>, Error> {
+        let name = bottle
+            .config
+            .runner
+            .as_deref()
+            .ok_or_else(|| Error::RunnerNotFound(format!("none configured for '{}'", bottle.name)))?;
+        self.find_runner(name)
+            .ok_or_else(|| Error::RunnerNotFound(name.to_string()))
+    }
+
+    /// Switch a bottle to another installed runner
+    ///
+    /// The prefix is then updated to the new runner, see
+    /// [`Manager::update_prefix`]. If the update fails the bottle keeps its
+    /// previous runner.
+    pub fn set_runner(&self, bottle_name: &str, runner_name: &str) -> Result<Bottle, Error> {
+        let mut bottle = self.get_bottle(bottle_name)?;
+        let previous = bottle.clone();
+        let runner = self
+            .find_runner(runner_name)
+            .ok_or_else(|| Error::RunnerNotFound(runner_name.to_string()))?;
+        let mut transaction = Transaction::new(format!(
+            "switch '{}' to {}",
+            bottle_name,
+            runner.info().name()
+        ));
+        bottle.config.runner = Some(runner.info().name().to_string());
+        transaction.step(
+            "update the index",
+            || self.persistence.update_bottle(&bottle),
+            || self.persistence.update_bottle(&previous),
+        )?;
+        self.update_prefix(bottle_name)?;
+        transaction.commit();
+        self.get_bottle(bottle_name)
+    }
+
+    /// Change the audio settings of a bottle
+    ///
+    /// The settings of wineasio are rewritten when it is installed in the
+    /// bottle, the latency applies from the next launch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the settings are invalid, see
+    /// [`AudioOptions::validate`]
+    pub fn set_audio(&self, bottle_name: &str, audio: AudioOptions) -> Result<Bottle, Error> {
+        audio.validate()?;
+        let _operation = self.begin(bottle_name, BottleState::Updating)?;
+        let mut bottle = self.get_bottle(bottle_name)?;
+        let previous = bottle.clone();
+        bottle.config.audio = audio;
+        self.reconfigure(&previous, &bottle, "change the audio settings", || {
+            let installed = InstalledComponents::load(&bottle.path)?;
+            if installed.get(ComponentKind::WineAsio).is_none() {
+                return Ok(());
+            }
+            let runner = self.runner_for(&bottle)?;
+            components::set_asio_settings(runner.as_ref(), &bottle.path, &bottle.config.audio)
+        })?;
+        Ok(bottle)
+    }
+
+    /// Show or hide the host's printers and scanners in a bottle
+    ///
+    /// Scanners are shown or hidden right away, printers from the next
+    /// launch.
+    pub fn set_peripherals(
+        &self,
+        bottle_name: &str,
+        peripherals: PeripheralOptions,
+    ) -> Result<Bottle, Error> {
+        let _operation = self.begin(bottle_name, BottleState::Updating)?;
+        let mut bottle = self.get_bottle(bottle_name)?;
+        let previous = bottle.clone();
+        bottle.config.peripherals = peripherals;
+        self.reconfigure(&previous, &bottle, "change the peripherals", || {
+            if bottle.config.peripherals.scanners == previous.config.peripherals.scanners {
+                return Ok(());
+            }
+            let runner = self.runner_for(&bottle)?;
+            peripherals::apply(runner.as_ref(), &bottle.path, &bottle.config.peripherals)
+        })?;
+        Ok(bottle)
+    }
+
+    /// Change how the host's HID devices reach the programs of a bottle, see
+    /// [`crate::ports`]
+    pub fn set_usb(&self, bottle_name: &str, usb: UsbOptions) -> Result<Bottle, Error> {
+        let _operation = self.begin(bottle_name, BottleState::Updating)?;
+        let mut bottle = self.get_bottle(bottle_name)?;
+        let previous = bottle.clone();
+        let runner = self.runner_for(&bottle)?;
+        bottle.config.usb = usb;
+        self.reconfigure(&previous, &bottle, "change the USB settings", || {
+            ports::apply_usb(runner.as_ref(), &bottle.path, &bottle.config.usb)
+        })?;
+        Ok(bottle)
+    }
+
+    /// Let the programs of a bottle use the host's smart card readers, or
+    /// stop them from doing so, see [`crate::smartcard`]
+    ///
+    /// The prefix is set up right away, the pcscd socket is passed from the
+    /// next launch.
+    pub fn set_smart_cards(&self, bottle_name: &str, enabled: bool) -> Result<Bottle, Error> {
+        let _operation = self.begin(bottle_name, BottleState::Updating)?;
+        let mut bottle = self.get_bottle(bottle_name)?;
+        let previous = bottle.clone();
+        let runner = self.runner_for(&bottle)?;
+        bottle.config.smart_cards = enabled;
+        self.reconfigure(&previous, &bottle, "change the smart card settings", || {
+            smartcard::apply(runner.as_ref(), &bottle.path, enabled)
+        })?;
+        Ok(bottle)
+    }
+
+    /// Change the winecfg settings of a bottle, writing the ones that changed
+    /// to its registry, see [`crate::winecfg`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the settings are invalid, see
+    /// [`WineSettings::validate`], or if they select the Wayland driver and
+    /// the runner of the bottle has none
+    pub fn set_wine_settings(
+        &self,
+        bottle_name: &str,
+        settings: WineSettings,
+    ) -> Result<Bottle, Error> {
+        settings.validate()?;
+        let _operation = self.begin(bottle_name, BottleState::Updating)?;
+        let mut bottle = self.get_bottle(bottle_name)?;
+        let previous = bottle.clone();
+        let runner = self.runner_for(&bottle)?;
+        let wayland = settings.graphics_driver == GraphicsDriver::Wayland;
+        if wayland && !runner.capabilities().wayland_driver {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{} is built without the Wayland driver", runner.info().name()),
+            )
+            .into());
+        }
+        bottle.config.wine_settings = settings;
+        self.reconfigure(&previous, &bottle, "change the Wine settings", || {
+            let settings = &bottle.config.wine_settings;
+            let current = &previous.config.wine_settings;
+            winecfg::apply(runner.as_ref(), &bottle.path, settings, current)
+        })?;
+        Ok(bottle)
+    }
+
+    /// Record the changed config of `bottle` in the index, then `apply` it to
+    /// its prefix, putting `previous` back in the index if that fails
+    fn reconfigure(
+        &self,
+        previous: &Bottle,
+        bottle: &Bottle,
+        what: &str,
+        apply: impl FnOnce() -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut transaction = Transaction::new(format!("{} of '{}'", what, bottle.name));
+        transaction.step(
+            "update the index",
+            || self.persistence.update_bottle(bottle),
+            || self.persistence.update_bottle(previous),
+        )?;
+        apply()?;
+        transaction.commit();
+        Ok(())
+    }
+
+    /// Check the config of a bottle against the capabilities of its runner,
+    /// see [`runner::Capabilities::check`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bottle or its runner can't be found
+    pub fn runner_checks(&self, bottle_name: &str) -> Result<Vec<Check>, Error> {
+        let bottle = self.get_bottle(bottle_name)?;
+        let runner = self.runner_for(&bottle)?;
+        let capabilities = runner.capabilities();
+        Ok(capabilities.check(runner.info().name(), &bottle.config))
+    }
+
+    /// Check whether the host's printers, scanners and smart card readers can
+    /// work in a bottle, see [`peripherals::diagnose`]
+    pub fn peripheral_checks(&self, bottle_name: &str) -> Result<Vec<Check>, Error> {
+        let bottle = self.get_bottle(bottle_name)?;
+        let runner = self.runner_for(&bottle).ok();
+        Ok(peripherals::diagnose(&bottle, runner.as_deref()))
+    }
+
+    /// Check whether Kerberos sign-on can work in a bottle, see
+    /// [`kerberos::diagnose`]
+    pub fn kerberos_checks(&self, bottle_name: &str) -> Result<Vec<Check>, Error> {
+        let bottle = self.get_bottle(bottle_name)?;
+        Ok(kerberos::diagnose(&bottle.config.kerberos))
+    }
+
+    /// Update the prefix of a bottle to the Wine version of its runner
+    ///
+    /// Runs `wineboot -u`, then replaces the originals of the installed
+    /// components with the runner's DLLs, so uninstalling them later doesn't
+    /// bring back the previous runner's files, and reinstalls the components
+    /// whose DLLs the update replaced. This happens before launching a program
+    /// when the runner was updated in place, since Wine would update the
+    /// prefix on its own.
+    ///
+    /// # Returns
+    ///
+    /// The reinstalled components
+    pub fn update_prefix(&self, bottle_name: &str) -> Result<Vec<InstalledComponent>, Error> {
+        let _operation = self.begin(bottle_name, BottleState::Updating)?;
+        let bottle = self.get_bottle(bottle_name)?;
+        let runner = self.runner_for(&bottle)?;
+        tracing::info!("Updating '{}' to {}", bottle.name, runner.info().version().trim());
+        let output = runner
+            .command(Path::new("wineboot"), &["-u".to_string()], &bottle.path, &HashMap::new())
+            .output()
+            .map_err(Error::Io)?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "wineboot -u failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+            .into());
+        }
+        prefix::flush_registry(self, &bottle);
+        self.deploy_bridge(&bottle.path);
+
+        components::refresh_originals(&bottle.path, runner.as_ref())?;
+        components::repair(self, bottle_name)
+    }
+
+    /// Launch a program inside a bottle with its configured runner
+    ///
+    /// The command is wrapped with the tools enabled in the bottle config (see
+    /// [`crate::launch`]). The started process is tracked as a [`Session`] until
+    /// it exits or is stopped with [`Manager::stop_session`].
+    pub fn launch_program(
+        &self,
+        bottle_name: &str,
+        program: &Path,
+        args: &[String],
+    ) -> Result<Session, Error> {
+        self.launch_program_with_env(bottle_name, program, args, &HashMap::new())
+    }
+
+    /// Launch a program like [`Manager::launch_program`], with extra variables
+    ///
+    /// `overrides` is the topmost layer of the environment, see
+    /// [`environment::resolve`].
+    pub fn launch_program_with_env(
+        &self,
+        bottle_name: &str,
+        program: &Path,
+        args: &[String],
+        overrides: &HashMap<String, String>,
+    ) -> Result<Session, Error> {
+        let bottle = self.get_bottle(bottle_name)?;
+        let runner = match bottle.config.passthrough {
+            Some(kind) => BottleRunner::PassThrough(PassThrough::find(kind)?),
+            None => BottleRunner::Wine(self.runner_for(&bottle)?),
+        };
+        self.update_if_outdated(&bottle, &runner);
+        let program = Program {
+            args: args.to_vec(),
+            ..Program::new(program)
+        };
+        self.launch(bottle, runner, &program, overrides)
+    }
+
+    /// Add a program to the library of a bottle
+    ///
+    /// The program gets the next free id, which is returned with it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a native program is not an absolute path to an
+    /// existing file, or names a runner
+    pub fn add_program(&self, bottle_name: &str, mut program: Program) -> Result<Program, Error> {
+        let _operation = self.begin(bottle_name, BottleState::Updating)?;
+        let mut bottle = self.get_bottle(bottle_name)?;
+        validate_program(&program)?;
+        program.id = bottle.programs.iter().map(|program| program.id + 1).max().unwrap_or(1);
+        bottle.programs.push(program.clone());
+        self.persistence.update_bottle(&bottle)?;
+        Ok(program)
+    }
+
+    /// Replace the program of a bottle's library with the same id as
+    /// `program`, e.g. to change its launch configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bottle has no such program, or if `program`
+    /// is invalid like for [`Manager::add_program`]
+    pub fn update_program(&self, bottle_name: &str, program: Program) -> Result<Program, Error> {
+        let _operation = self.begin(bottle_name, BottleState::Updating)?;
+        let mut bottle = self.get_bottle(bottle_name)?;
+        validate_program(&program)?;
+        let existing = bottle
+            .programs
+            .iter_mut()
+            .find(|existing| existing.id == program.id)
+            .ok_or_else(|| program_not_found(bottle_name, program.id))?;
+        // Playtime is only recorded by launches
+        let program = Program {
+            playtime: existing.playtime,
+            ..program
+        };
+        *existing = program.clone();
+        self.persistence.update_bottle(&bottle)?;
+        Ok(program)
+    }
+
+    /// Remove a program from the library of a bottle
+    pub fn remove_program(&self, bottle_name: &str, id: u64) -> Result<Program, Error> {
+        let _operation = self.begin(bottle_name, BottleState::Updating)?;
+        let mut bottle = self.get_bottle(bottle_name)?;
+        let index = bottle
+            .programs
+            .iter()
+            .position(|program| program.id == id)
+            .ok_or_else(|| program_not_found(bottle_name, id))?;
+        let program = bottle.programs.remove(index);
+        self.persistence.update_bottle(&bottle)?;
+        Ok(program)
+    }
+
+    /// The save directories of a program of a bottle's library, see
+    /// [`saves::locate`]
+    pub fn save_locations(&self, bottle_name: &str, id: u64) -> Result<Vec<SaveLocation>, Error> {
+        let bottle = self.get_bottle(bottle_name)?;
+        let program = bottle.program(id).ok_or_else(|| program_not_found(bottle_name, id))?;
+        Ok(saves::locate(&bottle, program))
+    }
+
+    /// Back up the saves of a program of a bottle's library now, as done
+    /// when it exits, see [`saves::backup`]
+    pub fn backup_saves(&self, bottle_name: &str, id: u64) -> Result<Vec<PathBuf>, Error> {
+        let _lock = self.lock_bottle(bottle_name, LockMode::Shared)?;
+        let bottle = self.get_bottle(bottle_name)?;
+        let program = bottle.program(id).ok_or_else(|| program_not_found(bottle_name, id))?;
+        saves::backup(&bottle, program, &bottle.config.save_backup)
+    }
+
+    /// Playtime of every bottle and library program, see [`crate::playtime`]
+    pub fn playtime(&self) -> Result<Stats, Error> {
+        Ok(Stats::of(&self.list_bottles()?))
+    }
+
+    /// Launch a program of the library of a bottle, see [`Bottle::programs`]
+    ///
+    /// Windows programs are run like with [`Manager::launch_program`], by
+    /// their own runner if they have one. Native programs are started
+    /// directly, with the bottle's environment and launch wrappers. The
+    /// program's variables override the bottle's environment, and its
+    /// pre-launch script must succeed for the program to start.
+    pub fn launch_library_program(&self, bottle_name: &str, id: u64) -> Result<Session, Error> {
+        let bottle = self.get_bottle(bottle_name)?;
+        let program = bottle
+            .program(id)
+            .cloned()
+            .ok_or_else(|| program_not_found(bottle_name, id))?;
+        let runner = match (program.kind, &program.runner) {
+            (ProgramKind::Native, _) => BottleRunner::Native,
+            (ProgramKind::Windows, Some(name)) => BottleRunner::Wine(
+                self.find_runner(name)
+                    .ok_or_else(|| Error::RunnerNotFound(name.clone()))?,
+            ),
+            (ProgramKind::Windows, None) => match bottle.config.passthrough {
+                Some(kind) => BottleRunner::PassThrough(PassThrough::find(kind)?),
+                None => BottleRunner::Wine(self.runner_for(&bottle)?),
+            },
+        };
+        // A program with its own runner leaves the prefix to the bottle's
+        if program.runner.is_none() {
+            self.update_if_outdated(&bottle, &runner);
+        }
+        self.launch(bottle, runner, &program, &program.environment)
+    }
+
+    /// Update the prefix if the bottle's runner was updated in place, see
+    /// [`Manager::update_prefix`]
+    fn update_if_outdated(&self, bottle: &Bottle, runner: &BottleRunner) {
+        let BottleRunner::Wine(runner) = runner else {
+            return;
+        };
+        let outdated = InstalledComponents::load(&bottle.path)
+            .is_ok_and(|installed| installed.is_outdated(runner.as_ref()));
+        if outdated {
+            if let Err(e) = self.update_prefix(&bottle.name) {
+                tracing::warn!("Cannot update '{}' to its runner: {}", bottle.name, e);
+            }
+        }
+    }
+
+    /// Start `entry` with `runner`, `overrides` being the per-launch
+    /// variables
+    fn launch(
+        &self,
+        bottle: Bottle,
+        runner: BottleRunner,
+        entry: &Program,
+        overrides: &HashMap<String, String>,
+    ) -> Result<Session, Error> {
+        let program = entry.executable.as_path();
+        let args = entry.args.as_slice();
+        let span = tracing::info_span!(
+            "launch",
+            bottle = %bottle.name,
+            runner = tracing::field::Empty,
+            program = %program.display(),
+            session = tracing::field::Empty,
+            pid = tracing::field::Empty
+        );
+        let _entered = span.enter();
+        let lock = self.lock_bottle(&bottle.name, LockMode::Shared)?;
+        let state = self.bottle_state(&bottle.name);
+        // Operations holding the bottle, like the installers run while it is
+        // created, launch programs in it whatever its state
+        if !lock.is_nested() && !state.allows(&BottleState::Running { pids: Vec::new() }) {
+            return Err(Error::InvalidState(bottle.name.clone(), state));
+        }
+        if let BottleRunner::Wine(runner) = &runner {
+            span.record("runner", runner.info().name());
+            let checks = runner.capabilities().check(runner.info().name(), &bottle.config);
+            for check in checks.iter().filter(|check| check.status == CheckStatus::Error) {
+                tracing::warn!("{}: {}", check.title, check.detail);
+            }
+        }
+        let working_dir = match (&entry.working_dir, entry.kind) {
+            (Some(directory), _) => Some(directory.clone()),
+            (None, ProgramKind::Native) => program.parent().map(Path::to_path_buf),
+            (None, ProgramKind::Windows) => None,
+        };
+        let mut env = environment::resolve(&bottle, overrides);
+        self.extensions.before_launch(&bottle, entry, &mut env)?;
+        // Scripts run where the program does, or in the bottle's directory
+        let script_dir = working_dir.clone().unwrap_or_else(|| bottle.path.clone());
+        if let Some(script) = &entry.pre_launch {
+            programs::run_script(script, &env, &script_dir)?;
+        }
+
+        let id = self.sessions.next_id();
+        span.record("session", id);
+        // MangoHud writes frame times for the performance history into a
+        // per-session directory
+        #[cfg(target_os = "linux")]
+        let frames = (bottle.config.performance_history && bottle.config.mangohud).then(|| {
+            let directory = self.logs_path().join(&bottle.name).join(format!("frames-{}", id));
+            if let Err(e) = fs::create_dir_all(&directory) {
+                tracing::warn!("Cannot create '{}': {}", directory.display(), e);
+            }
+            let mut options = env.get("MANGOHUD_CONFIG").cloned().unwrap_or_default();
+            if !options.is_empty() {
+                options.push(',');
+            }
+            options.push_str(&history::frame_log_options(&directory));
+            env.insert("MANGOHUD_CONFIG".to_string(), options);
+            directory
+        });
+        let command = match &runner {
+            BottleRunner::Wine(runner) => launch::steam_runtime::wrap(
+                runner.command(program, args, &bottle.path, &env),
+                bottle.config.steam_runtime,
+                runner.info().directory(),
+            ),
+            BottleRunner::PassThrough(runner) => runner.command(program, args, &bottle.path, &env),
+            BottleRunner::Native => {
+                let mut command = Command::new(program);
+                command.args(args).envs(&env);
+                flatpak::adapt(command)
+            }
+        };
+        let command = match &working_dir {
+            Some(directory) => flatpak::with_directory(command, directory),
+            None => command,
+        };
+        let mut command = launch::wrap(command, &bottle.config);
+        command.stdin(Stdio::null());
+
+        // Output goes to a per-session log so it can be followed with
+        // `logs::tail`; a missing log is not worth failing the launch
+        let path = self.session_log_path(&bottle.name, id);
+        let log = match open_log(&path) {
+            Ok(file) => {
+                let stderr = file.try_clone().map_err(Error::Io)?;
+                command.stdout(file).stderr(stderr);
+                Some(path)
+            }
+            Err(e) => {
+                tracing::warn!("Cannot write session log '{}': {}", path.display(), e);
+                None
+            }
+        };
+        let child = command.spawn().map_err(Error::Io)?;
+        span.record("pid", child.id());
+        tracing::info!("Launched '{}'", program.display());
+        let session = self.sessions.insert(id, &bottle.name, program, child, log.clone());
+        self.sessions.hold(id, lock);
+        if entry.kind == ProgramKind::Windows && bottle.config.save_backup.destination.is_some() {
+            let (sessions, bottle) = (self.sessions.clone(), bottle.clone());
+            saves::backup_after_exit(sessions, id, bottle, entry.clone());
+        }
+        // Ad-hoc launches run a program that is not in the library, with id 0
+        let library_program = (entry.id != 0).then_some(entry.id);
+        playtime::track(
+            self.persistence.clone(),
+            self.sessions.clone(),
+            session.clone(),
+            library_program,
+        );
+        if let Some(script) = &entry.post_launch {
+            let (sessions, script) = (self.sessions.clone(), script.clone());
+            programs::run_after_exit(sessions, id, script, env.clone(), script_dir);
+        }
+        if let Some(log) = log {
+            launch::rendering::watch(self.sessions.clone(), id, log);
+        }
+        #[cfg(target_os = "linux")]
+        if bottle.config.performance_history {
+            history::record(
+                self.sessions.clone(),
+                session.clone(),
+                history::Components::of(&bottle.config),
+                self.history_path(&bottle.name),
+                frames,
+            );
+        }
+        self.last_launches
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(
+                bottle.name.clone(),
+                Launch {
+                    program: program.to_path_buf(),
+                    args: args.to_vec(),
+                    overrides: overrides.clone(),
+                },
+            );
+        tracing::info!(
+            "Started session {} for '{}' in '{}' (pid {})",
+            session.id,
+            program.display(),
+            bottle.name,
+            session.pid
+        );
+        Ok(session)
+    }
+
+    /// The most recent program launched in a bottle since the manager started
+    pub fn last_launch(&self, bottle_name: &str) -> Option<Launch> {
+        self.last_launches
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(bottle_name)
+            .cloned()
+    }
+
+    /// List the sessions that are still running
+    pub fn sessions(&self) -> Vec<Session> {
+        self.sessions.list()
+    }
+
+    /// Kill the process of a running session
+    pub fn stop_session(&self, id: u64) -> Result<Session, Error> {
+        self.sessions.stop(id)
+    }
+
+    /// Run the WineBridge agent in a bottle, deploying it first, see
+    /// [`crate::winebridge`]
+    ///
+    /// A bridge already running in the bottle is returned as it is. It is
+    /// stopped once the bottle is idle, or with [`Manager::stop_bridge`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotAuthorized`] for [read-only](Bottle::read_only)
+    /// bottles, [`Error::InvalidState`] if the bottle can't run programs right
+    /// now, or an error if the bridge isn't installed or can't be started
+    pub fn start_bridge(&self, bottle_name: &str) -> Result<Bridge, Error> {
+        if let Some(bridge) = self.bridges.get(bottle_name) {
+            return Ok(bridge);
+        }
+        let bottle = self.get_bottle(bottle_name)?;
+        if bottle.read_only {
+            return Err(Error::NotAuthorized(format!(
+                "run winebridge in '{}', it is read-only",
+                bottle.name
+            )));
+        }
+        let _lock = self.lock_bottle(&bottle.name, LockMode::Shared)?;
+        let state = self.bottle_state(&bottle.name);
+        if !state.allows(&BottleState::Running { pids: Vec::new() }) {
+            return Err(Error::InvalidState(bottle.name.clone(), state));
+        }
+        let runner = self.runner_for(&bottle)?;
+        winebridge::deploy(&self.bridge_path(), &bottle.path)?;
+
+        // The bridge binds a free port itself, which nothing can take before
+        let executable = bottle.path.join(winebridge::PREFIX_PATH);
+        let args = ["--port".to_string(), "0".to_string()];
+        let token = Token::generate()?;
+        let mut env = environment::resolve(&bottle, &HashMap::new());
+        env.insert(winebridge::TOKEN_VARIABLE.to_string(), token.as_str().to_string());
+        let mut command = runner.command(&executable, &args, &bottle.path, &env);
+        launch::isolate(&mut command).stdin(Stdio::null()).stdout(Stdio::piped());
+        let log = self.logs_path().join(&bottle.name).join("winebridge.log");
+        let log = match open_log(&log) {
+            Ok(file) => {
+                command.stderr(file.try_clone().map_err(Error::Io)?);
+                Some(file)
+            }
+            Err(e) => {
+                tracing::warn!("Cannot write the log '{}': {}", log.display(), e);
+                None
+            }
+        };
+        let mut child = command.spawn().map_err(Error::Io)?;
+        let port = match child.stdout.take() {
+            Some(stdout) => winebridge::read_port(stdout, log),
+            None => Err(std::io::Error::other("the output of the bridge is not piped").into()),
+        };
+        let port = match port {
+            Ok(port) => port,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        };
+        let version = winebridge::version(&executable);
+        let bridge = Bridge::new(bottle.name.clone(), child.id(), port, version, token);
+        tracing::info!(
+            "Started winebridge in '{}' on port {} (pid {})",
+            bottle.name,
+            port,
+            bridge.pid
+        );
+        self.bridges.insert(bridge.clone(), child);
+        winebridge::stop_when_idle(self.bridges.clone(), self.sessions.clone(), bridge.clone());
+        Ok(bridge)
+    }
+
+    /// The WineBridge agent running in a bottle, if any
+    pub fn bridge(&self, bottle_name: &str) -> Option<Bridge> {
+        self.bridges.get(bottle_name)
+    }
+
+    /// Stop the WineBridge agent running in a bottle, if any
+    pub fn stop_bridge(&self, bottle_name: &str) -> Option<Bridge> {
+        self.bridges.stop(bottle_name)
+    }
+
+    /// Deploy the bridge into `prefix` if it is installed, see
+    /// [`crate::winebridge::deploy`]
+    fn deploy_bridge(&self, prefix: &Path) {
+        let source = self.bridge_path();
+        if !source.is_file() {
+            return;
+        }
+        if let Err(e) = winebridge::deploy(&source, prefix) {
+            tracing::warn!("Cannot deploy winebridge into '{}': {}", prefix.display(), e);
+        }
+    }
+
+    /// A recent preview of the window of a running session
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotAuthorized`] unless thumbnails are enabled in the
+    /// bottle's config, or an error if the window cannot be captured
+    pub fn thumbnail(&self, id: u64) -> Result<Thumbnail, Error> {
+        let running = self.sessions.list();
+        let session = running
+            .iter()
+            .find(|s| s.id == id)
+            .ok_or(Error::SessionNotFound(id))?;
+        let bottle = self.get_bottle(&session.bottle)?;
+        if !bottle.config.thumbnails.enabled {
+            return Err(Error::NotAuthorized(format!(
+                "capture the windows of '{}', thumbnails are disabled in its config",
+                bottle.name
+            )));
+        }
+        let running: Vec<u64> = running.iter().map(|s| s.id).collect();
+        self.thumbnails
+            .get(session, &bottle.config.thumbnails, &running)
+    }
+
+    /// Measure the resource usage of the running sessions, optionally only
+    /// those of one bottle
+    ///
+    /// CPU and GPU usage are averaged since the previous call, so callers
+    /// should poll at a steady interval; the first call reports them as zero.
+    #[cfg(target_os = "linux")]
+    pub fn resource_usage(&self, bottle_name: Option<&str>) -> Vec<ResourceUsage> {
+        let sessions: Vec<Session> = self
+            .sessions
+            .list()
+            .into_iter()
+            .filter(|s| bottle_name.is_none_or(|name| s.bottle == name))
+            .collect();
+        self.usage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .sample(&sessions)
+    }
+
+    /// The recorded performance of past sessions of a bottle
+    #[cfg(target_os = "linux")]
+    pub fn performance_history(&self, bottle_name: &str) -> Result<History, Error> {
+        self.get_bottle(bottle_name)?;
+        History::load(&self.history_path(bottle_name))
+    }
+
+    /// Add a new bottle to the index, removing it again on rollback
+    fn add_to_index<'a>(
+        &'a self,
+        transaction: &mut Transaction<'a>,
+        bottle: &Bottle,
+    ) -> Result<(), Error> {
+        let name = bottle.name.clone();
+        transaction.step(
+            "add to the index",
+            || self.persistence.add_bottle(bottle),
+            move || self.persistence.remove_bottle(&name).map(drop),
+        )
+    }
+}
+
+fn open_log(path: &Path) -> std::io::Result<fs::File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::File::create(path)
+}
+
+/// Check that a program can be added to a library
+fn validate_program(program: &Program) -> Result<(), Error> {
+    if program.kind != ProgramKind::Native {
+        return Ok(());
+    }
+    let invalid = if !program.executable.is_absolute() || !program.executable.is_file() {
+        Some(format!("'{}' is not an executable", program.executable.display()))
+    } else if program.runner.is_some() {
+        Some(format!("native program '{}' can't use a runner", program.name))
+    } else {
+        None
+    };
+    match invalid {
+        Some(message) => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, message).into()),
+        None => Ok(()),
+    }
+}
+
+fn program_not_found(bottle: &str, id: u64) -> Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("'{}' has no program {}", bottle, id),
+    )
+    .into()
+}
+
+fn validate_name(name: &str) -> Result<(), Error> {
+    validate_file_name("bottle", name)
+}
+
+/// Check that the name of a `what` can be used as a directory name, without
+/// leading anywhere else
+fn validate_file_name(what: &str, name: &str) -> Result<(), Error> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("'{}' is not a valid {} name", name, what),
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use crate::bottle::{BottleConfig, BottleType};
+use crate::components::ComponentKind;
+use crate::flatpak;
+use crate::persistence::migrate;
+use crate::registry::{Hive, RegistryFile};
+use crate::runner::Runner;
+use crate::templates::Template;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Declarative description of a bottle, used to create it in a repeatable way
+///
+/// Besides the bottle settings, a manifest lists what a correctly created
+/// bottle is expected to contain so the result can be verified right after
+/// creation instead of discovering a broken prefix on first launch.
+///
+/// A [strict](Self::strict) manifest also pins the artifacts the bottle is
+/// built from, see [`crate::lockfile`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BottleManifest {
+    pub name: String,
+    #[serde(default)]
+    pub kind: BottleType,
+    #[serde(default)]
+    pub config: BottleConfig,
+    #[serde(default)]
+    pub verify: Verification,
+    /// Defaults applied on creation; the built-in template of `kind` if unset
+    #[serde(default)]
+    pub template: Option<Template>,
+    /// Components installed once the prefix is created
+    #[serde(default)]
+    pub components: Vec<ComponentPin>,
+    /// Pinned digest of the runner, see [`crate::lockfile::artifact_sha256`]
+    #[serde(default)]
+    pub runner_sha256: Option<String>,
+    /// Fail creation unless the runner and every component are pinned and
+    /// match their digest, and record the bottle's lockfile
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl BottleManifest {
+    /// The JSON schema of manifests, see [`crate::schema`]
+    #[cfg(feature = "schema")]
+    pub fn schema() -> serde_json::Value {
+        crate::schema::schema(crate::schema::Document::BottleManifest)
+    }
+
+    pub fn new(name: impl Into<String>, kind: BottleType) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            config: BottleConfig::default(),
+            verify: Verification::default(),
+            template: None,
+            components: Vec::new(),
+            runner_sha256: None,
+            strict: false,
+        }
+    }
+
+    /// Load a manifest from a JSON file
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path).map_err(Error::Io)?;
+        let mut value: serde_json::Value = serde_json::from_str(&content)?;
+        if let Some(config) = value.get_mut("config") {
+            migrate::migrate_config(config)?;
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+/// A component of a manifest, pinned to a version and optionally a digest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ComponentPin {
+    pub kind: ComponentKind,
+    /// Version to install, or [`crate::templates::LATEST`] unless strict
+    pub version: String,
+    /// See [`crate::lockfile::artifact_sha256`]
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+/// Expectations checked against a freshly created prefix
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct Verification {
+    /// DLL names expected in `system32` or `syswow64`, e.g. `d3d11.dll`
+    pub dlls: Vec<String>,
+    /// Full key paths expected in the prefix registry, e.g. `HKCU\Software\Wine`
+    pub registry_keys: Vec<String>,
+}
+
+/// Outcome of a single verification check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct VerificationCheck {
+    pub name: String,
+    pub passed: bool,
+    pub detail: Option<String>,
+}
+
+/// Result of running a [`Verification`] against a prefix
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct VerificationReport {
+    pub checks: Vec<VerificationCheck>,
+}
+
+impl VerificationReport {
+    /// The JSON schema of verification reports, see [`crate::schema`]
+    #[cfg(feature = "schema")]
+    pub fn schema() -> serde_json::Value {
+        crate::schema::schema(crate::schema::Document::VerificationReport)
+    }
+
+    /// Whether every check passed
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    /// Iterate over the checks that failed
+    pub fn failures(&self) -> impl Iterator<Item = &VerificationCheck> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+
+    pub(crate) fn push(&mut self, name: String, passed: bool, detail: Option<String>) {
+        self.checks.push(VerificationCheck {
+            name,
+            passed,
+            detail,
+        });
+    }
+}
+
+impl Verification {
+    /// Run the verification pass against `prefix`
+    ///
+    /// The prefix is booted once more with `wineboot` to make sure it starts
+    /// cleanly, then the expected DLLs and registry keys are looked up. Checks
+    /// never abort the pass, every failure is recorded in the report.
+    ///
+    /// # Arguments
+    ///
+    /// * `runner` - The runner the bottle was created with
+    /// * `prefix` - Path of the prefix to verify
+    pub fn run(&self, runner: &dyn Runner, prefix: &Path) -> VerificationReport {
+        let mut report = VerificationReport::default();
+
+        let mut wineboot = Command::new(runner.wine().info().executable_path());
+        wineboot.arg("wineboot").env("WINEPREFIX", prefix);
+        let wineboot = flatpak::adapt(wineboot).output();
+        match wineboot {
+            Ok(output) if output.status.success() => {
+                report.push("wineboot".to_string(), true, None)
+            }
+            Ok(output) => report.push(
+                "wineboot".to_string(),
+                false,
+                Some(format!(
+                    "wineboot exited with {}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )),
+            ),
+            Err(e) => report.push("wineboot".to_string(), false, Some(e.to_string())),
+        }
+
+        let windows = prefix.join("drive_c").join("windows");
+        for dll in &self.dlls {
+            let found = ["system32", "syswow64"].iter().any(|dir| {
+                let dir = windows.join(dir);
+                dir.join(dll).is_file() || dir.join(dll.to_lowercase()).is_file()
+            });
+            let detail = (!found).then(|| format!("'{}' not found in system32 or syswow64", dll));
+            report.push(format!("dll:{}", dll), found, detail);
+        }
+
+        let mut hives: HashMap<Hive, Option<RegistryFile>> = HashMap::new();
+        for key in &self.registry_keys {
+            let name = format!("registry:{}", key);
+            let Some((hive, subkey)) = Hive::split(key) else {
+                report.push(name, false, Some("unsupported registry root".to_string()));
+                continue;
+            };
+            let file = hives
+                .entry(hive)
+                .or_insert_with(|| RegistryFile::load_hive(prefix, hive).ok());
+            let found = file.as_ref().is_some_and(|file| file.contains_key(subkey));
+            let detail = (!found).then(|| format!("key not found in {}", hive.file_name()));
+            report.push(name, found, detail);
+        }
+
+        report
+    }
+}
@@ -0,0 +1,443 @@
+//! Downloads of runners, components, installers and catalogs
+//!
+//! A [`Downloader`] is shared by everything downloading on behalf of a
+//! [`Manager`](crate::manager::Manager), see
+//! [`Manager::downloader`](crate::manager::Manager::downloader), so its
+//! [`Limits`] apply to all downloads at once: how many run at the same time
+//! and how fast they may go together.
+//!
+//! Files are downloaded next to their target, as `<file name>.part`, and
+//! renamed once complete and verified. An interrupted download is resumed
+//! from its partial file when the server supports ranges, also from another
+//! mirror, if the expected SHA-256 will catch a mix of two versions of the
+//! file. Otherwise it is only resumed from a server that can tell the file
+//! didn't change, through the `ETag` or `Last-Modified` it first sent, kept in
+//! `<file name>.part.validator`. A [`Request`] may list mirrors, tried in order when a server can't
+//! be reached, answers with an error or sends a file that doesn't match the
+//! expected SHA-256. A cancelled download (see [`Request::cancellable`])
+//! keeps its partial file, so downloading it again resumes it.
+
+use crate::integrity;
+use crate::jobs::CancelToken;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Size of the chunks files are read and written in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long connecting to a server may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a server may send nothing before the download fails
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A file to download
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Request {
+    /// Where the file is downloaded from, then its mirrors in order
+    pub urls: Vec<String>,
+    pub target: PathBuf,
+    /// Hex SHA-256 the file must have
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Stops the download once cancelled
+    #[serde(skip)]
+    pub token: Option<CancelToken>,
+}
+
+impl Request {
+    pub fn new(url: impl Into<String>, target: impl Into<PathBuf>) -> Self {
+        Self {
+            urls: vec![url.into()],
+            target: target.into(),
+            sha256: None,
+            token: None,
+        }
+    }
+
+    /// Add a mirror, tried when the previous URLs fail
+    pub fn mirror(mut self, url: impl Into<String>) -> Self {
+        self.urls.push(url.into());
+        self
+    }
+
+    /// Require the file to have the hex SHA-256 `sha256`
+    pub fn sha256(mut self, sha256: impl Into<String>) -> Self {
+        self.sha256 = Some(sha256.into());
+        self
+    }
+
+    /// Stop the download with [`Error::Cancelled`] once `token` is cancelled
+    pub fn cancellable(mut self, token: Option<&CancelToken>) -> Self {
+        self.token = token.cloned();
+        self
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        match &self.token {
+            Some(token) => token.check(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Progress of a download
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Progress {
+    /// URL the file is downloaded from
+    pub url: String,
+    pub target: PathBuf,
+    /// Bytes downloaded so far, including the resumed part
+    pub downloaded: u64,
+    /// Size of the file, when the server tells it
+    pub total: Option<u64>,
+}
+
+/// Limits of the downloads of a [`Downloader`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Downloads running at the same time, others wait for their turn
+    pub concurrency: usize,
+    /// Bytes per second all downloads may receive together, unlimited when
+    /// unset
+    pub rate: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            rate: None,
+        }
+    }
+}
+
+/// Downloads files within shared [`Limits`]
+pub struct Downloader {
+    agent: ureq::Agent,
+    slots: Mutex<Slots>,
+    freed: Condvar,
+    throttle: Mutex<Throttle>,
+}
+
+struct Slots {
+    active: usize,
+    limits: Limits,
+}
+
+/// Token bucket of the rate limit, holding up to a second of transfer
+struct Throttle {
+    available: f64,
+    updated: Instant,
+}
+
+/// A running download, freeing its slot when dropped
+struct Slot<'a>(&'a Downloader);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        lock(&self.0.slots).active -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Self::new(Limits::default())
+    }
+}
+
+impl Downloader {
+    pub fn new(limits: Limits) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(CONNECT_TIMEOUT)
+            .timeout_read(READ_TIMEOUT)
+            .user_agent(concat!("bottles-core/", env!("CARGO_PKG_VERSION")))
+            .build();
+        Self {
+            agent,
+            slots: Mutex::new(Slots { active: 0, limits }),
+            freed: Condvar::new(),
+            throttle: Mutex::new(Throttle {
+                available: 0.0,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        lock(&self.slots).limits
+    }
+
+    /// Change the limits, applying to running downloads too
+    pub fn set_limits(&self, limits: Limits) {
+        lock(&self.slots).limits = limits;
+        self.freed.notify_all();
+    }
+
+    /// Download `request`, calling `progress` as data arrives
+    ///
+    /// Blocks while [`Limits::concurrency`] downloads are already running.
+    /// A target that already has the expected SHA-256 is not downloaded
+    /// again.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the last URL tried if no URL gives a file with
+    /// the expected SHA-256
+    pub fn download(
+        &self,
+        request: &Request,
+        mut progress: impl FnMut(&Progress),
+    ) -> Result<PathBuf, Error> {
+        let _span = tracing::info_span!("download", file = %request.target.display()).entered();
+        if let Some(sha256) = &request.sha256 {
+            if request.target.is_file() && integrity::sha256(&request.target, sha256).is_ok() {
+                tracing::debug!("'{}' is already downloaded", request.target.display());
+                return Ok(request.target.clone());
+            }
+        }
+        if let Some(parent) = request.target.parent() {
+            fs::create_dir_all(parent).map_err(Error::Io)?;
+        }
+        let _slot = self.slot();
+        request.check()?;
+        let partial = partial_path(&request.target);
+        let mut last_error = None;
+        for url in &request.urls {
+            if let Err(e) = self.fetch(url, request, &partial, &mut progress) {
+                if matches!(e, Error::Cancelled) {
+                    tracing::info!("Cancelled the download of '{}'", url);
+                    return Err(e);
+                }
+                // The partial file is kept for the next URL to resume from,
+                // the checksum covers it whichever server sent it
+                tracing::warn!("Cannot download '{}': {}", url, e);
+                last_error = Some(e);
+                continue;
+            }
+            if let Some(sha256) = &request.sha256 {
+                if let Err(e) = integrity::sha256(&partial, sha256) {
+                    tracing::warn!("Discarding '{}': {}", url, e);
+                    let _ = fs::remove_file(&partial);
+                    let _ = fs::remove_file(validator_path(&partial));
+                    last_error = Some(e);
+                    continue;
+                }
+            }
+            fs::rename(&partial, &request.target).map_err(Error::Io)?;
+            let _ = fs::remove_file(validator_path(&partial));
+            tracing::info!("Downloaded '{}' to '{}'", url, request.target.display());
+            return Ok(request.target.clone());
+        }
+        Err(last_error.unwrap_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "no URL to download from").into()
+        }))
+    }
+
+    /// The body of `url`, for small documents such as release metadata
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server can't be reached or answers with an
+    /// error
+    pub fn get(&self, url: &str) -> Result<String, Error> {
+        let _slot = self.slot();
+        let response = self
+            .agent
+            .get(url)
+            .call()
+            .map_err(|e| std::io::Error::other(format!("cannot get '{}': {}", url, e)))?;
+        response.into_string().map_err(Error::Io)
+    }
+
+    /// Download `requests` concurrently, within the limits
+    ///
+    /// Returns the outcome of every request, in order.
+    pub fn download_all(
+        &self,
+        requests: &[Request],
+        progress: impl Fn(&Progress) + Sync,
+    ) -> Vec<Result<PathBuf, Error>> {
+        let progress = &progress;
+        std::thread::scope(|scope| {
+            let downloads: Vec<_> = requests
+                .iter()
+                .map(|request| scope.spawn(move || self.download(request, progress)))
+                .collect();
+            downloads
+                .into_iter()
+                .map(|download| {
+                    download.join().unwrap_or_else(|_| {
+                        Err(std::io::Error::other("the download thread panicked").into())
+                    })
+                })
+                .collect()
+        })
+    }
+
+    /// Download `url` into `partial`, resuming it if it exists and can be
+    /// checked, see the [module documentation](self)
+    fn fetch(
+        &self,
+        url: &str,
+        request: &Request,
+        partial: &Path,
+        progress: &mut impl FnMut(&Progress),
+    ) -> Result<(), Error> {
+        let validator_path = validator_path(partial);
+        let validator = fs::read_to_string(&validator_path).ok();
+        let resumed = if request.sha256.is_some() || validator.is_some() {
+            fs::metadata(partial).map(|metadata| metadata.len()).unwrap_or(0)
+        } else {
+            0
+        };
+        let _span = tracing::debug_span!("fetch", url, resumed).entered();
+        let mut call = self.agent.get(url);
+        if resumed > 0 {
+            call = call.set("Range", &format!("bytes={}-", resumed));
+            // The whole file comes back if it changed since
+            if let Some(validator) = &validator {
+                call = call.set("If-Range", validator);
+            }
+        }
+        let response = match call.call() {
+            Ok(response) => response,
+            // The partial file is complete, or longer than the file
+            Err(ureq::Error::Status(416, _)) if resumed > 0 => {
+                fs::remove_file(partial).map_err(Error::Io)?;
+                let _ = fs::remove_file(&validator_path);
+                return self.fetch(url, request, partial, progress);
+            }
+            Err(e) => return Err(std::io::Error::other(e.to_string()).into()),
+        };
+        // Servers ignoring the range send the whole file
+        let (mut file, mut downloaded) = if response.status() == 206 && resumed > 0 {
+            let file = OpenOptions::new().append(true).open(partial).map_err(Error::Io)?;
+            (file, resumed)
+        } else {
+            let file = File::create(partial).map_err(Error::Io)?;
+            // Weak tags don't tell whether the bytes are the same
+            let validator = response
+                .header("ETag")
+                .filter(|tag| !tag.starts_with("W/"))
+                .or_else(|| response.header("Last-Modified"));
+            match validator {
+                Some(validator) => fs::write(&validator_path, validator).map_err(Error::Io)?,
+                None => {
+                    let _ = fs::remove_file(&validator_path);
+                }
+            }
+            (file, 0)
+        };
+        let total = response
+            .header("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok())
+            .map(|length| length + downloaded);
+
+        let mut reader = response.into_reader();
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let read = reader.read(&mut buffer).map_err(Error::Io)?;
+            if read == 0 {
+                break;
+            }
+            request.check()?;
+            file.write_all(&buffer[..read]).map_err(Error::Io)?;
+            downloaded += read as u64;
+            progress(&Progress {
+                url: url.to_string(),
+                target: request.target.clone(),
+                downloaded,
+                total,
+            });
+            self.throttle(read);
+        }
+        file.flush().map_err(Error::Io)?;
+        if total.is_some_and(|total| downloaded < total) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("got {} of {} bytes", downloaded, total.unwrap_or_default()),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Wait for a download slot to be free
+    fn slot(&self) -> Slot<'_> {
+        let mut slots = lock(&self.slots);
+        while slots.active >= slots.limits.concurrency.max(1) {
+            slots = self
+                .freed
+                .wait(slots)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        slots.active += 1;
+        Slot(self)
+    }
+
+    /// Wait until `bytes` more bytes are within the rate limit
+    fn throttle(&self, bytes: usize) {
+        let Some(rate) = self.limits().rate.filter(|rate| *rate > 0) else {
+            return;
+        };
+        let rate = rate as f64;
+        let wait = {
+            let mut throttle = lock(&self.throttle);
+            let now = Instant::now();
+            let elapsed = now.duration_since(throttle.updated).as_secs_f64();
+            throttle.available = (throttle.available + elapsed * rate).min(rate);
+            throttle.updated = now;
+            throttle.available -= bytes as f64;
+            (throttle.available < 0.0).then(|| -throttle.available / rate)
+        };
+        if let Some(wait) = wait {
+            std::thread::sleep(Duration::from_secs_f64(wait));
+        }
+    }
+}
+
+/// The hex SHA-256 of the file at `path`
+pub fn sha256(path: &Path) -> Result<String, Error> {
+    let mut file = File::open(path).map_err(Error::Io)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buffer).map_err(Error::Io)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Where the file `target` is downloaded to until complete
+fn partial_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    target.with_file_name(name)
+}
+
+/// Where the `ETag` or `Last-Modified` of the file `partial` is kept
+fn validator_path(partial: &Path) -> PathBuf {
+    let mut name = partial.file_name().unwrap_or_default().to_os_string();
+    name.push(".validator");
+    partial.with_file_name(name)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
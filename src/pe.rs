@@ -0,0 +1,507 @@
+//! Reading Windows executables
+//!
+//! Frontends show the programs of a bottle with the name, version and icon
+//! their developers gave them. [`PeFile`] reads those from the headers and
+//! resources of a PE executable or DLL without running it, so it works for
+//! any bottle and on every platform, Wine or not.
+
+use crate::limits::Limits;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+const RT_ICON: u32 = 3;
+const RT_GROUP_ICON: u32 = 14;
+const RT_VERSION: u32 = 16;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const FIXED_FILE_INFO_SIGNATURE: u32 = 0xfeef_04bd;
+const IMAGE_FILE_DLL: u16 = 0x2000;
+/// High bit of resource directory offsets pointing to a subdirectory
+const SUBDIRECTORY: u32 = 0x8000_0000;
+
+/// Processor architecture an executable is built for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Architecture {
+    X86,
+    X64,
+    Arm,
+    Arm64,
+    /// Another machine type, as found in the file header
+    Other(u16),
+}
+
+impl Architecture {
+    fn from_machine(machine: u16) -> Self {
+        match machine {
+            0x14c => Self::X86,
+            0x8664 => Self::X64,
+            0x1c0 | 0x1c4 => Self::Arm,
+            0xaa64 => Self::Arm64,
+            other => Self::Other(other),
+        }
+    }
+
+    pub fn is_64_bit(self) -> bool {
+        matches!(self, Self::X64 | Self::Arm64)
+    }
+}
+
+/// Windows subsystem an executable runs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Subsystem {
+    /// Program with its own windows
+    Gui,
+    /// Command-line program, opening a console when started from the desktop
+    Console,
+    /// Another subsystem, e.g. a driver or an EFI application
+    Other(u16),
+}
+
+impl Subsystem {
+    fn from_value(value: u16) -> Self {
+        match value {
+            2 => Self::Gui,
+            3 => Self::Console,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// Format of an [`Icon`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IconFormat {
+    Png,
+    Ico,
+}
+
+impl IconFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Ico => "ico",
+        }
+    }
+}
+
+/// An icon extracted from an executable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Icon {
+    pub format: IconFormat,
+    pub data: Vec<u8>,
+}
+
+/// The version resource of an executable
+///
+/// Strings are taken from the English table when the executable has one, and
+/// from its first table otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionInfo {
+    /// e.g. `1.2.0.3`
+    pub file_version: Option<String>,
+    pub product_version: Option<String>,
+    pub product_name: Option<String>,
+    pub file_description: Option<String>,
+    pub company_name: Option<String>,
+    pub original_filename: Option<String>,
+    pub legal_copyright: Option<String>,
+}
+
+impl VersionInfo {
+    /// Name to show for the program, preferring the product name
+    pub fn name(&self) -> Option<&str> {
+        self.product_name
+            .as_deref()
+            .or(self.file_description.as_deref())
+            .filter(|name| !name.is_empty())
+    }
+}
+
+/// What frontends show about an executable
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub architecture: Architecture,
+    pub subsystem: Subsystem,
+    pub is_dll: bool,
+    pub version: Option<VersionInfo>,
+    pub has_icon: bool,
+}
+
+/// Read the [`Metadata`] of the executable at `path`
+pub fn metadata(path: &Path) -> Result<Metadata, Error> {
+    let file = PeFile::open(path)?;
+    Ok(Metadata {
+        architecture: file.architecture(),
+        subsystem: file.subsystem(),
+        is_dll: file.is_dll(),
+        version: file.version_info(),
+        has_icon: file.resource(RT_GROUP_ICON, None).is_some(),
+    })
+}
+
+/// A parsed PE executable or DLL
+#[derive(Clone)]
+pub struct PeFile {
+    data: Vec<u8>,
+    sections: Vec<Section>,
+    machine: u16,
+    characteristics: u16,
+    subsystem: u16,
+    /// File offset of the root resource directory, if the file has resources
+    resources: Option<usize>,
+    limits: Limits,
+}
+
+#[derive(Debug, Clone)]
+struct Section {
+    virtual_address: u32,
+    virtual_size: u32,
+    raw_offset: u32,
+    raw_size: u32,
+}
+
+impl PeFile {
+    /// Read and parse the file at `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, exceeds the
+    /// [default limits](Limits) or is not a PE file
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let size = fs::metadata(path).map_err(Error::Io)?.len();
+        if !usize::try_from(size).is_ok_and(|size| Limits::DEFAULT.allows_size(size)) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("'{}' is too large to be read", path.display()),
+            )
+            .into());
+        }
+        let data = fs::read(path).map_err(Error::Io)?;
+        Self::parse(data).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("'{}' is not a Windows executable", path.display()),
+            )
+            .into()
+        })
+    }
+
+    /// Parse the contents of a PE file, `None` if it isn't one
+    pub fn parse(data: Vec<u8>) -> Option<Self> {
+        Self::parse_with_limits(data, &Limits::DEFAULT)
+    }
+
+    /// Parse the contents of a PE file like [`PeFile::parse`], within
+    /// `limits`
+    ///
+    /// Every section and every image of an icon counts as an item, and icons
+    /// may not be larger than `limits` allow for the file itself.
+    pub fn parse_with_limits(data: Vec<u8>, limits: &Limits) -> Option<Self> {
+        if !limits.allows_size(data.len()) || data.get(..2)? != b"MZ" {
+            return None;
+        }
+        let pe = u32_at(&data, 0x3c)? as usize;
+        if data.get(pe..pe.checked_add(4)?)? != b"PE\0\0" {
+            return None;
+        }
+        let coff = pe + 4;
+        let machine = u16_at(&data, coff)?;
+        let section_count = u16_at(&data, coff + 2)? as usize;
+        if section_count > limits.max_items {
+            return None;
+        }
+        let optional_size = u16_at(&data, coff + 16)? as usize;
+        let characteristics = u16_at(&data, coff + 18)?;
+        let optional = coff + 20;
+        let directories = match u16_at(&data, optional)? {
+            0x10b => optional + 96,
+            0x20b => optional + 112,
+            _ => return None,
+        };
+        let subsystem = u16_at(&data, optional + 68)?;
+
+        let table = optional + optional_size;
+        let sections = (0..section_count)
+            .map(|index| {
+                let header = table + index * 40;
+                Some(Section {
+                    virtual_size: u32_at(&data, header + 8)?,
+                    virtual_address: u32_at(&data, header + 12)?,
+                    raw_size: u32_at(&data, header + 16)?,
+                    raw_offset: u32_at(&data, header + 20)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let mut file = Self {
+            data,
+            sections,
+            machine,
+            characteristics,
+            subsystem,
+            resources: None,
+            limits: *limits,
+        };
+
+        // The resource table is the third data directory
+        let directory_count = u32_at(&file.data, directories - 4)?;
+        let resources_rva = u32_at(&file.data, directories + 16).unwrap_or(0);
+        if directory_count >= 3 && resources_rva != 0 {
+            file.resources = file.offset(resources_rva);
+        }
+        Some(file)
+    }
+
+    pub fn architecture(&self) -> Architecture {
+        Architecture::from_machine(self.machine)
+    }
+
+    pub fn subsystem(&self) -> Subsystem {
+        Subsystem::from_value(self.subsystem)
+    }
+
+    pub fn is_dll(&self) -> bool {
+        self.characteristics & IMAGE_FILE_DLL != 0
+    }
+
+    /// The largest icon of the executable
+    ///
+    /// Icons stored as PNG are returned as is; other icons are returned as an
+    /// `.ico` file holding every size of the executable's first icon.
+    pub fn icon(&self) -> Option<Icon> {
+        let group = self.resource(RT_GROUP_ICON, None)?;
+        let count = u16_at(group, 4)? as usize;
+        if count > self.limits.max_items {
+            return None;
+        }
+        let mut images = Vec::new();
+        // Entries may all point to the same image, the icon must not grow
+        // with their count
+        let mut size = 0usize;
+        for index in 0..count {
+            let entry = group.get(6 + index * 14..6 + (index + 1) * 14)?;
+            let id = u16_at(entry, 12)? as u32;
+            if let Some(image) = self.resource(RT_ICON, Some(id)) {
+                size = size.checked_add(16 + image.len())?;
+                if !self.limits.allows_size(size) {
+                    return None;
+                }
+                images.push((entry, image));
+            }
+        }
+
+        let (_, largest) = images.iter().max_by_key(|(entry, _)| {
+            // A width of 0 stands for 256 pixels
+            let width = if entry[0] == 0 { 256 } else { entry[0] as u16 };
+            (width, u16_at(entry, 6))
+        })?;
+        if largest.starts_with(PNG_SIGNATURE) {
+            return Some(Icon {
+                format: IconFormat::Png,
+                data: largest.to_vec(),
+            });
+        }
+
+        let mut ico = vec![0, 0, 1, 0];
+        ico.extend_from_slice(&(images.len() as u16).to_le_bytes());
+        let mut offset = 6 + 16 * images.len();
+        for (entry, image) in &images {
+            ico.extend_from_slice(&entry[..8]);
+            ico.extend_from_slice(&(image.len() as u32).to_le_bytes());
+            ico.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += image.len();
+        }
+        for (_, image) in &images {
+            ico.extend_from_slice(image);
+        }
+        Some(Icon {
+            format: IconFormat::Ico,
+            data: ico,
+        })
+    }
+
+    /// The version resource of the executable
+    pub fn version_info(&self) -> Option<VersionInfo> {
+        let data = self.resource(RT_VERSION, None)?;
+        let root = Block::parse(data)?;
+        if root.key != "VS_VERSION_INFO" {
+            return None;
+        }
+
+        let mut info = VersionInfo::default();
+        if u32_at(root.value, 0) == Some(FIXED_FILE_INFO_SIGNATURE) {
+            let version = |offset: usize| -> Option<String> {
+                let high = u32_at(root.value, offset)?;
+                let low = u32_at(root.value, offset + 4)?;
+                Some(format!("{}.{}.{}.{}", high >> 16, high & 0xffff, low >> 16, low & 0xffff))
+            };
+            info.file_version = version(8);
+            info.product_version = version(16);
+        }
+
+        let tables: Vec<Block> = Block::children(root.children)
+            .filter(|block| block.key == "StringFileInfo")
+            .flat_map(|block| Block::children(block.children))
+            .collect();
+        let table = tables
+            .iter()
+            .find(|table| table.key.to_ascii_lowercase().starts_with("0409"))
+            .or(tables.first());
+        let mut file_version = None;
+        let mut product_version = None;
+        if let Some(table) = table {
+            for string in Block::children(table.children) {
+                let value = Some(string.text()).filter(|value| !value.is_empty());
+                let field = match string.key.as_str() {
+                    "FileVersion" => &mut file_version,
+                    "ProductVersion" => &mut product_version,
+                    "ProductName" => &mut info.product_name,
+                    "FileDescription" => &mut info.file_description,
+                    "CompanyName" => &mut info.company_name,
+                    "OriginalFilename" => &mut info.original_filename,
+                    "LegalCopyright" => &mut info.legal_copyright,
+                    _ => continue,
+                };
+                *field = value;
+            }
+        }
+        // The fixed versions are numbers, more reliable than free-form text
+        info.file_version = info.file_version.or(file_version);
+        info.product_version = info.product_version.or(product_version);
+        Some(info)
+    }
+
+    /// File offset of a relative virtual address
+    fn offset(&self, rva: u32) -> Option<usize> {
+        self.sections
+            .iter()
+            .find(|section| {
+                let size = section.virtual_size.max(section.raw_size);
+                rva >= section.virtual_address && rva - section.virtual_address < size
+            })
+            .and_then(|section| (rva - section.virtual_address).checked_add(section.raw_offset))
+            .map(|offset| offset as usize)
+    }
+
+    /// Data of the resource of type `kind` with the given id, or of the first
+    /// one, in any language
+    fn resource(&self, kind: u32, id: Option<u32>) -> Option<&[u8]> {
+        let root = self.resources?;
+        let (_, names) = self.entries(0).into_iter().find(|(name, _)| *name == kind)?;
+        let names = self.entries(names);
+        let (_, languages) = match id {
+            Some(id) => names.into_iter().find(|(name, _)| *name == id)?,
+            None => names.into_iter().next()?,
+        };
+        let (_, leaf) = self.entries(languages).into_iter().next()?;
+        if leaf & SUBDIRECTORY != 0 {
+            return None;
+        }
+        let entry = root + leaf as usize;
+        let start = self.offset(u32_at(&self.data, entry)?)?;
+        let size = u32_at(&self.data, entry + 4)? as usize;
+        self.data.get(start..start.checked_add(size)?)
+    }
+
+    /// Entries of a resource directory, as `(name or id, offset)` pairs
+    ///
+    /// Returns nothing if `offset` points to data instead of a directory,
+    /// except for the root directory at offset 0.
+    fn entries(&self, offset: u32) -> Vec<(u32, u32)> {
+        let Some(root) = self.resources else {
+            return Vec::new();
+        };
+        if offset != 0 && offset & SUBDIRECTORY == 0 {
+            return Vec::new();
+        }
+        let start = root + (offset & !SUBDIRECTORY) as usize;
+        let named = u16_at(&self.data, start + 12).unwrap_or(0) as usize;
+        let ids = u16_at(&self.data, start + 14).unwrap_or(0) as usize;
+        (0..named + ids)
+            .map_while(|index| {
+                let entry = start + 16 + index * 8;
+                Some((u32_at(&self.data, entry)?, u32_at(&self.data, entry + 4)?))
+            })
+            .collect()
+    }
+}
+
+/// A node of a version resource: a key, a value and child nodes
+struct Block<'a> {
+    key: String,
+    value: &'a [u8],
+    /// Whether `value` is UTF-16 text
+    is_text: bool,
+    children: &'a [u8],
+}
+
+impl<'a> Block<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        let length = (u16_at(data, 0)? as usize).min(data.len());
+        let value_length = u16_at(data, 2)? as usize;
+        let is_text = u16_at(data, 4)? == 1;
+        let data = &data[..length];
+
+        let mut end = 6;
+        let mut key = Vec::new();
+        loop {
+            let unit = u16_at(data, end)?;
+            end += 2;
+            if unit == 0 {
+                break;
+            }
+            key.push(unit);
+        }
+        let value_start = align(end).min(length);
+        // The length of text values is counted in UTF-16 units
+        let value_size = if is_text { value_length * 2 } else { value_length };
+        let value_end = (value_start + value_size).min(length);
+        Some(Self {
+            key: String::from_utf16_lossy(&key),
+            value: &data[value_start..value_end],
+            is_text,
+            children: &data[align(value_end).min(length)..],
+        })
+    }
+
+    /// The blocks following each other in `data`
+    fn children(mut data: &'a [u8]) -> impl Iterator<Item = Block<'a>> {
+        std::iter::from_fn(move || {
+            let length = u16_at(data, 0)? as usize;
+            if length == 0 {
+                return None;
+            }
+            let block = Self::parse(data)?;
+            data = data.get(align(length)..).unwrap_or_default();
+            Some(block)
+        })
+    }
+
+    /// The value as text, up to its terminating NUL
+    fn text(&self) -> String {
+        if !self.is_text {
+            return String::new();
+        }
+        let units: Vec<u16> = self
+            .value
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|unit| *unit != 0)
+            .collect();
+        String::from_utf16_lossy(&units).trim().to_string()
+    }
+}
+
+/// Round `offset` up to the next 4-byte boundary
+fn align(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
@@ -0,0 +1,206 @@
+//! Printers and scanners of the host inside prefixes
+//!
+//! Wine shows the host's CUPS printers to Windows programs, and bridges SANE
+//! scanners to TWAIN through its `sane.ds` data source. Both need the host
+//! libraries, the 32-bit ones for 32-bit programs, and a sandbox that can
+//! reach them when running in Flatpak. A bottle can hide them with
+//! [`PeripheralOptions`], and [`diagnose`] explains what keeps them from
+//! working.
+//!
+//! Printers are hidden by pointing CUPS to a server that doesn't exist, so
+//! Wine finds none when it starts. Scanners are hidden by disabling the TWAIN
+//! data sources with DLL overrides, see [`crate::manager::Manager::set_peripherals`].
+
+use crate::bottle::Bottle;
+use crate::components;
+use crate::flatpak;
+use crate::runner::Runner;
+use crate::smartcard;
+use crate::system::diagnostics::{self, Check, CheckStatus, Feature};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Value of `CUPS_SERVER` hiding the host's printers
+const NO_CUPS_SERVER: &str = "/nonexistent/cups.sock";
+
+/// Sockets of a local CUPS server
+const CUPS_SOCKETS: &[&str] = &["/run/cups/cups.sock", "/var/run/cups/cups.sock"];
+
+/// TWAIN data sources of Wine, for SANE scanners and gPhoto2 cameras
+const TWAIN_SOURCES: &[&str] = &["sane.ds", "gphoto2.ds"];
+
+/// Host printers and scanners visible in a bottle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct PeripheralOptions {
+    /// Show the host's CUPS printers
+    pub printers: bool,
+    /// Show the host's SANE scanners to TWAIN programs
+    pub scanners: bool,
+}
+
+impl Default for PeripheralOptions {
+    fn default() -> Self {
+        Self {
+            printers: true,
+            scanners: true,
+        }
+    }
+}
+
+impl PeripheralOptions {
+    /// Variables hiding the host's printers when disabled
+    pub fn environment(&self) -> Vec<(&'static str, String)> {
+        if self.printers {
+            Vec::new()
+        } else {
+            vec![("CUPS_SERVER", NO_CUPS_SERVER.to_string())]
+        }
+    }
+}
+
+/// Enable or disable the TWAIN data sources of `prefix`
+pub(crate) fn apply(
+    runner: &dyn Runner,
+    prefix: &Path,
+    options: &PeripheralOptions,
+) -> Result<(), Error> {
+    // An empty override disables the DLL
+    let mode = (!options.scanners).then_some("");
+    for source in TWAIN_SOURCES {
+        components::set_override(runner, prefix, source, mode)?;
+    }
+    Ok(())
+}
+
+/// Check whether printers and scanners can work in `bottle`, run by `runner`
+///
+/// The checks of [`smartcard::diagnose`] are included when smart cards are
+/// enabled for the bottle.
+pub fn diagnose(bottle: &Bottle, runner: Option<&dyn Runner>) -> Vec<Check> {
+    let options = &bottle.config.peripherals;
+    let mut checks = Vec::new();
+
+    if options.printers {
+        checks.extend(diagnostics::library_checks(
+            "printing.libcups",
+            "CUPS library",
+            "libcups.so.2",
+            "cups-libs or libcups2",
+            Feature::Printing,
+        ));
+        checks.push(cups_server());
+    } else {
+        checks.push(disabled(
+            "printing.enabled",
+            "Printers",
+            "printers",
+            &bottle.name,
+            Feature::Printing,
+        ));
+    }
+
+    if options.scanners {
+        checks.extend(diagnostics::library_checks(
+            "scanning.libsane",
+            "SANE library",
+            "libsane.so.1",
+            "sane-backends or libsane1",
+            Feature::Scanning,
+        ));
+        if let Some(runner) = runner {
+            checks.push(twain(runner));
+        }
+    } else {
+        checks.push(disabled(
+            "scanning.enabled",
+            "Scanners",
+            "scanners",
+            &bottle.name,
+            Feature::Scanning,
+        ));
+    }
+
+    if bottle.config.smart_cards {
+        checks.extend(smartcard::diagnose());
+    }
+    checks
+}
+
+fn cups_server() -> Check {
+    const ID: &str = "printing.cups";
+    const TITLE: &str = "CUPS server";
+
+    let server = std::env::var("CUPS_SERVER").ok().filter(|server| !server.is_empty());
+    let reachable = match &server {
+        // Remote servers can't be checked without connecting
+        Some(server) if !server.starts_with('/') => {
+            return Check::new(ID, TITLE, CheckStatus::Ok, format!("printing to {}", server));
+        }
+        Some(socket) => Path::new(socket).exists().then(|| socket.clone()),
+        None => CUPS_SOCKETS
+            .iter()
+            .find(|socket| Path::new(socket).exists())
+            .map(|socket| socket.to_string()),
+    };
+    match reachable {
+        Some(socket) => Check::new(
+            ID,
+            TITLE,
+            CheckStatus::Ok,
+            format!("CUPS is listening on {}", socket),
+        ),
+        None => {
+            let hint = match flatpak::sandbox() {
+                Some(sandbox) => format!(
+                    "Allow access to CUPS with: flatpak override --user --socket=cups {}",
+                    sandbox.app_id.as_deref().unwrap_or("<application id>")
+                ),
+                None => "Start CUPS, e.g. with: systemctl enable --now cups".to_string(),
+            };
+            Check::new(ID, TITLE, CheckStatus::Error, "no CUPS server is reachable")
+                .hint(hint)
+                .affects(&[Feature::Printing])
+        }
+    }
+}
+
+/// Whether the runner ships Wine's SANE data source
+fn twain(runner: &dyn Runner) -> Check {
+    const ID: &str = "scanning.twain";
+    const TITLE: &str = "TWAIN bridge";
+
+    let name = runner.info().name();
+    let source = Path::new(TWAIN_SOURCES[0]);
+    let found = components::runner_dll(runner, true, source)
+        .or_else(|| components::runner_dll(runner, false, source));
+    match found {
+        Some(path) => Check::new(
+            ID,
+            TITLE,
+            CheckStatus::Ok,
+            format!("{} ships {}", name, path.display()),
+        ),
+        None => Check::new(
+            ID,
+            TITLE,
+            CheckStatus::Error,
+            format!("{} ships no sane.ds, TWAIN programs can't see scanners", name),
+        )
+        .hint("Use a runner built with SANE support, such as your distribution's Wine")
+        .affects(&[Feature::Scanning]),
+    }
+}
+
+fn disabled(id: &str, title: &str, what: &str, bottle: &str, feature: Feature) -> Check {
+    Check::new(
+        id,
+        title,
+        CheckStatus::Warning,
+        format!("the host's {} are hidden from '{}'", what, bottle),
+    )
+    .hint(format!("Enable {} in the settings of the bottle", what))
+    .affects(&[feature])
+}
@@ -0,0 +1,141 @@
+use super::{yaml_scalar as scalar, Tweaks};
+use crate::bottle::{Bottle, BottleType};
+use crate::sync::SyncMode;
+use crate::Error;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Name of the configuration file Bottles keeps in every bottle directory
+const CONFIG_FILE: &str = "bottle.yml";
+
+/// The subset of Bottles' `bottle.yml` that maps onto a [`Bottle`]
+///
+/// Everything is optional because the format changed between releases and
+/// files are often edited by hand.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClassicBottle {
+    #[serde(rename = "Name")]
+    name: Option<String>,
+    #[serde(rename = "Runner")]
+    runner: Value,
+    #[serde(rename = "DXVK")]
+    dxvk: Value,
+    #[serde(rename = "VKD3D")]
+    vkd3d: Value,
+    #[serde(rename = "Environment")]
+    environment: Option<String>,
+    #[serde(rename = "Parameters")]
+    parameters: ClassicParameters,
+    #[serde(rename = "Environment_Variables")]
+    environment_variables: HashMap<String, Value>,
+    #[serde(rename = "DLL_Overrides")]
+    dll_overrides: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClassicParameters {
+    dxvk: bool,
+    vkd3d: bool,
+    sync: Option<String>,
+    fsr: bool,
+    discrete_gpu: bool,
+}
+
+/// Import bottles from an installation of the original Bottles application
+///
+/// `path` is either a single bottle directory (containing `bottle.yml`) or the
+/// directory holding all of them, usually `~/.local/share/bottles/bottles`.
+/// The imported bottles keep pointing at their current prefix.
+///
+/// Settings without a dedicated [`crate::bottle::BottleConfig`] field (FSR,
+/// discrete GPU, DLL overrides) are translated to the environment variables
+/// Bottles would have set for them.
+///
+/// # Errors
+///
+/// Returns an error if `path` cannot be read. Bottles whose `bottle.yml` cannot
+/// be parsed are skipped with a warning.
+pub fn from_bottles_classic(path: &Path) -> Result<Vec<Bottle>, Error> {
+    if path.join(CONFIG_FILE).is_file() {
+        return Ok(vec![read_bottle(path)?]);
+    }
+
+    let mut directories: Vec<_> = fs::read_dir(path)
+        .map_err(Error::Io)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|dir| dir.join(CONFIG_FILE).is_file())
+        .collect();
+    directories.sort();
+
+    let mut bottles = Vec::new();
+    for directory in directories {
+        match read_bottle(&directory) {
+            Ok(bottle) => bottles.push(bottle),
+            Err(e) => tracing::warn!("Skipping '{}': {}", directory.display(), e),
+        }
+    }
+    Ok(bottles)
+}
+
+fn read_bottle(directory: &Path) -> Result<Bottle, Error> {
+    let content = fs::read_to_string(directory.join(CONFIG_FILE)).map_err(Error::Io)?;
+    let classic: ClassicBottle = serde_yaml::from_str(&content)?;
+
+    let name = classic
+        .name
+        .clone()
+        .filter(|name| !name.is_empty())
+        .or_else(|| directory.file_name().map(|n| n.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "imported".to_string());
+    let kind = match classic.environment.as_deref() {
+        Some("Gaming") => BottleType::Gaming,
+        Some("Application") | Some("Software") => BottleType::Software,
+        _ => BottleType::Custom,
+    };
+
+    let mut bottle = Bottle::new(name, directory, kind);
+    let config = &mut bottle.config;
+    config.runner = string(&classic.runner);
+    if classic.parameters.dxvk {
+        config.dxvk_version = string(&classic.dxvk);
+    }
+    if classic.parameters.vkd3d {
+        config.vkd3d_version = string(&classic.vkd3d);
+    }
+
+    for (key, value) in &classic.environment_variables {
+        if let Some(value) = scalar(value) {
+            config.environment.insert(key.clone(), value);
+        }
+    }
+    let sync = match classic.parameters.sync.as_deref() {
+        Some("esync") => SyncMode::Esync,
+        Some("fsync") | Some("futex2") => SyncMode::Fsync,
+        Some("ntsync") => SyncMode::Ntsync,
+        _ => SyncMode::None,
+    };
+    let tweaks = Tweaks {
+        sync,
+        fsr: classic.parameters.fsr,
+        discrete_gpu: classic.parameters.discrete_gpu,
+        dll_overrides: classic
+            .dll_overrides
+            .iter()
+            .filter_map(|(dll, mode)| scalar(mode).map(|mode| (dll.clone(), mode)))
+            .collect(),
+    };
+    tweaks.apply(config);
+
+    Ok(bottle)
+}
+
+/// A non-empty string value; Bottles writes `false` or `null` for unset versions
+fn string(value: &Value) -> Option<String> {
+    value.as_str().filter(|s| !s.is_empty()).map(str::to_string)
+}
@@ -0,0 +1,168 @@
+use super::{runner_name_from_binary, Tweaks};
+use crate::bottle::{Bottle, BottleType};
+use crate::sync::SyncMode;
+use crate::Error;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Locate the Heroic Games Launcher config directory of the current user
+pub fn default_heroic_dir() -> Option<PathBuf> {
+    let home = PathBuf::from(std::env::var_os("HOME")?);
+    [
+        home.join(".config/heroic"),
+        home.join(".var/app/com.heroicgameslauncher.hgl/config/heroic"),
+    ]
+    .into_iter()
+    .find(|dir| dir.join("GamesConfig").is_dir())
+}
+
+/// Import the Wine prefixes of games configured in Heroic Games Launcher
+///
+/// Reads the per-game files in `<path>/GamesConfig` and resolves game titles
+/// from Heroic's store library caches, falling back to the store's app name.
+///
+/// # Errors
+///
+/// Returns an error if the `GamesConfig` directory cannot be read. Configs that
+/// cannot be parsed are skipped with a warning.
+pub fn from_heroic(path: &Path) -> Result<Vec<Bottle>, Error> {
+    let titles = titles(&path.join("store_cache"));
+
+    let mut files: Vec<PathBuf> = fs::read_dir(path.join("GamesConfig"))
+        .map_err(Error::Io)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|file| file.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+
+    let mut bottles = Vec::new();
+    for file in files {
+        match read_game(&file, &titles) {
+            Ok(Some(bottle)) => bottles.push(bottle),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Skipping '{}': {}", file.display(), e),
+        }
+    }
+    Ok(bottles)
+}
+
+fn read_game(file: &Path, titles: &HashMap<String, String>) -> Result<Option<Bottle>, Error> {
+    let Some(app_name) = file.file_stem().and_then(|s| s.to_str()) else {
+        return Ok(None);
+    };
+    let content = fs::read_to_string(file).map_err(Error::Io)?;
+    let document: Value = serde_json::from_str(&content)?;
+    // The settings are nested under the app name; the other keys are metadata
+    let Some(settings) = document.get(app_name) else {
+        return Ok(None);
+    };
+    let Some(prefix) = settings
+        .get("winePrefix")
+        .and_then(Value::as_str)
+        .filter(|prefix| !prefix.is_empty())
+    else {
+        return Ok(None);
+    };
+
+    let name = titles
+        .get(app_name)
+        .cloned()
+        .unwrap_or_else(|| app_name.to_string())
+        .replace(['/', '\\'], "-");
+    let mut bottle = Bottle::new(name, prefix, BottleType::Gaming);
+    let flag = |key: &str| settings.get(key).and_then(Value::as_bool).unwrap_or(false);
+
+    let config = &mut bottle.config;
+    let wine_version = settings.get("wineVersion");
+    config.runner = wine_version
+        .and_then(|version| version.get("bin"))
+        .and_then(Value::as_str)
+        .and_then(|bin| runner_name_from_binary(Path::new(bin)))
+        .or_else(|| {
+            wine_version
+                .and_then(|version| version.get("name"))
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        });
+
+    // Heroic spells this key "enviromentOptions"
+    let options = settings
+        .get("enviromentOptions")
+        .or_else(|| settings.get("environmentOptions"))
+        .and_then(Value::as_array);
+    for option in options.into_iter().flatten() {
+        let key = option.get("key").and_then(Value::as_str);
+        let value = option.get("value").and_then(Value::as_str);
+        if let (Some(key), Some(value)) = (key, value) {
+            if !key.is_empty() {
+                config.environment.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+
+    let sync = if flag("enableFsync") {
+        SyncMode::Fsync
+    } else if flag("enableEsync") {
+        SyncMode::Esync
+    } else {
+        SyncMode::None
+    };
+    let tweaks = Tweaks {
+        sync,
+        fsr: flag("enableFSR"),
+        discrete_gpu: flag("nvidiaPrime"),
+        dll_overrides: Vec::new(),
+    };
+    tweaks.apply(config);
+
+    Ok(Some(bottle))
+}
+
+/// Collect `app_name` → `title` pairs from every library cache Heroic keeps
+///
+/// Each store (Epic, GOG, Amazon) uses its own file layout, but all of them
+/// describe games as objects with `app_name` and `title` fields, so the files
+/// are searched structurally instead of per store.
+fn titles(store_cache: &Path) -> HashMap<String, String> {
+    let mut titles = HashMap::new();
+    let Ok(entries) = fs::read_dir(store_cache) else {
+        return titles;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if !path.extension().is_some_and(|ext| ext == "json") {
+            continue;
+        }
+        if let Some(document) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|c| serde_json::from_str::<Value>(&c).ok())
+        {
+            collect_titles(&document, &mut titles);
+        }
+    }
+    titles
+}
+
+fn collect_titles(value: &Value, titles: &mut HashMap<String, String>) {
+    match value {
+        Value::Object(object) => {
+            let app_name = object.get("app_name").and_then(Value::as_str);
+            let title = object.get("title").and_then(Value::as_str);
+            if let (Some(app_name), Some(title)) = (app_name, title) {
+                titles.insert(app_name.to_string(), title.to_string());
+            }
+            for child in object.values() {
+                collect_titles(child, titles);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                collect_titles(item, titles);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,154 @@
+use super::{yaml_scalar, Tweaks};
+use crate::bottle::{Bottle, BottleType};
+use crate::sync::SyncMode;
+use crate::Error;
+use serde_yaml::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Locate the Lutris data directory of the current user
+///
+/// Recent Lutris releases keep game configs in `~/.local/share/lutris/games`,
+/// older ones in `~/.config/lutris/games`; the Flatpak build is checked too.
+pub fn default_lutris_dir() -> Option<PathBuf> {
+    let home = PathBuf::from(std::env::var_os("HOME")?);
+    [
+        home.join(".local/share/lutris"),
+        home.join(".config/lutris"),
+        home.join(".var/app/net.lutris.Lutris/data/lutris"),
+    ]
+    .into_iter()
+    .find(|dir| dir.join("games").is_dir())
+}
+
+/// Import the Wine prefixes of games configured in Lutris
+///
+/// `path` is either the Lutris data directory or its `games` directory. Only
+/// games using the Wine runner with an explicit prefix are imported; the bottle
+/// is named after the game's config file slug.
+///
+/// # Errors
+///
+/// Returns an error if the games directory cannot be read. Configs that cannot
+/// be parsed are skipped with a warning.
+pub fn from_lutris(path: &Path) -> Result<Vec<Bottle>, Error> {
+    let games = if path.join("games").is_dir() {
+        path.join("games")
+    } else {
+        path.to_path_buf()
+    };
+
+    let mut files: Vec<PathBuf> = fs::read_dir(&games)
+        .map_err(Error::Io)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|file| file.extension().is_some_and(|ext| ext == "yml"))
+        .collect();
+    files.sort();
+
+    let mut bottles = Vec::new();
+    for file in files {
+        match read_game(&file) {
+            Ok(Some(bottle)) => bottles.push(bottle),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Skipping '{}': {}", file.display(), e),
+        }
+    }
+    Ok(bottles)
+}
+
+fn read_game(file: &Path) -> Result<Option<Bottle>, Error> {
+    let content = fs::read_to_string(file).map_err(Error::Io)?;
+    let document: Value = serde_yaml::from_str(&content)?;
+
+    let Some(prefix) = document
+        .get("game")
+        .and_then(|game| game.get("prefix"))
+        .and_then(Value::as_str)
+        .filter(|prefix| !prefix.is_empty())
+    else {
+        return Ok(None);
+    };
+
+    let slug = file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut bottle = Bottle::new(name_from_slug(&slug), expand_home(prefix), BottleType::Gaming);
+
+    let empty = Value::Null;
+    let wine = document.get("wine").unwrap_or(&empty);
+    let flag = |section: &Value, key: &str| section.get(key).and_then(Value::as_bool);
+    let text = |section: &Value, key: &str| {
+        section
+            .get(key)
+            .and_then(Value::as_str)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+    };
+
+    let config = &mut bottle.config;
+    config.runner = text(wine, "version");
+    // Lutris enables DXVK unless told otherwise, VKD3D is opt-in
+    if flag(wine, "dxvk").unwrap_or(true) {
+        config.dxvk_version = text(wine, "dxvk_version");
+    }
+    if flag(wine, "vkd3d").unwrap_or(false) {
+        config.vkd3d_version = text(wine, "vkd3d_version");
+    }
+
+    if let Some(env) = document
+        .get("system")
+        .and_then(|system| system.get("env"))
+        .and_then(Value::as_mapping)
+    {
+        for (key, value) in env {
+            if let (Some(key), Some(value)) = (key.as_str(), yaml_scalar(value)) {
+                config.environment.insert(key.to_string(), value);
+            }
+        }
+    }
+
+    let dll_overrides: Vec<(String, String)> = wine
+        .get("overrides")
+        .and_then(Value::as_mapping)
+        .map(|overrides| {
+            overrides
+                .iter()
+                .filter_map(|(dll, mode)| Some((dll.as_str()?.to_string(), yaml_scalar(mode)?)))
+                .collect()
+        })
+        .unwrap_or_default();
+    let sync = if flag(wine, "fsync").unwrap_or(false) {
+        SyncMode::Fsync
+    } else if flag(wine, "esync").unwrap_or(false) {
+        SyncMode::Esync
+    } else {
+        SyncMode::None
+    };
+    let tweaks = Tweaks {
+        sync,
+        fsr: flag(wine, "fsr").unwrap_or(false),
+        discrete_gpu: false,
+        dll_overrides,
+    };
+    tweaks.apply(config);
+
+    Ok(Some(bottle))
+}
+
+/// Turn `the-witcher-3-1699999999` into `the witcher 3`
+fn name_from_slug(slug: &str) -> String {
+    let slug = match slug.rsplit_once('-') {
+        Some((name, timestamp)) if timestamp.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => slug,
+    };
+    slug.replace(['-', '_'], " ")
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
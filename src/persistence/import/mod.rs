@@ -0,0 +1,109 @@
+//! Importers turning prefixes managed by other tools into bottles
+//!
+//! Importers only read the other tool's files and return [`Bottle`] entries that
+//! point at the existing prefixes; nothing is copied or modified. Pass the result
+//! to [`register`] to add them to an index.
+
+mod bottles_classic;
+mod heroic;
+mod lutris;
+mod steam;
+
+pub use bottles_classic::from_bottles_classic;
+pub use heroic::{default_heroic_dir, from_heroic};
+pub use lutris::{default_lutris_dir, from_lutris};
+pub use steam::{default_steam_root, from_steam, SteamImportMode};
+pub(crate) use steam::libraries as steam_libraries;
+
+use super::Backend;
+use crate::bottle::{Bottle, BottleConfig};
+use crate::gpu::GpuPreference;
+use crate::launch::upscaling::FsrOptions;
+use crate::sync::SyncMode;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Outcome of registering imported bottles
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// Names of the bottles added to the index
+    pub imported: Vec<String>,
+    /// Bottles that were not added, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Add imported bottles to `backend`
+///
+/// Bottles whose name is already in the index are skipped rather than
+/// overwritten, so running an import twice is harmless.
+pub fn register(backend: &dyn Backend, bottles: Vec<Bottle>) -> ImportReport {
+    let mut report = ImportReport::default();
+    for bottle in bottles {
+        match backend.add_bottle(&bottle) {
+            Ok(()) => report.imported.push(bottle.name),
+            Err(e) => report.skipped.push((bottle.name, e.to_string())),
+        }
+    }
+    report
+}
+
+/// Launcher settings that have no dedicated [`BottleConfig`] field, shared by
+/// the importers
+#[derive(Debug, Default)]
+struct Tweaks {
+    sync: SyncMode,
+    fsr: bool,
+    discrete_gpu: bool,
+    dll_overrides: Vec<(String, String)>,
+}
+
+impl Tweaks {
+    /// Store the sync mode, FSR and GPU selection, and translate the other
+    /// settings to the environment variables the launchers set for them;
+    /// variables already present in `config` win
+    fn apply(&self, config: &mut BottleConfig) {
+        config.sync = self.sync;
+        if self.fsr {
+            config.fsr.get_or_insert_with(FsrOptions::default);
+        }
+        if self.discrete_gpu && config.gpu == GpuPreference::Default {
+            config.gpu = GpuPreference::Discrete;
+        }
+        let mut set = |key: &str, value: String| {
+            config.environment.entry(key.to_string()).or_insert(value);
+        };
+        if !self.dll_overrides.is_empty() {
+            let overrides: Vec<String> = self
+                .dll_overrides
+                .iter()
+                .map(|(dll, mode)| format!("{}={}", dll, mode))
+                .collect();
+            set("WINEDLLOVERRIDES", overrides.join(";"));
+        }
+    }
+}
+
+/// Derive a runner name from the path of a wine or proton binary
+///
+/// Launchers store the binary path, while runners are identified by the name of
+/// their directory: `.../GE-Proton9-20/proton` and `.../wine-ge-8/bin/wine`
+/// give `GE-Proton9-20` and `wine-ge-8`.
+fn runner_name_from_binary(binary: &Path) -> Option<String> {
+    let parent = binary.parent()?;
+    let directory = if parent.file_name().is_some_and(|n| n == "bin") {
+        parent.parent()?
+    } else {
+        parent
+    };
+    directory.file_name().map(|n| n.to_string_lossy().to_string())
+}
+
+/// Stringify a YAML scalar; launchers are not consistent about quoting values
+fn yaml_scalar(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(s) => Some(s.clone()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        serde_yaml::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,158 @@
+use crate::bottle::{Bottle, BottleType};
+use crate::vdf;
+use crate::Error;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How bottles imported from Steam treat their prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SteamImportMode {
+    /// The prefix stays owned by Steam, see [`Bottle::read_only`]
+    #[default]
+    ReadOnly,
+    /// The prefix is managed like any other bottle, including deletion
+    Managed,
+}
+
+/// Locate the Steam installation of the current user
+///
+/// Checks the native and Flatpak locations, in that order.
+pub fn default_steam_root() -> Option<PathBuf> {
+    let home = PathBuf::from(std::env::var_os("HOME")?);
+    [
+        home.join(".steam/steam"),
+        home.join(".local/share/Steam"),
+        home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam"),
+    ]
+    .into_iter()
+    .find(|root| root.join("steamapps").is_dir())
+}
+
+/// Import the Proton prefixes of the games installed through Steam
+///
+/// Every Steam library listed in `libraryfolders.vdf` is scanned for
+/// `steamapps/compatdata/<appid>/pfx` prefixes, and the app ids are matched to
+/// game names through the `appmanifest_<appid>.acf` files. Prefixes without a
+/// manifest (uninstalled games) are still imported, named after their app id.
+///
+/// # Arguments
+///
+/// * `steam_root` - The Steam installation directory, see [`default_steam_root`]
+/// * `mode` - Whether the imported bottles may modify their prefix
+///
+/// # Errors
+///
+/// Returns an error if `steam_root` has no `steamapps` directory
+pub fn from_steam(steam_root: &Path, mode: SteamImportMode) -> Result<Vec<Bottle>, Error> {
+    let steamapps = steam_root.join("steamapps");
+    if !steamapps.is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("'{}' is not a Steam installation", steam_root.display()),
+        )
+        .into());
+    }
+
+    let libraries = libraries(steam_root);
+    let names = app_names(&libraries);
+
+    let mut bottles = Vec::new();
+    for library in &libraries {
+        let Ok(entries) = fs::read_dir(library.join("steamapps").join("compatdata")) else {
+            continue;
+        };
+        let mut entries: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        entries.sort();
+
+        for compat_data in entries {
+            let Some(appid) = compat_data
+                .file_name()
+                .and_then(|n| n.to_str())
+                .filter(|n| n.parse::<u64>().is_ok_and(|id| id != 0))
+                .map(str::to_string)
+            else {
+                continue;
+            };
+            let prefix = compat_data.join("pfx");
+            if !prefix.is_dir() {
+                continue;
+            }
+
+            let name = names
+                .get(&appid)
+                .cloned()
+                .unwrap_or_else(|| format!("Steam App {}", appid));
+            let mut bottle = Bottle::new(sanitize(&name), &prefix, BottleType::Gaming);
+            bottle.read_only = mode == SteamImportMode::ReadOnly;
+            let environment = &mut bottle.config.environment;
+            environment.insert("SteamAppId".into(), appid.clone());
+            environment.insert("SteamGameId".into(), appid.clone());
+            environment.insert(
+                "STEAM_COMPAT_DATA_PATH".into(),
+                compat_data.display().to_string(),
+            );
+            bottles.push(bottle);
+        }
+    }
+    Ok(bottles)
+}
+
+/// All Steam library folders, starting with the Steam root itself
+pub(crate) fn libraries(steam_root: &Path) -> Vec<PathBuf> {
+    let mut libraries = vec![steam_root.to_path_buf()];
+    let file = steam_root.join("steamapps").join("libraryfolders.vdf");
+    let Some(document) = fs::read_to_string(file).ok().and_then(|c| vdf::parse(&c)) else {
+        return libraries;
+    };
+
+    if let Some(folders) = document.get("libraryfolders") {
+        for (_, folder) in folders.entries() {
+            if let Some(path) = folder.get("path").and_then(|p| p.as_str()) {
+                let path = PathBuf::from(path);
+                let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+                let known = libraries
+                    .iter()
+                    .any(|l| l.canonicalize().unwrap_or_else(|_| l.clone()) == canonical);
+                if !known {
+                    libraries.push(path);
+                }
+            }
+        }
+    }
+    libraries
+}
+
+/// Map app ids to game names using the app manifests of every library
+fn app_names(libraries: &[PathBuf]) -> HashMap<String, String> {
+    let mut names = HashMap::new();
+    for library in libraries {
+        let Ok(entries) = fs::read_dir(library.join("steamapps")) else {
+            continue;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let is_manifest = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("appmanifest_") && n.ends_with(".acf"));
+            if !is_manifest {
+                continue;
+            }
+            let Some(manifest) = fs::read_to_string(&path).ok().and_then(|c| vdf::parse(&c)) else {
+                continue;
+            };
+            let appid = manifest.path(&["AppState", "appid"]).and_then(|v| v.as_str());
+            let name = manifest.path(&["AppState", "name"]).and_then(|v| v.as_str());
+            if let (Some(appid), Some(name)) = (appid, name) {
+                names.insert(appid.to_string(), name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// Game names can contain characters that are not valid in bottle names
+fn sanitize(name: &str) -> String {
+    name.replace(['/', '\\'], "-")
+}
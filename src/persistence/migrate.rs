@@ -0,0 +1,173 @@
+//! Upgrades of older on-disk formats to the current one
+//!
+//! Serialized bottles and configs carry a `version` field. Data is migrated as
+//! raw JSON before being deserialized, so a migration can rename fields or enum
+//! variants that the current structs no longer know about. Data written before
+//! versioning was introduced has no `version` field and is treated as version 0.
+//!
+//! To change the format, bump [`CURRENT_VERSION`] and append a migration to
+//! both [`BOTTLE_MIGRATIONS`] and [`CONFIG_MIGRATIONS`] (a no-op for the one
+//! that didn't change).
+
+use crate::bottle::Bottle;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Version of the format written by this crate
+pub const CURRENT_VERSION: u32 = 3;
+
+/// Format version stored in serialized bottles and configs
+///
+/// Defaults to [`CURRENT_VERSION`], so anything created in memory is written
+/// with the current version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct SchemaVersion(pub u32);
+
+impl Default for SchemaVersion {
+    fn default() -> Self {
+        Self(CURRENT_VERSION)
+    }
+}
+
+/// Upgrade of a serialized object by one version
+type Migration = fn(&mut Map<String, Value>);
+
+/// `BOTTLE_MIGRATIONS[n]` upgrades a bottle from version `n` to `n + 1`
+const BOTTLE_MIGRATIONS: &[Migration] = &[introduce_version, unchanged, unchanged];
+
+/// `CONFIG_MIGRATIONS[n]` upgrades a config from version `n` to `n + 1`
+const CONFIG_MIGRATIONS: &[Migration] =
+    &[introduce_version, sync_from_environment, fsr_from_environment];
+
+/// 0 → 1: the format itself is unchanged, only the `version` field is new
+fn introduce_version(_object: &mut Map<String, Value>) {}
+
+/// A version bump that didn't change this object
+fn unchanged(_object: &mut Map<String, Value>) {}
+
+/// 1 → 2: sync primitives moved from environment variables to `sync`
+fn sync_from_environment(object: &mut Map<String, Value>) {
+    let Some(Value::Object(environment)) = object.get_mut("environment") else {
+        return;
+    };
+    let variables = [
+        ("WINEESYNC", "Esync"),
+        ("WINEFSYNC", "Fsync"),
+        ("WINENTSYNC", "Ntsync"),
+    ];
+    // Nothing was set before, which leaves the choice to the runner
+    let mut sync = "Default";
+    for (variable, mode) in variables {
+        if environment.get(variable).and_then(Value::as_str) == Some("1") {
+            environment.remove(variable);
+            sync = mode;
+        }
+    }
+    object
+        .entry("sync".to_string())
+        .or_insert_with(|| Value::from(sync));
+}
+
+/// 2 → 3: Wine's FSR settings moved from environment variables to `fsr`
+fn fsr_from_environment(object: &mut Map<String, Value>) {
+    let Some(Value::Object(environment)) = object.get_mut("environment") else {
+        return;
+    };
+    if environment.get("WINE_FULLSCREEN_FSR").and_then(Value::as_str) != Some("1") {
+        return;
+    }
+    environment.remove("WINE_FULLSCREEN_FSR");
+
+    let mut fsr = Map::new();
+    if let Some(strength) = environment
+        .get("WINE_FULLSCREEN_FSR_STRENGTH")
+        .and_then(Value::as_str)
+        .and_then(|strength| strength.parse::<u8>().ok())
+    {
+        environment.remove("WINE_FULLSCREEN_FSR_STRENGTH");
+        fsr.insert("strength".to_string(), Value::from(strength));
+    }
+    if let Some((width, height)) = environment
+        .get("WINE_FULLSCREEN_FSR_CUSTOM_MODE")
+        .and_then(Value::as_str)
+        .and_then(|mode| mode.split_once('x'))
+        .and_then(|(width, height)| {
+            Some((width.parse::<u32>().ok()?, height.parse::<u32>().ok()?))
+        })
+    {
+        environment.remove("WINE_FULLSCREEN_FSR_CUSTOM_MODE");
+        fsr.insert("resolution".to_string(), Value::from(vec![width, height]));
+    }
+    object
+        .entry("fsr".to_string())
+        .or_insert_with(|| Value::Object(fsr));
+}
+
+/// Upgrade a serialized bottle, including its config, in place
+///
+/// # Errors
+///
+/// Returns an error if the value is not an object or was written by a newer
+/// version of this crate
+pub fn migrate_bottle(value: &mut Value) -> Result<(), Error> {
+    apply(value, BOTTLE_MIGRATIONS)?;
+    if let Some(config) = value.get_mut("config") {
+        migrate_config(config)?;
+    }
+    Ok(())
+}
+
+/// Upgrade a serialized bottle config in place
+pub fn migrate_config(value: &mut Value) -> Result<(), Error> {
+    apply(value, CONFIG_MIGRATIONS)
+}
+
+/// Deserialize a bottle, migrating it first
+pub fn bottle_from_str(content: &str) -> Result<Bottle, Error> {
+    let mut value: Value = serde_json::from_str(content)?;
+    migrate_bottle(&mut value)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Deserialize a list of bottles, migrating each of them first
+pub fn bottles_from_str(content: &str) -> Result<Vec<Bottle>, Error> {
+    let values: Vec<Value> = serde_json::from_str(content)?;
+    values
+        .into_iter()
+        .map(|mut value| {
+            migrate_bottle(&mut value)?;
+            Ok(serde_json::from_value(value)?)
+        })
+        .collect()
+}
+
+fn apply(value: &mut Value, migrations: &[Migration]) -> Result<(), Error> {
+    debug_assert_eq!(migrations.len(), CURRENT_VERSION as usize);
+    let Value::Object(object) = value else {
+        return Err(invalid("expected an object"));
+    };
+
+    let version = match object.get("version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| invalid("'version' is not a valid number"))?,
+    };
+    if version > CURRENT_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+
+    for migration in &migrations[version as usize..] {
+        migration(object);
+    }
+    object.insert("version".to_string(), Value::from(CURRENT_VERSION));
+    Ok(())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::Serde(<serde_json::Error as serde::de::Error>::custom(message))
+}
@@ -0,0 +1,206 @@
+pub mod import;
+pub mod migrate;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqlitePersistence;
+
+use crate::bottle::{Bottle, BottleFilter, BottleSummary};
+use crate::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Storage for the bottle index
+///
+/// The default backend is [`Persistence`], a single JSON file. Installations that
+/// update bottle state often can opt into `SqlitePersistence` through the
+/// `sqlite` feature; both behave the same from the caller's point of view.
+pub trait Backend: Send + Sync {
+    /// Load every bottle in the index, in the order they were saved
+    fn load_bottles(&self) -> Result<Vec<Bottle>, Error>;
+
+    /// Summarize every bottle in the index, in the order they were saved
+    ///
+    /// Only the index is read, backends may leave out what summaries don't
+    /// show.
+    fn load_summaries(&self) -> Result<Vec<BottleSummary>, Error> {
+        Ok(self.load_bottles()?.iter().map(BottleSummary::from).collect())
+    }
+
+    /// Summarize the bottles matching `filter`, in the order they were saved,
+    /// skipping the first `offset` of them and returning at most `limit`
+    ///
+    /// Backends that can read the index a bottle at a time only keep the
+    /// page in memory.
+    fn load_summaries_page(
+        &self,
+        filter: &BottleFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<BottleSummary>, Error> {
+        Ok(self
+            .load_summaries()?
+            .into_iter()
+            .filter(|summary| filter.matches(summary))
+            .skip(offset)
+            .take(limit)
+            .collect())
+    }
+
+    /// Replace the whole index with `bottles`
+    fn save_bottles(&self, bottles: &[Bottle]) -> Result<(), Error>;
+
+    /// Get a single bottle by name
+    fn get_bottle(&self, name: &str) -> Result<Option<Bottle>, Error>;
+
+    /// Append a bottle to the index
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::BottleExists`] if a bottle with the same name is already stored
+    fn add_bottle(&self, bottle: &Bottle) -> Result<(), Error>;
+
+    /// Replace the stored bottle with the same name as `bottle`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::BottleNotFound`] if no bottle with that name is stored
+    fn update_bottle(&self, bottle: &Bottle) -> Result<(), Error>;
+
+    /// Remove a bottle from the index, returning what was stored
+    ///
+    /// Only the index entry is removed, the prefix on disk is left untouched.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::BottleNotFound`] if no bottle with that name is stored
+    fn remove_bottle(&self, name: &str) -> Result<Bottle, Error>;
+}
+
+/// Span of the operation `operation` of `backend` on the index, concerning
+/// `bottle` if it is about a single one
+pub(crate) fn span(
+    backend: &'static str,
+    operation: &'static str,
+    bottle: Option<&str>,
+) -> tracing::span::EnteredSpan {
+    let span = tracing::debug_span!(
+        "persistence",
+        backend,
+        operation,
+        bottle = tracing::field::Empty
+    );
+    if let Some(bottle) = bottle {
+        span.record("bottle", bottle);
+    }
+    span.entered()
+}
+
+/// JSON file backend, storing the index in `bottles.json` under the base path
+///
+/// Every operation rewrites the whole file, but read-modify-write cycles are
+/// serialized within the process and the file is replaced atomically, so
+/// concurrent per-bottle updates don't lose each other's changes.
+pub struct Persistence {
+    base_path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl Persistence {
+    pub fn new(base_path: impl Into<PathBuf>) -> Self {
+        Self {
+            base_path: base_path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn index_file(&self) -> PathBuf {
+        self.base_path.join("bottles.json")
+    }
+
+    /// Load the index, let `f` change it and write it back, holding the lock
+    fn modify<T>(&self, f: impl FnOnce(&mut Vec<Bottle>) -> Result<T, Error>) -> Result<T, Error> {
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut bottles = self.read()?;
+        let result = f(&mut bottles)?;
+        self.write(&bottles)?;
+        Ok(result)
+    }
+
+    fn read(&self) -> Result<Vec<Bottle>, Error> {
+        let path = self.index_file();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = fs::read_to_string(&path).map_err(Error::Io)?;
+        migrate::bottles_from_str(&content)
+    }
+
+    fn write(&self, bottles: &[Bottle]) -> Result<(), Error> {
+        if !self.base_path.exists() {
+            fs::create_dir_all(&self.base_path).map_err(Error::Io)?;
+        }
+
+        let content = serde_json::to_string_pretty(bottles).map_err(|e| Error::Io(e.into()))?;
+        let temporary = self.index_file().with_extension("json.tmp");
+        fs::write(&temporary, content).map_err(Error::Io)?;
+        fs::rename(&temporary, self.index_file()).map_err(Error::Io)?;
+        Ok(())
+    }
+}
+
+impl Backend for Persistence {
+    fn load_bottles(&self) -> Result<Vec<Bottle>, Error> {
+        let _span = span("json", "load_bottles", None);
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.read()
+    }
+
+    fn save_bottles(&self, bottles: &[Bottle]) -> Result<(), Error> {
+        let _span = span("json", "save_bottles", None);
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.write(bottles)
+    }
+
+    fn get_bottle(&self, name: &str) -> Result<Option<Bottle>, Error> {
+        let _span = span("json", "get_bottle", Some(name));
+        Ok(self.load_bottles()?.into_iter().find(|b| b.name == name))
+    }
+
+    fn add_bottle(&self, bottle: &Bottle) -> Result<(), Error> {
+        let _span = span("json", "add_bottle", Some(&bottle.name));
+        self.modify(|bottles| {
+            if bottles.iter().any(|b| b.name == bottle.name) {
+                return Err(Error::BottleExists(bottle.name.clone()));
+            }
+            bottles.push(bottle.clone());
+            Ok(())
+        })
+    }
+
+    fn update_bottle(&self, bottle: &Bottle) -> Result<(), Error> {
+        let _span = span("json", "update_bottle", Some(&bottle.name));
+        self.modify(|bottles| {
+            let stored = bottles
+                .iter_mut()
+                .find(|b| b.name == bottle.name)
+                .ok_or_else(|| Error::BottleNotFound(bottle.name.clone()))?;
+            *stored = bottle.clone();
+            Ok(())
+        })
+    }
+
+    fn remove_bottle(&self, name: &str) -> Result<Bottle, Error> {
+        let _span = span("json", "remove_bottle", Some(name));
+        self.modify(|bottles| {
+            let index = bottles
+                .iter()
+                .position(|b| b.name == name)
+                .ok_or_else(|| Error::BottleNotFound(name.to_string()))?;
+            Ok(bottles.remove(index))
+        })
+    }
+}
@@ -0,0 +1,200 @@
+use super::{migrate, span, Backend};
+use crate::bottle::{Bottle, BottleFilter, BottleSummary};
+use crate::Error;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+/// Schema migrations, applied in order
+///
+/// The number of applied migrations is tracked in SQLite's `user_version` pragma,
+/// so released entries must never be edited: append a new migration instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: bottle index, bottles are stored as their JSON serialization
+    "CREATE TABLE bottles (
+        name TEXT PRIMARY KEY NOT NULL,
+        position INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );",
+];
+
+/// SQLite backend for the bottle index
+///
+/// Unlike the JSON backend, writes are transactional, so a crash while saving
+/// never leaves a truncated index behind.
+pub struct SqlitePersistence {
+    connection: Mutex<Connection>,
+}
+
+impl SqlitePersistence {
+    /// Open (or create) the database at `path` and bring its schema up to date
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(Error::Io)?;
+        }
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Open a database that only lives as long as the returned instance
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut connection: Connection) -> Result<Self, Error> {
+        migrate(&mut connection)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        // A panic while holding the lock can't leave the database inconsistent,
+        // every write happens inside a transaction
+        self.connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Apply the migrations the database hasn't seen yet
+fn migrate(connection: &mut Connection) -> Result<(), Error> {
+    let applied: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let applied = usize::try_from(applied).unwrap_or(0);
+    if applied >= MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    let transaction = connection.transaction()?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        tracing::info!("Applying SQLite persistence migration {}", index + 1);
+        transaction.execute_batch(migration)?;
+        transaction.execute_batch(&format!("PRAGMA user_version = {}", index + 1))?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+impl Backend for SqlitePersistence {
+    fn load_bottles(&self) -> Result<Vec<Bottle>, Error> {
+        let _span = span("sqlite", "load_bottles", None);
+        let connection = self.connection();
+        let mut statement = connection.prepare("SELECT data FROM bottles ORDER BY position")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+
+        let mut bottles = Vec::new();
+        for data in rows {
+            bottles.push(migrate::bottle_from_str(&data?)?);
+        }
+        Ok(bottles)
+    }
+
+    fn load_summaries_page(
+        &self,
+        filter: &BottleFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<BottleSummary>, Error> {
+        let _span = span("sqlite", "load_summaries_page", None);
+        let connection = self.connection();
+        let mut statement = connection.prepare("SELECT data FROM bottles ORDER BY position")?;
+        let mut rows = statement.query([])?;
+
+        let (mut skipped, mut summaries) = (0, Vec::new());
+        while summaries.len() < limit {
+            let Some(row) = rows.next()? else {
+                break;
+            };
+            let bottle = migrate::bottle_from_str(&row.get::<_, String>(0)?)?;
+            let summary = BottleSummary::from(&bottle);
+            if !filter.matches(&summary) {
+                continue;
+            }
+            if skipped < offset {
+                skipped += 1;
+            } else {
+                summaries.push(summary);
+            }
+        }
+        Ok(summaries)
+    }
+
+    fn save_bottles(&self, bottles: &[Bottle]) -> Result<(), Error> {
+        let _span = span("sqlite", "save_bottles", None);
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM bottles", [])?;
+        {
+            let mut insert = transaction
+                .prepare("INSERT INTO bottles (name, position, data) VALUES (?1, ?2, ?3)")?;
+            for (position, bottle) in bottles.iter().enumerate() {
+                let data = serde_json::to_string(bottle)?;
+                insert.execute(params![bottle.name, position as i64, data])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn get_bottle(&self, name: &str) -> Result<Option<Bottle>, Error> {
+        let _span = span("sqlite", "get_bottle", Some(name));
+        let data: Option<String> = self
+            .connection()
+            .query_row("SELECT data FROM bottles WHERE name = ?1", [name], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        match data {
+            Some(data) => Ok(Some(migrate::bottle_from_str(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn add_bottle(&self, bottle: &Bottle) -> Result<(), Error> {
+        let _span = span("sqlite", "add_bottle", Some(&bottle.name));
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let exists: bool = transaction.query_row(
+            "SELECT EXISTS(SELECT 1 FROM bottles WHERE name = ?1)",
+            [&bottle.name],
+            |row| row.get(0),
+        )?;
+        if exists {
+            return Err(Error::BottleExists(bottle.name.clone()));
+        }
+        transaction.execute(
+            "INSERT INTO bottles (name, position, data)
+             VALUES (?1, (SELECT COALESCE(MAX(position) + 1, 0) FROM bottles), ?2)",
+            params![bottle.name, serde_json::to_string(bottle)?],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn update_bottle(&self, bottle: &Bottle) -> Result<(), Error> {
+        let _span = span("sqlite", "update_bottle", Some(&bottle.name));
+        let updated = self.connection().execute(
+            "UPDATE bottles SET data = ?2 WHERE name = ?1",
+            params![bottle.name, serde_json::to_string(bottle)?],
+        )?;
+        if updated == 0 {
+            return Err(Error::BottleNotFound(bottle.name.clone()));
+        }
+        Ok(())
+    }
+
+    fn remove_bottle(&self, name: &str) -> Result<Bottle, Error> {
+        let _span = span("sqlite", "remove_bottle", Some(name));
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let data: String = transaction
+            .query_row("SELECT data FROM bottles WHERE name = ?1", [name], |row| {
+                row.get(0)
+            })
+            .optional()?
+            .ok_or_else(|| Error::BottleNotFound(name.to_string()))?;
+        transaction.execute("DELETE FROM bottles WHERE name = ?1", [name])?;
+        transaction.commit()?;
+        migrate::bottle_from_str(&data)
+    }
+}
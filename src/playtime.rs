@@ -0,0 +1,126 @@
+//! Time spent in bottles and their programs
+//!
+//! Every session is timed from its start until its process is found to have
+//! exited. The duration is added to the [`Playtime`] of the bottle, and of the
+//! library program that was launched if any (see [`crate::programs`]), both
+//! stored with the bottle in persistence. [`Stats`] aggregates them so
+//! frontends can show e.g. "played 12h, last played yesterday".
+
+use crate::bottle::Bottle;
+use crate::persistence::Backend;
+use crate::session::{Session, Sessions};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// How often running sessions are checked for having exited
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Accumulated time of a bottle or a program
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct Playtime {
+    pub total: Duration,
+    /// Number of sessions that ended
+    pub sessions: u64,
+    /// When the last session ended
+    pub last_played: Option<SystemTime>,
+}
+
+impl Playtime {
+    /// Add a session that ran from `started_at` to `ended_at`
+    pub fn add(&mut self, started_at: SystemTime, ended_at: SystemTime) {
+        self.total += ended_at.duration_since(started_at).unwrap_or_default();
+        self.sessions += 1;
+        self.last_played = self.last_played.max(Some(ended_at));
+    }
+
+    /// Combine the time of two bottles or programs
+    pub fn merge(&mut self, other: &Playtime) {
+        self.total += other.total;
+        self.sessions += other.sessions;
+        self.last_played = self.last_played.max(other.last_played);
+    }
+}
+
+/// Playtime of a program of a bottle's library
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgramPlaytime {
+    pub bottle: String,
+    /// Id of the program in the bottle's library
+    pub program: u64,
+    pub name: String,
+    pub playtime: Playtime,
+}
+
+/// Playtime across all bottles
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    /// Time of every bottle combined
+    pub total: Playtime,
+    /// Bottles that were played, most recently played first
+    pub bottles: Vec<(String, Playtime)>,
+    /// Library programs that were played, most recently played first
+    pub programs: Vec<ProgramPlaytime>,
+}
+
+impl Stats {
+    pub fn of(bottles: &[Bottle]) -> Self {
+        let mut stats = Self::default();
+        for bottle in bottles.iter().filter(|bottle| bottle.playtime.sessions > 0) {
+            stats.total.merge(&bottle.playtime);
+            stats.bottles.push((bottle.name.clone(), bottle.playtime));
+            let played = bottle.programs.iter().filter(|program| program.playtime.sessions > 0);
+            stats.programs.extend(played.map(|program| ProgramPlaytime {
+                bottle: bottle.name.clone(),
+                program: program.id,
+                name: program.name.clone(),
+                playtime: program.playtime,
+            }));
+        }
+        stats.bottles.sort_by(|a, b| b.1.last_played.cmp(&a.1.last_played));
+        stats
+            .programs
+            .sort_by(|a, b| b.playtime.last_played.cmp(&a.playtime.last_played));
+        stats
+    }
+}
+
+/// Record the playtime of `session` once it exited
+///
+/// `program` is the id of the library program the session runs, if any.
+pub(crate) fn track(
+    persistence: Arc<dyn Backend>,
+    sessions: Arc<Sessions>,
+    session: Session,
+    program: Option<u64>,
+) {
+    std::thread::spawn(move || {
+        while sessions.get(session.id).is_some() {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        if let Err(e) = record(persistence.as_ref(), &session, program, SystemTime::now()) {
+            tracing::warn!("Cannot record the playtime of session {}: {}", session.id, e);
+        }
+    });
+}
+
+fn record(
+    persistence: &dyn Backend,
+    session: &Session,
+    program: Option<u64>,
+    ended_at: SystemTime,
+) -> Result<(), Error> {
+    // The bottle may have been deleted in the meantime
+    let Some(mut bottle) = persistence.get_bottle(&session.bottle)? else {
+        return Ok(());
+    };
+    bottle.playtime.add(session.started_at, ended_at);
+    let program = program.and_then(|id| bottle.programs.iter_mut().find(|p| p.id == id));
+    if let Some(program) = program {
+        program.playtime.add(session.started_at, ended_at);
+    }
+    persistence.update_bottle(&bottle)
+}
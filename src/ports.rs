@@ -0,0 +1,301 @@
+//! Serial ports and USB devices of the host inside prefixes
+//!
+//! Flashing and diagnostic tools talk to their hardware through COM ports,
+//! which Wine maps to host serial devices with symbolic links named after
+//! the port in the prefix's `dosdevices` directory, like drives (see
+//! [`crate::drives`]), e.g. `com3` linking to `/dev/ttyUSB0`. The devices
+//! must be readable and writable by the user, usually by being in the
+//! `dialout` or `uucp` group.
+//!
+//! Wine has no generic USB passthrough: HID devices, like controllers,
+//! dongles and many flashing adapters, reach programs through Wine's
+//! `winebus` driver, either raw through `hidraw` or as input devices. Both
+//! can be turned off per bottle with [`UsbOptions`], e.g. when a program
+//! picks the wrong device. In Flatpak, devices are only visible when the
+//! sandbox is given access to all of them.
+
+use crate::components;
+use crate::runner::Runner;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Highest COM port number Wine maps
+pub const MAX_COM_PORT: u16 = 256;
+
+/// Prefixes of the host's USB serial adapters in `/dev`
+const SERIAL_DEVICES: &[&str] = &["ttyUSB", "ttyACM"];
+
+/// Registry key holding the settings of Wine's `winebus` driver
+const WINEBUS_KEY: &str = "HKEY_LOCAL_MACHINE\\System\\CurrentControlSet\\Services\\winebus";
+
+/// Directory listing the host's USB devices
+const USB_DEVICES: &str = "/sys/bus/usb/devices";
+
+/// A COM port of a prefix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerialPort {
+    /// Number of the port, `3` for `COM3`
+    pub number: u16,
+    /// Device the port maps to, on the host
+    pub device: PathBuf,
+}
+
+/// A serial device of the host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostSerialDevice {
+    pub device: PathBuf,
+    /// Whether the user can read and write it
+    pub accessible: bool,
+}
+
+/// A USB device of the host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsbDevice {
+    /// Vendor id, e.g. `046d`
+    pub vendor_id: String,
+    /// Product id, e.g. `c52b`
+    pub product_id: String,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    /// Device node in `/dev/bus/usb`
+    pub node: Option<PathBuf>,
+    /// Whether the user can open the device node, as needed by programs
+    /// using it through libusb
+    pub accessible: bool,
+}
+
+/// How the host's HID devices reach the programs of a bottle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct UsbOptions {
+    /// Let programs access HID devices directly through `hidraw`
+    pub hidraw: bool,
+    /// Let programs see keyboards, mice and controllers as HID devices
+    pub input: bool,
+}
+
+impl Default for UsbOptions {
+    fn default() -> Self {
+        Self {
+            hidraw: true,
+            input: true,
+        }
+    }
+}
+
+/// The COM ports of `prefix`, sorted by number
+pub fn serial_ports(prefix: &Path) -> Result<Vec<SerialPort>, Error> {
+    let dosdevices = prefix.join("dosdevices");
+    let mut ports = Vec::new();
+    for entry in fs::read_dir(&dosdevices).map_err(Error::Io)? {
+        let entry = entry.map_err(Error::Io)?;
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        let Some(number) = name.strip_prefix("com").and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        let Ok(device) = fs::read_link(entry.path()) else {
+            continue;
+        };
+        ports.push(SerialPort { number, device });
+    }
+    ports.sort_by_key(|port| port.number);
+    Ok(ports)
+}
+
+/// The USB serial adapters of the host
+pub fn host_serial_devices() -> Vec<HostSerialDevice> {
+    let Ok(entries) = fs::read_dir("/dev") else {
+        return Vec::new();
+    };
+    let mut devices: Vec<HostSerialDevice> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            SERIAL_DEVICES.iter().any(|prefix| name.starts_with(prefix))
+        })
+        .map(|entry| {
+            let device = entry.path();
+            let accessible = is_serial_accessible(&device);
+            HostSerialDevice { device, accessible }
+        })
+        .collect();
+    devices.sort_by(|a, b| a.device.cmp(&b.device));
+    devices
+}
+
+/// Map `COM<number>` of `prefix` to the host serial device `device`
+///
+/// # Errors
+///
+/// Returns an error if the port number is out of range or already mapped,
+/// or if `device` doesn't exist
+pub fn add_serial_port(prefix: &Path, number: u16, device: &Path) -> Result<SerialPort, Error> {
+    let link = serial_link(prefix, number)?;
+    if link.symlink_metadata().is_ok() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("COM{} is already mapped", number),
+        )
+        .into());
+    }
+    if !device.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("'{}' does not exist", device.display()),
+        )
+        .into());
+    }
+    if !is_serial_accessible(device) {
+        tracing::warn!(
+            "'{}' is not accessible, COM{} won't work until the user can read and write it",
+            device.display(),
+            number
+        );
+    }
+    std::os::unix::fs::symlink(device, &link).map_err(Error::Io)?;
+    tracing::info!("Mapped COM{} to '{}'", number, device.display());
+    Ok(SerialPort {
+        number,
+        device: device.to_path_buf(),
+    })
+}
+
+/// Unmap `COM<number>` of `prefix`
+pub fn remove_serial_port(prefix: &Path, number: u16) -> Result<(), Error> {
+    let link = serial_link(prefix, number)?;
+    fs::remove_file(&link).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => {
+            std::io::Error::new(e.kind(), format!("COM{} is not mapped", number)).into()
+        }
+        _ => Error::Io(e),
+    })
+}
+
+/// The USB devices of the host, hubs excluded
+pub fn usb_devices() -> Vec<UsbDevice> {
+    let Ok(entries) = fs::read_dir(USB_DEVICES) else {
+        return Vec::new();
+    };
+    let mut devices = Vec::new();
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let read = |name: &str| {
+            fs::read_to_string(path.join(name))
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        // Interfaces have no ids, hubs have class 09
+        let (Some(vendor_id), Some(product_id)) = (read("idVendor"), read("idProduct")) else {
+            continue;
+        };
+        if read("bDeviceClass").as_deref() == Some("09") {
+            continue;
+        }
+        let number = |name: &str| read(name).and_then(|value| value.parse::<u32>().ok());
+        let node = match (number("busnum"), number("devnum")) {
+            (Some(bus), Some(device)) => {
+                Some(PathBuf::from(format!("/dev/bus/usb/{:03}/{:03}", bus, device)))
+            }
+            _ => None,
+        };
+        devices.push(UsbDevice {
+            vendor_id,
+            product_id,
+            manufacturer: read("manufacturer"),
+            product: read("product"),
+            accessible: node.as_deref().is_some_and(is_accessible),
+            node,
+        });
+    }
+    devices.sort_by(|a, b| (&a.vendor_id, &a.product_id).cmp(&(&b.vendor_id, &b.product_id)));
+    devices
+}
+
+/// Write the `winebus` settings of `prefix`
+pub(crate) fn apply_usb(
+    runner: &dyn Runner,
+    prefix: &Path,
+    options: &UsbOptions,
+) -> Result<(), Error> {
+    let settings = [
+        ("DisableHidraw", !options.hidraw),
+        ("DisableInput", !options.input),
+    ];
+    for (name, disabled) in settings {
+        let value = u32::from(disabled).to_string();
+        let args = [
+            "add",
+            WINEBUS_KEY,
+            "/v",
+            name,
+            "/t",
+            "REG_DWORD",
+            "/d",
+            value.as_str(),
+            "/f",
+        ];
+        components::run(runner, prefix, "reg", &args)?;
+    }
+    Ok(())
+}
+
+/// Link of `COM<number>` in `dosdevices`
+fn serial_link(prefix: &Path, number: u16) -> Result<PathBuf, Error> {
+    if !(1..=MAX_COM_PORT).contains(&number) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("COM{} is not a port between COM1 and COM{}", number, MAX_COM_PORT),
+        )
+        .into());
+    }
+    Ok(prefix.join("dosdevices").join(format!("com{}", number)))
+}
+
+/// Whether the user can open a USB device node
+fn is_accessible(device: &Path) -> bool {
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device)
+        .is_ok()
+}
+
+/// Whether the permissions of a serial device let the user read and write
+/// it
+///
+/// Opening a serial port resets many boards, so it is not tried like for
+/// USB devices. ACLs are not taken into account.
+fn is_serial_accessible(device: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(metadata) = fs::metadata(device) else {
+        return false;
+    };
+    let Ok(status) = fs::read_to_string("/proc/self/status") else {
+        return false;
+    };
+    let ids = |field: &str| -> Vec<u32> {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(field))
+            .map(|ids| ids.split_whitespace().filter_map(|id| id.parse().ok()).collect())
+            .unwrap_or_default()
+    };
+    // The effective ids are the second ones
+    let uid = ids("Uid:").get(1).copied();
+    let gid = ids("Gid:").get(1).copied();
+    let mode = metadata.mode();
+    if uid == Some(0) {
+        true
+    } else if uid == Some(metadata.uid()) {
+        mode & 0o600 == 0o600
+    } else if gid == Some(metadata.gid()) || ids("Groups:").contains(&metadata.gid()) {
+        mode & 0o060 == 0o060
+    } else {
+        mode & 0o006 == 0o006
+    }
+}
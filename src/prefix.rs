@@ -0,0 +1,295 @@
+//! Point-in-time state of a Wine prefix
+//!
+//! A [`Snapshot`] records the files under `drive_c` and the registry hives of
+//! a prefix, so the changes made by a program can be found afterwards with
+//! [`diff`].
+
+use crate::bottle::Bottle;
+use crate::jobs::CancelToken;
+use crate::manager::Manager;
+use crate::registry::{Hive, RegistryFile, RegistryValue};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Registry keys every Wine session updates, which say nothing about what a
+/// program did
+const VOLATILE_KEYS: &[&str] = &[
+    "software\\microsoft\\windows\\currentversion\\explorer",
+    "software\\wine\\explorer",
+];
+
+/// Size and modification time of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileState {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Files and registry of a prefix at one point in time
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub prefix: PathBuf,
+    pub taken_at: SystemTime,
+    /// Files under `drive_c`, keyed by their path relative to the prefix
+    pub files: BTreeMap<PathBuf, FileState>,
+    pub registry: BTreeMap<Hive, RegistryFile>,
+}
+
+impl Snapshot {
+    /// Record the current state of `prefix`
+    ///
+    /// Symbolic links are not followed, so the user directories Wine links to
+    /// the host home are not walked. A hive that doesn't exist yet is recorded
+    /// as empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `drive_c` cannot be read
+    pub fn capture(prefix: &Path) -> Result<Self, Error> {
+        Self::capture_cancellable(prefix, &CancelToken::default())
+    }
+
+    /// Record the state of `prefix` like [`Snapshot::capture`], stopping with
+    /// [`Error::Cancelled`] once `token` is cancelled
+    pub fn capture_cancellable(prefix: &Path, token: &CancelToken) -> Result<Self, Error> {
+        let mut files = BTreeMap::new();
+        walk(prefix, &prefix.join("drive_c"), &mut files, token)?;
+        token.check()?;
+
+        let mut registry = BTreeMap::new();
+        for hive in [Hive::LocalMachine, Hive::CurrentUser] {
+            let file = match RegistryFile::load_hive(prefix, hive) {
+                Ok(file) => file,
+                Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                    RegistryFile::default()
+                }
+                Err(e) => return Err(e),
+            };
+            registry.insert(hive, file);
+        }
+
+        Ok(Self {
+            prefix: prefix.to_path_buf(),
+            taken_at: SystemTime::now(),
+            files,
+            registry,
+        })
+    }
+}
+
+fn walk(
+    prefix: &Path,
+    directory: &Path,
+    files: &mut BTreeMap<PathBuf, FileState>,
+    token: &CancelToken,
+) -> Result<(), Error> {
+    for entry in fs::read_dir(directory).map_err(Error::Io)? {
+        token.check()?;
+        let entry = entry.map_err(Error::Io)?;
+        let Ok(metadata) = entry.path().symlink_metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            // Unreadable directories (e.g. left behind by a crashed installer)
+            // shouldn't prevent recording the rest
+            match walk(prefix, &entry.path(), files, token) {
+                Err(Error::Cancelled) => return Err(Error::Cancelled),
+                Err(e) => tracing::debug!("Skipping '{}': {}", entry.path().display(), e),
+                Ok(()) => {}
+            }
+        } else if metadata.is_file() {
+            let path = entry.path();
+            let relative = path.strip_prefix(prefix).unwrap_or(&path).to_path_buf();
+            files.insert(
+                relative,
+                FileState {
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+/// A registry value added, changed or removed between two snapshots
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValueChange {
+    /// Full key path, e.g. `HKEY_CURRENT_USER\Software\Wine\DllOverrides`
+    pub key: String,
+    /// Value name, empty for the default value
+    pub name: String,
+    /// `None` if the value was added
+    pub before: Option<RegistryValue>,
+    /// `None` if the value was removed
+    pub after: Option<RegistryValue>,
+}
+
+/// What changed in a prefix between two snapshots
+///
+/// Files are relative to the prefix and registry keys are full paths. Keys
+/// Wine updates in every session are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixDiff {
+    pub added_files: Vec<PathBuf>,
+    pub modified_files: Vec<PathBuf>,
+    pub removed_files: Vec<PathBuf>,
+    pub added_keys: Vec<String>,
+    pub removed_keys: Vec<String>,
+    /// Values changed in keys that exist in both snapshots, and the values
+    /// of added and removed keys
+    pub values: Vec<ValueChange>,
+}
+
+impl PrefixDiff {
+    /// Whether nothing changed
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Values that were added or changed, with their new value
+    pub fn written_values(&self) -> impl Iterator<Item = (&ValueChange, &RegistryValue)> {
+        self.values
+            .iter()
+            .filter_map(|change| change.after.as_ref().map(|value| (change, value)))
+    }
+}
+
+/// Compare two snapshots of the same prefix
+pub fn diff(before: &Snapshot, after: &Snapshot) -> PrefixDiff {
+    let mut report = PrefixDiff::default();
+    for (path, state) in &after.files {
+        match before.files.get(path) {
+            None => report.added_files.push(path.clone()),
+            Some(previous) if previous != state => report.modified_files.push(path.clone()),
+            Some(_) => {}
+        }
+    }
+    report.removed_files = before
+        .files
+        .keys()
+        .filter(|path| !after.files.contains_key(*path))
+        .cloned()
+        .collect();
+
+    let empty = RegistryFile::default();
+    let hives: BTreeSet<&Hive> = before.registry.keys().chain(after.registry.keys()).collect();
+    for hive in hives {
+        let old = before.registry.get(hive).unwrap_or(&empty);
+        let new = after.registry.get(hive).unwrap_or(&empty);
+        let full = |name: &str| format!("{}\\{}", hive.root_name(), name);
+        let mut change = |key: &str, name: &str, from: Option<&RegistryValue>, to| {
+            report.values.push(ValueChange {
+                key: full(key),
+                name: name.to_string(),
+                before: from.cloned(),
+                after: to,
+            });
+        };
+
+        for key in new.keys().filter(|key| !is_volatile(&key.name)) {
+            let previous = old.key(&key.name);
+            for (name, value) in &key.values {
+                let old_value = previous.and_then(|previous| previous.value(name));
+                if old_value != Some(value) {
+                    change(&key.name, name, old_value, Some(value.clone()));
+                }
+            }
+            if let Some(previous) = previous {
+                for (name, value) in &previous.values {
+                    if key.value(name).is_none() {
+                        change(&key.name, name, Some(value), None);
+                    }
+                }
+            }
+        }
+        for key in old.keys().filter(|key| !is_volatile(&key.name)) {
+            if !new.contains_key(&key.name) {
+                for (name, value) in &key.values {
+                    change(&key.name, name, Some(value), None);
+                }
+            }
+        }
+
+        report.added_keys.extend(
+            new.keys()
+                .filter(|key| !is_volatile(&key.name) && !old.contains_key(&key.name))
+                .map(|key| full(&key.name)),
+        );
+        report.removed_keys.extend(
+            old.keys()
+                .filter(|key| !is_volatile(&key.name) && !new.contains_key(&key.name))
+                .map(|key| full(&key.name)),
+        );
+    }
+    report
+}
+
+fn is_volatile(key: &str) -> bool {
+    let lowered = key.to_lowercase();
+    VOLATILE_KEYS.iter().any(|volatile| lowered.starts_with(volatile))
+}
+
+/// Wait for the wineserver of the bottle to exit, which is when Wine writes
+/// the registry back to the hive files
+pub(crate) fn flush_registry(manager: &Manager, bottle: &Bottle) {
+    let Ok(runner) = manager.runner_for(bottle) else {
+        return;
+    };
+    if let Err(e) = runner.wine().wineserver_wait(&bottle.path) {
+        tracing::warn!(
+            "Cannot wait for the wineserver of '{}', the registry may be incomplete: {}",
+            bottle.name,
+            e
+        );
+    }
+}
+
+/// Windows path of a file given relative to the prefix, e.g.
+/// `drive_c/Program Files/App/app.exe` → `C:\Program Files\App\app.exe`
+///
+/// Returns `None` for files outside `drive_c`.
+pub fn windows_path(relative: &Path) -> Option<String> {
+    let rest = relative.strip_prefix("drive_c").ok()?;
+    let components: Vec<String> = rest
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect();
+    Some(format!("C:\\{}", components.join("\\")))
+}
+
+/// Host path of a Windows path inside `prefix`, e.g.
+/// `C:\Program Files\App\app.exe` → `<prefix>/drive_c/Program Files/App/app.exe`
+///
+/// Drives other than `C:` are resolved through the prefix's `dosdevices`.
+/// Returns `None` for paths without a drive letter.
+pub fn host_path(prefix: &Path, windows: &str) -> Option<PathBuf> {
+    let (drive, rest) = windows.trim().trim_matches('"').split_once(':')?;
+    if drive.len() != 1 || !drive.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let drive = drive.to_ascii_lowercase();
+    let root = if drive == "c" {
+        prefix.join("drive_c")
+    } else {
+        prefix.join("dosdevices").join(format!("{}:", drive))
+    };
+    let components = rest.split(['\\', '/']).filter(|component| !component.is_empty());
+    Some(components.fold(root, |path, component| path.join(component)))
+}
+
+/// Host path of a program as passed to a runner: absolute host paths are
+/// kept, Windows paths resolved with [`host_path`], and other paths taken as
+/// relative to `drive_c`
+pub fn host_executable(prefix: &Path, program: &Path) -> PathBuf {
+    if program.is_absolute() {
+        return program.to_path_buf();
+    }
+    let path = program.to_string_lossy();
+    host_path(prefix, &path)
+        .unwrap_or_else(|| prefix.join("drive_c").join(path.replace('\\', "/")))
+}
@@ -0,0 +1,26 @@
+//! What most frontends need, in one import
+//!
+//! ```no_run
+//! use bottles_core::prelude::*;
+//!
+//! let manager = Manager::new("/home/user/.local/share/bottles");
+//! for summary in manager.bottle_summaries(BottleFilter::default()) {
+//!     println!("{}", summary?.name);
+//! }
+//! # Ok::<(), Error>(())
+//! ```
+//!
+//! Only items of the stable tier are re-exported, see the
+//! [crate documentation](crate).
+
+pub use crate::bottle::{Bottle, BottleConfig, BottleFilter, BottleState, BottleSummary, BottleType};
+pub use crate::catalog::{Catalog, Snapshot};
+pub use crate::jobs::{CancelToken, Job, JobKind, JobStatus};
+pub use crate::manager::{CreationReport, Manager};
+pub use crate::manifest::BottleManifest;
+pub use crate::runner::{
+    Capabilities, Proton, ProtonVersion, Runner, RunnerFlavor, RunnerInfo, Wine,
+};
+pub use crate::session::Session;
+pub use crate::version::Version;
+pub use crate::Error;
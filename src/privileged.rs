@@ -0,0 +1,371 @@
+//! Operations that need more privileges than the daemon has
+//!
+//! The daemon runs as the user, but some operations write to system-wide
+//! locations, such as installing a runner for every user of the machine. They
+//! are described as an [`Operation`] and handed to a [`Helper`], which decides
+//! how to get the privileges: [`Direct`] performs the operation in-process, and
+//! with the `polkit` feature [`Pkexec`] runs a small helper executable through
+//! `pkexec`, so the user is asked to authenticate instead of running the whole
+//! daemon as root.
+//!
+//! The helper executable is expected to call [`Operation::from_args`] on its
+//! command line and then [`Operation::perform`].
+
+use crate::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Where runners available to every user are installed
+pub const SYSTEM_RUNNERS_DIR: &str = "/usr/local/share/bottles/runners";
+
+/// A privileged operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    /// Copy the runner in `source` to `SYSTEM_RUNNERS_DIR/<name>`, replacing any
+    /// runner with the same name
+    InstallRunner { source: PathBuf, name: String },
+    /// Remove `SYSTEM_RUNNERS_DIR/<name>`
+    RemoveRunner { name: String },
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InstallRunner { name, .. } => write!(f, "install runner '{}'", name),
+            Self::RemoveRunner { name } => write!(f, "remove runner '{}'", name),
+        }
+    }
+}
+
+impl Operation {
+    /// Encode the operation as helper command line arguments
+    pub fn to_args(&self) -> Vec<OsString> {
+        match self {
+            Self::InstallRunner { source, name } => vec![
+                "install-runner".into(),
+                source.clone().into_os_string(),
+                name.into(),
+            ],
+            Self::RemoveRunner { name } => vec!["remove-runner".into(), name.into()],
+        }
+    }
+
+    /// Decode helper command line arguments (without the program name)
+    pub fn from_args(args: &[OsString]) -> Result<Self, Error> {
+        let args: Vec<&str> = args.iter().filter_map(|arg| arg.to_str()).collect();
+        match args.as_slice() {
+            ["install-runner", source, name] => Ok(Self::InstallRunner {
+                source: PathBuf::from(source),
+                name: name.to_string(),
+            }),
+            ["remove-runner", name] => Ok(Self::RemoveRunner {
+                name: name.to_string(),
+            }),
+            _ => Err(invalid("unknown privileged operation".to_string())),
+        }
+    }
+
+    /// Carry out the operation with the privileges of the current process
+    ///
+    /// When running under `pkexec`, every file copied from the caller must be
+    /// owned by the calling user, so the helper cannot be used to publish files
+    /// the user could not read themselves.
+    pub fn perform(&self) -> Result<(), Error> {
+        let root = Path::new(SYSTEM_RUNNERS_DIR);
+        match self {
+            Self::InstallRunner { source, name } => {
+                validate_name(name)?;
+                if !source.is_absolute() || !source.is_dir() {
+                    return Err(invalid(format!(
+                        "'{}' is not a runner directory",
+                        source.display()
+                    )));
+                }
+                let caller = std::env::var("PKEXEC_UID")
+                    .ok()
+                    .and_then(|uid| uid.parse::<u32>().ok());
+
+                fs::create_dir_all(root).map_err(Error::Io)?;
+                let target = root.join(name);
+                let staging = root.join(format!(".{}.partial", name));
+                if staging.exists() {
+                    fs::remove_dir_all(&staging).map_err(Error::Io)?;
+                }
+                if let Err(e) = copy_tree(source, &staging, caller) {
+                    let _ = fs::remove_dir_all(&staging);
+                    return Err(e);
+                }
+                if target.exists() {
+                    fs::remove_dir_all(&target).map_err(Error::Io)?;
+                }
+                fs::rename(&staging, &target).map_err(Error::Io)?;
+                tracing::info!("Installed system runner '{}'", name);
+                Ok(())
+            }
+            Self::RemoveRunner { name } => {
+                validate_name(name)?;
+                let target = root.join(name);
+                if !target.is_dir() {
+                    return Err(Error::RunnerNotFound(name.clone()));
+                }
+                fs::remove_dir_all(&target).map_err(Error::Io)?;
+                tracing::info!("Removed system runner '{}'", name);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Something able to carry out privileged operations
+pub trait Helper: Send + Sync {
+    /// Run `operation`, asking for authorization if needed
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotAuthorized`] if the user refused or failed to
+    /// authenticate
+    fn run(&self, operation: &Operation) -> Result<(), Error>;
+}
+
+/// Performs operations in-process; only useful when already privileged
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Direct;
+
+impl Helper for Direct {
+    fn run(&self, operation: &Operation) -> Result<(), Error> {
+        operation.perform().map_err(|e| match e {
+            Error::Io(io) if io.kind() == std::io::ErrorKind::PermissionDenied => {
+                Error::NotAuthorized(operation.to_string())
+            }
+            e => e,
+        })
+    }
+}
+
+/// Runs a helper executable through `pkexec`
+///
+/// Install the policy returned by [`Pkexec::policy`] to
+/// `/usr/share/polkit-1/actions` so the authentication dialog names the helper
+/// instead of showing a generic "run a program as root" prompt.
+#[cfg(feature = "polkit")]
+#[derive(Debug, Clone)]
+pub struct Pkexec {
+    helper: PathBuf,
+}
+
+#[cfg(feature = "polkit")]
+impl Pkexec {
+    /// Polkit action covering every privileged operation
+    pub const ACTION: &'static str = "org.bottles.next.privileged";
+
+    /// Default location of the helper executable
+    pub const DEFAULT_HELPER: &'static str = "/usr/libexec/bottles-next-helper";
+
+    pub fn new(helper: impl Into<PathBuf>) -> Self {
+        Self {
+            helper: helper.into(),
+        }
+    }
+
+    /// Render the polkit action file for the helper
+    pub fn policy(&self) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE policyconfig PUBLIC "-//freedesktop//DTD PolicyKit Policy Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/PolicyKit/1/policyconfig.dtd">
+<policyconfig>
+  <action id="{}">
+    <description>Manage system-wide Bottles runners</description>
+    <message>Authentication is required to install or remove runners for all users</message>
+    <defaults>
+      <allow_any>auth_admin</allow_any>
+      <allow_inactive>auth_admin</allow_inactive>
+      <allow_active>auth_admin_keep</allow_active>
+    </defaults>
+    <annotate key="org.freedesktop.policykit.exec.path">{}</annotate>
+  </action>
+</policyconfig>
+"#,
+            Self::ACTION,
+            self.helper.display()
+        )
+    }
+}
+
+#[cfg(feature = "polkit")]
+impl Default for Pkexec {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_HELPER)
+    }
+}
+
+#[cfg(feature = "polkit")]
+impl Helper for Pkexec {
+    fn run(&self, operation: &Operation) -> Result<(), Error> {
+        let status = std::process::Command::new("pkexec")
+            .arg(&self.helper)
+            .args(operation.to_args())
+            .status()
+            .map_err(Error::Io)?;
+        match status.code() {
+            Some(0) => Ok(()),
+            // pkexec uses 126 when authorization was refused and 127 when the
+            // dialog was dismissed or authentication failed
+            Some(126) | Some(127) => Err(Error::NotAuthorized(operation.to_string())),
+            _ => Err(std::io::Error::other(format!(
+                "privileged helper failed to {} ({})",
+                operation, status
+            ))
+            .into()),
+        }
+    }
+}
+
+/// The helper the daemon should use on this system
+///
+/// [`Direct`] when running as root, otherwise [`Pkexec`] with the `polkit`
+/// feature. The helper location can be overridden with
+/// `BOTTLES_PRIVILEGED_HELPER`.
+pub fn default_helper() -> Box<dyn Helper> {
+    #[cfg(feature = "polkit")]
+    {
+        if !is_root() {
+            let helper = std::env::var_os("BOTTLES_PRIVILEGED_HELPER")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(Pkexec::DEFAULT_HELPER));
+            return Box::new(Pkexec::new(helper));
+        }
+    }
+    Box::new(Direct)
+}
+
+/// Copy a directory tree, keeping permission bits and symlinks
+///
+/// Entries are opened without following symlinks and checked once open,
+/// through the descriptors of their directories, so parts of the tree can't
+/// be swapped for links to other files while it is copied. If `owner` is set,
+/// every entry must belong to that user. Only the permission bits are kept,
+/// never the setuid, setgid and sticky ones, and the copies belong to root
+/// when running as root.
+///
+/// Directories are listed through `/proc/self/fd`, so this only works on
+/// Linux.
+fn copy_tree(source: &Path, target: &Path, owner: Option<u32>) -> Result<(), Error> {
+    let directory = open_entry(source).map_err(Error::Io)?;
+    copy_directory(&directory, source, target, owner)
+}
+
+/// Copy the open directory `directory`, found at `source`, to `target`
+fn copy_directory(
+    directory: &fs::File,
+    source: &Path,
+    target: &Path,
+    owner: Option<u32>,
+) -> Result<(), Error> {
+    let metadata = directory.metadata().map_err(Error::Io)?;
+    check_owner(source, &metadata, owner)?;
+    if !metadata.is_dir() {
+        return Err(invalid(format!("'{}' is not a directory", source.display())));
+    }
+    fs::DirBuilder::new()
+        .mode(permissions(&metadata))
+        .create(target)
+        .map_err(Error::Io)?;
+    // The mode given to mkdir is reduced by the umask
+    fs::set_permissions(target, fs::Permissions::from_mode(permissions(&metadata)))
+        .map_err(Error::Io)?;
+    if is_root() {
+        std::os::unix::fs::chown(target, Some(0), Some(0)).map_err(Error::Io)?;
+    }
+
+    let opened = PathBuf::from(format!("/proc/self/fd/{}", directory.as_raw_fd()));
+    for entry in fs::read_dir(&opened).map_err(Error::Io)? {
+        let name = entry.map_err(Error::Io)?.file_name();
+        let path = source.join(&name);
+        let destination = target.join(&name);
+        let metadata = fs::symlink_metadata(opened.join(&name)).map_err(Error::Io)?;
+        check_owner(&path, &metadata, owner)?;
+
+        if metadata.file_type().is_symlink() {
+            let link = fs::read_link(opened.join(&name)).map_err(Error::Io)?;
+            std::os::unix::fs::symlink(link, &destination).map_err(Error::Io)?;
+            if is_root() {
+                std::os::unix::fs::lchown(&destination, Some(0), Some(0)).map_err(Error::Io)?;
+            }
+        } else if metadata.is_dir() || metadata.is_file() {
+            let mut file = open_entry(&opened.join(&name)).map_err(Error::Io)?;
+            // What was opened is checked again, the entry may have changed
+            let metadata = file.metadata().map_err(Error::Io)?;
+            if metadata.is_dir() {
+                copy_directory(&file, &path, &destination, owner)?;
+            } else if metadata.is_file() {
+                check_owner(&path, &metadata, owner)?;
+                copy_file(&mut file, &metadata, &destination)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copy the open file `source` to a new file at `target`
+fn copy_file(source: &mut fs::File, metadata: &fs::Metadata, target: &Path) -> Result<(), Error> {
+    let mut copy = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(permissions(metadata))
+        .open(target)
+        .map_err(Error::Io)?;
+    std::io::copy(source, &mut copy).map_err(Error::Io)?;
+    if is_root() {
+        std::os::unix::fs::fchown(&copy, Some(0), Some(0)).map_err(Error::Io)?;
+    }
+    copy.set_permissions(fs::Permissions::from_mode(permissions(metadata)))
+        .map_err(Error::Io)?;
+    Ok(())
+}
+
+/// Open a file or directory for reading, failing on a symlink
+///
+/// `O_NONBLOCK` keeps a FIFO swapped in from blocking the open, it is then
+/// skipped like any entry that isn't a file or a directory.
+fn open_entry(path: &Path) -> std::io::Result<fs::File> {
+    fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path)
+}
+
+fn check_owner(path: &Path, metadata: &fs::Metadata, owner: Option<u32>) -> Result<(), Error> {
+    match owner {
+        Some(uid) if metadata.uid() != uid => Err(invalid(format!(
+            "'{}' is not owned by the requesting user",
+            path.display()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Permission bits of an entry, without setuid, setgid and sticky
+fn permissions(metadata: &fs::Metadata) -> u32 {
+    metadata.mode() & 0o777
+}
+
+fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+fn validate_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(invalid(format!("'{}' is not a valid runner name", name)));
+    }
+    Ok(())
+}
+
+fn invalid(message: String) -> Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message).into()
+}
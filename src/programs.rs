@@ -0,0 +1,345 @@
+//! Programs installed in a prefix
+//!
+//! Installers register what they install under the `Uninstall` keys of the
+//! registry, like on Windows, which gives the name, version and uninstaller
+//! of most programs. Programs copied into `Program Files` without an
+//! installer are found by looking for directories no registered program
+//! claims, named after the directory and described by their main executable
+//! (see [`crate::pe`]).
+//!
+//! The library of a bottle lists the [`Program`]s users launch from it,
+//! whether they were found this way, added by hand, or native Linux programs
+//! tracked alongside the Windows ones. Every program carries its own launch
+//! configuration: arguments, working directory, variables overriding the
+//! bottle's environment, and shell scripts run before it starts and after it
+//! exits.
+
+use crate::pe;
+use crate::playtime::Playtime;
+use crate::prefix;
+use crate::registry::{Hive, RegistryFile, RegistryKey, RegistryValue};
+use crate::session::Sessions;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Keys holding one subkey per installed program, in both hives
+const UNINSTALL_KEYS: &[&str] = &[
+    "Software\\Microsoft\\Windows\\CurrentVersion\\Uninstall",
+    "Software\\Wow6432Node\\Microsoft\\Windows\\CurrentVersion\\Uninstall",
+];
+
+/// Program directories of a prefix, relative to `drive_c`
+const PROGRAM_DIRS: &[&str] = &["Program Files", "Program Files (x86)"];
+
+/// How often the end of a session is checked for, to run post-launch scripts
+const EXIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Directories Wine creates in [`PROGRAM_DIRS`] for its own programs
+const WINE_DIRS: &[&str] = &[
+    "Common Files",
+    "Internet Explorer",
+    "Windows Media Player",
+    "Windows NT",
+];
+
+/// How a [`Program`] of the library runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ProgramKind {
+    /// A Windows program, run by a runner
+    #[default]
+    Windows,
+    /// A Linux executable started directly, with the bottle's environment
+    /// and launch wrappers but without Wine
+    Native,
+}
+
+/// A program of a bottle's library
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Program {
+    /// Identifier of the program in its bottle
+    pub id: u64,
+    pub name: String,
+    /// Executable, as passed to the runner for Windows programs, a host path
+    /// for native ones
+    pub executable: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub kind: ProgramKind,
+    /// Runner used instead of the bottle's, always `None` for
+    /// [`ProgramKind::Native`] programs
+    #[serde(default)]
+    pub runner: Option<String>,
+    /// Directory the program starts in, on the host; the executable's
+    /// directory for native programs when unset
+    #[serde(default)]
+    pub working_dir: Option<PathBuf>,
+    /// Variables overriding the bottle's environment, as per-launch overrides
+    /// (see [`crate::environment`])
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+    /// Shell script run before the program starts, a failure cancels the
+    /// launch
+    #[serde(default)]
+    pub pre_launch: Option<String>,
+    /// Shell script run once the program exited
+    #[serde(default)]
+    pub post_launch: Option<String>,
+    /// Time spent running the program, recorded by the manager
+    #[serde(default)]
+    pub playtime: Playtime,
+}
+
+impl Program {
+    /// A Windows program run by the bottle's runner, named after its
+    /// executable
+    ///
+    /// The id is assigned when the program is added to a bottle, see
+    /// [`crate::manager::Manager::add_program`].
+    pub fn new(executable: impl Into<PathBuf>) -> Self {
+        let executable = executable.into();
+        let name = executable
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self {
+            id: 0,
+            name,
+            executable,
+            args: Vec::new(),
+            kind: ProgramKind::Windows,
+            runner: None,
+            working_dir: None,
+            environment: HashMap::new(),
+            pre_launch: None,
+            post_launch: None,
+            playtime: Playtime::default(),
+        }
+    }
+
+    /// A native Linux program, named after its executable
+    pub fn native(executable: impl Into<PathBuf>) -> Self {
+        Self {
+            kind: ProgramKind::Native,
+            ..Self::new(executable)
+        }
+    }
+}
+
+/// Run a launch script of a program with `sh -c`
+///
+/// The script gets the program's environment and runs in `directory`.
+///
+/// # Errors
+///
+/// Returns an error if the script cannot be started or fails
+pub(crate) fn run_script(
+    script: &str,
+    env: &HashMap<String, String>,
+    directory: &Path,
+) -> Result<(), Error> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(script)
+        .envs(env)
+        .current_dir(directory)
+        .stdin(Stdio::null())
+        .status()
+        .map_err(Error::Io)?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!("launch script failed: {}", status)).into());
+    }
+    Ok(())
+}
+
+/// Run `script` once session `id` exited, see [`run_script`]
+pub(crate) fn run_after_exit(
+    sessions: Arc<Sessions>,
+    id: u64,
+    script: String,
+    env: HashMap<String, String>,
+    directory: PathBuf,
+) {
+    std::thread::spawn(move || {
+        while sessions.get(id).is_some() {
+            std::thread::sleep(EXIT_POLL_INTERVAL);
+        }
+        if let Err(e) = run_script(&script, &env, &directory) {
+            tracing::warn!("Post-launch script of session {}: {}", id, e);
+        }
+    });
+}
+
+/// Where an [`InstalledProgram`] was found
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ProgramSource {
+    /// Registered by its installer, under this key of `hive`
+    Registry { hive: Hive, key: String },
+    /// Found in a program directory without registration
+    ProgramFiles,
+}
+
+/// A program installed in a prefix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct InstalledProgram {
+    pub name: String,
+    pub version: Option<String>,
+    pub publisher: Option<String>,
+    /// Install directory on the host
+    pub install_location: Option<PathBuf>,
+    /// Command removing the program, as a Windows command line
+    pub uninstall_command: Option<String>,
+    /// Executable or icon file holding the program's icon, on the host
+    pub icon: Option<PathBuf>,
+    pub source: ProgramSource,
+}
+
+/// Find the programs installed in `prefix`, sorted by name
+///
+/// Updates and system components hidden from Windows' own list are skipped.
+///
+/// # Errors
+///
+/// Returns an error if `prefix` doesn't exist
+pub fn installed(prefix: &Path) -> Result<Vec<InstalledProgram>, Error> {
+    if !prefix.is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("'{}' does not exist", prefix.display()),
+        )
+        .into());
+    }
+
+    let mut programs = Vec::new();
+    for hive in [Hive::LocalMachine, Hive::CurrentUser] {
+        let Ok(registry) = RegistryFile::load_hive(prefix, hive) else {
+            continue;
+        };
+        for uninstall in UNINSTALL_KEYS {
+            for key in registry.subkeys(uninstall) {
+                let Some(program) = registered(prefix, hive, key) else {
+                    continue;
+                };
+                // 64-bit installers may register in both views
+                let duplicate = programs.iter().any(|other: &InstalledProgram| {
+                    other.name == program.name && other.version == program.version
+                });
+                if !duplicate {
+                    programs.push(program);
+                }
+            }
+        }
+    }
+
+    for directory in PROGRAM_DIRS {
+        let Ok(entries) = fs::read_dir(prefix.join("drive_c").join(directory)) else {
+            continue;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !path.is_dir() || WINE_DIRS.iter().any(|dir| dir.eq_ignore_ascii_case(&name)) {
+                continue;
+            }
+            let claimed = programs.iter().any(|program| {
+                program
+                    .install_location
+                    .as_ref()
+                    .is_some_and(|location| same_path(location, &path))
+            });
+            if !claimed {
+                programs.push(unregistered(name, path));
+            }
+        }
+    }
+
+    programs.sort_by_key(|program| program.name.to_lowercase());
+    Ok(programs)
+}
+
+/// The program registered under `key`, if it is shown in Windows' list
+fn registered(prefix: &Path, hive: Hive, key: &RegistryKey) -> Option<InstalledProgram> {
+    let text = |name: &str| {
+        key.value(name)
+            .and_then(RegistryValue::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+    let hidden = key
+        .value("SystemComponent")
+        .and_then(RegistryValue::as_u64)
+        .is_some_and(|value| value != 0);
+    if hidden || text("ParentKeyName").is_some() || text("ReleaseType").is_some() {
+        return None;
+    }
+    let name = text("DisplayName")?;
+
+    let icon = text("DisplayIcon").and_then(|icon| {
+        // Icons are given as `path,index`
+        let path = match icon.rsplit_once(',') {
+            Some((path, index)) if index.trim().parse::<i32>().is_ok() => path.to_string(),
+            _ => icon,
+        };
+        prefix::host_path(prefix, &path)
+    });
+    Some(InstalledProgram {
+        name,
+        version: text("DisplayVersion"),
+        publisher: text("Publisher"),
+        install_location: text("InstallLocation")
+            .and_then(|location| prefix::host_path(prefix, &location)),
+        uninstall_command: text("QuietUninstallString").or_else(|| text("UninstallString")),
+        icon,
+        source: ProgramSource::Registry {
+            hive,
+            key: key.name.clone(),
+        },
+    })
+}
+
+/// A program directory nothing registered, described by its largest
+/// executable
+fn unregistered(name: String, directory: PathBuf) -> InstalledProgram {
+    let executable = fs::read_dir(&directory)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_lowercase();
+            name.ends_with(".exe") && !name.starts_with("unins")
+        })
+        .filter_map(|entry| Some((entry.metadata().ok()?.len(), entry.path())))
+        .max()
+        .map(|(_, path)| path);
+    let version = executable
+        .as_deref()
+        .and_then(|executable| pe::metadata(executable).ok())
+        .and_then(|metadata| metadata.version);
+    InstalledProgram {
+        name,
+        version: version.as_ref().and_then(|version| version.product_version.clone()),
+        publisher: version.and_then(|version| version.company_name),
+        install_location: Some(directory),
+        uninstall_command: None,
+        icon: executable,
+        source: ProgramSource::ProgramFiles,
+    }
+}
+
+/// Whether two host paths point to the same directory, ignoring case like
+/// Windows does
+fn same_path(a: &Path, b: &Path) -> bool {
+    let normalize = |path: &Path| path.to_string_lossy().trim_end_matches('/').to_lowercase();
+    normalize(a) == normalize(b)
+}
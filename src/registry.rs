@@ -0,0 +1,396 @@
+use crate::limits::Limits;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Registry hives Wine stores as plain text files in the prefix root
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Hive {
+    /// `HKEY_LOCAL_MACHINE`, stored in `system.reg`
+    LocalMachine,
+    /// `HKEY_CURRENT_USER`, stored in `user.reg`
+    CurrentUser,
+}
+
+impl Hive {
+    /// Name of the file backing this hive inside a prefix
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Self::LocalMachine => "system.reg",
+            Self::CurrentUser => "user.reg",
+        }
+    }
+
+    /// Canonical root name of the hive, as used by `reg.exe` and `regedit`
+    pub fn root_name(&self) -> &'static str {
+        match self {
+            Self::LocalMachine => "HKEY_LOCAL_MACHINE",
+            Self::CurrentUser => "HKEY_CURRENT_USER",
+        }
+    }
+
+    /// Split a full key path such as `HKLM\Software\Wine` into its hive and the
+    /// path relative to it
+    ///
+    /// Both the long (`HKEY_LOCAL_MACHINE`) and short (`HKLM`) root names are
+    /// accepted, case-insensitively.
+    ///
+    /// # Returns
+    ///
+    /// The hive and the remaining subkey, or `None` if the root is not a hive
+    /// backed by a prefix file
+    pub fn split(path: &str) -> Option<(Hive, &str)> {
+        let (root, rest) = match path.find('\\') {
+            Some(index) => (&path[..index], &path[index + 1..]),
+            None => (path, ""),
+        };
+        let hive = match root.to_ascii_uppercase().as_str() {
+            "HKEY_LOCAL_MACHINE" | "HKLM" => Hive::LocalMachine,
+            "HKEY_CURRENT_USER" | "HKCU" => Hive::CurrentUser,
+            _ => return None,
+        };
+        Some((hive, rest))
+    }
+}
+
+/// A single value stored under a registry key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum RegistryValue {
+    /// `REG_SZ`
+    String(String),
+    /// `REG_EXPAND_SZ`
+    ExpandString(String),
+    /// `REG_MULTI_SZ`
+    MultiString(Vec<String>),
+    /// `REG_DWORD`
+    Dword(u32),
+    /// `REG_QWORD`
+    Qword(u64),
+    /// `REG_BINARY`
+    Binary(Vec<u8>),
+    /// Any other value type, kept as its numeric type and raw bytes
+    Raw { kind: u32, data: Vec<u8> },
+}
+
+impl RegistryValue {
+    /// Get the value as a string if it is one of the string types
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) | Self::ExpandString(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a number if it is a `REG_DWORD` or `REG_QWORD`
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::Dword(v) => Some(u64::from(*v)),
+            Self::Qword(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+/// A registry key and its values
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryKey {
+    /// Key path relative to its hive, with its original casing
+    pub name: String,
+    /// Values keyed by name; the default value uses the empty name
+    pub values: BTreeMap<String, RegistryValue>,
+}
+
+impl RegistryKey {
+    /// Look up a value by name, ignoring case like Windows does
+    pub fn value(&self, name: &str) -> Option<&RegistryValue> {
+        self.values
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+}
+
+/// Parsed contents of a Wine registry file (`system.reg`, `user.reg`, ...)
+///
+/// Keys are looked up case-insensitively. The parser is lenient: lines it does
+/// not understand are skipped rather than failing the whole file, since the
+/// files are written by whatever Wine version last touched the prefix.
+#[derive(Debug, Clone, Default)]
+pub struct RegistryFile {
+    keys: BTreeMap<String, RegistryKey>,
+}
+
+impl RegistryFile {
+    /// Read and parse a registry file from disk
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or exceeds the
+    /// [default limits](Limits)
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let too_large = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("'{}' is too large for a registry file", path.display()),
+            )
+        };
+        let size = fs::metadata(path).map_err(Error::Io)?.len();
+        if !usize::try_from(size).is_ok_and(|size| Limits::DEFAULT.allows_size(size)) {
+            return Err(too_large().into());
+        }
+        let content = fs::read_to_string(path).map_err(Error::Io)?;
+        Self::parse_with_limits(&content, &Limits::DEFAULT).ok_or_else(|| too_large().into())
+    }
+
+    /// Read the file backing `hive` inside `prefix`
+    pub fn load_hive(prefix: &Path, hive: Hive) -> Result<Self, Error> {
+        Self::load(&prefix.join(hive.file_name()))
+    }
+
+    /// Parse the textual contents of a registry file
+    ///
+    /// Contents exceeding the [default limits](Limits) parse as an empty
+    /// file, see [`RegistryFile::parse_with_limits`].
+    pub fn parse(content: &str) -> Self {
+        Self::parse_with_limits(content, &Limits::DEFAULT).unwrap_or_default()
+    }
+
+    /// Parse the textual contents of a registry file, within `limits`
+    ///
+    /// Every key and every value counts as an item. Returns `None` if the
+    /// contents exceed `limits`; malformed lines are skipped as by
+    /// [`RegistryFile::parse`].
+    pub fn parse_with_limits(content: &str, limits: &Limits) -> Option<Self> {
+        if !limits.allows_size(content.len()) {
+            return None;
+        }
+        let mut keys = BTreeMap::new();
+        let mut current: Option<RegistryKey> = None;
+        let mut items = 0;
+
+        for line in logical_lines(content) {
+            if items > limits.max_items {
+                return None;
+            }
+            if line.starts_with('[') {
+                if let Some(key) = current.take() {
+                    keys.insert(key.name.to_lowercase(), key);
+                }
+                if let Some(end) = line.rfind(']') {
+                    items += 1;
+                    current = Some(RegistryKey {
+                        name: unescape(&line[1..end]),
+                        values: BTreeMap::new(),
+                    });
+                }
+            } else if let Some(key) = current.as_mut() {
+                if let Some((name, value)) = parse_value_line(&line) {
+                    items += 1;
+                    key.values.insert(name, value);
+                }
+            }
+        }
+        if items > limits.max_items {
+            return None;
+        }
+        if let Some(key) = current.take() {
+            keys.insert(key.name.to_lowercase(), key);
+        }
+
+        Some(Self { keys })
+    }
+
+    /// Get a key by its path relative to the hive, e.g. `Software\Wine\Drives`
+    pub fn key(&self, path: &str) -> Option<&RegistryKey> {
+        self.keys.get(&normalize(path))
+    }
+
+    /// Check whether a key exists
+    pub fn contains_key(&self, path: &str) -> bool {
+        self.keys.contains_key(&normalize(path))
+    }
+
+    /// Iterate over all keys in the file
+    pub fn keys(&self) -> impl Iterator<Item = &RegistryKey> {
+        self.keys.values()
+    }
+
+    /// Iterate over the direct children of `parent`
+    pub fn subkeys<'a>(&'a self, parent: &str) -> impl Iterator<Item = &'a RegistryKey> + 'a {
+        let prefix = format!("{}\\", normalize(parent));
+        self.keys
+            .iter()
+            .filter(move |(path, _)| {
+                path.strip_prefix(&prefix)
+                    .is_some_and(|rest| !rest.is_empty() && !rest.contains('\\'))
+            })
+            .map(|(_, key)| key)
+    }
+}
+
+fn normalize(path: &str) -> String {
+    path.trim_matches('\\').to_lowercase()
+}
+
+/// Join lines continued with a trailing backslash, as used by long hex values
+fn logical_lines(content: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut pending = String::new();
+    for line in content.lines() {
+        let trimmed = line.trim();
+        let continued =
+            trimmed.ends_with('\\') && (!pending.is_empty() || trimmed.contains("=hex"));
+        if continued {
+            pending.push_str(&trimmed[..trimmed.len() - 1]);
+        } else if !pending.is_empty() {
+            pending.push_str(trimmed);
+            lines.push(std::mem::take(&mut pending));
+        } else {
+            lines.push(trimmed.to_string());
+        }
+    }
+    if !pending.is_empty() {
+        lines.push(pending);
+    }
+    lines
+}
+
+fn parse_value_line(line: &str) -> Option<(String, RegistryValue)> {
+    let (name, rest) = if let Some(rest) = line.strip_prefix('@') {
+        (String::new(), rest)
+    } else if line.starts_with('"') {
+        parse_quoted(line)?
+    } else {
+        return None;
+    };
+    let data = rest.trim_start().strip_prefix('=')?.trim();
+    Some((name, parse_data(data)?))
+}
+
+fn parse_data(data: &str) -> Option<RegistryValue> {
+    if data.starts_with('"') {
+        let (value, _) = parse_quoted(data)?;
+        return Some(RegistryValue::String(value));
+    }
+    if let Some(rest) = data.strip_prefix("str(") {
+        let (kind, rest) = rest.split_once("):")?;
+        let (value, _) = parse_quoted(rest)?;
+        return Some(match kind {
+            "2" => RegistryValue::ExpandString(value),
+            "7" => RegistryValue::MultiString(split_multi(&value)),
+            _ => RegistryValue::String(value),
+        });
+    }
+    if let Some(rest) = data.strip_prefix("dword:") {
+        return u32::from_str_radix(rest.trim(), 16)
+            .ok()
+            .map(RegistryValue::Dword);
+    }
+    if let Some(rest) = data.strip_prefix("hex:") {
+        return Some(RegistryValue::Binary(parse_hex(rest)));
+    }
+    if let Some(rest) = data.strip_prefix("hex(") {
+        let (kind, rest) = rest.split_once("):")?;
+        let kind = u32::from_str_radix(kind, 16).ok()?;
+        let bytes = parse_hex(rest);
+        return Some(match kind {
+            1 => RegistryValue::String(decode_utf16(&bytes)),
+            2 => RegistryValue::ExpandString(decode_utf16(&bytes)),
+            3 => RegistryValue::Binary(bytes),
+            4 if bytes.len() == 4 => {
+                RegistryValue::Dword(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+            7 => RegistryValue::MultiString(split_multi(&decode_utf16(&bytes))),
+            0xb if bytes.len() == 8 => {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(&bytes);
+                RegistryValue::Qword(u64::from_le_bytes(buf))
+            }
+            _ => RegistryValue::Raw { kind, data: bytes },
+        });
+    }
+    None
+}
+
+fn parse_hex(data: &str) -> Vec<u8> {
+    data.split(',')
+        .filter_map(|byte| u8::from_str_radix(byte.trim(), 16).ok())
+        .collect()
+}
+
+fn decode_utf16(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+fn split_multi(value: &str) -> Vec<String> {
+    value
+        .split('\0')
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parse a quoted, escaped string at the start of `input`
+///
+/// Returns the unescaped contents and whatever follows the closing quote.
+fn parse_quoted(input: &str) -> Option<(String, &str)> {
+    let body = input.strip_prefix('"')?;
+    let mut escaped = false;
+    for (index, c) in body.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            return Some((unescape(&body[..index]), &body[index + 1..]));
+        }
+    }
+    None
+}
+
+fn unescape(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('x') => {
+                let mut code = String::new();
+                while code.len() < 4 && chars.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                    code.extend(chars.next());
+                }
+                if let Some(c) = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                    out.push(c);
+                }
+            }
+            Some(d) if d.is_digit(8) => {
+                let mut code = d.to_string();
+                while code.len() < 3 && chars.peek().is_some_and(|c| c.is_digit(8)) {
+                    code.extend(chars.next());
+                }
+                if let Some(c) = u32::from_str_radix(&code, 8).ok().and_then(char::from_u32) {
+                    out.push(c);
+                }
+            }
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
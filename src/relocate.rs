@@ -0,0 +1,136 @@
+//! Moving the prefix of a bottle, see [`Manager::move_bottle`]
+//!
+//! A prefix is renamed in place when it stays on its file system, and copied
+//! then removed otherwise. Wine and installers record absolute host paths in
+//! the registry, either as they are or through the `Z:` drive mapping the
+//! host's root, and the config of a bottle holds host paths too: those under
+//! the previous location are rewritten to the new one.
+//!
+//! [`Manager::move_bottle`]: crate::manager::Manager::move_bottle
+
+use crate::bottle::Bottle;
+use crate::Error;
+use std::fs;
+use std::path::Path;
+
+/// Registry files of a prefix
+pub(crate) const REGISTRY_FILES: &[&str] = &["system.reg", "user.reg", "userdef.reg"];
+
+/// Move the directory `from` to `to`, which must not exist
+///
+/// Directories on another file system are copied, keeping symbolic links,
+/// then removed. A copy that fails half-way is removed again.
+pub(crate) fn move_dir(from: &Path, to: &Path) -> Result<(), Error> {
+    match fs::rename(from, to) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.raw_os_error() != Some(libc::EXDEV) => return Err(Error::Io(e)),
+        Err(_) => {}
+    }
+    tracing::debug!("Copying '{}' to another file system", from.display());
+    if let Err(e) = copy_tree(from, to) {
+        let _ = fs::remove_dir_all(to);
+        return Err(e);
+    }
+    fs::remove_dir_all(from).map_err(Error::Io)
+}
+
+/// `bottle` with the host paths under `from` moved under `to`
+pub(crate) fn rewrite_bottle(bottle: &Bottle, from: &Path, to: &Path) -> Result<Bottle, Error> {
+    let (from, to) = (from.to_string_lossy(), to.to_string_lossy());
+    let mut value = serde_json::to_value(bottle)?;
+    rewrite_value(&mut value, &from, &to);
+    let mut moved: Bottle = serde_json::from_value(value)?;
+    moved.active = bottle.active;
+    moved.state = bottle.state.clone();
+    Ok(moved)
+}
+
+fn rewrite_value(value: &mut serde_json::Value, from: &str, to: &str) {
+    match value {
+        serde_json::Value::String(string) => {
+            if let Some(rewritten) = rewrite_str(string, from, to) {
+                *string = rewritten;
+            }
+        }
+        serde_json::Value::Array(values) => {
+            values.iter_mut().for_each(|value| rewrite_value(value, from, to));
+        }
+        serde_json::Value::Object(map) => {
+            map.values_mut().for_each(|value| rewrite_value(value, from, to));
+        }
+        _ => {}
+    }
+}
+
+/// `string` with the paths under `from` moved under `to`, `None` if it has
+/// none
+fn rewrite_str(string: &str, from: &str, to: &str) -> Option<String> {
+    if string == from {
+        return Some(to.to_string());
+    }
+    let (from, to) = (format!("{}/", from), format!("{}/", to));
+    string.contains(&from).then(|| string.replace(&from, &to))
+}
+
+/// Rewrite the paths under `from` to `to` in the registry file `file`
+///
+/// # Returns
+///
+/// Whether the file changed, `false` if it doesn't exist
+pub(crate) fn rewrite_registry(file: &Path, from: &Path, to: &Path) -> Result<bool, Error> {
+    let content = match fs::read(file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(Error::Io(e)),
+    };
+    let (from, to) = (from.to_string_lossy(), to.to_string_lossy());
+    // Backslashes are escaped in registry files, `Z:\\home\\user`
+    let windows = |path: &str| format!("Z:{}", path.replace('/', "\\\\"));
+    let mut rewritten = content.clone();
+    for (from, to) in [(windows(&from), windows(&to)), (from.to_string(), to.to_string())] {
+        for separator in ["\\\\", "/", "\""] {
+            let from = format!("{}{}", from, separator);
+            let to = format!("{}{}", to, separator);
+            rewritten = replace(&rewritten, from.as_bytes(), to.as_bytes());
+        }
+    }
+    if rewritten == content {
+        return Ok(false);
+    }
+    fs::write(file, rewritten).map_err(Error::Io)?;
+    Ok(true)
+}
+
+fn replace(haystack: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(haystack.len());
+    let mut rest = haystack;
+    while let Some(index) = rest.windows(from.len()).position(|window| window == from) {
+        result.extend_from_slice(&rest[..index]);
+        result.extend_from_slice(to);
+        rest = &rest[index + from.len()..];
+    }
+    result.extend_from_slice(rest);
+    result
+}
+
+/// Copy a directory tree, keeping permissions and symbolic links
+fn copy_tree(source: &Path, target: &Path) -> Result<(), Error> {
+    let metadata = fs::symlink_metadata(source).map_err(Error::Io)?;
+    fs::create_dir(target).map_err(Error::Io)?;
+    fs::set_permissions(target, metadata.permissions()).map_err(Error::Io)?;
+    for entry in fs::read_dir(source).map_err(Error::Io)? {
+        let entry = entry.map_err(Error::Io)?;
+        let path = entry.path();
+        let destination = target.join(entry.file_name());
+        let file_type = entry.file_type().map_err(Error::Io)?;
+        if file_type.is_dir() {
+            copy_tree(&path, &destination)?;
+        } else if file_type.is_symlink() {
+            let link = fs::read_link(&path).map_err(Error::Io)?;
+            std::os::unix::fs::symlink(link, &destination).map_err(Error::Io)?;
+        } else if file_type.is_file() {
+            fs::copy(&path, &destination).map_err(Error::Io)?;
+        }
+    }
+    Ok(())
+}
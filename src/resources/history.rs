@@ -0,0 +1,320 @@
+//! Per-bottle history of session performance
+//!
+//! When [`BottleConfig::performance_history`] is enabled, [`record`] samples a
+//! session while it runs and appends a [`SessionSummary`] to the bottle's
+//! history once it exits. Only summaries are stored and old entries are
+//! dropped (see [`MAX_ENTRIES`] and [`MAX_AGE`]), so the file stays small.
+//! [`History::compare`] groups the sessions of a program by the components
+//! they ran with, to answer questions like "did the last runner update make
+//! this game slower?".
+//!
+//! Frame times are read from MangoHud's frame logs, so they are only recorded
+//! for bottles with MangoHud enabled.
+
+use super::Sampler;
+use crate::bottle::BottleConfig;
+use crate::session::{Session, Sessions};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// How often a recorded session is sampled
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Sessions kept per bottle, the oldest are dropped first
+pub const MAX_ENTRIES: usize = 200;
+
+/// Age after which sessions are dropped from the history
+pub const MAX_AGE: Duration = Duration::from_secs(180 * 24 * 60 * 60);
+
+/// Serializes the updates of history files by concurrent recorders
+static UPDATES: Mutex<()> = Mutex::new(());
+
+/// What a session ran with, the key performance is compared by
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Components {
+    pub runner: Option<String>,
+    pub dxvk_version: Option<String>,
+    pub vkd3d_version: Option<String>,
+}
+
+impl Components {
+    pub fn of(config: &BottleConfig) -> Self {
+        Self {
+            runner: config.runner.clone(),
+            dxvk_version: config.dxvk_version.clone(),
+            vkd3d_version: config.vkd3d_version.clone(),
+        }
+    }
+}
+
+/// Frame pacing of a session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameSummary {
+    pub frames: u64,
+    pub avg_fps: f32,
+    /// Average frame rate of the slowest 1% of frames
+    pub low_1_percent_fps: f32,
+    /// 99th percentile of the frame times
+    pub p99_frame_time_ms: f32,
+}
+
+impl FrameSummary {
+    /// Summarize frame times given in milliseconds
+    pub fn from_frame_times(frame_times: &[f32]) -> Option<Self> {
+        let mut sorted: Vec<f32> = frame_times
+            .iter()
+            .copied()
+            .filter(|time| time.is_finite() && *time > 0.0)
+            .collect();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_by(f32::total_cmp);
+
+        let fps = |times: &[f32]| 1000.0 * times.len() as f32 / times.iter().sum::<f32>();
+        let slowest = sorted.len().div_ceil(100);
+        let p99 = ((sorted.len() as f32 * 0.99).ceil() as usize).clamp(1, sorted.len());
+        Some(Self {
+            frames: sorted.len() as u64,
+            avg_fps: fps(&sorted),
+            low_1_percent_fps: fps(&sorted[sorted.len() - slowest..]),
+            p99_frame_time_ms: sorted[p99 - 1],
+        })
+    }
+
+    /// Summarize a MangoHud frame log
+    ///
+    /// The log starts with a system information block, followed by a header
+    /// line naming the columns (among them `frametime`, in milliseconds) and
+    /// one line per frame.
+    pub fn from_mangohud_log(content: &str) -> Option<Self> {
+        Self::from_frame_times(&mangohud_frame_times(content))
+    }
+}
+
+/// Performance of a finished session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub program: PathBuf,
+    pub components: Components,
+    pub started_at: SystemTime,
+    pub duration_secs: u64,
+    /// Resource samples the averages are computed from
+    pub samples: u32,
+    pub avg_cpu_percent: f32,
+    pub peak_memory_bytes: u64,
+    pub avg_gpu_percent: Option<f32>,
+    pub frames: Option<FrameSummary>,
+}
+
+/// Averages of the sessions of a program that ran with the same components
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComponentStats {
+    pub components: Components,
+    pub sessions: u32,
+    pub last_played: SystemTime,
+    pub avg_cpu_percent: f32,
+    pub avg_gpu_percent: Option<f32>,
+    pub avg_fps: Option<f32>,
+    pub low_1_percent_fps: Option<f32>,
+}
+
+/// The recorded sessions of a bottle, oldest first
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct History {
+    pub sessions: Vec<SessionSummary>,
+}
+
+impl History {
+    /// Load a history file, an empty history if it doesn't exist
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    /// Write the history, replacing the file atomically
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(Error::Io)?;
+        }
+        let staging = path.with_extension("json.tmp");
+        fs::write(&staging, serde_json::to_string(self)?).map_err(Error::Io)?;
+        fs::rename(&staging, path).map_err(Error::Io)
+    }
+
+    /// Add a session and drop the entries past the retention limits
+    pub fn push(&mut self, summary: SessionSummary) {
+        self.sessions.push(summary);
+        let now = SystemTime::now();
+        self.sessions.retain(|session| {
+            now.duration_since(session.started_at)
+                .map_or(true, |age| age <= MAX_AGE)
+        });
+        let excess = self.sessions.len().saturating_sub(MAX_ENTRIES);
+        self.sessions.drain(..excess);
+    }
+
+    /// Performance of `program` for every set of components it ran with, the
+    /// most recently played first
+    pub fn compare(&self, program: &Path) -> Vec<ComponentStats> {
+        let mut stats: Vec<(ComponentStats, Vec<&SessionSummary>)> = Vec::new();
+        for session in self.sessions.iter().filter(|s| s.program == program) {
+            match stats
+                .iter_mut()
+                .find(|(stats, _)| stats.components == session.components)
+            {
+                Some((_, sessions)) => sessions.push(session),
+                None => stats.push((
+                    ComponentStats {
+                        components: session.components.clone(),
+                        sessions: 0,
+                        last_played: session.started_at,
+                        avg_cpu_percent: 0.0,
+                        avg_gpu_percent: None,
+                        avg_fps: None,
+                        low_1_percent_fps: None,
+                    },
+                    vec![session],
+                )),
+            }
+        }
+
+        let mut stats: Vec<ComponentStats> = stats
+            .into_iter()
+            .map(|(mut stats, sessions)| {
+                stats.sessions = sessions.len() as u32;
+                if let Some(last) = sessions.iter().map(|s| s.started_at).max() {
+                    stats.last_played = last;
+                }
+                stats.avg_cpu_percent =
+                    average(sessions.iter().map(|s| s.avg_cpu_percent)).unwrap_or(0.0);
+                stats.avg_gpu_percent = average(sessions.iter().filter_map(|s| s.avg_gpu_percent));
+                let frames = || sessions.iter().filter_map(|s| s.frames.as_ref());
+                stats.avg_fps = average(frames().map(|f| f.avg_fps));
+                stats.low_1_percent_fps = average(frames().map(|f| f.low_1_percent_fps));
+                stats
+            })
+            .collect();
+        stats.sort_by(|a, b| b.last_played.cmp(&a.last_played));
+        stats
+    }
+}
+
+/// MangoHud options starting a frame log in `directory` as soon as the
+/// program starts, to append to `MANGOHUD_CONFIG`
+pub fn frame_log_options(directory: &Path) -> String {
+    format!("output_folder={},autostart_log=1,log_duration=0", directory.display())
+}
+
+/// Sample a session in the background and add its summary to the history at
+/// `path` once it exits
+///
+/// `frames` is the directory the session's MangoHud frame logs are written to,
+/// see [`frame_log_options`]; it is removed after being summarized.
+pub fn record(
+    sessions: Arc<Sessions>,
+    session: Session,
+    components: Components,
+    path: PathBuf,
+    frames: Option<PathBuf>,
+) {
+    std::thread::spawn(move || {
+        let mut sampler = Sampler::default();
+        let (mut samples, mut cpu, mut gpu, mut gpu_samples) = (0u32, 0.0f32, 0.0f32, 0u32);
+        let mut peak_memory_bytes = 0;
+
+        // The first sample only sets the baseline of the rates
+        let mut baseline = true;
+        while let Some(current) = sessions.get(session.id) {
+            if let Some(usage) = sampler.sample(std::slice::from_ref(&current)).pop() {
+                peak_memory_bytes = peak_memory_bytes.max(usage.memory_bytes);
+                if !baseline {
+                    samples += 1;
+                    cpu += usage.cpu_percent;
+                    if let Some(percent) = usage.gpu_percent {
+                        gpu += percent;
+                        gpu_samples += 1;
+                    }
+                }
+                baseline = false;
+            }
+            std::thread::sleep(SAMPLE_INTERVAL);
+        }
+
+        let frames = frames.and_then(|directory| {
+            let summary = frame_logs(&directory);
+            if let Err(e) = fs::remove_dir_all(&directory) {
+                tracing::debug!("Cannot remove '{}': {}", directory.display(), e);
+            }
+            summary
+        });
+        let summary = SessionSummary {
+            program: session.program.clone(),
+            components,
+            started_at: session.started_at,
+            duration_secs: session.started_at.elapsed().map_or(0, |d| d.as_secs()),
+            samples,
+            avg_cpu_percent: if samples > 0 { cpu / samples as f32 } else { 0.0 },
+            peak_memory_bytes,
+            avg_gpu_percent: (gpu_samples > 0).then(|| gpu / gpu_samples as f32),
+            frames,
+        };
+
+        let _guard = UPDATES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let result = History::load(&path).and_then(|mut history| {
+            history.push(summary);
+            history.save(&path)
+        });
+        if let Err(e) = result {
+            tracing::warn!(
+                "Cannot record the performance of session {}: {}",
+                session.id,
+                e
+            );
+        }
+    });
+}
+
+/// Summary of the frames of all the logs in a directory
+fn frame_logs(directory: &Path) -> Option<FrameSummary> {
+    let mut frame_times = Vec::new();
+    for entry in fs::read_dir(directory).ok()?.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "csv") {
+            let content = fs::read_to_string(&path).unwrap_or_default();
+            frame_times.extend(mangohud_frame_times(&content));
+        }
+    }
+    FrameSummary::from_frame_times(&frame_times)
+}
+
+/// Frame times of a MangoHud frame log, in milliseconds
+fn mangohud_frame_times(content: &str) -> Vec<f32> {
+    let mut lines = content.lines();
+    let Some(column) = lines.by_ref().find_map(|line| {
+        let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+        if !columns.contains(&"fps") {
+            return None;
+        }
+        columns.iter().position(|column| *column == "frametime")
+    }) else {
+        return Vec::new();
+    };
+    lines
+        .filter_map(|line| line.split(',').nth(column)?.trim().parse().ok())
+        .collect()
+}
+
+fn average(values: impl Iterator<Item = f32>) -> Option<f32> {
+    let (sum, count) = values.fold((0.0, 0u32), |(sum, count), value| (sum + value, count + 1));
+    (count > 0).then(|| sum / count as f32)
+}
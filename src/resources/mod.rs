@@ -0,0 +1,264 @@
+//! CPU, memory and GPU usage of running sessions
+//!
+//! Usage is measured over the whole process tree of a session, since Wine runs
+//! a program as several processes (the runner script, wineserver clients,
+//! helper services). CPU and GPU usage are rates, so they are computed from
+//! the difference between two samples taken by the same [`Sampler`].
+//!
+//! GPU usage comes from the DRM `fdinfo` interface, which the amdgpu, i915, xe
+//! and nouveau/nova drivers implement; it is `None` on drivers without it.
+//!
+//! Summaries of finished sessions can be kept per bottle, see [`history`].
+
+pub mod history;
+
+use crate::session::Session;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::time::Instant;
+
+/// Resource usage of a session's process tree
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub session: u64,
+    pub bottle: String,
+    pub pid: u32,
+    /// Processes in the tree, including the session's own process
+    pub processes: u32,
+    /// CPU time used since the previous sample; 100 means one core
+    pub cpu_percent: f32,
+    /// Resident memory of all processes
+    pub memory_bytes: u64,
+    /// Busy time of the busiest GPU engine since the previous sample
+    pub gpu_percent: Option<f32>,
+    /// GPU memory allocated by the processes
+    pub gpu_memory_bytes: Option<u64>,
+}
+
+/// Keeps the previous sample of every session to compute usage rates
+#[derive(Debug, Default)]
+pub struct Sampler {
+    previous: HashMap<u64, Sample>,
+}
+
+#[derive(Debug)]
+struct Sample {
+    at: Instant,
+    cpu_ticks: u64,
+    /// Busy time per GPU engine, in nanoseconds
+    gpu_engines: HashMap<String, u64>,
+}
+
+impl Sampler {
+    /// Measure the usage of `sessions`
+    ///
+    /// Rates are zero on the first sample of a session. Sessions whose process
+    /// is gone are skipped and forgotten.
+    pub fn sample(&mut self, sessions: &[Session]) -> Vec<ResourceUsage> {
+        let processes = processes();
+        let now = Instant::now();
+        let mut usages = Vec::new();
+        let mut seen = HashSet::new();
+
+        for session in sessions {
+            let tree = tree(&processes, session.pid);
+            if tree.is_empty() {
+                continue;
+            }
+            seen.insert(session.id);
+
+            let cpu_ticks = tree
+                .iter()
+                .filter_map(|pid| processes.get(pid))
+                .map(|process| process.cpu_ticks)
+                .sum();
+            let memory_bytes = tree.iter().filter_map(|pid| resident_memory(*pid)).sum();
+            let gpu = gpu_clients(&tree);
+
+            let previous = self.previous.get(&session.id);
+            let elapsed = previous.map(|p| now.duration_since(p.at).as_secs_f64());
+            let cpu_percent = match (previous, elapsed) {
+                (Some(previous), Some(elapsed)) if elapsed > 0.0 => {
+                    let ticks = cpu_ticks.saturating_sub(previous.cpu_ticks) as f64;
+                    (ticks / clock_ticks() / elapsed * 100.0) as f32
+                }
+                _ => 0.0,
+            };
+            let gpu_percent = gpu.as_ref().map(|gpu| match (previous, elapsed) {
+                (Some(previous), Some(elapsed)) if elapsed > 0.0 => gpu
+                    .engines
+                    .iter()
+                    .map(|(engine, busy)| {
+                        let before = previous.gpu_engines.get(engine).copied().unwrap_or(*busy);
+                        busy.saturating_sub(before) as f64 / (elapsed * 1e9) * 100.0
+                    })
+                    .fold(0.0, f64::max)
+                    .min(100.0) as f32,
+                _ => 0.0,
+            });
+
+            usages.push(ResourceUsage {
+                session: session.id,
+                bottle: session.bottle.clone(),
+                pid: session.pid,
+                processes: tree.len() as u32,
+                cpu_percent,
+                memory_bytes,
+                gpu_percent,
+                gpu_memory_bytes: gpu.as_ref().map(|gpu| gpu.memory_bytes),
+            });
+            self.previous.insert(
+                session.id,
+                Sample {
+                    at: now,
+                    cpu_ticks,
+                    gpu_engines: gpu.map(|gpu| gpu.engines).unwrap_or_default(),
+                },
+            );
+        }
+
+        self.previous.retain(|id, _| seen.contains(id));
+        usages
+    }
+}
+
+/// Number of threads of a process
+pub fn threads(pid: u32) -> Option<u32> {
+    stat(pid).map(|process| process.threads)
+}
+
+/// The processes started by `root`, directly or not, excluding `root` itself
+pub fn descendants(root: u32) -> Vec<u32> {
+    tree(&processes(), root).into_iter().skip(1).collect()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Process {
+    parent: u32,
+    cpu_ticks: u64,
+    threads: u32,
+}
+
+/// Every process on the system, by pid
+fn processes() -> HashMap<u32, Process> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str()?.parse::<u32>().ok())
+        .filter_map(|pid| Some((pid, stat(pid)?)))
+        .collect()
+}
+
+fn stat(pid: u32) -> Option<Process> {
+    let content = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name can contain spaces and parentheses, the fields after
+    // its closing parenthesis can't
+    let (_, fields) = content.rsplit_once(')')?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    let field = |index: usize| fields.get(index)?.parse::<u64>().ok();
+    Some(Process {
+        parent: field(1)? as u32,
+        cpu_ticks: field(11)? + field(12)?,
+        threads: field(17)? as u32,
+    })
+}
+
+/// `root` and all of its descendants, or nothing if `root` is gone
+fn tree(processes: &HashMap<u32, Process>, root: u32) -> Vec<u32> {
+    if !processes.contains_key(&root) {
+        return Vec::new();
+    }
+    let mut tree = vec![root];
+    let mut index = 0;
+    while index < tree.len() {
+        let parent = tree[index];
+        tree.extend(
+            processes
+                .iter()
+                .filter(|(_, process)| process.parent == parent)
+                .map(|(pid, _)| *pid),
+        );
+        index += 1;
+    }
+    tree
+}
+
+fn resident_memory(pid: u32) -> Option<u64> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[derive(Debug, Default)]
+struct GpuUsage {
+    engines: HashMap<String, u64>,
+    memory_bytes: u64,
+}
+
+/// GPU usage of the DRM clients opened by `pids`
+///
+/// Several processes can share a DRM file, so clients are counted once by
+/// their `drm-client-id`.
+fn gpu_clients(pids: &[u32]) -> Option<GpuUsage> {
+    let mut usage = GpuUsage::default();
+    let mut clients = HashSet::new();
+    for pid in pids {
+        let Ok(entries) = fs::read_dir(format!("/proc/{}/fdinfo", pid)) else {
+            continue;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let Ok(info) = fs::read_to_string(entry.path()) else {
+                continue;
+            };
+            let Some(client) = info
+                .lines()
+                .find_map(|line| line.strip_prefix("drm-client-id:"))
+                .map(|id| id.trim().to_string())
+            else {
+                continue;
+            };
+            if !clients.insert(client) {
+                continue;
+            }
+
+            for line in info.lines() {
+                let Some((key, value)) = line.split_once(':') else {
+                    continue;
+                };
+                let mut value = value.split_whitespace();
+                let amount: u64 = match value.next().and_then(|v| v.parse().ok()) {
+                    Some(amount) => amount,
+                    None => continue,
+                };
+                if let Some(engine) = key.strip_prefix("drm-engine-") {
+                    if engine != "capacity" {
+                        *usage.engines.entry(engine.to_string()).or_default() += amount;
+                    }
+                } else if key == "drm-memory-vram" || key == "drm-total-vram" {
+                    let multiplier = match value.next() {
+                        Some("KiB") => 1024,
+                        Some("MiB") => 1024 * 1024,
+                        _ => 1,
+                    };
+                    usage.memory_bytes += amount * multiplier;
+                }
+            }
+        }
+    }
+    (!clients.is_empty()).then_some(usage)
+}
+
+/// Kernel clock ticks per second, the unit of CPU times in `/proc`
+fn clock_ticks() -> f64 {
+    // SAFETY: sysconf has no preconditions
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks > 0 {
+        ticks as f64
+    } else {
+        100.0
+    }
+}
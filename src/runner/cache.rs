@@ -0,0 +1,107 @@
+//! Versions of runners, remembered across runs
+//!
+//! Asking a runner for its version starts it, which takes a while for Wine
+//! and even longer for Proton. Versions are kept in a small file of the
+//! user's cache directory, keyed by the path of the executable and valid as
+//! long as its modification time and size don't change.
+
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::SystemTime;
+
+/// Name of the cache file in the cache directory
+const FILE_NAME: &str = "runner-versions.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    modified: SystemTime,
+    size: u64,
+    version: String,
+}
+
+/// The version of the executable at `path`, from the cache or from `probe`
+///
+/// # Errors
+///
+/// Returns the error of `probe`; a cache that can't be read or written is
+/// only logged
+pub(crate) fn version(
+    path: &Path,
+    probe: impl FnOnce() -> Result<String, Error>,
+) -> Result<String, Error> {
+    let stamp = stamp(path);
+    if let Some((modified, size)) = stamp {
+        let cached = entries().get(path).cloned();
+        let fresh = cached.filter(|entry| entry.modified == modified && entry.size == size);
+        if let Some(entry) = fresh {
+            return Ok(entry.version);
+        }
+    }
+    let version = probe()?;
+    if let Some((modified, size)) = stamp {
+        let entry = Entry {
+            modified,
+            size,
+            version: version.clone(),
+        };
+        let mut entries = entries();
+        entries.insert(path.to_path_buf(), entry);
+        save(&entries);
+    }
+    Ok(version)
+}
+
+/// Forget the version of the executable at `path`
+pub(crate) fn forget(path: &Path) {
+    let mut entries = entries();
+    if entries.remove(path).is_some() {
+        save(&entries);
+    }
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// The entries of the cache, read from disk on first use
+fn entries() -> MutexGuard<'static, BTreeMap<PathBuf, Entry>> {
+    static ENTRIES: OnceLock<Mutex<BTreeMap<PathBuf, Entry>>> = OnceLock::new();
+    ENTRIES
+        .get_or_init(|| Mutex::new(load().unwrap_or_default()))
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn load() -> Option<BTreeMap<PathBuf, Entry>> {
+    let content = fs::read_to_string(cache_path()?).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn save(entries: &BTreeMap<PathBuf, Entry>) {
+    let Some(path) = cache_path() else {
+        return;
+    };
+    let result = (|| -> Result<(), Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(Error::Io)?;
+        }
+        let temporary = path.with_extension("json.new");
+        fs::write(&temporary, serde_json::to_string(entries)?).map_err(Error::Io)?;
+        fs::rename(&temporary, &path).map_err(Error::Io)
+    })();
+    if let Err(e) = result {
+        tracing::debug!("Cannot write '{}': {}", path.display(), e);
+    }
+}
+
+fn cache_path() -> Option<PathBuf> {
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".cache")))?;
+    Some(cache.join("bottles").join(FILE_NAME))
+}
@@ -0,0 +1,173 @@
+use super::ProtonVersion;
+use crate::bottle::BottleConfig;
+use crate::launch::steam_runtime::SteamRuntimeMode;
+use crate::sync::SyncMode;
+use crate::system::diagnostics::{Check, CheckStatus, Feature};
+use crate::winecfg::GraphicsDriver;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Directories of Wine builds holding the Unix side of their DLLs
+const UNIX_LIBRARY_DIRS: &[&str] = &[
+    "lib/wine/x86_64-unix",
+    "lib64/wine/x86_64-unix",
+    "lib64/wine",
+    "lib/wine",
+    "lib/x86_64-linux-gnu/wine/x86_64-unix",
+];
+
+/// Directories of Wine builds holding their 32-bit DLLs
+const WIN32_LIBRARY_DIRS: &[&str] = &[
+    "lib/wine/i386-windows",
+    "lib32/wine/i386-windows",
+    "lib/i386-linux-gnu/wine/i386-windows",
+];
+
+/// Directories of Proton builds holding their own DXVK
+const DXVK_DIRS: &[&str] = &["lib/wine/dxvk", "lib64/wine/dxvk"];
+
+/// What a runner build supports, see [`Runner::capabilities`](super::Runner::capabilities)
+///
+/// Capabilities are read from the files of the build, so a config can be
+/// checked against its runner before anything is launched, see
+/// [`Capabilities::check`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Capabilities {
+    /// The build has the fsync patches, see [`SyncMode::Fsync`]
+    pub fsync: bool,
+    /// The build can use the ntsync driver, see [`SyncMode::Ntsync`]
+    pub ntsync: bool,
+    /// The build ships its own DXVK, like Proton does
+    pub dxvk_builtin: bool,
+    /// The build has the Wayland driver
+    pub wayland_driver: bool,
+    /// The build runs 32-bit programs
+    pub win32: bool,
+    /// The build asks to run inside a Steam runtime, see
+    /// [`crate::launch::steam_runtime`]
+    pub steam_runtime: bool,
+}
+
+impl Capabilities {
+    /// Read the capabilities of the Wine build at `wine`, part of the runner
+    /// installed at `directory`
+    pub fn detect(wine: &Path, directory: &Path) -> Self {
+        let ntdll = find(wine, UNIX_LIBRARY_DIRS, "ntdll.so")
+            .and_then(|path| fs::read(path).ok())
+            .unwrap_or_default();
+        Self {
+            fsync: contains(&ntdll, b"WINEFSYNC"),
+            ntsync: contains(&ntdll, b"WINENTSYNC"),
+            dxvk_builtin: DXVK_DIRS.iter().any(|dir| wine.join(dir).is_dir()),
+            wayland_driver: find(wine, UNIX_LIBRARY_DIRS, "winewayland.so").is_some()
+                || find(wine, UNIX_LIBRARY_DIRS, "winewayland.drv.so").is_some(),
+            win32: WIN32_LIBRARY_DIRS.iter().any(|dir| wine.join(dir).is_dir())
+                || find(wine, UNIX_LIBRARY_DIRS, "ntdll.dll.so").is_some()
+                || wine.join("lib32/wine").is_dir(),
+            steam_runtime: ProtonVersion::read(directory).require_tool_appid.is_some(),
+        }
+    }
+
+    /// Check `config` against the capabilities of its runner `runner`
+    ///
+    /// Settings the runner can't honor fail their check, instead of being
+    /// silently ignored when launching.
+    pub fn check(&self, runner: &str, config: &BottleConfig) -> Vec<Check> {
+        let mut checks = Vec::new();
+
+        let sync = match config.sync {
+            SyncMode::Fsync if !self.fsync => Some(Feature::Fsync),
+            SyncMode::Ntsync if !self.ntsync => Some(Feature::Ntsync),
+            _ => None,
+        };
+        checks.push(match sync {
+            Some(feature) => Check::new(
+                "runner.sync",
+                "Synchronization",
+                CheckStatus::Error,
+                format!("{} doesn't support {}", runner, feature.label()),
+            )
+            .hint("Use a runner built with it, such as Proton, or another sync mode")
+            .affects(&[feature]),
+            None => Check::new(
+                "runner.sync",
+                "Synchronization",
+                CheckStatus::Ok,
+                format!("{} supports the sync mode of the bottle", runner),
+            ),
+        });
+
+        checks.push(if self.dxvk_builtin || config.dxvk_version.is_some() {
+            Check::new("runner.dxvk", "DXVK", CheckStatus::Ok, "Direct3D 9 to 11 use DXVK")
+        } else {
+            Check::new(
+                "runner.dxvk",
+                "DXVK",
+                CheckStatus::Warning,
+                format!("{} ships no DXVK, Direct3D 9 to 11 use WineD3D", runner),
+            )
+            .hint("Install DXVK in the bottle")
+            .affects(&[Feature::Dxvk])
+        });
+
+        checks.push(if self.win32 {
+            Check::new("runner.win32", "32-bit programs", CheckStatus::Ok, "supported")
+        } else {
+            Check::new(
+                "runner.win32",
+                "32-bit programs",
+                CheckStatus::Warning,
+                format!("{} is 64-bit only, 32-bit programs don't start", runner),
+            )
+            .hint("Use a runner with 32-bit or WoW64 support")
+            .affects(&[Feature::ThirtyTwoBit])
+        });
+
+        if config.wine_settings.graphics_driver == GraphicsDriver::Wayland {
+            checks.push(if self.wayland_driver {
+                Check::new("runner.wayland", "Wayland driver", CheckStatus::Ok, "included")
+            } else {
+                Check::new(
+                    "runner.wayland",
+                    "Wayland driver",
+                    CheckStatus::Error,
+                    format!("{} is built without the Wayland driver", runner),
+                )
+                .hint("Use a runner built with it, or the X11 driver")
+                .affects(&[Feature::Wayland])
+            });
+        }
+
+        let steam_runtime = match (config.steam_runtime, self.steam_runtime) {
+            (SteamRuntimeMode::Off, true) => Check::new(
+                "runner.steam_runtime",
+                "Steam runtime",
+                CheckStatus::Warning,
+                format!("{} is meant to run in a Steam runtime", runner),
+            )
+            .hint("Set the Steam runtime of the bottle to automatic"),
+            (SteamRuntimeMode::Auto, false) => Check::new(
+                "runner.steam_runtime",
+                "Steam runtime",
+                CheckStatus::Warning,
+                format!("{} asks for no Steam runtime, none is used", runner),
+            ),
+            _ => Check::new("runner.steam_runtime", "Steam runtime", CheckStatus::Ok, "as set"),
+        };
+        checks.push(steam_runtime);
+        checks
+    }
+}
+
+/// The first file `name` of the directories `dirs` of `root`
+fn find(root: &Path, dirs: &[&str], name: &str) -> Option<std::path::PathBuf> {
+    dirs.iter()
+        .map(|dir| root.join(dir).join(name))
+        .find(|path| path.is_file())
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|window| window == needle)
+}
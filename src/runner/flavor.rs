@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Name suffixes of Kron4ek's builds, naming their architecture
+const KRON4EK_SUFFIXES: &[&str] = &["-amd64", "-amd64-wow64", "-x86"];
+
+/// Where a Wine build comes from, telling how its files are laid out
+///
+/// Runners are found by their layout, see [`RunnerFlavor::detect`], so
+/// builds packaged differently are listed alike. [`Wine::root`](super::Wine::root)
+/// is the directory of the Wine build inside the runner's directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunnerFlavor {
+    /// The layout of upstream Wine, `bin/wine`, used by most builds
+    #[default]
+    Wine,
+    /// Kron4ek's builds, named after their architecture, e.g.
+    /// `wine-9.0-staging-tkg-amd64`; `x86` builds only run 32-bit programs
+    Kron4ek,
+    /// wine-tkg, packaged under `usr/`
+    Tkg,
+    /// CrossOver, an application bundle starting Wine with `wineloader`
+    CrossOver,
+    /// Proton, its Wine in `files/`, or `dist/` for builds older than 5.13
+    Proton,
+    /// The Wine installed by the distribution, see
+    /// [`Wine::system`](super::Wine::system)
+    System,
+}
+
+impl RunnerFlavor {
+    /// The flavor of the runner installed in `directory`, `None` if it holds
+    /// no Wine build
+    pub fn detect(directory: &Path) -> Option<Self> {
+        let name = directory.file_name()?.to_string_lossy().to_lowercase();
+        let flavor = if directory.join("proton").is_file() {
+            Self::Proton
+        } else if Self::CrossOver.locate(directory).is_some() {
+            Self::CrossOver
+        } else if KRON4EK_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+            Self::Kron4ek
+        } else if name.contains("tkg") || directory.join("usr/bin/wine").is_file() {
+            Self::Tkg
+        } else {
+            Self::Wine
+        };
+        flavor.locate(directory).map(|_| flavor)
+    }
+
+    /// Directories of the Wine build relative to the runner, most likely
+    /// first
+    fn layouts(self) -> &'static [&'static str] {
+        match self {
+            Self::Wine | Self::Kron4ek | Self::System => &[""],
+            Self::Tkg => &["usr", ""],
+            Self::CrossOver => &[
+                "Contents/SharedSupport/CrossOver",
+                "CrossOver.app/Contents/SharedSupport/CrossOver",
+            ],
+            Self::Proton => &["files", "dist"],
+        }
+    }
+
+    /// Executables starting Wine, relative to the Wine build
+    ///
+    /// `bin/wine` of CrossOver is a script managing CrossOver's own bottles,
+    /// and builds without 32-bit support may only ship `bin/wine64`.
+    fn executables(self) -> &'static [&'static str] {
+        match self {
+            Self::CrossOver => &["bin/wineloader", "bin/wine"],
+            _ => &["bin/wine", "bin/wine64"],
+        }
+    }
+
+    /// The directory of the Wine build in `directory` and its executable,
+    /// both relative to `directory`
+    pub(crate) fn locate(self, directory: &Path) -> Option<(&'static str, PathBuf)> {
+        self.layouts().iter().find_map(|layout| {
+            self.executables()
+                .iter()
+                .map(|executable| Path::new(layout).join(executable))
+                .find(|executable| directory.join(executable).is_file())
+                .map(|executable| (*layout, executable))
+        })
+    }
+}
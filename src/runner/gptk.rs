@@ -88,17 +88,25 @@ impl Runner for GPTK {
         arch_output == "i386" || arch_output == "arm64"
     }
 
-    fn initialize(&self, _prefix: &Path) -> Result<(), crate::Error> {
-        todo!("Initialize GPTK")
+    fn initialize(&self, prefix: &Path) -> Result<(), crate::Error> {
+        self.wine.initialize(prefix)
     }
 
-    fn launch(
+    fn initialize_cancellable(
         &self,
-        _executable: &Path,
-        _args: &[String],
-        _prefix: &Path,
-        _env: &std::collections::HashMap<String, String>,
-    ) -> Result<std::process::Child, crate::Error> {
-        todo!("Launch GPTK")
+        prefix: &Path,
+        token: &crate::jobs::CancelToken,
+    ) -> Result<(), crate::Error> {
+        self.wine.initialize_cancellable(prefix, token)
+    }
+
+    fn command(
+        &self,
+        executable: &Path,
+        args: &[String],
+        prefix: &Path,
+        env: &std::collections::HashMap<String, String>,
+    ) -> std::process::Command {
+        self.wine.command(executable, args, prefix, env)
     }
 }
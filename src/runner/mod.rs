@@ -1,29 +1,105 @@
+mod cache;
+mod capabilities;
+mod flavor;
 #[cfg(target_os = "macos")]
 mod gptk;
+mod passthrough;
 mod proton;
+mod smoke;
 mod umu;
 mod wine;
 
+pub use capabilities::Capabilities;
+pub use flavor::RunnerFlavor;
 #[cfg(target_os = "macos")]
 pub use gptk::GPTK;
-pub use proton::Proton;
+pub use passthrough::{PassThrough, PassThroughKind};
+pub use proton::{Proton, ProtonVersion};
+pub use smoke::SmokeTestReport;
 pub use umu::UMU;
-pub use wine::Wine;
+pub use wine::{PrefixArch, Wine, WineserverStatus, SYSTEM_WINE};
 
+use crate::jobs::CancelToken;
+use crate::version::Version;
+use crate::winebridge::{Bridge, BridgedProcess};
 use crate::Error;
 use std::{
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, ExitStatus, Stdio},
+    sync::OnceLock,
+    time::Duration,
 };
 
+/// How often a command run by [`run_cancellable`] checks for cancellation
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Find the runners installed in `directory`
+///
+/// Every subdirectory is probed as a Proton build first and as a plain Wine build
+/// otherwise. Entries that are neither are skipped, so a missing or empty directory
+/// simply yields no runners.
+///
+/// # Returns
+///
+/// The runners found, sorted by directory name
+pub fn discover(directory: &Path) -> Vec<Box<dyn Runner>> {
+    discover_with(directory, false)
+}
+
+/// Find the runners installed in `directory` like [`discover`], without
+/// starting any of them
+///
+/// Versions are resolved on first use of [`RunnerInfo::version`], or read
+/// from the `version` file of Proton builds, so runners are listed even when
+/// they can't be executed right now, e.g. from a `noexec` mount.
+pub fn discover_lazy(directory: &Path) -> Vec<Box<dyn Runner>> {
+    discover_with(directory, true)
+}
+
+fn discover_with(directory: &Path, lazy: bool) -> Vec<Box<dyn Runner>> {
+    candidates(directory)
+        .iter()
+        .filter_map(|path| open(path, lazy))
+        .collect()
+}
+
+/// The subdirectories of `directory` that may hold a runner, sorted
+pub(crate) fn candidates(directory: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// The runner installed in `directory`, a Proton build or a Wine build of
+/// any [`RunnerFlavor`]
+///
+/// With `lazy` the runner isn't started, see [`discover_lazy`].
+pub(crate) fn open(directory: &Path, lazy: bool) -> Option<Box<dyn Runner>> {
+    let runner: Box<dyn Runner> = match (RunnerFlavor::detect(directory)?, lazy) {
+        (RunnerFlavor::Proton, true) => Box::new(Proton::lazy(directory).ok()?),
+        (RunnerFlavor::Proton, false) => Box::new(Proton::try_from(directory).ok()?),
+        (_, true) => Box::new(Wine::lazy(directory).ok()?),
+        (_, false) => Box::new(Wine::try_from(directory).ok()?),
+    };
+    Some(runner)
+}
+
 /// Contains metadata and paths for any runner implementation. This struct is used
 /// internally by all runner types to store their basic information.
 #[derive(Debug)]
 pub struct RunnerInfo {
     /// Human-readable name of the runner, typically derived from the directory name
     name: String,
-    /// Version string obtained from the runner's `--version` output
-    version: String,
+    /// Version string obtained from the runner's `--version` output, once
+    /// resolved
+    version: OnceLock<String>,
     /// Base directory where the runner is installed
     directory: PathBuf,
     /// Relative path to the main executable within the directory
@@ -50,6 +126,22 @@ impl RunnerInfo {
     /// This function will return an error if the directory or executable path is invalid,
     /// or if the executable cannot be executed to determine its version.
     fn try_from(directory: &Path, executable: &Path) -> Result<Self, Error> {
+        let info = Self::lazy(directory, executable)?;
+        let path = info.executable_path();
+        let version = cache::version(&path, || probe_version(&path, &info.name))?;
+        Ok(info.with_version(version))
+    }
+
+    /// Create a RunnerInfo like [`RunnerInfo::try_from`], without executing
+    /// the runner
+    ///
+    /// The version is resolved on the first call to [`RunnerInfo::version`],
+    /// unless set with [`RunnerInfo::with_version`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory or executable path is invalid
+    fn lazy(directory: &Path, executable: &Path) -> Result<Self, Error> {
         if !directory.exists() {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
@@ -77,23 +169,26 @@ impl RunnerInfo {
             .map(|s| s.to_string())
             .unwrap_or_else(|| "unknown".to_string());
 
-        let version = Command::new(&full_path)
-            .arg("--version")
-            .output()
-            .map(|output| {
-                let ver = String::from_utf8_lossy(&output.stdout).to_string();
-                if ver.is_empty() { name.clone() } else { ver }
-            })
-            .map_err(Error::Io)?;
-
         Ok(RunnerInfo {
             name,
             directory: directory.to_path_buf(),
             executable: executable.to_path_buf(),
-            version,
+            version: OnceLock::new(),
         })
     }
 
+    /// Name the runner `name` instead of after its directory
+    fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Use `version` instead of asking the runner for it
+    fn with_version(mut self, version: String) -> Self {
+        self.version = OnceLock::from(version);
+        self
+    }
+
     /// Get the full path to the executable for the runner
     ///
     /// Combines the base directory with the relative executable path to provide
@@ -105,6 +200,32 @@ impl RunnerInfo {
     pub fn executable_path(&self) -> PathBuf {
         self.directory.join(&self.executable)
     }
+
+    /// Ask the runner for its version again
+    ///
+    /// Versions are cached by the path and modification time of the
+    /// executable, so a runner is only started once to find its version.
+    /// This replaces the cached version, e.g. after a runner was changed in
+    /// place without its modification time changing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the executable cannot be started
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let path = self.executable_path();
+        cache::forget(&path);
+        let version = cache::version(&path, || probe_version(&path, &self.name))?;
+        self.version = OnceLock::from(version);
+        Ok(())
+    }
+}
+
+/// Start the executable at `path` to read its version, `name` if it prints
+/// none
+fn probe_version(path: &Path, name: &str) -> Result<String, Error> {
+    let output = Command::new(path).arg("--version").output().map_err(Error::Io)?;
+    let version = String::from_utf8_lossy(&output.stdout).to_string();
+    Ok(if version.is_empty() { name.to_string() } else { version })
 }
 
 impl RunnerInfo {
@@ -124,12 +245,29 @@ impl RunnerInfo {
     ///
     /// Returns the version string as reported by the runner's `--version` command.
     /// If the version cannot be determined, this may return the runner's name instead.
+    /// A runner created without being executed (see [`discover_lazy`]) is asked for
+    /// its version on the first call.
     ///
     /// # Returns
     ///
     /// A string slice containing the runner's version information
     pub fn version(&self) -> &str {
-        &self.version
+        self.version.get_or_init(|| {
+            let path = self.executable_path();
+            cache::version(&path, || probe_version(&path, &self.name)).unwrap_or_else(|e| {
+                tracing::warn!("Cannot get the version of '{}': {}", path.display(), e);
+                self.name.clone()
+            })
+        })
+    }
+
+    /// Get the version of the runner parsed for comparisons, see
+    /// [`crate::version`]
+    ///
+    /// Returns `None` if the version has no numbers, e.g. when the runner
+    /// reported none and [`RunnerInfo::version`] is its name.
+    pub fn parsed_version(&self) -> Option<Version> {
+        Version::parse(self.version())
     }
 
     /// Returns the directory path where the runner is installed.
@@ -204,6 +342,15 @@ pub trait Runner {
         executable_path.exists() && executable_path.is_file()
     }
 
+    /// What the runner supports, for configs to be checked against it before
+    /// launching, see [`Capabilities::check`]
+    ///
+    /// The default implementation reads the files of the underlying Wine
+    /// build without starting it.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::detect(self.wine().root(), self.info().directory())
+    }
+
     /// Initialize a prefix at the specified path using the runner's executable.
     ///
     /// # Arguments
@@ -212,6 +359,42 @@ pub trait Runner {
     ///   created if it doesn't exist.
     fn initialize(&self, prefix: &Path) -> Result<(), Error>;
 
+    /// Initialize a prefix like [`Runner::initialize`], stopping as soon as
+    /// `token` is cancelled.
+    ///
+    /// A cancelled initialization returns [`Error::Cancelled`] and leaves the
+    /// prefix half-created, for the caller to remove.
+    fn initialize_cancellable(&self, prefix: &Path, token: &CancelToken) -> Result<(), Error> {
+        let _span = tracing::info_span!(
+            "initialize_prefix",
+            runner = self.info().name(),
+            prefix = %prefix.display()
+        )
+        .entered();
+        token.check()?;
+        self.initialize(prefix)?;
+        token.check()
+    }
+
+    /// Build the command that runs an executable inside the runner environment.
+    ///
+    /// The returned command is fully configured (program, arguments and environment)
+    /// but not spawned, so callers can adjust stdio or the working directory first.
+    ///
+    /// # Arguments
+    ///
+    /// * `executable` - Path to the executable to run (inside the bottle).
+    /// * `args` - Arguments to pass to the executable.
+    /// * `prefix` - The Wine prefix path.
+    /// * `env` - Additional environment variables.
+    fn command(
+        &self,
+        executable: &Path,
+        args: &[String],
+        prefix: &Path,
+        env: &std::collections::HashMap<String, String>,
+    ) -> Command;
+
     /// Launch a command inside the runner environment.
     ///
     /// # Arguments
@@ -230,5 +413,111 @@ pub trait Runner {
         args: &[String],
         prefix: &Path,
         env: &std::collections::HashMap<String, String>,
-    ) -> Result<std::process::Child, Error>;
+    ) -> Result<std::process::Child, Error> {
+        let span = tracing::info_span!(
+            "runner_launch",
+            runner = self.info().name(),
+            executable = %executable.display(),
+            pid = tracing::field::Empty
+        );
+        let _entered = span.enter();
+        let mut command = self.command(executable, args, prefix, env);
+        let child = crate::launch::isolate(&mut command).spawn()?;
+        span.record("pid", child.id());
+        Ok(child)
+    }
+
+    /// Launch a command like [`Runner::launch`], or through the WineBridge
+    /// agent running in the prefix when `bridge` is given.
+    ///
+    /// A process created by the bridge reports its real Windows exit code and
+    /// streams its output over the bridge connection, see [`BridgedProcess`].
+    /// Bridges are started with
+    /// [`Manager::start_bridge`](crate::manager::Manager::start_bridge).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command can't be spawned, or the bridge can't
+    /// be reached or can't create the process
+    fn launch_with(
+        &self,
+        executable: &Path,
+        args: &[String],
+        prefix: &Path,
+        env: &std::collections::HashMap<String, String>,
+        bridge: Option<&Bridge>,
+    ) -> Result<Process, Error> {
+        match bridge {
+            Some(bridge) => BridgedProcess::spawn(bridge, prefix, executable, args, env)
+                .map(Process::Bridged),
+            None => self.launch(executable, args, prefix, env).map(Process::Child),
+        }
+    }
+
+    /// Check that the runner actually works on this host.
+    ///
+    /// Creates a throwaway prefix in the temporary directory, initializes it and runs
+    /// `cmd /c exit 0` inside it. Meant to be called right after installing a runner,
+    /// since a runner can be present on disk and still fail to start (missing 32-bit
+    /// libraries, wrong architecture, ...). The temporary prefix is always removed.
+    ///
+    /// # Returns
+    ///
+    /// A report describing whether the runner works, how long it took and what it printed
+    ///
+    /// # Errors
+    ///
+    /// Only fails if the temporary prefix cannot be created; runner failures are
+    /// reported through [`SmokeTestReport::success`].
+    fn smoke_test(&self) -> Result<SmokeTestReport, Error> {
+        smoke::run(self)
+    }
+}
+
+/// A program started by [`Runner::launch_with`]
+#[derive(Debug)]
+pub enum Process {
+    /// Run by a command of the runner
+    Child(std::process::Child),
+    /// Created by the WineBridge agent of the prefix
+    Bridged(BridgedProcess),
+}
+
+impl Process {
+    /// Process id: of the host process for a child, of the Windows process
+    /// for a bridged one
+    pub fn id(&self) -> u32 {
+        match self {
+            Self::Child(child) => child.id(),
+            Self::Bridged(process) => process.pid,
+        }
+    }
+}
+
+/// Run `command` to completion, killing it once `token` is cancelled
+pub(crate) fn run_cancellable(
+    mut command: Command,
+    token: &CancelToken,
+) -> Result<ExitStatus, Error> {
+    token.check()?;
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let pid = child.id();
+    tracing::debug!(pid, "Started {:?}", command.get_program());
+    loop {
+        if let Some(status) = child.try_wait()? {
+            tracing::debug!(pid, "{:?} exited with {}", command.get_program(), status);
+            return Ok(status);
+        }
+        if token.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            tracing::info!(pid, "Killed {:?}, cancelled", command.get_program());
+            return Err(Error::Cancelled);
+        }
+        std::thread::sleep(CANCEL_POLL_INTERVAL);
+    }
 }
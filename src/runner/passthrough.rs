@@ -0,0 +1,178 @@
+use crate::{flatpak, launch, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+/// A program running games without Wine
+///
+/// DOS games and classic adventure games run best in DOSBox-staging and
+/// ScummVM. A bottle can use one of them instead of a Wine runner (see
+/// [`BottleConfig::passthrough`](crate::bottle::BottleConfig::passthrough)):
+/// its directory then holds the games and their configuration instead of a
+/// prefix, and its programs are launched like any other, with the bottle's
+/// environment and launch wrappers, and tracked as sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum PassThroughKind {
+    /// DOS games, through DOSBox-staging or DOSBox
+    DosBox,
+    /// Point-and-click adventure games supported by ScummVM
+    ScummVm,
+}
+
+impl PassThroughKind {
+    pub const ALL: [Self; 2] = [Self::DosBox, Self::ScummVm];
+
+    /// Identifier of the runner, e.g. `dosbox`
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::DosBox => "dosbox",
+            Self::ScummVm => "scummvm",
+        }
+    }
+
+    /// Human-readable name, as shown to users
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::DosBox => "DOSBox",
+            Self::ScummVm => "ScummVM",
+        }
+    }
+
+    /// Executables looked up in `PATH`, preferred one first
+    fn executables(self) -> &'static [&'static str] {
+        match self {
+            Self::DosBox => &["dosbox-staging", "dosbox"],
+            Self::ScummVm => &["scummvm"],
+        }
+    }
+
+    /// Configuration file read from the bottle's directory when present
+    fn config_file(self) -> &'static str {
+        match self {
+            Self::DosBox => "dosbox.conf",
+            Self::ScummVm => "scummvm.ini",
+        }
+    }
+}
+
+impl fmt::Display for PassThroughKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl FromStr for PassThroughKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dosbox" | "dosbox-staging" => Ok(Self::DosBox),
+            "scummvm" => Ok(Self::ScummVm),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{}' is not a pass-through runner", s),
+            )
+            .into()),
+        }
+    }
+}
+
+/// An installed DOSBox or ScummVM
+#[derive(Debug, Clone)]
+pub struct PassThrough {
+    kind: PassThroughKind,
+    executable: PathBuf,
+}
+
+impl PassThrough {
+    /// Find the runner in `PATH`, or on the host when running in Flatpak
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::RunnerNotFound`] if it is not installed
+    pub fn find(kind: PassThroughKind) -> Result<Self, Error> {
+        kind.executables()
+            .iter()
+            .find_map(|name| launch::find_in_path(name))
+            .map(|executable| Self { kind, executable })
+            .ok_or_else(|| Error::RunnerNotFound(kind.id().to_string()))
+    }
+
+    pub fn kind(&self) -> PassThroughKind {
+        self.kind
+    }
+
+    pub fn executable(&self) -> &Path {
+        &self.executable
+    }
+
+    /// First line of the runner's `--version` output
+    pub fn version(&self) -> Option<String> {
+        let mut command = Command::new(&self.executable);
+        command.arg("--version");
+        let output = flatpak::adapt(command).output().ok()?;
+        let version = String::from_utf8_lossy(&output.stdout);
+        version.lines().next().map(|line| line.trim().to_string())
+    }
+
+    /// Build the command running `program` of a bottle stored in `directory`
+    ///
+    /// For DOSBox, `program` is the game's executable (`.exe`, `.com` or
+    /// `.bat`), whose directory is mounted as `C:`. For ScummVM, it is the
+    /// game's directory or a file in it, the game is then detected by
+    /// ScummVM. Relative paths are relative to `directory`.
+    pub fn command(
+        &self,
+        program: &Path,
+        args: &[String],
+        directory: &Path,
+        env: &HashMap<String, String>,
+    ) -> Command {
+        let program = directory.join(program);
+        let mut command = Command::new(&self.executable);
+        let config = directory.join(self.kind.config_file());
+        match self.kind {
+            PassThroughKind::DosBox => {
+                if config.is_file() {
+                    command.arg("-conf").arg(&config);
+                }
+                let game = program.parent().unwrap_or(directory);
+                let name = program
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let mut run = name;
+                for arg in args {
+                    run.push(' ');
+                    run.push_str(arg);
+                }
+                command
+                    .arg("-c")
+                    .arg(format!("mount c \"{}\"", game.display()))
+                    .args(["-c", "c:", "-c"])
+                    .arg(run)
+                    .args(["-c", "exit"]);
+            }
+            PassThroughKind::ScummVm => {
+                if config.is_file() {
+                    command.arg(format!("--config={}", config.display()));
+                }
+                let game = if program.is_dir() {
+                    program.as_path()
+                } else {
+                    program.parent().unwrap_or(directory)
+                };
+                command
+                    .arg(format!("--path={}", game.display()))
+                    .arg("--auto-detect")
+                    .args(args);
+            }
+        }
+        command.current_dir(directory).envs(env);
+        flatpak::adapt(command)
+    }
+}
@@ -1,8 +1,12 @@
 use super::{Runner, RunnerInfo, Wine};
-use std::{
-    path::{Path, PathBuf},
-    process::Command,
-};
+use crate::flatpak;
+use crate::jobs::CancelToken;
+use crate::vdf;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path, process::Command};
+
+/// Launcher script of Proton builds, relative to their directory
+const EXECUTABLE: &str = "./proton";
 
 /// Proton runner implementation
 ///
@@ -18,17 +22,118 @@ use std::{
 pub struct Proton {
     info: RunnerInfo,
     wine: Wine,
+    release: ProtonVersion,
+}
+
+/// What a Proton build tells about itself
+///
+/// Proton has no `--version`: builds ship a `version` file, e.g.
+/// `1694167124 GE-Proton8-16`, and a `toolmanifest.vdf` for Steam.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtonVersion {
+    /// Build id of the `version` file, the Unix time of the build
+    pub build_id: Option<u64>,
+    /// Name of the build, e.g. `GE-Proton8-16` or `proton-8.0-4`
+    pub name: Option<String>,
+    /// Version of the Wine the build is based on, e.g. `wine-8.0`, as
+    /// reported by that Wine
+    pub wine_version: Option<String>,
+    /// Version of the format of `toolmanifest.vdf`
+    pub manifest_version: Option<u32>,
+    /// Steam app id of the runtime the build asks to run in, see
+    /// [`crate::launch::steam_runtime`]
+    pub require_tool_appid: Option<u32>,
+}
+
+impl ProtonVersion {
+    /// Read the `version` file and `toolmanifest.vdf` of the build at `path`
+    ///
+    /// Nothing is started, so [`ProtonVersion::wine_version`] is left unset,
+    /// see [`Proton::version`] for it. Missing or malformed files leave their
+    /// fields unset.
+    pub fn read(path: &Path) -> Self {
+        let mut version = Self::default();
+        if let Ok(content) = fs::read_to_string(path.join("version")) {
+            let mut parts = content.split_whitespace();
+            let first = parts.next();
+            version.build_id = first.and_then(|first| first.parse().ok());
+            version.name = match version.build_id {
+                Some(_) => parts.next(),
+                None => first,
+            }
+            .map(str::to_string);
+        }
+        let manifest = fs::read_to_string(path.join("toolmanifest.vdf"))
+            .ok()
+            .and_then(|content| vdf::parse(&content));
+        if let Some(manifest) = manifest {
+            let field = |key: &str| {
+                manifest
+                    .path(&["manifest", key])
+                    .and_then(vdf::Value::as_str)
+                    .and_then(|value| value.trim().parse().ok())
+            };
+            version.manifest_version = field("version");
+            version.require_tool_appid = field("require_tool_appid");
+        }
+        version
+    }
 }
 
 impl TryFrom<&Path> for Proton {
     type Error = Box<dyn std::error::Error>;
 
+    /// Create a Proton runner from the build at `path`
+    ///
+    /// The version of the runner is the name of the build, from its `version`
+    /// file, or the version of its Wine if it has none.
     fn try_from(path: &Path) -> Result<Self, Self::Error> {
-        let executable = PathBuf::from("./proton");
-        let info = RunnerInfo::try_from(path, &executable)?;
-        let mut wine = Wine::try_from(path.join("files").as_path())?;
+        let info = RunnerInfo::lazy(path, Path::new(EXECUTABLE))?;
+        let wine = Wine::try_from(path)?;
+        let fallback = wine.info().version().trim().to_string();
+        Ok(Self::assemble(path, info, wine, fallback))
+    }
+}
+
+impl Proton {
+    /// Create a Proton runner without starting it, see
+    /// [`discover_lazy`](super::discover_lazy)
+    ///
+    /// The version of the runner is the name of the build, from its
+    /// `version` file, or the name of its directory if it has none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `path` holds no Proton build
+    pub fn lazy(path: &Path) -> Result<Self, crate::Error> {
+        let info = RunnerInfo::lazy(path, Path::new(EXECUTABLE))?;
+        let wine = Wine::lazy(path)?;
+        let fallback = info.name.clone();
+        Ok(Self::assemble(path, info, wine, fallback))
+    }
+
+    fn assemble(path: &Path, info: RunnerInfo, mut wine: Wine, fallback: String) -> Self {
+        let release = ProtonVersion::read(path);
+        let version = release.name.clone().unwrap_or(fallback);
+        let info = info.with_version(version);
         wine.info_mut().name = info.name.clone();
-        Ok(Proton { wine, info })
+        Proton {
+            info,
+            wine,
+            release,
+        }
+    }
+
+    /// What the build tells about itself, see [`ProtonVersion`]
+    ///
+    /// The Wine of a runner created with [`Proton::lazy`] is started to find
+    /// its version, unless it is cached.
+    pub fn version(&self) -> ProtonVersion {
+        let wine_version = self.wine.info().version().trim();
+        ProtonVersion {
+            wine_version: Some(wine_version.to_string()).filter(|version| !version.is_empty()),
+            ..self.release.clone()
+        }
     }
 }
 
@@ -46,25 +151,50 @@ impl Runner for Proton {
     }
 
     fn initialize(&self, prefix: &Path) -> Result<(), crate::Error> {
-        // FIXME: Launch winebridge to initialize the prefix
-        Command::new(self.info().executable_path())
+        self.initialize_cancellable(prefix, &CancelToken::default())
+    }
+
+    fn initialize_cancellable(
+        &self,
+        prefix: &Path,
+        token: &CancelToken,
+    ) -> Result<(), crate::Error> {
+        let _span = tracing::info_span!(
+            "initialize_prefix",
+            runner = self.info().name(),
+            prefix = %prefix.display()
+        )
+        .entered();
+        // winebridge can only run in an existing prefix, the manager deploys
+        // it once initialized, see crate::winebridge
+        let mut command = Command::new(self.info().executable_path());
+        command
             .arg("run")
             .arg("wineboot")
             .env("WINEPREFIX", prefix)
             .env("STEAM_COMPAT_DATA_PATH", prefix)
-            .env("STEAM_COMPAT_CLIENT_INSTALL_PATH", "")
-            .output()?;
+            .env("STEAM_COMPAT_CLIENT_INSTALL_PATH", "");
+        super::run_cancellable(flatpak::adapt(command), token)?;
 
         Ok(())
     }
 
-    fn launch(
+    fn command(
         &self,
         executable: &Path,
         args: &[String],
         prefix: &Path,
         env: &std::collections::HashMap<String, String>,
-    ) -> Result<std::process::Child, crate::Error> {
-        todo!("Launch Proton")
+    ) -> Command {
+        let mut command = Command::new(self.info().executable_path());
+        command
+            .arg("run")
+            .arg(executable)
+            .args(args)
+            .env("WINEPREFIX", prefix)
+            .env("STEAM_COMPAT_DATA_PATH", prefix)
+            .env("STEAM_COMPAT_CLIENT_INSTALL_PATH", "")
+            .envs(env);
+        flatpak::adapt(command)
     }
 }
@@ -0,0 +1,89 @@
+use super::Runner;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Result of [`Runner::smoke_test`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmokeTestReport {
+    /// Whether the prefix was initialized and the test program exited successfully
+    pub success: bool,
+    /// Time spent initializing the temporary prefix
+    pub initialize_time: Duration,
+    /// Time spent running the test program
+    pub run_time: Duration,
+    /// Exit code of the test program, if it ran to completion
+    pub exit_code: Option<i32>,
+    /// Captured standard output of the test program
+    pub stdout: String,
+    /// Captured standard error of the test program
+    pub stderr: String,
+    /// Description of the step that failed, if any
+    pub error: Option<String>,
+}
+
+pub(super) fn run<R: Runner + ?Sized>(runner: &R) -> Result<SmokeTestReport, Error> {
+    let prefix = temporary_prefix();
+    fs::create_dir_all(&prefix).map_err(Error::Io)?;
+
+    let report = run_in(runner, &prefix);
+    if let Err(e) = fs::remove_dir_all(&prefix) {
+        tracing::warn!("Failed to remove smoke test prefix '{}': {}", prefix.display(), e);
+    }
+    Ok(report)
+}
+
+fn run_in<R: Runner + ?Sized>(runner: &R, prefix: &Path) -> SmokeTestReport {
+    let mut report = SmokeTestReport {
+        success: false,
+        initialize_time: Duration::ZERO,
+        run_time: Duration::ZERO,
+        exit_code: None,
+        stdout: String::new(),
+        stderr: String::new(),
+        error: None,
+    };
+
+    let started = Instant::now();
+    let initialized = runner.initialize(prefix);
+    report.initialize_time = started.elapsed();
+    if let Err(e) = initialized {
+        report.error = Some(format!("Failed to initialize prefix: {}", e));
+        return report;
+    }
+
+    let args = ["/c", "exit", "0"].map(String::from);
+    let started = Instant::now();
+    let output = runner
+        .command(Path::new("cmd"), &args, prefix, &HashMap::new())
+        .stdin(Stdio::null())
+        .output();
+    report.run_time = started.elapsed();
+
+    match output {
+        Ok(output) => {
+            report.exit_code = output.status.code();
+            report.stdout = String::from_utf8_lossy(&output.stdout).to_string();
+            report.stderr = String::from_utf8_lossy(&output.stderr).to_string();
+            report.success = output.status.success();
+            if !report.success {
+                report.error = Some(format!("Test program exited with {}", output.status));
+            }
+        }
+        Err(e) => report.error = Some(format!("Failed to run test program: {}", e)),
+    }
+
+    report
+}
+
+fn temporary_prefix() -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    std::env::temp_dir().join(format!("bottles-smoke-{}-{}", std::process::id(), nanos))
+}
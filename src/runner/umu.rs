@@ -1,9 +1,24 @@
-use super::{Proton, Runner, RunnerInfo, Wine};
+use super::{Capabilities, Proton, Runner, RunnerFlavor, RunnerInfo, Wine};
+use crate::flatpak;
+use crate::jobs::CancelToken;
+use crate::version::{self, Version};
 use std::{
     path::{Path, PathBuf},
     process::Command,
+    sync::OnceLock,
 };
 
+/// Value of `PROTONPATH` asking umu-run for the latest UMU-Proton, which it
+/// downloads when it isn't installed
+const UMU_PROTON: &str = "UMU-Proton";
+
+/// Directories umu-run installs UMU-Proton into, relative to the home
+/// directory
+const COMPATIBILITY_TOOLS_DIRS: &[&str] = &[
+    ".local/share/Steam/compatibilitytools.d",
+    ".steam/steam/compatibilitytools.d",
+];
+
 /// UMU (Unified Launcher) runner implementation
 ///
 /// UMU is a universal compatibility layer that wraps other runners like Proton
@@ -15,9 +30,12 @@ pub struct UMU {
     info: RunnerInfo,
     /// Underlying Proton runner that UMU wraps
     ///
-    /// When present, UMU will use this Proton instance to run applications.
-    /// If None, UMU will download the latest Proton version it can find and set that up.
-    proton: Option<Proton>,
+    /// When given, UMU will use this Proton instance to run applications.
+    /// Otherwise it is the newest UMU-Proton installed, resolved on first use,
+    /// and umu-run downloads the latest UMU-Proton when there is none.
+    proton: OnceLock<Proton>,
+    /// Wine of the runner until a Proton is resolved, umu-run itself
+    fallback: Wine,
 }
 
 impl UMU {
@@ -26,22 +44,62 @@ impl UMU {
         proton: Option<Proton>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let executable = PathBuf::from("./umu-run");
-        let mut info = RunnerInfo::try_from(path, &executable)?;
+        let info = RunnerInfo::try_from(path, &executable)?;
         let pretty_version = info
-            .version
+            .version()
             .split_whitespace()
             .nth(2)
             .unwrap_or("unknown")
             .to_string();
-        info.version = pretty_version;
-        Ok(UMU { info, proton })
+        let fallback = RunnerInfo::lazy(path, &executable)?.with_version(pretty_version.clone());
+        let info = info.with_version(pretty_version);
+        let fallback = Wine::from_parts(fallback, RunnerFlavor::Proton, path.to_path_buf());
+        let proton = proton.map(OnceLock::from).unwrap_or_default();
+        Ok(UMU {
+            info,
+            proton,
+            fallback,
+        })
+    }
+
+    /// The Proton UMU runs, the one it was created with or the newest
+    /// UMU-Proton installed
+    ///
+    /// Returns `None` while no UMU-Proton is installed: umu-run downloads it
+    /// the first time it runs, e.g. when a prefix is initialized.
+    pub fn proton(&self) -> Option<&Proton> {
+        if let Some(proton) = self.proton.get() {
+            return Some(proton);
+        }
+        let proton = installed_umu_proton()?;
+        tracing::debug!("UMU runs {}", proton.info().name());
+        Some(self.proton.get_or_init(|| proton))
+    }
+
+    /// Value of `PROTONPATH` for umu-run
+    fn proton_path(&self) -> PathBuf {
+        self.proton()
+            .map(|proton| proton.info().directory().to_path_buf())
+            .unwrap_or_else(|| PathBuf::from(UMU_PROTON))
     }
 }
 
+/// The newest UMU-Proton umu-run installed
+fn installed_umu_proton() -> Option<Proton> {
+    let home = PathBuf::from(std::env::var_os("HOME")?);
+    let candidates = COMPATIBILITY_TOOLS_DIRS
+        .iter()
+        .flat_map(|directory| super::candidates(&home.join(directory)));
+    let newest = version::newest(candidates, UMU_PROTON, |path| {
+        Version::parse(&path.file_name()?.to_string_lossy())
+    })?;
+    Proton::lazy(&newest).ok()
+}
+
 impl Runner for UMU {
+    /// The Wine of [`UMU::proton`], or umu-run itself while there is none
     fn wine(&self) -> &Wine {
-        // TODO: Make sure an unwrap is possible
-        self.proton.as_ref().unwrap().wine()
+        self.proton().map_or(&self.fallback, Proton::wine)
     }
 
     fn info(&self) -> &RunnerInfo {
@@ -52,24 +110,65 @@ impl Runner for UMU {
         &mut self.info
     }
 
+    fn capabilities(&self) -> Capabilities {
+        let capabilities = match self.proton() {
+            Some(proton) => proton.capabilities(),
+            // UMU sets up a recent Proton itself
+            None => Capabilities {
+                fsync: true,
+                ntsync: false,
+                dxvk_builtin: true,
+                wayland_driver: false,
+                win32: true,
+                steam_runtime: true,
+            },
+        };
+        Capabilities {
+            steam_runtime: true,
+            ..capabilities
+        }
+    }
+
     fn initialize(&self, prefix: &Path) -> Result<(), crate::Error> {
-        // FIXME: Launch winebridge to initialize the prefix
-        let proton_path = self.proton.as_ref().unwrap().info().directory();
-        Command::new(self.info().executable_path())
+        self.initialize_cancellable(prefix, &CancelToken::default())
+    }
+
+    fn initialize_cancellable(
+        &self,
+        prefix: &Path,
+        token: &CancelToken,
+    ) -> Result<(), crate::Error> {
+        let _span = tracing::info_span!(
+            "initialize_prefix",
+            runner = self.info().name(),
+            prefix = %prefix.display()
+        )
+        .entered();
+        // winebridge can only run in an existing prefix, the manager deploys
+        // it once initialized, see crate::winebridge
+        let mut command = Command::new(self.info().executable_path());
+        command
             .arg("wineboot") // This is wrong but it'll anyways initialize the prefix
             .env("WINEPREFIX", prefix)
-            .env("PROTONPATH", proton_path)
-            .output()?;
+            .env("PROTONPATH", self.proton_path());
+        super::run_cancellable(flatpak::adapt(command), token)?;
         Ok(())
     }
 
-    fn launch(
+    fn command(
         &self,
         executable: &Path,
         args: &[String],
         prefix: &Path,
         env: &std::collections::HashMap<String, String>,
-    ) -> Result<std::process::Child, crate::Error> {
-        todo!("Launch UMU")
+    ) -> Command {
+        let mut command = Command::new(self.info().executable_path());
+        command
+            .arg(executable)
+            .args(args)
+            .env("WINEPREFIX", prefix)
+            .env("PROTONPATH", self.proton_path())
+            .envs(env);
+        flatpak::adapt(command)
     }
 }
@@ -1,4 +1,11 @@
-use super::{Runner, RunnerInfo};
+use super::{Runner, RunnerFlavor, RunnerInfo};
+use crate::flatpak;
+use crate::jobs::CancelToken;
+use crate::launch;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
@@ -7,9 +14,14 @@ use std::process::Command;
 /// Wine is the base compatibility layer that all other runners build upon. It provides
 /// the core Windows API translation functionality that allows Windows applications
 /// to run on Unix-like systems.
+///
+/// Builds are found by their layout, see [`RunnerFlavor`].
 #[derive(Debug)]
 pub struct Wine {
     info: RunnerInfo,
+    flavor: RunnerFlavor,
+    /// Directory of the Wine build, holding its `bin` and `lib` directories
+    root: PathBuf,
 }
 
 /// Architecture for Wine prefix creation
@@ -17,14 +29,27 @@ pub struct Wine {
 /// Determines whether a Wine prefix should be configured for 32-bit or 64-bit
 /// Windows compatibility. This affects which Windows applications can run
 /// in the prefix
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
 pub enum PrefixArch {
     /// 32-bit Windows prefix architecture
     Win32,
     /// 64-bit Windows prefix architecture (recommended)
+    #[default]
     Win64,
 }
 
+impl PrefixArch {
+    /// Value of `WINEARCH`, as recorded in the registry of the prefix
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Win32 => "win32",
+            Self::Win64 => "win64",
+        }
+    }
+}
+
 /// Windows version compatibility settings
 ///
 /// Specifies which version of Windows the Wine prefix should emulate.
@@ -37,13 +62,193 @@ pub enum WindowsVersion {
     Win10,
 }
 
+/// Whether a wineserver runs for a prefix, see [`Wine::wineserver_status`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum WineserverStatus {
+    /// No program runs in the prefix, its registry is on disk
+    Stopped,
+    /// Programs run in the prefix, or just exited and the wineserver
+    /// lingers; the registry on disk may be out of date
+    Running { pid: u32 },
+}
+
+impl WineserverStatus {
+    pub fn is_running(self) -> bool {
+        matches!(self, Self::Running { .. })
+    }
+}
+
+/// Name of the Wine installed by the distribution, see [`Wine::system`]
+pub const SYSTEM_WINE: &str = "system-wine";
+
+/// Executables of distribution packages, in order of preference
+const SYSTEM_EXECUTABLES: &[&str] = &["wine", "wine64", "wine-stable"];
+
 impl TryFrom<&Path> for Wine {
     type Error = crate::Error;
 
     fn try_from(path: &Path) -> Result<Self, Self::Error> {
-        let executable = PathBuf::from("./bin/wine");
-        let info = RunnerInfo::try_from(path, &executable)?;
-        Ok(Wine { info })
+        Self::open(path, false)
+    }
+}
+
+impl Wine {
+    /// Create a Wine runner without starting it, see
+    /// [`discover_lazy`](super::discover_lazy)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `path` holds no Wine build
+    pub fn lazy(path: &Path) -> Result<Self, crate::Error> {
+        Self::open(path, true)
+    }
+
+    fn open(path: &Path, lazy: bool) -> Result<Self, crate::Error> {
+        let flavor = RunnerFlavor::detect(path).unwrap_or_default();
+        // A missing executable is reported by RunnerInfo
+        let (layout, executable) = flavor
+            .locate(path)
+            .unwrap_or(("", PathBuf::from("bin/wine")));
+        let info = if lazy {
+            RunnerInfo::lazy(path, &executable)?
+        } else {
+            RunnerInfo::try_from(path, &executable)?
+        };
+        let root = match layout {
+            "" => path.to_path_buf(),
+            layout => path.join(layout),
+        };
+        Ok(Wine { info, flavor, root })
+    }
+
+    /// A Wine runner made of `info`, for runners standing in for a build
+    pub(super) fn from_parts(info: RunnerInfo, flavor: RunnerFlavor, root: PathBuf) -> Self {
+        Wine { info, flavor, root }
+    }
+
+    /// Where the build comes from, see [`RunnerFlavor`]
+    pub fn flavor(&self) -> RunnerFlavor {
+        self.flavor
+    }
+
+    /// Directory of the Wine build, holding its `bin` and `lib` directories
+    ///
+    /// This is the directory of the runner for most builds, and a
+    /// subdirectory of it for others, e.g. `files` for Proton.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The Wine installed by the distribution, found on `PATH`
+    ///
+    /// Packages install `wine` in a shared `bin` directory, e.g.
+    /// `/usr/bin/wine`, its parent being the directory of the runner, where
+    /// its libraries are found. Symlinks, like Debian's alternatives, are
+    /// followed. The runner is named [`SYSTEM_WINE`], whatever its directory.
+    ///
+    /// Returns `None` if there is no Wine on `PATH`
+    pub fn system() -> Option<Self> {
+        Self::system_with(false)
+    }
+
+    /// Find the Wine installed by the distribution like [`Wine::system`],
+    /// without starting it
+    pub fn system_lazy() -> Option<Self> {
+        Self::system_with(true)
+    }
+
+    fn system_with(lazy: bool) -> Option<Self> {
+        let found = SYSTEM_EXECUTABLES
+            .iter()
+            .find_map(|name| launch::search_path(name))?;
+        let path = fs::canonicalize(&found).unwrap_or(found);
+        let bin = path.parent()?;
+        let file = Path::new(path.file_name()?);
+        let (directory, executable) = match bin.parent() {
+            Some(root) if bin.ends_with("bin") => (root, Path::new("bin").join(file)),
+            _ => (bin, file.to_path_buf()),
+        };
+        let info = if lazy {
+            RunnerInfo::lazy(directory, &executable)
+        } else {
+            RunnerInfo::try_from(directory, &executable)
+        };
+        match info {
+            Ok(info) => Some(Wine {
+                info: info.with_name(SYSTEM_WINE),
+                flavor: RunnerFlavor::System,
+                root: directory.to_path_buf(),
+            }),
+            Err(e) => {
+                tracing::debug!("Cannot use the Wine at '{}': {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Whether a wineserver runs for `prefix`, whichever runner started it
+    ///
+    /// Wine keeps a lock in a directory named after the device and inode of
+    /// the prefix, under `/tmp/.wine-<uid>`; it is held by the wineserver as
+    /// long as it runs. Nothing is started to find out. Wineservers started
+    /// on the host from a Flatpak sandbox, see [`crate::flatpak`], use the
+    /// host's `/tmp` and are not seen.
+    pub fn wineserver_status(&self, prefix: &Path) -> WineserverStatus {
+        match server_dir(prefix).and_then(|dir| lock_owner(&dir.join("lock"))) {
+            Some(pid) => WineserverStatus::Running { pid },
+            None => WineserverStatus::Stopped,
+        }
+    }
+
+    /// Wait for the wineserver of `prefix` to exit, once all its programs
+    /// have, which is when Wine writes the registry back to the hive files
+    ///
+    /// Returns at once if no wineserver runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `wineserver` can't be started
+    pub fn wineserver_wait(&self, prefix: &Path) -> Result<(), crate::Error> {
+        if !self.wineserver_status(prefix).is_running() {
+            return Ok(());
+        }
+        self.wineserver(prefix, "-w")
+    }
+
+    /// Stop the wineserver of `prefix` and all programs running in it
+    ///
+    /// Programs are killed without a chance to save anything, and the
+    /// registry changes not written yet are lost: use it before deleting a
+    /// prefix, or when programs hang.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `wineserver` can't be started, or the wineserver
+    /// still runs afterwards
+    pub fn wineserver_kill(&self, prefix: &Path) -> Result<(), crate::Error> {
+        if !self.wineserver_status(prefix).is_running() {
+            return Ok(());
+        }
+        tracing::info!("Killing the wineserver of '{}'", prefix.display());
+        self.wineserver(prefix, "-k")?;
+        if let WineserverStatus::Running { pid } = self.wineserver_status(prefix) {
+            return Err(std::io::Error::other(format!(
+                "The wineserver of '{}' still runs as {}",
+                prefix.display(),
+                pid
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Run `wineserver` of the build for `prefix` with `flag`
+    fn wineserver(&self, prefix: &Path, flag: &str) -> Result<(), crate::Error> {
+        let mut command = Command::new(self.root.join("bin").join("wineserver"));
+        command.arg(flag).env("WINEPREFIX", prefix);
+        flatpak::adapt(command).status()?;
+        Ok(())
     }
 }
 
@@ -61,23 +266,69 @@ impl Runner for Wine {
     }
 
     fn initialize(&self, prefix: &Path) -> Result<(), crate::Error> {
-        // FIXME: Launch winebridge to initialize the prefix
-        Command::new(self.info().executable_path())
+        self.initialize_cancellable(prefix, &CancelToken::default())
+    }
+
+    fn initialize_cancellable(
+        &self,
+        prefix: &Path,
+        token: &CancelToken,
+    ) -> Result<(), crate::Error> {
+        let _span = tracing::info_span!(
+            "initialize_prefix",
+            runner = self.info().name(),
+            prefix = %prefix.display()
+        )
+        .entered();
+        // winebridge can only run in an existing prefix, the manager deploys
+        // it once initialized, see crate::winebridge
+        let mut command = Command::new(self.info().executable_path());
+        command
             .arg("wineboot")
             .arg("--init")
-            .env("WINEPREFIX", prefix)
-            .output()?;
+            .env("WINEPREFIX", prefix);
+        super::run_cancellable(flatpak::adapt(command), token)?;
 
         Ok(())
     }
 
-    fn launch(
+    fn command(
         &self,
         executable: &Path,
         args: &[String],
         prefix: &Path,
         env: &std::collections::HashMap<String, String>,
-    ) -> Result<std::process::Child, crate::Error> {
-        todo!("Launch WINE")
+    ) -> Command {
+        let mut command = Command::new(self.info().executable_path());
+        command
+            .arg(executable)
+            .args(args)
+            .env("WINEPREFIX", prefix)
+            .envs(env);
+        flatpak::adapt(command)
+    }
+}
+
+/// Directory of the wineserver of `prefix`, as named by Wine
+fn server_dir(prefix: &Path) -> Option<PathBuf> {
+    let metadata = fs::metadata(prefix).ok()?;
+    let uid = unsafe { libc::getuid() };
+    Some(PathBuf::from(format!(
+        "/tmp/.wine-{}/server-{:x}-{:x}",
+        uid,
+        metadata.dev(),
+        metadata.ino()
+    )))
+}
+
+/// The process holding the lock `path`, if any
+fn lock_owner(path: &Path) -> Option<u32> {
+    let file = fs::File::open(path).ok()?;
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = libc::F_WRLCK as _;
+    lock.l_whence = libc::SEEK_SET as _;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut lock) } != 0 {
+        return None;
     }
+    (lock.l_type != libc::F_UNLCK as _).then_some(lock.l_pid as u32)
 }
@@ -0,0 +1,370 @@
+//! Save games inside prefixes
+//!
+//! Windows games keep their saves in a few places of the user profile:
+//! `Documents` and its `My Games` directory, `Saved Games`, and the roaming,
+//! local and low-integrity `AppData` directories. [`locate`] looks there for
+//! the directories named after a program, its product or its executable, and
+//! inside those named after its publisher, so frontends can show where the
+//! saves are.
+//!
+//! Once a program exits, its save directories can be backed up to a
+//! directory outside of the prefix, e.g. one synced by a cloud storage
+//! client, as configured by [`SaveBackupOptions`]. Backups are mirrored with
+//! `rsync` when it is installed, and copied otherwise.
+
+use crate::bottle::Bottle;
+use crate::pe;
+use crate::prefix;
+use crate::programs::Program;
+use crate::session::Sessions;
+use crate::{flatpak, launch, Error};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// How often the end of a session is checked for, to back up its saves
+const EXIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Directories of the save roots that never hold saves
+const IGNORED: &[&str] = &["microsoft", "wine", "temp", "packages", "mygames", "crashdumps"];
+
+/// Parent directories of executables that don't name the program
+const GENERIC_DIRS: &[&str] = &[
+    "bin", "bin32", "bin64", "binaries", "win32", "win64", "x64", "x86",
+];
+
+/// Names shorter than this are only matched exactly, not as part of a
+/// directory name
+const MIN_PARTIAL_MATCH: usize = 4;
+
+/// Directory of a user profile saves are looked for in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SaveRoot {
+    Documents,
+    /// `Documents/My Games`, used by many games of the Games for Windows era
+    MyGames,
+    SavedGames,
+    /// `AppData/Roaming`
+    RoamingAppData,
+    /// `AppData/Local`
+    LocalAppData,
+    /// `AppData/LocalLow`, used by Unity games
+    LocalLowAppData,
+}
+
+impl SaveRoot {
+    pub const ALL: [Self; 6] = [
+        Self::Documents,
+        Self::MyGames,
+        Self::SavedGames,
+        Self::RoamingAppData,
+        Self::LocalAppData,
+        Self::LocalLowAppData,
+    ];
+
+    /// Identifier of the root, also naming it in backups
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Documents => "documents",
+            Self::MyGames => "my-games",
+            Self::SavedGames => "saved-games",
+            Self::RoamingAppData => "appdata-roaming",
+            Self::LocalAppData => "appdata-local",
+            Self::LocalLowAppData => "appdata-locallow",
+        }
+    }
+
+    /// Path of the root relative to a user profile
+    pub fn path(self) -> &'static str {
+        match self {
+            Self::Documents => "Documents",
+            Self::MyGames => "Documents/My Games",
+            Self::SavedGames => "Saved Games",
+            Self::RoamingAppData => "AppData/Roaming",
+            Self::LocalAppData => "AppData/Local",
+            Self::LocalLowAppData => "AppData/LocalLow",
+        }
+    }
+}
+
+/// A directory holding saves of a program
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SaveLocation {
+    pub root: SaveRoot,
+    /// The directory, on the host
+    pub path: PathBuf,
+    /// Total size of the files in the directory
+    pub size: u64,
+    /// When a file of the directory was last modified
+    pub modified: Option<SystemTime>,
+}
+
+/// How save directories are backed up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum BackupMethod {
+    /// `rsync` when installed, [`BackupMethod::Copy`] otherwise
+    #[default]
+    Auto,
+    /// Mirror with `rsync -a --delete`
+    Rsync,
+    /// Replace the backup with a copy
+    Copy,
+}
+
+/// Backups of the saves of a bottle's programs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct SaveBackupOptions {
+    /// Directory receiving the backups, no backups are made when unset
+    ///
+    /// Saves are backed up to `<destination>/<bottle>/<program>/<root>/<dir>`,
+    /// see [`SaveRoot::id`].
+    pub destination: Option<PathBuf>,
+    pub method: BackupMethod,
+}
+
+/// Find the save directories of `program` in `bottle`, most recently
+/// modified first
+pub fn locate(bottle: &Bottle, program: &Program) -> Vec<SaveLocation> {
+    let (names, publishers) = names(bottle, program);
+    if names.is_empty() {
+        return Vec::new();
+    }
+
+    let mut locations: Vec<SaveLocation> = Vec::new();
+    for profile in profiles(&bottle.path) {
+        for root in SaveRoot::ALL {
+            for directory in subdirectories(&profile.join(root.path())) {
+                let name = normalize(&directory.to_string_lossy());
+                if IGNORED.contains(&name.as_str()) {
+                    continue;
+                }
+                let parent = profile.join(root.path()).join(&directory);
+                let mut found = Vec::new();
+                if matches(&name, &names) {
+                    found.push(parent);
+                } else if publishers.contains(&name) {
+                    let products = subdirectories(&parent).into_iter().filter(|product| {
+                        matches(&normalize(&product.to_string_lossy()), &names)
+                    });
+                    found.extend(products.map(|product| parent.join(product)));
+                }
+                for path in found {
+                    if locations.iter().any(|location| location.path == path) {
+                        continue;
+                    }
+                    let (size, modified) = contents(&path);
+                    locations.push(SaveLocation {
+                        root,
+                        path,
+                        size,
+                        modified,
+                    });
+                }
+            }
+        }
+    }
+    locations.sort_by(|a, b| b.modified.cmp(&a.modified));
+    locations
+}
+
+/// Back up the save directories of `program` to `options.destination`
+///
+/// # Returns
+///
+/// The backups that were written
+///
+/// # Errors
+///
+/// Returns an error if no destination is configured, or if a directory
+/// cannot be backed up
+pub fn backup(
+    bottle: &Bottle,
+    program: &Program,
+    options: &SaveBackupOptions,
+) -> Result<Vec<PathBuf>, Error> {
+    let destination = options.destination.as_ref().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("'{}' has no save backup directory", bottle.name),
+        )
+    })?;
+    let rsync = match options.method {
+        BackupMethod::Auto => launch::find_in_path("rsync").is_some(),
+        BackupMethod::Rsync => true,
+        BackupMethod::Copy => false,
+    };
+
+    let mut backups = Vec::new();
+    for location in locate(bottle, program) {
+        let Some(name) = location.path.file_name() else {
+            continue;
+        };
+        let target = destination
+            .join(&bottle.name)
+            .join(&program.name)
+            .join(location.root.id())
+            .join(name);
+        fs::create_dir_all(&target).map_err(Error::Io)?;
+        if rsync {
+            mirror(&location.path, &target)?;
+        } else {
+            fs::remove_dir_all(&target).map_err(Error::Io)?;
+            copy_dir(&location.path, &target)?;
+        }
+        backups.push(target);
+    }
+    tracing::info!(
+        "Backed up {} save directories of {} in '{}'",
+        backups.len(),
+        program.name,
+        bottle.name
+    );
+    Ok(backups)
+}
+
+/// Back up the saves of `program` once session `id` exited, see [`backup`]
+pub(crate) fn backup_after_exit(
+    sessions: Arc<Sessions>,
+    id: u64,
+    bottle: Bottle,
+    program: Program,
+) {
+    std::thread::spawn(move || {
+        while sessions.get(id).is_some() {
+            std::thread::sleep(EXIT_POLL_INTERVAL);
+        }
+        if let Err(e) = backup(&bottle, &program, &bottle.config.save_backup) {
+            tracing::warn!("Cannot back up the saves of session {}: {}", id, e);
+        }
+    });
+}
+
+/// Normalized names a save directory of `program` may have, and the
+/// normalized names of its publisher
+fn names(bottle: &Bottle, program: &Program) -> (Vec<String>, Vec<String>) {
+    let executable = prefix::host_executable(&bottle.path, &program.executable);
+    let mut names = vec![normalize(&program.name)];
+    if let Some(stem) = executable.file_stem() {
+        names.push(normalize(&stem.to_string_lossy()));
+    }
+    let directory = executable.parent().and_then(Path::file_name);
+    if let Some(directory) = directory {
+        let directory = normalize(&directory.to_string_lossy());
+        if !GENERIC_DIRS.contains(&directory.as_str()) {
+            names.push(directory);
+        }
+    }
+    let mut publishers = Vec::new();
+    if let Some(version) = pe::metadata(&executable).ok().and_then(|m| m.version) {
+        names.extend(version.product_name.as_deref().map(normalize));
+        publishers.extend(version.company_name.as_deref().map(normalize));
+    }
+    names.retain(|name| !name.is_empty());
+    names.sort();
+    names.dedup();
+    publishers.retain(|publisher| !publisher.is_empty());
+    (names, publishers)
+}
+
+/// Whether a normalized directory name refers to one of `names`
+fn matches(directory: &str, names: &[String]) -> bool {
+    let partial = |a: &str, b: &str| b.len() >= MIN_PARTIAL_MATCH && a.contains(b);
+    names
+        .iter()
+        .any(|name| directory == name || partial(directory, name) || partial(name, directory))
+}
+
+/// Lowercase letters and digits of `name`, so `The Witcher 3` matches
+/// `TheWitcher3`
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// User profiles of a prefix, e.g. `drive_c/users/steamuser` for Proton
+fn profiles(prefix: &Path) -> Vec<PathBuf> {
+    let users = prefix.join("drive_c/users");
+    subdirectories(&users)
+        .into_iter()
+        .filter(|name| !name.eq_ignore_ascii_case("Public"))
+        .map(|name| users.join(name))
+        .collect()
+}
+
+/// Names of the directories in `path`
+fn subdirectories(path: &Path) -> Vec<std::ffi::OsString> {
+    let Ok(entries) = fs::read_dir(path) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name())
+        .collect()
+}
+
+/// Total size and last modification of the files under `path`
+fn contents(path: &Path) -> (u64, Option<SystemTime>) {
+    let Ok(entries) = fs::read_dir(path) else {
+        return (0, None);
+    };
+    let mut size = 0;
+    let mut modified = None;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            let (inner_size, inner_modified) = contents(&entry.path());
+            size += inner_size;
+            modified = modified.max(inner_modified);
+        } else if metadata.is_file() {
+            size += metadata.len();
+            modified = modified.max(metadata.modified().ok());
+        }
+    }
+    (size, modified)
+}
+
+/// Mirror `source` to `target` with rsync
+fn mirror(source: &Path, target: &Path) -> Result<(), Error> {
+    // The trailing slashes copy the contents rather than the directory
+    let mut command = Command::new("rsync");
+    command
+        .arg("-a")
+        .arg("--delete")
+        .arg(format!("{}/", source.display()))
+        .arg(format!("{}/", target.display()))
+        .stdin(Stdio::null());
+    let output = flatpak::adapt(command).output().map_err(Error::Io)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = format!("rsync failed: {}", stderr.trim());
+        return Err(std::io::Error::other(message).into());
+    }
+    Ok(())
+}
+
+/// Copy the files under `source` to `target`, skipping symbolic links
+fn copy_dir(source: &Path, target: &Path) -> Result<(), Error> {
+    fs::create_dir_all(target).map_err(Error::Io)?;
+    for entry in fs::read_dir(source).map_err(Error::Io)? {
+        let entry = entry.map_err(Error::Io)?;
+        let file_type = entry.file_type().map_err(Error::Io)?;
+        let destination = target.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &destination)?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), &destination).map_err(Error::Io)?;
+        }
+    }
+    Ok(())
+}
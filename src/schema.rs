@@ -0,0 +1,107 @@
+//! JSON schemas of the documents exchanged with automation
+//!
+//! Manifests and recipes are written by tools such as Ansible or Nix modules,
+//! reports and sessions are read by them. [`schema`] emits the JSON schema of
+//! each [`Document`], so those tools can be generated from, and validate
+//! against, the crate they talk to. The types also have their own
+//! `schema()` function, e.g. [`BottleManifest::schema`].
+//!
+//! Schemas are versioned by [`SCHEMA_VERSION`], part of their `$id`. The
+//! version changes when a document valid under a schema may no longer be,
+//! i.e. when a field is removed, renamed or changes type; new optional fields
+//! don't change it.
+
+use crate::export::CompatibilityReport;
+use crate::installers::setup::InstallerReport;
+use crate::installers::Recipe;
+use crate::lockfile::Lockfile;
+use crate::manifest::{BottleManifest, VerificationReport};
+use crate::session::Session;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+/// Version of the schemas, see the [module documentation](self)
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A document with a published schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Document {
+    /// Input of bottle creation, see [`BottleManifest`]
+    BottleManifest,
+    /// Installer recipe, see [`Recipe`]
+    Recipe,
+    /// Outcome of a manifest's verification, see [`VerificationReport`]
+    VerificationReport,
+    /// Snapshot of an installation, see [`CompatibilityReport`]
+    CompatibilityReport,
+    /// Outcome of an installer run, see [`InstallerReport`]
+    InstallerReport,
+    /// A running program, as listed and reported on, see [`Session`]
+    Session,
+    /// What a bottle was built from, see [`Lockfile`]
+    Lockfile,
+}
+
+impl Document {
+    pub const ALL: [Self; 7] = [
+        Self::BottleManifest,
+        Self::Recipe,
+        Self::VerificationReport,
+        Self::CompatibilityReport,
+        Self::InstallerReport,
+        Self::Session,
+        Self::Lockfile,
+    ];
+
+    /// Identifier of the document, e.g. `bottle-manifest`
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::BottleManifest => "bottle-manifest",
+            Self::Recipe => "recipe",
+            Self::VerificationReport => "verification-report",
+            Self::CompatibilityReport => "compatibility-report",
+            Self::InstallerReport => "installer-report",
+            Self::Session => "session",
+            Self::Lockfile => "lockfile",
+        }
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+/// The JSON schema of `document`
+pub fn schema(document: Document) -> Value {
+    match document {
+        Document::BottleManifest => of::<BottleManifest>(document),
+        Document::Recipe => of::<Recipe>(document),
+        Document::VerificationReport => of::<VerificationReport>(document),
+        Document::CompatibilityReport => of::<CompatibilityReport>(document),
+        Document::InstallerReport => of::<InstallerReport>(document),
+        Document::Session => of::<Session>(document),
+        Document::Lockfile => of::<Lockfile>(document),
+    }
+}
+
+/// The schemas of every document, by id, e.g. to write them to files
+pub fn all() -> Vec<(&'static str, Value)> {
+    Document::ALL
+        .into_iter()
+        .map(|document| (document.id(), schema(document)))
+        .collect()
+}
+
+/// The schema of `T`, identified as `document`
+fn of<T: JsonSchema>(document: Document) -> Value {
+    let mut schema = serde_json::to_value(schemars::schema_for!(T)).unwrap_or_default();
+    if let Some(object) = schema.as_object_mut() {
+        let id = format!("urn:bottles-core:schema:{}:{}", document.id(), SCHEMA_VERSION);
+        object.insert("$id".to_string(), Value::String(id));
+    }
+    schema
+}
@@ -0,0 +1,116 @@
+//! Authentication of the clients of the API, see [`Auth`]
+//!
+//! Served on a Unix socket the API is only reachable by its user, see
+//! [`super::serve_uds`]. Served over TCP, e.g. by a gaming PC driven from a
+//! laptop, anyone reaching the address could launch programs: clients then
+//! prove who they are with a token shared beforehand or, with the `tls`
+//! feature, a certificate. Every request is checked by an interceptor in front
+//! of all the services, health checking and reflection included.
+
+use std::fmt;
+use std::sync::Arc;
+use tonic::service::Interceptor;
+#[cfg(feature = "tls")]
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use tonic::{Request, Status};
+
+/// How the clients of the API are authenticated, not at all by default
+#[derive(Clone, Default)]
+pub enum Auth {
+    /// Anyone reaching the address is trusted
+    #[default]
+    None,
+    /// Clients send the token in their `authorization` metadata, as
+    /// `Bearer <token>`
+    Token(String),
+    /// Connections are encrypted with `identity`, and clients present a
+    /// certificate signed by `client_ca` (mutual TLS)
+    #[cfg(feature = "tls")]
+    Tls {
+        identity: Identity,
+        client_ca: Certificate,
+    },
+}
+
+impl Auth {
+    pub fn is_none(&self) -> bool {
+        matches!(self, Self::None)
+    }
+
+    /// TLS configuration of the server, for mutual TLS
+    #[cfg(feature = "tls")]
+    pub(crate) fn tls_config(&self) -> Option<ServerTlsConfig> {
+        match self {
+            Self::Tls {
+                identity,
+                client_ca,
+            } => Some(
+                ServerTlsConfig::new()
+                    .identity(identity.clone())
+                    .client_ca_root(client_ca.clone()),
+            ),
+            _ => None,
+        }
+    }
+}
+
+// Keeps the token out of logs
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "None"),
+            Self::Token(_) => write!(f, "Token(..)"),
+            #[cfg(feature = "tls")]
+            Self::Tls { .. } => write!(f, "Tls {{ .. }}"),
+        }
+    }
+}
+
+/// Interceptor refusing the requests of clients not authenticated by `auth`
+#[derive(Debug, Clone)]
+pub(crate) struct Authenticator {
+    token: Option<Arc<str>>,
+    #[cfg(feature = "tls")]
+    certificate: bool,
+}
+
+impl Authenticator {
+    pub(crate) fn new(auth: &Auth) -> Self {
+        Self {
+            token: match auth {
+                Auth::Token(token) => Some(token.as_str().into()),
+                _ => None,
+            },
+            #[cfg(feature = "tls")]
+            certificate: matches!(auth, Auth::Tls { .. }),
+        }
+    }
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(token) = &self.token {
+            let given = request
+                .metadata()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "));
+            match given {
+                Some(given) if same(given.as_bytes(), token.as_bytes()) => {}
+                Some(_) => return Err(Status::unauthenticated("invalid token")),
+                None => return Err(Status::unauthenticated("missing token")),
+            }
+        }
+        // The TLS handshake already requires a certificate signed by the CA
+        #[cfg(feature = "tls")]
+        if self.certificate && !request.peer_certs().is_some_and(|certs| !certs.is_empty()) {
+            return Err(Status::unauthenticated("missing client certificate"));
+        }
+        Ok(request)
+    }
+}
+
+/// Compare tokens in a time that doesn't depend on where they differ
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
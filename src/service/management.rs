@@ -0,0 +1,243 @@
+use crate::bottle::{BottleFilter, BottleSummary, BottleType};
+use crate::manager::{Manager, BOTTLE_PAGE_SIZE};
+use crate::manifest::BottleManifest;
+use crate::proto::bottles::{
+    self as pb, management_server::Management, Bottle, BottleRequest, CancelJobRequest,
+    CreateBottleRequest, DeleteBottleRequest, GetBottleRequest, Job, ListBottlesRequest,
+    ListBottlesResponse, ListJobsRequest, ListJobsResponse, ResultResponse, StreamBottlesRequest,
+    SubscribeRequest,
+};
+use crate::Error;
+use super::blocking;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+/// Implementation of the `Management` gRPC service on top of a [`Manager`]
+pub struct ManagementService {
+    manager: Arc<Manager>,
+}
+
+impl ManagementService {
+    pub fn new(manager: Arc<Manager>) -> Self {
+        Self { manager }
+    }
+}
+
+#[tonic::async_trait]
+impl Management for ManagementService {
+    type StreamBottlesStream = ReceiverStream<Result<Bottle, Status>>;
+    type SubscribeStream = Pin<Box<dyn Stream<Item = Result<pb::Event, Status>> + Send>>;
+
+    async fn create_bottle(
+        &self,
+        request: Request<CreateBottleRequest>,
+    ) -> Result<Response<Bottle>, Status> {
+        let request = request.into_inner();
+        let kind = bottle_type(&request)?;
+
+        let manager = self.manager.clone();
+        let report = blocking(move || {
+            let runner = if request.runner.is_empty() {
+                manager.runners().into_iter().find(|r| r.is_available())
+            } else {
+                manager.find_runner(&request.runner)
+            }
+            .ok_or_else(|| Error::RunnerNotFound(request.runner.clone()))?;
+            manager.create_bottle(&BottleManifest::new(request.name, kind), runner.as_ref())
+        })
+        .await?;
+
+        Ok(Response::new((&report.bottle).into()))
+    }
+
+    async fn delete_bottle(
+        &self,
+        request: Request<DeleteBottleRequest>,
+    ) -> Result<Response<ResultResponse>, Status> {
+        let name = request.into_inner().name;
+        let manager = self.manager.clone();
+        blocking(move || manager.delete_bottle(&name)).await?;
+        Ok(Response::new(ResultResponse {
+            success: true,
+            error_message: String::new(),
+        }))
+    }
+
+    async fn list_bottles(
+        &self,
+        request: Request<ListBottlesRequest>,
+    ) -> Result<Response<ListBottlesResponse>, Status> {
+        let request = request.into_inner();
+        let filter = bottle_filter(request.filter)?;
+        let offset = request.offset as usize;
+        let limit = match request.limit {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+        let manager = self.manager.clone();
+        let bottles: Vec<Bottle> = blocking(move || {
+            if request.summary {
+                let summaries = manager.list_bottle_summaries_page(&filter, offset, limit)?;
+                return Ok(summaries.iter().map(Bottle::from).collect());
+            }
+            let mut bottles = manager.list_bottles()?;
+            for bottle in &mut bottles {
+                bottle.state = manager.bottle_state(&bottle.name);
+            }
+            Ok(bottles
+                .iter()
+                .filter(|bottle| filter.matches(&BottleSummary::from(*bottle)))
+                .skip(offset)
+                .take(limit)
+                .map(Bottle::from)
+                .collect())
+        })
+        .await?;
+        Ok(Response::new(ListBottlesResponse { bottles }))
+    }
+
+    /// Stream the summaries of the matching bottles, reading the index a page
+    /// at a time, see [`Manager::bottle_summaries`]
+    async fn stream_bottles(
+        &self,
+        request: Request<StreamBottlesRequest>,
+    ) -> Result<Response<Self::StreamBottlesStream>, Status> {
+        let filter = bottle_filter(request.into_inner().filter)?;
+        let (sender, receiver) = tokio::sync::mpsc::channel(BOTTLE_PAGE_SIZE);
+        let manager = self.manager.clone();
+        tokio::task::spawn_blocking(move || {
+            for summary in manager.bottle_summaries(filter) {
+                let bottle = summary.map(|summary| Bottle::from(&summary));
+                if sender.blocking_send(bottle.map_err(Status::from)).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn get_bottle(
+        &self,
+        request: Request<GetBottleRequest>,
+    ) -> Result<Response<Bottle>, Status> {
+        let name = request.into_inner().name;
+        let manager = self.manager.clone();
+        let bottle = blocking(move || {
+            let mut bottle = manager.get_bottle(&name)?;
+            bottle.state = manager.bottle_state(&bottle.name);
+            Ok(bottle)
+        })
+        .await?;
+        Ok(Response::new((&bottle).into()))
+    }
+
+    async fn queue_create_bottle(
+        &self,
+        request: Request<CreateBottleRequest>,
+    ) -> Result<Response<Job>, Status> {
+        let request = request.into_inner();
+        let kind = bottle_type(&request)?;
+        let runner = Some(request.runner).filter(|runner| !runner.is_empty());
+        let manifest = BottleManifest::new(request.name, kind);
+        let id = self.manager.queue_create_bottle(manifest, runner);
+        let job = self.manager.jobs().get(id).ok_or(Error::JobNotFound(id))?;
+        Ok(Response::new((&job).into()))
+    }
+
+    async fn list_jobs(
+        &self,
+        _request: Request<ListJobsRequest>,
+    ) -> Result<Response<ListJobsResponse>, Status> {
+        let jobs = self.manager.jobs().list();
+        Ok(Response::new(ListJobsResponse {
+            jobs: jobs.iter().map(Job::from).collect(),
+        }))
+    }
+
+    async fn cancel_job(
+        &self,
+        request: Request<CancelJobRequest>,
+    ) -> Result<Response<Job>, Status> {
+        let job = self.manager.jobs().cancel(request.into_inner().job_id)?;
+        Ok(Response::new((&job).into()))
+    }
+
+    /// Stream the events of the manager matching the request, see
+    /// [`crate::events`]
+    ///
+    /// A subscriber too slow to keep up gets a `DATA_LOSS` status, which ends
+    /// its stream; it should subscribe again and refresh what it shows.
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let request = request.into_inner();
+        // Read as the client reads, so nothing outlives its stream
+        let events = BroadcastStream::new(self.manager.events().subscribe());
+        let stream = events.filter_map(move |received| {
+            let event = match received {
+                Ok(event) => event,
+                Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                    tracing::warn!("A subscriber fell behind, {} events were dropped", missed);
+                    let status = format!("{} events were dropped", missed);
+                    return Some(Err(Status::data_loss(status)));
+                }
+            };
+            let kind =
+                request.kinds.is_empty() || request.kinds.iter().any(|kind| kind == event.id());
+            let bottle = request.bottle_name.is_empty()
+                || event.bottle().is_none_or(|bottle| bottle == request.bottle_name);
+            (kind && bottle).then(|| Ok((&event).into()))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn start_bottle(
+        &self,
+        _request: Request<BottleRequest>,
+    ) -> Result<Response<ResultResponse>, Status> {
+        Err(Status::unimplemented("Bottle agents are not supported yet"))
+    }
+
+    async fn stop_bottle(
+        &self,
+        _request: Request<BottleRequest>,
+    ) -> Result<Response<ResultResponse>, Status> {
+        Err(Status::unimplemented("Bottle agents are not supported yet"))
+    }
+
+    async fn restart_bottle(
+        &self,
+        _request: Request<BottleRequest>,
+    ) -> Result<Response<ResultResponse>, Status> {
+        Err(Status::unimplemented("Bottle agents are not supported yet"))
+    }
+}
+
+/// The type of the bottle to create, the default one if unset
+fn bottle_filter(filter: Option<pb::BottleFilter>) -> Result<BottleFilter, Status> {
+    let filter = filter.unwrap_or_default();
+    let kind = match filter.r#type.as_str() {
+        "" => None,
+        kind => Some(kind.parse().map_err(|e: Error| Status::invalid_argument(e.to_string()))?),
+    };
+    Ok(BottleFilter {
+        name: Some(filter.name).filter(|name| !name.is_empty()),
+        kind,
+        runner: Some(filter.runner).filter(|runner| !runner.is_empty()),
+    })
+}
+
+fn bottle_type(request: &CreateBottleRequest) -> Result<BottleType, Status> {
+    if request.r#type.is_empty() {
+        return Ok(BottleType::default());
+    }
+    request
+        .r#type
+        .parse()
+        .map_err(|e: Error| Status::invalid_argument(e.to_string()))
+}
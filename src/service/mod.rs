@@ -0,0 +1,453 @@
+mod auth;
+mod management;
+mod runtime;
+mod system;
+
+pub use auth::Auth;
+pub use management::ManagementService;
+pub use runtime::RuntimeService;
+pub use system::SystemService;
+
+use crate::bottle::{Bottle, BottleConfig, BottleState, BottleSummary};
+use crate::events::Event;
+use crate::jobs::{Job, JobStatus};
+use crate::manager::Manager;
+use crate::proto::bottles as pb;
+use crate::sync::SyncMode;
+use crate::Error;
+use auth::Authenticator;
+use pb::management_server::ManagementServer;
+use pb::runtime_server::RuntimeServer;
+use pb::system_server::SystemServer;
+use std::fmt;
+use std::net::SocketAddr;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::server::Connected;
+use tonic::transport::Server;
+use tonic::Status;
+
+/// Where [`serve`] listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Listen {
+    Tcp(SocketAddr),
+    /// A Unix socket only the current user can use, see [`serve_uds`]
+    Unix(PathBuf),
+}
+
+impl From<SocketAddr> for Listen {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr)
+    }
+}
+
+impl fmt::Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for Listen {
+    type Err = Error;
+
+    /// Parse `unix:<path>`, or a TCP address like `127.0.0.1:50051`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        s.parse().map(Self::Tcp).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{}' is neither an address nor unix:<path>", s),
+            )
+            .into()
+        })
+    }
+}
+
+/// Serve the bottles gRPC API on `listen`, a TCP address or a Unix socket
+///
+/// Besides the bottles services, the server implements the standard gRPC
+/// health checking protocol, reporting every service as serving, and server
+/// reflection, so generic clients like `grpcurl` list and call the methods
+/// without the protocol files.
+///
+/// With the `web` feature enabled the server also accepts gRPC-Web over
+/// HTTP/1.1, so browser-based frontends and dashboards can call it directly
+/// without a native gRPC client. Cross-origin access (CORS) is deliberately not
+/// enabled; put a reverse proxy in front of the daemon if the frontend is served
+/// from a different origin.
+///
+/// When running under systemd, readiness is reported once the address is bound
+/// and watchdog pings are sent for as long as the server runs. If the daemon was
+/// socket activated, the socket passed by systemd is served instead and
+/// `listen` is ignored, so the daemon only runs once a frontend connects.
+///
+/// Clients are not authenticated, see [`serve_with`] to expose the API
+/// beyond this machine.
+///
+/// # Errors
+///
+/// Returns an error if the address cannot be bound or the server fails
+pub async fn serve(manager: Arc<Manager>, listen: impl Into<Listen>) -> Result<(), Error> {
+    serve_with(manager, listen, Auth::None).await
+}
+
+/// Serve the bottles gRPC API on `listen` like [`serve`], refusing the
+/// requests of clients not authenticated by `auth`
+///
+/// # Errors
+///
+/// Returns an error if the address cannot be bound, if the TLS configuration
+/// is invalid or the server fails
+pub async fn serve_with(
+    manager: Arc<Manager>,
+    listen: impl Into<Listen>,
+    auth: Auth,
+) -> Result<(), Error> {
+    #[cfg(target_os = "linux")]
+    if let Some(listener) = crate::systemd::activated_listener()? {
+        return serve_activated(manager, listener, auth).await;
+    }
+
+    let listen = listen.into();
+    match &listen {
+        Listen::Tcp(addr) => {
+            let listener = TcpListener::bind(addr).await.map_err(Error::Io)?;
+            tracing::info!("Serving bottles API on {}", listen);
+            if auth.is_none() && !addr.ip().is_loopback() {
+                tracing::warn!("Clients on the network reaching {} are not authenticated", addr);
+            }
+            run(manager, TcpListenerStream::new(listener), auth).await
+        }
+        Listen::Unix(path) => serve_unix(manager, path, auth).await,
+    }
+}
+
+/// Serve the bottles gRPC API on a Unix socket at `path` that only the
+/// current user can use
+///
+/// The socket is only readable and writable by its owner, in a private
+/// directory if it has to be created, and the credentials of the process
+/// connecting are checked (`SO_PEERCRED`): connections of other users are
+/// dropped, even if the permissions were loosened. This is the transport of
+/// a daemon serving the frontends of its user, see [`default_socket`]. A
+/// stale socket left by a daemon that didn't exit cleanly is replaced.
+///
+/// # Errors
+///
+/// Returns an error if a daemon already serves on `path`, if the socket
+/// cannot be bound or the server fails
+pub async fn serve_uds(manager: Arc<Manager>, path: impl AsRef<Path>) -> Result<(), Error> {
+    serve_unix(manager, path.as_ref(), Auth::None).await
+}
+
+/// Serve on a Unix socket only the current user can use, see [`serve_uds`]
+async fn serve_unix(manager: Arc<Manager>, path: &Path, auth: Auth) -> Result<(), Error> {
+    let listener = bind_unix(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).map_err(Error::Io)?;
+    tracing::info!("Serving bottles API on unix:{}", path.display());
+    run(manager, owner_connections(listener), auth).await
+}
+
+/// The connections to `listener` of processes running as the current user
+fn owner_connections(listener: UnixListener) -> impl Stream<Item = std::io::Result<UnixStream>> {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    let uid = unsafe { libc::geteuid() };
+    UnixListenerStream::new(listener).filter(move |connection| match connection {
+        Ok(stream) => is_owner(stream, uid),
+        Err(_) => true,
+    })
+}
+
+/// Where the socket of the daemon of the current user is, relative to its
+/// runtime directory
+pub const SOCKET_PATH: &str = "bottles/bottles.sock";
+
+/// The socket of the daemon of the current user,
+/// `$XDG_RUNTIME_DIR/bottles/bottles.sock`, see [`SOCKET_PATH`]
+///
+/// Returns `None` if `XDG_RUNTIME_DIR` is not set.
+pub fn default_socket() -> Option<PathBuf> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty())?;
+    Some(PathBuf::from(runtime_dir).join(SOCKET_PATH))
+}
+
+/// Whether the process at the other end of `stream` runs as the user `uid`
+fn is_owner(stream: &UnixStream, uid: u32) -> bool {
+    match stream.peer_cred() {
+        Ok(peer) if peer.uid() == uid => true,
+        Ok(peer) => {
+            tracing::warn!("Refused a connection of user {} (pid {:?})", peer.uid(), peer.pid());
+            false
+        }
+        Err(e) => {
+            tracing::warn!("Refused a connection, its peer is unknown: {}", e);
+            false
+        }
+    }
+}
+
+/// Bind a Unix socket at `path`, removing a stale one
+fn bind_unix(path: &Path) -> Result<UnixListener, Error> {
+    if path.symlink_metadata().is_ok() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("A daemon already serves on '{}'", path.display()),
+            )
+            .into());
+        }
+        std::fs::remove_file(path).map_err(Error::Io)?;
+    }
+    if let Some(parent) = path.parent().filter(|parent| !parent.exists()) {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)
+            .map_err(Error::Io)?;
+    }
+    UnixListener::bind(path).map_err(Error::Io)
+}
+
+/// Serve the bottles gRPC API on a socket passed in by systemd
+#[cfg(target_os = "linux")]
+async fn serve_activated(
+    manager: Arc<Manager>,
+    listener: crate::systemd::ActivatedListener,
+    auth: Auth,
+) -> Result<(), Error> {
+    use crate::systemd::ActivatedListener;
+
+    match listener {
+        ActivatedListener::Tcp(listener) => {
+            listener.set_nonblocking(true).map_err(Error::Io)?;
+            let listener = TcpListener::from_std(listener).map_err(Error::Io)?;
+            if let Ok(addr) = listener.local_addr() {
+                tracing::info!("Serving bottles API on {} (socket activated)", addr);
+            }
+            run(manager, TcpListenerStream::new(listener), auth).await
+        }
+        ActivatedListener::Unix(listener) => {
+            listener.set_nonblocking(true).map_err(Error::Io)?;
+            let listener = UnixListener::from_std(listener).map_err(Error::Io)?;
+            if let Ok(addr) = listener.local_addr() {
+                let path = addr.as_pathname().map(|p| p.display().to_string());
+                tracing::info!(
+                    "Serving bottles API on {} (socket activated)",
+                    path.as_deref().unwrap_or("an unnamed socket")
+                );
+            }
+            // Whoever can reach the socket, only the user may use the API
+            run(manager, owner_connections(listener), auth).await
+        }
+    }
+}
+
+/// Run the server on already accepted connections until it fails
+async fn run<I, IO, IE>(manager: Arc<Manager>, incoming: I, auth: Auth) -> Result<(), Error>
+where
+    I: Stream<Item = Result<IO, IE>>,
+    IO: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
+    IO::ConnectInfo: Clone + Send + Sync + 'static,
+    IE: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let management = ManagementServer::new(ManagementService::new(manager.clone()));
+    let runtime = RuntimeServer::new(RuntimeService::new(manager));
+    let system = SystemServer::new(SystemService);
+
+    let (reporter, health) = tonic_health::server::health_reporter();
+    reporter.set_serving::<ManagementServer<ManagementService>>().await;
+    reporter.set_serving::<RuntimeServer<RuntimeService>>().await;
+    reporter.set_serving::<SystemServer<SystemService>>().await;
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(crate::proto::FILE_DESCRIPTOR_SET)
+        .build_v1()
+        .map_err(std::io::Error::other)?;
+    // Older clients only know the alpha version of reflection
+    let reflection_alpha = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(crate::proto::FILE_DESCRIPTOR_SET)
+        .build_v1alpha()
+        .map_err(std::io::Error::other)?;
+
+    #[allow(unused_mut)]
+    let mut builder = Server::builder();
+    #[cfg(feature = "tls")]
+    if let Some(tls) = auth.tls_config() {
+        builder = builder.tls_config(tls)?;
+    }
+    let authenticator = tonic::service::interceptor(Authenticator::new(&auth));
+
+    #[cfg(not(feature = "web"))]
+    let router = builder
+        .layer(authenticator)
+        .add_service(management)
+        .add_service(runtime)
+        .add_service(system)
+        .add_service(health)
+        .add_service(reflection)
+        .add_service(reflection_alpha);
+    #[cfg(feature = "web")]
+    let router = builder
+        .accept_http1(true)
+        .layer(tonic_web::GrpcWebLayer::new())
+        .layer(authenticator)
+        .add_service(management)
+        .add_service(runtime)
+        .add_service(system)
+        .add_service(health)
+        .add_service(reflection)
+        .add_service(reflection_alpha);
+
+    #[cfg(target_os = "linux")]
+    let _watchdog = crate::systemd::Watchdog::start();
+    #[cfg(target_os = "linux")]
+    crate::systemd::notify_ready();
+
+    router.serve_with_incoming(incoming).await?;
+    Ok(())
+}
+
+/// Run a blocking manager operation off the async runtime
+async fn blocking<T, F>(f: F) -> Result<T, Status>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(Status::from)
+}
+
+impl From<Error> for Status {
+    fn from(error: Error) -> Self {
+        match &error {
+            Error::BottleNotFound(_)
+            | Error::RunnerNotFound(_)
+            | Error::SessionNotFound(_)
+            | Error::JobNotFound(_) => Status::not_found(error.to_string()),
+            Error::BottleExists(_) => Status::already_exists(error.to_string()),
+            Error::BottleBusy(_) => Status::unavailable(error.to_string()),
+            Error::InvalidState(..) => Status::failed_precondition(error.to_string()),
+            Error::NotAuthorized(_) => Status::permission_denied(error.to_string()),
+            Error::Integrity(_) => Status::data_loss(error.to_string()),
+            Error::Cancelled => Status::cancelled(error.to_string()),
+            Error::Remote(status) => status.as_ref().clone(),
+            _ => Status::internal(error.to_string()),
+        }
+    }
+}
+
+impl From<&Bottle> for pb::Bottle {
+    fn from(bottle: &Bottle) -> Self {
+        Self {
+            name: bottle.name.clone(),
+            path: bottle.path.display().to_string(),
+            r#type: bottle.kind.to_string(),
+            active: bottle.active,
+            config: Some((&bottle.config).into()),
+            runner: bottle.config.runner.clone().unwrap_or_default(),
+            read_only: bottle.read_only,
+            state: bottle.state.id().to_string(),
+            pids: pids(&bottle.state),
+        }
+    }
+}
+
+impl From<&BottleSummary> for pb::Bottle {
+    fn from(bottle: &BottleSummary) -> Self {
+        Self {
+            name: bottle.name.clone(),
+            path: bottle.path.display().to_string(),
+            r#type: bottle.kind.to_string(),
+            active: bottle.active,
+            config: None,
+            runner: bottle.runner.clone().unwrap_or_default(),
+            read_only: bottle.read_only,
+            state: bottle.state.id().to_string(),
+            pids: pids(&bottle.state),
+        }
+    }
+}
+
+fn pids(state: &BottleState) -> Vec<u32> {
+    match state {
+        BottleState::Running { pids } => pids.clone(),
+        _ => Vec::new(),
+    }
+}
+
+impl From<&Job> for pb::Job {
+    fn from(job: &Job) -> Self {
+        let progress = job.progress.as_ref();
+        Self {
+            id: job.id,
+            kind: job.kind.id().to_string(),
+            description: job.description.clone(),
+            status: job.status.id().to_string(),
+            error: match &job.status {
+                JobStatus::Failed { error } => error.clone(),
+                _ => String::new(),
+            },
+            progress_current: progress.map(|progress| progress.current).unwrap_or_default(),
+            progress_total: progress.and_then(|progress| progress.total).unwrap_or_default(),
+            progress_message: progress
+                .map(|progress| progress.message.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&Event> for pb::Event {
+    fn from(event: &Event) -> Self {
+        let mut message = Self {
+            kind: event.id().to_string(),
+            bottle_name: event.bottle().unwrap_or_default().to_string(),
+            ..Self::default()
+        };
+        match event {
+            Event::ProcessStarted {
+                session,
+                pid,
+                program,
+                ..
+            }
+            | Event::ProcessExited {
+                session,
+                pid,
+                program,
+                ..
+            } => {
+                message.session_id = *session;
+                message.pid = *pid;
+                message.program = program.display().to_string();
+            }
+            Event::JobProgress { job } => message.job = Some(job.into()),
+            Event::BottleCreated { .. } | Event::BottleDeleted { .. } => {}
+        }
+        message
+    }
+}
+
+impl From<&BottleConfig> for pb::BottleConfig {
+    fn from(config: &BottleConfig) -> Self {
+        Self {
+            runner: config.runner.clone().unwrap_or_default(),
+            dxvk_version: config.dxvk_version.clone().unwrap_or_default(),
+            vkd3d_version: config.vkd3d_version.clone().unwrap_or_default(),
+            esync: config.sync == SyncMode::Esync,
+            fsync: config.sync == SyncMode::Fsync,
+            ..Default::default()
+        }
+    }
+}
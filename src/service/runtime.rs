@@ -0,0 +1,268 @@
+use super::blocking;
+use crate::integrations::sunshine::Sunshine;
+use crate::logs::{self, LogFilter};
+use crate::manager::Manager;
+use crate::programs::Program;
+use crate::proto::bottles::{
+    runtime_server::Runtime, BottleRequest, LaunchProgramRequest, LaunchProgramResponse, LogLine,
+    ProcessInfo, ProcessList, ResourceUsageList, ResultResponse, SaveLocation,
+    SaveLocationsRequest, SaveLocationsResponse, StreamLogsRequest, TerminateProgramRequest,
+    ThumbnailRequest, ThumbnailResponse, WatchResourceUsageRequest,
+};
+use crate::saves;
+#[cfg(target_os = "linux")]
+use crate::Error;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+use std::time::UNIX_EPOCH;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+/// Polling interval of `WatchResourceUsage` when the request doesn't set one
+#[cfg(target_os = "linux")]
+const DEFAULT_USAGE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shortest polling interval accepted by `WatchResourceUsage`
+#[cfg(target_os = "linux")]
+const MIN_USAGE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Implementation of the `Runtime` gRPC service on top of a [`Manager`]
+pub struct RuntimeService {
+    manager: Arc<Manager>,
+}
+
+impl RuntimeService {
+    pub fn new(manager: Arc<Manager>) -> Self {
+        Self { manager }
+    }
+}
+
+#[tonic::async_trait]
+impl Runtime for RuntimeService {
+    type WatchResourceUsageStream = ReceiverStream<Result<ResourceUsageList, Status>>;
+    type StreamLogsStream = Pin<Box<dyn Stream<Item = Result<LogLine, Status>> + Send>>;
+
+    async fn launch_program(
+        &self,
+        request: Request<LaunchProgramRequest>,
+    ) -> Result<Response<LaunchProgramResponse>, Status> {
+        let request = request.into_inner();
+        let manager = self.manager.clone();
+        let (session, stream_app) = blocking(move || {
+            let overrides: HashMap<String, String> = request.env_overrides.into_iter().collect();
+            let session = manager.launch_program_with_env(
+                &request.bottle_name,
+                &PathBuf::from(request.program_path),
+                &request.arguments,
+                &overrides,
+            )?;
+            // The program runs either way, streaming it is a convenience
+            let stream_app = if request.stream {
+                Sunshine::find()
+                    .and_then(|sunshine| sunshine.register(&session, &request.arguments))
+                    .map(|handoff| handoff.app)
+                    .unwrap_or_else(|e| {
+                        tracing::warn!("Cannot register '{}' for streaming: {}", session.bottle, e);
+                        String::new()
+                    })
+            } else {
+                String::new()
+            };
+            Ok((session, stream_app))
+        })
+        .await?;
+        Ok(Response::new(LaunchProgramResponse {
+            pid: session.pid,
+            success: true,
+            session_id: session.id,
+            stream_app,
+        }))
+    }
+
+    async fn terminate_program(
+        &self,
+        request: Request<TerminateProgramRequest>,
+    ) -> Result<Response<ResultResponse>, Status> {
+        let request = request.into_inner();
+        let manager = self.manager.clone();
+        blocking(move || {
+            let session = manager
+                .sessions()
+                .into_iter()
+                .find(|s| s.bottle == request.bottle_name && s.pid == request.pid)
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("no program with pid {} in '{}'", request.pid, request.bottle_name),
+                    )
+                })?;
+            manager.stop_session(session.id)
+        })
+        .await?;
+        Ok(Response::new(ResultResponse {
+            success: true,
+            error_message: String::new(),
+        }))
+    }
+
+    async fn list_running_processes(
+        &self,
+        request: Request<BottleRequest>,
+    ) -> Result<Response<ProcessList>, Status> {
+        let name = request.into_inner().name;
+        let processes = self
+            .manager
+            .sessions()
+            .into_iter()
+            .filter(|s| s.bottle == name)
+            .map(|s| ProcessInfo {
+                pid: s.pid,
+                name: s
+                    .program
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                #[cfg(target_os = "linux")]
+                threads: crate::resources::threads(s.pid).unwrap_or(0),
+                #[cfg(not(target_os = "linux"))]
+                threads: 0,
+            })
+            .collect();
+        Ok(Response::new(ProcessList { processes }))
+    }
+
+    async fn get_thumbnail(
+        &self,
+        request: Request<ThumbnailRequest>,
+    ) -> Result<Response<ThumbnailResponse>, Status> {
+        let id = request.into_inner().session_id;
+        let manager = self.manager.clone();
+        let thumbnail = blocking(move || manager.thumbnail(id)).await?;
+        let captured_at = thumbnail
+            .captured_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        Ok(Response::new(ThumbnailResponse {
+            png: thumbnail.png,
+            captured_at,
+        }))
+    }
+
+    async fn list_save_locations(
+        &self,
+        request: Request<SaveLocationsRequest>,
+    ) -> Result<Response<SaveLocationsResponse>, Status> {
+        let request = request.into_inner();
+        let manager = self.manager.clone();
+        let locations = blocking(move || {
+            let bottle = manager.get_bottle(&request.bottle_name)?;
+            Ok(saves::locate(&bottle, &Program::new(request.program_path)))
+        })
+        .await?;
+        let locations = locations
+            .into_iter()
+            .map(|location| SaveLocation {
+                root: location.root.id().to_string(),
+                path: location.path.display().to_string(),
+                size_bytes: location.size,
+                modified_at: location
+                    .modified
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_secs() as i64),
+            })
+            .collect();
+        Ok(Response::new(SaveLocationsResponse { locations }))
+    }
+
+    /// Stream the output of a session until it exits, see [`logs::tail`]
+    ///
+    /// Lines are sent unparsed, clients parse them with [`logs::parse_line`].
+    async fn stream_logs(
+        &self,
+        request: Request<StreamLogsRequest>,
+    ) -> Result<Response<Self::StreamLogsStream>, Status> {
+        let request = request.into_inner();
+        let lines = logs::tail(
+            self.manager.clone(),
+            &request.bottle_name,
+            request.session_id,
+            LogFilter::default(),
+        )
+        .await?;
+        let lines = lines.map(|line| Ok(LogLine { raw: line.raw }));
+        Ok(Response::new(Box::pin(lines)))
+    }
+
+    /// Stream the resource usage of the running sessions until the client
+    /// disconnects
+    async fn watch_resource_usage(
+        &self,
+        request: Request<WatchResourceUsageRequest>,
+    ) -> Result<Response<Self::WatchResourceUsageStream>, Status> {
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = request;
+            return Err(Status::unimplemented(
+                "resource usage is only available on Linux",
+            ));
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            let request = request.into_inner();
+            let bottle = (!request.bottle_name.is_empty()).then_some(request.bottle_name);
+            if let Some(name) = &bottle {
+                let manager = self.manager.clone();
+                let name = name.clone();
+                blocking(move || manager.get_bottle(&name)).await?;
+            }
+            let interval = match request.interval_ms {
+                0 => DEFAULT_USAGE_INTERVAL,
+                ms => Duration::from_millis(ms.into()).max(MIN_USAGE_INTERVAL),
+            };
+
+            let (sender, receiver) = tokio::sync::mpsc::channel(4);
+            let manager = self.manager.clone();
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(interval);
+                loop {
+                    ticks.tick().await;
+                    let manager = manager.clone();
+                    let bottle = bottle.clone();
+                    let usages = blocking(move || {
+                        Ok::<_, Error>(manager.resource_usage(bottle.as_deref()))
+                    })
+                    .await;
+                    let list = usages.map(|usages| ResourceUsageList {
+                        sessions: usages.into_iter().map(Into::into).collect(),
+                    });
+                    if sender.send(list).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok(Response::new(ReceiverStream::new(receiver)))
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<crate::resources::ResourceUsage> for crate::proto::bottles::ResourceUsage {
+    fn from(usage: crate::resources::ResourceUsage) -> Self {
+        Self {
+            session_id: usage.session,
+            bottle_name: usage.bottle,
+            pid: usage.pid,
+            processes: usage.processes,
+            cpu_percent: usage.cpu_percent,
+            memory_bytes: usage.memory_bytes,
+            gpu_percent: usage.gpu_percent,
+            gpu_memory_bytes: usage.gpu_memory_bytes,
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use super::blocking;
+use crate::proto::bottles::{
+    system_server::System, CheckStatus, DiagnosticCheck, DiagnosticsRequest, DiagnosticsResponse,
+    HealthRequest, HealthResponse, NotifyRequest, NotifyResponse,
+};
+use crate::system::diagnostics::{self, Check, Feature};
+use crate::Error;
+use tonic::{Request, Response, Status};
+
+/// Features the diagnostics explanations are given for
+const EXPLAINED: &[Feature] = &[
+    Feature::Dxvk,
+    Feature::Vkd3d,
+    Feature::ThirtyTwoBit,
+    Feature::Esync,
+    Feature::Fsync,
+    Feature::Ntsync,
+];
+
+/// Implementation of the `System` gRPC service
+#[derive(Debug, Default)]
+pub struct SystemService;
+
+#[tonic::async_trait]
+impl System for SystemService {
+    async fn health(
+        &self,
+        _request: Request<HealthRequest>,
+    ) -> Result<Response<HealthResponse>, Status> {
+        Ok(Response::new(HealthResponse {
+            ok: true,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }))
+    }
+
+    async fn notify(
+        &self,
+        request: Request<NotifyRequest>,
+    ) -> Result<Response<NotifyResponse>, Status> {
+        tracing::info!("Notification: {}", request.into_inner().message);
+        Ok(Response::new(NotifyResponse { success: true }))
+    }
+
+    async fn diagnostics(
+        &self,
+        _request: Request<DiagnosticsRequest>,
+    ) -> Result<Response<DiagnosticsResponse>, Status> {
+        let report = blocking(|| Ok::<_, Error>(diagnostics::run())).await?;
+        let explanations = EXPLAINED
+            .iter()
+            .filter_map(|feature| report.explain(*feature))
+            .collect();
+        Ok(Response::new(DiagnosticsResponse {
+            checks: report.checks.into_iter().map(Into::into).collect(),
+            explanations,
+        }))
+    }
+}
+
+impl From<Check> for DiagnosticCheck {
+    fn from(check: Check) -> Self {
+        let status = match check.status {
+            diagnostics::CheckStatus::Ok => CheckStatus::Ok,
+            diagnostics::CheckStatus::Warning => CheckStatus::Warning,
+            diagnostics::CheckStatus::Error => CheckStatus::Error,
+        };
+        Self {
+            id: check.id,
+            title: check.title,
+            status: status.into(),
+            detail: check.detail,
+            hint: check.hint.unwrap_or_default(),
+            affects: check.affects.iter().map(|feature| feature.to_string()).collect(),
+        }
+    }
+}
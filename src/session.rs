@@ -0,0 +1,185 @@
+use crate::events::{Event, Events};
+use crate::lock::BottleLock;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Child;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+/// A program launched in a bottle through the manager
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Session {
+    /// Identifier of the session, unique for the lifetime of the manager
+    pub id: u64,
+    /// Name of the bottle the program runs in
+    pub bottle: String,
+    /// Program that was launched
+    pub program: PathBuf,
+    /// Process id of the launched runner process
+    pub pid: u32,
+    pub started_at: SystemTime,
+    /// File receiving the output of the program, see [`crate::logs`]
+    #[serde(default)]
+    pub log: Option<PathBuf>,
+    /// Problems noticed while the program runs
+    #[serde(default)]
+    pub warnings: Vec<SessionWarning>,
+}
+
+impl Session {
+    /// The JSON schema of sessions, see [`crate::schema`]
+    #[cfg(feature = "schema")]
+    pub fn schema() -> serde_json::Value {
+        crate::schema::schema(crate::schema::Document::Session)
+    }
+}
+
+/// A problem noticed in a running session, with hints on how to fix it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionWarning {
+    pub message: String,
+    pub hints: Vec<String>,
+}
+
+/// What was launched in a bottle, so it can be launched again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Launch {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    /// Per-launch environment overrides
+    pub overrides: HashMap<String, String>,
+}
+
+/// Registry of the sessions started by a manager
+///
+/// Sessions are removed as soon as their process is found to have exited,
+/// emitting [`Event::ProcessExited`]. The manager checks every session it
+/// launched each second for that.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    next_id: AtomicU64,
+    running: Mutex<HashMap<u64, (Session, Child)>>,
+    /// Locks on their bottle held by sessions, see [`Sessions::hold`]
+    locks: Mutex<HashMap<u64, BottleLock>>,
+    events: Events,
+}
+
+impl Sessions {
+    /// Emit the starts and exits of sessions on `events`
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Reserve the id of a session about to be started
+    pub fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Track a freshly spawned process under an id from [`Sessions::next_id`]
+    pub fn insert(
+        &self,
+        id: u64,
+        bottle: &str,
+        program: impl Into<PathBuf>,
+        child: Child,
+        log: Option<PathBuf>,
+    ) -> Session {
+        let session = Session {
+            id,
+            bottle: bottle.to_string(),
+            program: program.into(),
+            pid: child.id(),
+            started_at: SystemTime::now(),
+            log,
+            warnings: Vec::new(),
+        };
+        self.running()
+            .insert(session.id, (session.clone(), child));
+        self.events.emit(Event::ProcessStarted {
+            bottle: session.bottle.clone(),
+            session: session.id,
+            pid: session.pid,
+            program: session.program.clone(),
+        });
+        session
+    }
+
+    /// List the sessions that are still running
+    pub fn list(&self) -> Vec<Session> {
+        let mut running = self.running();
+        running.retain(|_, (session, child)| {
+            let alive = matches!(child.try_wait(), Ok(None));
+            if !alive {
+                self.events.emit(exited(session));
+            }
+            alive
+        });
+        self.locks().retain(|id, _| running.contains_key(id));
+        let mut sessions: Vec<Session> = running.values().map(|(s, _)| s.clone()).collect();
+        sessions.sort_by_key(|s| s.id);
+        sessions
+    }
+
+    /// Get a running session by id
+    pub fn get(&self, id: u64) -> Option<Session> {
+        self.list().into_iter().find(|s| s.id == id)
+    }
+
+    /// Keep `lock` until the session `id` ends
+    pub fn hold(&self, id: u64, lock: BottleLock) {
+        self.locks().insert(id, lock);
+    }
+
+    /// Attach a warning to a running session
+    pub fn warn(&self, id: u64, warning: SessionWarning) {
+        if let Some((session, _)) = self.running().get_mut(&id) {
+            session.warnings.push(warning);
+        }
+    }
+
+    /// Kill the process of a session and stop tracking it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SessionNotFound`] if no session with that id is running
+    pub fn stop(&self, id: u64) -> Result<Session, Error> {
+        let (session, mut child) = self
+            .running()
+            .remove(&id)
+            .ok_or(Error::SessionNotFound(id))?;
+        if matches!(child.try_wait(), Ok(None)) {
+            child.kill().map_err(Error::Io)?;
+        }
+        child.wait().map_err(Error::Io)?;
+        self.locks().remove(&id);
+        self.events.emit(exited(&session));
+        Ok(session)
+    }
+
+    fn running(&self) -> MutexGuard<'_, HashMap<u64, (Session, Child)>> {
+        self.running
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn locks(&self) -> MutexGuard<'_, HashMap<u64, BottleLock>> {
+        self.locks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn exited(session: &Session) -> Event {
+    Event::ProcessExited {
+        bottle: session.bottle.clone(),
+        session: session.id,
+        pid: session.pid,
+        program: session.program.clone(),
+    }
+}
@@ -0,0 +1,424 @@
+//! Desktop shortcuts for programs in a bottle
+//!
+//! A shortcut lets users start a program from their desktop's application
+//! menu without opening a frontend. On Linux it is an XDG `.desktop` entry in
+//! the user's `applications` directory, on macOS an `.app` bundle in
+//! `~/Applications`. The icon is extracted from the program's executable, see
+//! [`crate::pe`].
+//!
+//! Shortcuts don't run Wine themselves: they launch back through the daemon,
+//! either by opening a `bottles://run/...` URI (see [`uri`]), handled by the
+//! frontend registered for the scheme, or by running a command line entry
+//! point. That way programs started from the desktop get the bottle's runner,
+//! environment and launch wrappers, and are tracked as sessions.
+
+use crate::bottle::Bottle;
+use crate::flatpak;
+use crate::pe::{Icon, PeFile};
+use crate::prefix;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Scheme of the URIs opened by shortcuts
+pub const URI_SCHEME: &str = "bottles";
+
+/// Prefix of the file names of the shortcuts, so they can be told apart from
+/// other applications
+const FILE_PREFIX: &str = "bottles-";
+
+/// How a shortcut starts its program
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Launcher {
+    /// Open the program's [`uri`]
+    #[default]
+    Uri,
+    /// Run this command line entry point as
+    /// `<command> run <bottle> <program> [args...]`
+    Command(PathBuf),
+}
+
+/// A program to create a shortcut for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shortcut {
+    /// Name shown in the application menu
+    pub name: String,
+    /// The program's executable, as passed to
+    /// [`crate::manager::Manager::launch_program`]
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    pub launcher: Launcher,
+}
+
+impl Shortcut {
+    /// A shortcut named after the executable, opening the program's [`uri`]
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        let program = program.into();
+        let name = program
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| program.to_string_lossy().into_owned());
+        Self {
+            name,
+            program,
+            args: Vec::new(),
+            launcher: Launcher::Uri,
+        }
+    }
+}
+
+/// URI starting `program` in `bottle`, e.g.
+/// `bottles://run/Games/C%3A%2Fgame.exe?arg=-windowed`
+pub fn uri(bottle: &str, program: &Path, args: &[String]) -> String {
+    let mut uri = format!(
+        "{}://run/{}/{}",
+        URI_SCHEME,
+        percent_encode(bottle),
+        percent_encode(&program.to_string_lossy())
+    );
+    for (index, arg) in args.iter().enumerate() {
+        uri.push(if index == 0 { '?' } else { '&' });
+        uri.push_str("arg=");
+        uri.push_str(&percent_encode(arg));
+    }
+    uri
+}
+
+/// Directory the desktop entries of the user are read from
+///
+/// Inside Flatpak, `XDG_DATA_HOME` points into the sandbox, so the host's
+/// `~/.local/share/applications` is used instead.
+pub fn applications_dir() -> Option<PathBuf> {
+    Some(data_dir()?.join("applications"))
+}
+
+/// Create a shortcut for a program of `bottle`
+///
+/// An existing shortcut with the same name is replaced. A shortcut is still
+/// created when no icon can be extracted from the executable.
+///
+/// # Returns
+///
+/// The path of the `.desktop` file, or of the `.app` bundle on macOS
+pub fn create(bottle: &Bottle, shortcut: &Shortcut) -> Result<PathBuf, Error> {
+    let icon = PeFile::open(&prefix::host_executable(&bottle.path, &shortcut.program))
+        .ok()
+        .and_then(|file| file.icon());
+    if icon.is_none() {
+        tracing::debug!("No icon found in '{}'", shortcut.program.display());
+    }
+    platform::create(bottle, shortcut, icon.as_ref())
+}
+
+/// Remove the shortcut named `name` of `bottle`, along with its icon
+///
+/// Removing a shortcut that doesn't exist is not an error.
+pub fn remove(bottle: &Bottle, name: &str) -> Result<(), Error> {
+    platform::remove(bottle, name)
+}
+
+#[cfg(not(target_os = "macos"))]
+mod platform {
+    use super::{file_stem, icon_dir, Launcher, Shortcut};
+    use crate::pe::Icon;
+    use crate::bottle::{Bottle, BottleType};
+    use crate::{flatpak, Error};
+    use std::fs;
+    use std::path::PathBuf;
+
+    pub(super) fn create(
+        bottle: &Bottle,
+        shortcut: &Shortcut,
+        icon: Option<&Icon>,
+    ) -> Result<PathBuf, Error> {
+        let stem = file_stem(bottle, &shortcut.name);
+        let icon = match icon {
+            Some(icon) => Some(super::write_icon(&stem, icon)?),
+            None => None,
+        };
+
+        let mut entry = String::from("[Desktop Entry]\nType=Application\nVersion=1.5\n");
+        entry.push_str(&format!("Name={}\n", escape(&shortcut.name)));
+        entry.push_str(&format!("Comment=Run in the {} bottle\n", escape(&bottle.name)));
+        entry.push_str(&format!("Exec={}\n", escape(&exec(bottle, shortcut))));
+        if let Some(icon) = icon {
+            entry.push_str(&format!("Icon={}\n", escape(&icon.to_string_lossy())));
+        }
+        entry.push_str("Terminal=false\n");
+        if matches!(bottle.kind, BottleType::Gaming) {
+            entry.push_str("Categories=Game;\n");
+        }
+        // Wine names the windows of a program after its executable
+        if let Some(name) = shortcut.program.file_name() {
+            let class = name.to_string_lossy().to_lowercase();
+            entry.push_str(&format!("StartupWMClass={}\n", escape(&class)));
+        }
+        entry.push_str(&format!("X-Bottles-Bottle={}\n", escape(&bottle.name)));
+
+        let directory = super::applications_dir().ok_or_else(super::no_home)?;
+        fs::create_dir_all(&directory).map_err(Error::Io)?;
+        let path = directory.join(format!("{}.desktop", stem));
+        fs::write(&path, entry).map_err(Error::Io)?;
+        Ok(path)
+    }
+
+    pub(super) fn remove(bottle: &Bottle, name: &str) -> Result<(), Error> {
+        let stem = file_stem(bottle, name);
+        let directory = super::applications_dir().ok_or_else(super::no_home)?;
+        super::remove_file(&directory.join(format!("{}.desktop", stem)))?;
+        if let Some(icons) = icon_dir() {
+            for extension in ["png", "ico"] {
+                super::remove_file(&icons.join(format!("{}.{}", stem, extension)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The `Exec` line of the entry, before escaping
+    fn exec(bottle: &Bottle, shortcut: &Shortcut) -> String {
+        let mut argv = match &shortcut.launcher {
+            Launcher::Uri => vec![
+                "xdg-open".to_string(),
+                super::uri(&bottle.name, &shortcut.program, &shortcut.args),
+            ],
+            Launcher::Command(command) => {
+                let mut argv = Vec::new();
+                // The entry point is only visible inside the sandbox
+                if let Some(app_id) = flatpak::sandbox().and_then(|s| s.app_id.as_ref()) {
+                    argv.push("flatpak".to_string());
+                    argv.push("run".to_string());
+                    argv.push(format!("--command={}", command.display()));
+                    argv.push(app_id.clone());
+                } else {
+                    argv.push(command.to_string_lossy().into_owned());
+                }
+                argv.push("run".to_string());
+                argv.push(bottle.name.clone());
+                argv.push(shortcut.program.to_string_lossy().into_owned());
+                argv.extend(shortcut.args.iter().cloned());
+                argv
+            }
+        };
+        for arg in &mut argv {
+            *arg = quote(arg);
+        }
+        argv.join(" ")
+    }
+
+    /// Quote an argument of the `Exec` key, following the desktop entry
+    /// specification
+    fn quote(arg: &str) -> String {
+        let arg = arg.replace('%', "%%");
+        let reserved = |c: char| " \t\n\"'\\><~|&;$*?#()`".contains(c);
+        if !arg.is_empty() && !arg.contains(reserved) {
+            return arg;
+        }
+        let mut quoted = String::from("\"");
+        for c in arg.chars() {
+            if matches!(c, '"' | '`' | '$' | '\\') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    /// Escape a value of the entry
+    fn escape(value: &str) -> String {
+        value
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\t', "\\t")
+            .replace('\r', "\\r")
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::{file_stem, Launcher, Shortcut};
+    use crate::pe::Icon;
+    use crate::bottle::Bottle;
+    use crate::Error;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    pub(super) fn create(
+        bottle: &Bottle,
+        shortcut: &Shortcut,
+        icon: Option<&Icon>,
+    ) -> Result<PathBuf, Error> {
+        let bundle = bundle_path(bottle, &shortcut.name)?;
+        if bundle.exists() {
+            fs::remove_dir_all(&bundle).map_err(Error::Io)?;
+        }
+        let contents = bundle.join("Contents");
+        let executables = contents.join("MacOS");
+        let resources = contents.join("Resources");
+        fs::create_dir_all(&executables).map_err(Error::Io)?;
+        fs::create_dir_all(&resources).map_err(Error::Io)?;
+
+        let argv: Vec<String> = match &shortcut.launcher {
+            Launcher::Uri => vec![
+                "open".to_string(),
+                super::uri(&bottle.name, &shortcut.program, &shortcut.args),
+            ],
+            Launcher::Command(command) => {
+                let mut argv = vec![
+                    command.to_string_lossy().into_owned(),
+                    "run".to_string(),
+                    bottle.name.clone(),
+                    shortcut.program.to_string_lossy().into_owned(),
+                ];
+                argv.extend(shortcut.args.iter().cloned());
+                argv
+            }
+        };
+        let argv: Vec<String> = argv.iter().map(|arg| quote(arg)).collect();
+        let launcher = executables.join("launcher");
+        fs::write(&launcher, format!("#!/bin/sh\nexec {}\n", argv.join(" ")))
+            .map_err(Error::Io)?;
+        fs::set_permissions(&launcher, fs::Permissions::from_mode(0o755)).map_err(Error::Io)?;
+
+        // Finder only shows .icns icons, converted with the system's sips
+        let mut has_icon = false;
+        if let Some(icon) = icon {
+            let source = resources.join(format!("icon.{}", icon.format.extension()));
+            fs::write(&source, &icon.data).map_err(Error::Io)?;
+            has_icon = Command::new("sips")
+                .args(["-s", "format", "icns"])
+                .arg(&source)
+                .arg("--out")
+                .arg(resources.join("icon.icns"))
+                .output()
+                .is_ok_and(|output| output.status.success());
+        }
+
+        let identifier = file_stem(bottle, &shortcut.name);
+        let mut plist = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+             \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+             <plist version=\"1.0\">\n<dict>\n",
+        );
+        let mut key = |key: &str, value: &str| {
+            plist.push_str(&format!(
+                "  <key>{}</key>\n  <string>{}</string>\n",
+                key,
+                escape(value)
+            ));
+        };
+        key("CFBundleName", &shortcut.name);
+        key("CFBundleDisplayName", &shortcut.name);
+        key("CFBundleIdentifier", &format!("com.usebottles.shortcut.{}", identifier));
+        key("CFBundleExecutable", "launcher");
+        key("CFBundlePackageType", "APPL");
+        if has_icon {
+            key("CFBundleIconFile", "icon");
+        }
+        plist.push_str("</dict>\n</plist>\n");
+        fs::write(contents.join("Info.plist"), plist).map_err(Error::Io)?;
+        Ok(bundle)
+    }
+
+    pub(super) fn remove(bottle: &Bottle, name: &str) -> Result<(), Error> {
+        let bundle = bundle_path(bottle, name)?;
+        match fs::remove_dir_all(&bundle) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(Error::Io(error)),
+            _ => Ok(()),
+        }
+    }
+
+    fn bundle_path(bottle: &Bottle, name: &str) -> Result<PathBuf, Error> {
+        let home = std::env::var_os("HOME").ok_or_else(super::no_home)?;
+        let name = format!("{} ({}).app", name.replace('/', "-"), bottle.name.replace('/', "-"));
+        Ok(PathBuf::from(home).join("Applications").join(name))
+    }
+
+    fn quote(arg: &str) -> String {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+
+    fn escape(value: &str) -> String {
+        value
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+}
+
+/// The user's data directory, `~/.local/share` by default
+fn data_dir() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    if flatpak::is_sandboxed() {
+        return Some(home?.join(".local").join("share"));
+    }
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(home?.join(".local").join("share")))
+}
+
+/// Directory icons extracted for shortcuts are written to
+#[cfg_attr(target_os = "macos", allow(dead_code))]
+fn icon_dir() -> Option<PathBuf> {
+    Some(data_dir()?.join("bottles").join("icons"))
+}
+
+/// Write `icon` to [`icon_dir`], returning its path
+#[cfg_attr(target_os = "macos", allow(dead_code))]
+fn write_icon(stem: &str, icon: &Icon) -> Result<PathBuf, Error> {
+    let directory = icon_dir().ok_or_else(no_home)?;
+    fs::create_dir_all(&directory).map_err(Error::Io)?;
+    let path = directory.join(format!("{}.{}", stem, icon.format.extension()));
+    fs::write(&path, &icon.data).map_err(Error::Io)?;
+    Ok(path)
+}
+
+/// File name of a shortcut without extension, e.g. `bottles-games-setup`
+fn file_stem(bottle: &Bottle, name: &str) -> String {
+    let slug = |value: &str| -> String {
+        value
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '-'
+                }
+            })
+            .collect()
+    };
+    format!("{}{}-{}", FILE_PREFIX, slug(&bottle.name), slug(name))
+}
+
+#[cfg_attr(target_os = "macos", allow(dead_code))]
+fn remove_file(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(Error::Io(error)),
+        _ => Ok(()),
+    }
+}
+
+fn no_home() -> Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        "cannot determine the home directory of the user",
+    )
+    .into()
+}
+
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
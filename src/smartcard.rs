@@ -0,0 +1,100 @@
+//! Smart cards and security tokens inside prefixes
+//!
+//! Wine's `winscard` talks to the host's `pcscd` through libpcsclite, so
+//! programs signing or logging in with a smart card see the host's readers.
+//! When enabled for a bottle (see
+//! [`BottleConfig::smart_cards`](crate::bottle::BottleConfig::smart_cards)),
+//! the pcscd socket is passed to launches, including those inside the Steam
+//! Linux Runtime container, and the prefix is set up for the middleware of
+//! card vendors: the builtin `winscard` is used even when an installer copied
+//! a native one, and the smart card service is registered as started, as
+//! middleware installers and some programs check for it.
+//!
+//! In Flatpak, the sandbox needs the `pcsc` socket permission.
+
+use crate::components;
+use crate::flatpak;
+use crate::runner::Runner;
+use crate::system::diagnostics::{self, Check, CheckStatus, Feature};
+use crate::Error;
+use std::path::{Path, PathBuf};
+
+/// Where pcscd listens by default
+const DEFAULT_SOCKET: &str = "/run/pcscd/pcscd.comm";
+
+/// Variable of libpcsclite naming the socket of pcscd
+const SOCKET_VARIABLE: &str = "PCSCLITE_CSOCK_NAME";
+
+/// Registry key of the Windows smart card service
+const SERVICE_KEY: &str = "HKEY_LOCAL_MACHINE\\System\\CurrentControlSet\\Services\\SCardSvr";
+
+/// The socket of the host's pcscd
+pub fn socket() -> PathBuf {
+    std::env::var_os(SOCKET_VARIABLE)
+        .filter(|socket| !socket.is_empty())
+        .map_or_else(|| PathBuf::from(DEFAULT_SOCKET), PathBuf::from)
+}
+
+/// Variables passing the pcscd socket to launches
+pub fn environment() -> Vec<(&'static str, String)> {
+    vec![(SOCKET_VARIABLE, socket().display().to_string())]
+}
+
+/// Host paths the Steam Linux Runtime container needs to see for smart
+/// cards to work
+pub fn shared_paths() -> Vec<PathBuf> {
+    socket().parent().map(Path::to_path_buf).into_iter().collect()
+}
+
+/// Set up `prefix` for smart cards, or undo it with `enabled` set to false
+pub(crate) fn apply(runner: &dyn Runner, prefix: &Path, enabled: bool) -> Result<(), Error> {
+    components::set_override(runner, prefix, "winscard", enabled.then_some("builtin"))?;
+    if enabled {
+        // Automatic start, as a shared service process
+        for (name, value) in [("Start", "2"), ("Type", "32")] {
+            let args = ["add", SERVICE_KEY, "/v", name, "/t", "REG_DWORD", "/d", value, "/f"];
+            components::run(runner, prefix, "reg", &args)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check whether smart cards can work in bottles
+pub fn diagnose() -> Vec<Check> {
+    const ID: &str = "smartcard.pcscd";
+    const TITLE: &str = "PC/SC daemon";
+
+    let mut checks = diagnostics::library_checks(
+        "smartcard.libpcsclite",
+        "PC/SC library",
+        "libpcsclite.so.1",
+        "pcsc-lite or libpcsclite1",
+        Feature::SmartCards,
+    );
+    let socket = socket();
+    checks.push(if socket.exists() {
+        Check::new(
+            ID,
+            TITLE,
+            CheckStatus::Ok,
+            format!("pcscd is listening on {}", socket.display()),
+        )
+    } else {
+        let hint = match flatpak::sandbox() {
+            Some(sandbox) => format!(
+                "Allow access to pcscd with: flatpak override --user --socket=pcsc {}",
+                sandbox.app_id.as_deref().unwrap_or("<application id>")
+            ),
+            None => "Start pcscd, e.g. with: systemctl enable --now pcscd.socket".to_string(),
+        };
+        Check::new(
+            ID,
+            TITLE,
+            CheckStatus::Error,
+            format!("pcscd is not listening on {}", socket.display()),
+        )
+        .hint(hint)
+        .affects(&[Feature::SmartCards])
+    });
+    checks
+}
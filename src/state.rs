@@ -0,0 +1,106 @@
+//! States of the bottles of a manager, see [`BottleState`]
+//!
+//! Running bottles are found from the sessions and locked ones from their
+//! lock, see [`crate::lock`]; this keeps what only the manager knows: the
+//! operations in progress and the bottles found broken.
+
+use crate::bottle::BottleState;
+use crate::lock::BottleLock;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+
+/// Operations in progress and bottles found broken, by bottle name
+#[derive(Debug, Default)]
+pub(crate) struct States {
+    operations: Mutex<HashMap<String, BottleState>>,
+    broken: Mutex<HashSet<String>>,
+}
+
+impl States {
+    /// The state of the operation in progress on `bottle`, if any
+    pub(crate) fn operation(&self, bottle: &str) -> Option<BottleState> {
+        self.operations().get(bottle).cloned()
+    }
+
+    pub(crate) fn is_broken(&self, bottle: &str) -> bool {
+        self.broken().contains(bottle)
+    }
+
+    /// Record whether `bottle` was found broken, see [`crate::health`]
+    pub(crate) fn set_broken(&self, bottle: &str, broken: bool) {
+        if broken {
+            self.broken().insert(bottle.to_string());
+        } else {
+            self.broken().remove(bottle);
+        }
+    }
+
+    /// Carry the state of `from` over to its new name `to`
+    pub(crate) fn rename(&self, from: &str, to: &str) {
+        if self.broken().remove(from) {
+            self.broken().insert(to.to_string());
+        }
+    }
+
+    /// Forget `bottle`, once deleted
+    pub(crate) fn forget(&self, bottle: &str) {
+        self.broken().remove(bottle);
+    }
+
+    /// Start an operation on `bottle` holding it with `lock`, its state
+    /// being `state` until it ends
+    pub(crate) fn start(
+        &self,
+        bottle: &str,
+        state: BottleState,
+        lock: BottleLock,
+    ) -> Operation<'_> {
+        self.operations().insert(bottle.to_string(), state);
+        Operation {
+            states: Some(self),
+            bottle: bottle.to_string(),
+            _lock: lock,
+        }
+    }
+
+    fn operations(&self) -> MutexGuard<'_, HashMap<String, BottleState>> {
+        self.operations
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn broken(&self) -> MutexGuard<'_, HashSet<String>> {
+        self.broken
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// An operation changing a bottle, holding it exclusively until dropped,
+/// see [`Manager::begin`](crate::manager::Manager::begin)
+#[derive(Debug)]
+pub(crate) struct Operation<'a> {
+    /// `None` for an operation run by another one on the same bottle
+    states: Option<&'a States>,
+    bottle: String,
+    _lock: BottleLock,
+}
+
+impl Operation<'_> {
+    /// An operation run by another one holding the bottle
+    pub(crate) fn nested(bottle: &str, lock: BottleLock) -> Self {
+        Self {
+            states: None,
+            bottle: bottle.to_string(),
+            _lock: lock,
+        }
+    }
+}
+
+impl Drop for Operation<'_> {
+    fn drop(&mut self) {
+        if let Some(states) = self.states {
+            states.operations().remove(&self.bottle);
+        }
+    }
+}
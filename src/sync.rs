@@ -0,0 +1,129 @@
+//! Wine thread synchronization primitives
+//!
+//! Besides the wineserver default, Wine builds can synchronize threads with
+//! eventfd (esync), futex waits (fsync, needs kernel futex_waitv) or the
+//! in-kernel NT synchronization driver (ntsync). Each needs support from the
+//! host, so [`SyncSupport::detect`] checks what is available and
+//! [`SyncMode::effective`] falls back to the best supported mode below the
+//! requested one.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Open file limit below which esync runs out of descriptors in large games
+pub(crate) const ESYNC_MIN_FILES: u64 = 524_288;
+
+/// First kernel release with the `futex_waitv` system call
+const FUTEX_WAITV_KERNEL: (u32, u32) = (5, 16);
+
+/// Synchronization primitive a bottle asks Wine to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum SyncMode {
+    /// Whatever the runner uses when not told, Proton enables fsync or esync
+    #[default]
+    Default,
+    /// Plain wineserver synchronization
+    None,
+    Esync,
+    Fsync,
+    Ntsync,
+}
+
+impl SyncMode {
+    /// Environment variables enabling the mode
+    ///
+    /// Proton enables esync and fsync unless told otherwise, so the modes that
+    /// don't use them disable them explicitly. [`SyncMode::Default`] sets
+    /// nothing.
+    pub fn environment(self) -> Vec<(&'static str, &'static str)> {
+        match self {
+            Self::Default => Vec::new(),
+            Self::None => vec![("PROTON_NO_ESYNC", "1"), ("PROTON_NO_FSYNC", "1")],
+            Self::Esync => vec![("WINEESYNC", "1"), ("PROTON_NO_FSYNC", "1")],
+            Self::Fsync => vec![("WINEFSYNC", "1")],
+            Self::Ntsync => vec![("WINENTSYNC", "1"), ("PROTON_USE_NTSYNC", "1")],
+        }
+    }
+
+    /// The mode actually usable on a host with `support`
+    ///
+    /// Unsupported modes fall back to the next simpler one
+    /// (ntsync → fsync → esync → none).
+    pub fn effective(self, support: &SyncSupport) -> Self {
+        let mut mode = self;
+        while !support.supports(mode) {
+            mode = match mode {
+                Self::Ntsync => Self::Fsync,
+                Self::Fsync => Self::Esync,
+                Self::Esync | Self::None => Self::None,
+                Self::Default => Self::Default,
+            };
+        }
+        mode
+    }
+}
+
+/// Synchronization primitives supported by the host
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncSupport {
+    /// The open file limit can be raised high enough for esync
+    pub esync: bool,
+    /// The kernel provides `futex_waitv` (or the older futex2 patches)
+    pub fsync: bool,
+    /// `/dev/ntsync` is present
+    pub ntsync: bool,
+}
+
+impl SyncSupport {
+    /// Check the running kernel and resource limits
+    pub fn detect() -> Self {
+        Self {
+            esync: file_limit().is_some_and(|limit| limit >= ESYNC_MIN_FILES),
+            fsync: kernel_version().is_some_and(|version| version >= FUTEX_WAITV_KERNEL)
+                || Path::new("/sys/kernel/futex2").exists(),
+            ntsync: Path::new("/dev/ntsync").exists(),
+        }
+    }
+
+    pub fn supports(&self, mode: SyncMode) -> bool {
+        match mode {
+            SyncMode::Default | SyncMode::None => true,
+            SyncMode::Esync => self.esync,
+            SyncMode::Fsync => self.fsync,
+            SyncMode::Ntsync => self.ntsync,
+        }
+    }
+}
+
+/// Hard limit of open files, which a process can raise its soft limit to
+#[cfg(unix)]
+pub(crate) fn file_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes to the struct passed to it
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    // rlim_t is not 64 bits wide on every platform
+    #[allow(clippy::unnecessary_cast)]
+    let hard = limit.rlim_max as u64;
+    Some(hard)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn file_limit() -> Option<u64> {
+    None
+}
+
+/// Major and minor version of the running kernel
+pub(crate) fn kernel_version() -> Option<(u32, u32)> {
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+    let mut parts = release.trim().split(['.', '-']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
@@ -0,0 +1,492 @@
+//! Checks of the host's ability to run Windows games
+//!
+//! Most "the game doesn't start" reports come down to the host: no Vulkan
+//! driver, a driver too old for current DXVK, missing 32-bit drivers, a low
+//! open file limit, or a Flatpak sandbox without GPU access. [`run`] checks all
+//! of these and returns a [`DiagnosticsReport`] that says which [`Feature`]s
+//! each problem breaks, so frontends can explain why something can't work
+//! instead of showing a crash.
+
+use crate::flatpak::{self, Sandbox};
+use crate::gpu;
+use crate::sync::{self, SyncSupport};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Vulkan version required by DXVK 2.x and current VKD3D-Proton releases
+const REQUIRED_VULKAN: (u32, u32) = (1, 3);
+
+/// Where distributions and the Flatpak GL32 extension put 32-bit libraries
+const LIB32_DIRS: &[&str] = &[
+    "/usr/lib32",
+    "/usr/lib/i386-linux-gnu",
+    "/usr/lib/i386-linux-gnu/GL/default/lib",
+    "/usr/lib",
+];
+
+/// Where distributions put 64-bit libraries
+const LIB64_DIRS: &[&str] = &[
+    "/usr/lib64",
+    "/usr/lib/x86_64-linux-gnu",
+    "/usr/lib/aarch64-linux-gnu",
+    "/usr/lib",
+];
+
+/// What a failed check prevents from working
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Feature {
+    Dxvk,
+    Vkd3d,
+    /// Running 32-bit programs with GPU acceleration
+    ThirtyTwoBit,
+    Esync,
+    Fsync,
+    Ntsync,
+    /// Printing to the host's CUPS printers
+    Printing,
+    /// Scanning with the host's SANE scanners through TWAIN
+    Scanning,
+    /// Using the host's smart card readers through pcscd
+    SmartCards,
+    /// Signing in to Active Directory with the host's Kerberos tickets
+    Kerberos,
+    /// Drawing windows with Wine's Wayland driver
+    Wayland,
+}
+
+impl Feature {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Dxvk => "DXVK",
+            Self::Vkd3d => "VKD3D-Proton",
+            Self::ThirtyTwoBit => "32-bit programs",
+            Self::Esync => "esync",
+            Self::Fsync => "fsync",
+            Self::Ntsync => "NTSync",
+            Self::Printing => "printing",
+            Self::Scanning => "scanning",
+            Self::SmartCards => "smart cards",
+            Self::Kerberos => "Kerberos sign-on",
+            Self::Wayland => "Wayland driver",
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// Outcome of a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CheckStatus {
+    Ok,
+    /// Works, but worse than it could
+    Warning,
+    /// The affected features can't work
+    Error,
+}
+
+/// Result of one diagnostic check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Check {
+    /// Stable identifier, e.g. `vulkan.driver`
+    pub id: String,
+    pub title: String,
+    pub status: CheckStatus,
+    /// What was found
+    pub detail: String,
+    /// How to fix the problem, if there is one
+    pub hint: Option<String>,
+    /// Features broken or degraded when the check doesn't pass
+    pub affects: Vec<Feature>,
+}
+
+impl Check {
+    pub(crate) fn new(id: &str, title: &str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            id: id.to_string(),
+            title: title.to_string(),
+            status,
+            detail: detail.into(),
+            hint: None,
+            affects: Vec::new(),
+        }
+    }
+
+    pub(crate) fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub(crate) fn affects(mut self, features: &[Feature]) -> Self {
+        self.affects = features.to_vec();
+        self
+    }
+}
+
+/// Results of all the checks
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DiagnosticsReport {
+    pub checks: Vec<Check>,
+}
+
+impl DiagnosticsReport {
+    /// Whether no check failed; warnings are allowed
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.status != CheckStatus::Error)
+    }
+
+    /// The failed checks that prevent `feature` from working
+    pub fn blockers(&self, feature: Feature) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(move |check| {
+            check.status == CheckStatus::Error && check.affects.contains(&feature)
+        })
+    }
+
+    /// A sentence explaining why `feature` can't work, if it can't
+    pub fn explain(&self, feature: Feature) -> Option<String> {
+        let reasons: Vec<&str> = self
+            .blockers(feature)
+            .map(|check| check.detail.as_str())
+            .collect();
+        if reasons.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "Your system can't run {} because {}",
+            feature,
+            reasons.join(", and ")
+        ))
+    }
+}
+
+/// Run every check
+///
+/// Takes a moment, as it runs `vulkaninfo`.
+pub fn run() -> DiagnosticsReport {
+    let mut checks = vulkan();
+    checks.push(thirty_two_bit());
+    checks.extend(sync_checks());
+    if let Some(sandbox) = flatpak::sandbox() {
+        checks.extend(flatpak_checks(sandbox));
+    }
+    DiagnosticsReport { checks }
+}
+
+fn vulkan() -> Vec<Check> {
+    const GRAPHICS: &[Feature] = &[Feature::Dxvk, Feature::Vkd3d];
+
+    let icds = gpu::icd_files(&[""]);
+    let Some(devices) = gpu::vulkan_devices() else {
+        let check = if icds.is_empty() {
+            Check::new(
+                "vulkan.driver",
+                "Vulkan driver",
+                CheckStatus::Error,
+                "no Vulkan driver is installed",
+            )
+            .hint("Install the Vulkan driver for your GPU (Mesa's RADV or ANV, or the NVIDIA driver)")
+        } else {
+            Check::new(
+                "vulkan.driver",
+                "Vulkan driver",
+                CheckStatus::Warning,
+                format!(
+                    "{} Vulkan driver(s) are installed, but they could not be queried",
+                    icds.len()
+                ),
+            )
+            .hint("Install vulkaninfo (vulkan-tools) for a complete diagnosis")
+        };
+        return vec![check.affects(GRAPHICS)];
+    };
+
+    let hardware: Vec<&gpu::VulkanDevice> = devices
+        .iter()
+        .filter(|device| !is_software(&device.name))
+        .collect();
+    if hardware.is_empty() {
+        let renderer = devices.first().map_or("none", |device| device.name.as_str());
+        return vec![Check::new(
+            "vulkan.driver",
+            "Vulkan driver",
+            CheckStatus::Error,
+            format!("only a software Vulkan driver ({}) is available", renderer),
+        )
+        .hint("Install the Vulkan driver for your GPU, games would render on the CPU")
+        .affects(GRAPHICS)];
+    }
+
+    let mut checks = Vec::new();
+    let names: Vec<&str> = hardware.iter().map(|device| device.name.as_str()).collect();
+    checks.push(Check::new(
+        "vulkan.driver",
+        "Vulkan driver",
+        CheckStatus::Ok,
+        format!("Vulkan devices: {}", names.join(", ")),
+    ));
+
+    for device in hardware {
+        let driver = match (&device.driver_name, &device.driver_info) {
+            (Some(name), Some(info)) => format!("{} {}", name, info),
+            (Some(name), None) => name.clone(),
+            (None, Some(info)) => info.clone(),
+            (None, None) => "unknown driver".to_string(),
+        };
+        let api = device.api_version.as_deref().unwrap_or("unknown");
+        let supported = device
+            .api_version
+            .as_deref()
+            .and_then(vulkan_version)
+            .is_none_or(|version| version >= REQUIRED_VULKAN);
+        let check = if supported {
+            Check::new(
+                "vulkan.version",
+                "Vulkan version",
+                CheckStatus::Ok,
+                format!("{}: {} (Vulkan {})", device.name, driver, api),
+            )
+        } else {
+            Check::new(
+                "vulkan.version",
+                "Vulkan version",
+                CheckStatus::Error,
+                format!(
+                    "the driver of {} ({}) only supports Vulkan {}, {}.{} is required",
+                    device.name, driver, api, REQUIRED_VULKAN.0, REQUIRED_VULKAN.1
+                ),
+            )
+            .hint("Update your GPU driver, or use DXVK 1.10 which works with older drivers")
+            .affects(GRAPHICS)
+        };
+        checks.push(check);
+    }
+    checks
+}
+
+fn thirty_two_bit() -> Check {
+    let found = LIB32_DIRS
+        .iter()
+        .map(|directory| Path::new(directory).join("libvulkan.so.1"))
+        .find(|library| is_elf32(library));
+    match found {
+        Some(library) => Check::new(
+            "vulkan.32bit",
+            "32-bit Vulkan",
+            CheckStatus::Ok,
+            format!("32-bit Vulkan loader found at {}", library.display()),
+        ),
+        None => Check::new(
+            "vulkan.32bit",
+            "32-bit Vulkan",
+            CheckStatus::Warning,
+            "no 32-bit Vulkan loader is installed",
+        )
+        .hint("Install the 32-bit (lib32 or i386) variants of the Vulkan loader and your GPU driver")
+        .affects(&[Feature::ThirtyTwoBit]),
+    }
+}
+
+fn sync_checks() -> Vec<Check> {
+    let support = SyncSupport::detect();
+    let mut checks = Vec::new();
+
+    let limit = sync::file_limit();
+    checks.push(if support.esync {
+        Check::new(
+            "sync.esync",
+            "esync",
+            CheckStatus::Ok,
+            format!("open file limit: {}", limit.unwrap_or_default()),
+        )
+    } else {
+        Check::new(
+            "sync.esync",
+            "esync",
+            CheckStatus::Warning,
+            match limit {
+                Some(limit) => format!(
+                    "the open file limit is {}, esync needs at least {}",
+                    limit,
+                    sync::ESYNC_MIN_FILES
+                ),
+                None => "the open file limit could not be read".to_string(),
+            },
+        )
+        .hint("Raise the hard limit with DefaultLimitNOFILE=1048576 in systemd's system.conf and user.conf")
+        .affects(&[Feature::Esync])
+    });
+
+    let kernel = sync::kernel_version()
+        .map(|(major, minor)| format!("{}.{}", major, minor))
+        .unwrap_or_else(|| "unknown".to_string());
+    checks.push(if support.fsync {
+        Check::new("sync.fsync", "fsync", CheckStatus::Ok, format!("kernel {}", kernel))
+    } else {
+        Check::new(
+            "sync.fsync",
+            "fsync",
+            CheckStatus::Warning,
+            format!("kernel {} lacks futex_waitv, which fsync needs", kernel),
+        )
+        .hint("Upgrade to Linux 5.16 or newer")
+        .affects(&[Feature::Fsync])
+    });
+
+    checks.push(if support.ntsync {
+        Check::new("sync.ntsync", "NTSync", CheckStatus::Ok, "/dev/ntsync is available")
+    } else {
+        Check::new(
+            "sync.ntsync",
+            "NTSync",
+            CheckStatus::Warning,
+            "/dev/ntsync is not available",
+        )
+        .hint("Use Linux 6.14 or newer and load the ntsync module")
+        .affects(&[Feature::Ntsync])
+    });
+    checks
+}
+
+fn flatpak_checks(sandbox: &Sandbox) -> Vec<Check> {
+    let app_id = sandbox.app_id.as_deref().unwrap_or("<application id>");
+    let mut checks = vec![if sandbox.host_access {
+        Check::new(
+            "flatpak.host",
+            "Flatpak host access",
+            CheckStatus::Ok,
+            format!(
+                "running in the {} sandbox, host programs are started with flatpak-spawn",
+                app_id
+            ),
+        )
+    } else {
+        Check::new(
+            "flatpak.host",
+            "Flatpak host access",
+            CheckStatus::Warning,
+            format!(
+                "running in the {} sandbox, which can't start gamescope or runners installed on \
+                 the host",
+                app_id
+            ),
+        )
+        .hint(format!(
+            "Allow it with: flatpak override --user --talk-name=org.freedesktop.Flatpak {}",
+            app_id
+        ))
+    }];
+
+    checks.push(if sandbox.gpu_access() {
+        Check::new(
+            "flatpak.gpu",
+            "Flatpak GPU access",
+            CheckStatus::Ok,
+            "the sandbox can access the GPU",
+        )
+    } else {
+        Check::new(
+            "flatpak.gpu",
+            "Flatpak GPU access",
+            CheckStatus::Error,
+            "the Flatpak sandbox has no access to the GPU",
+        )
+        .hint(format!("Allow it with: flatpak override --user --device=dri {}", app_id))
+        .affects(&[Feature::Dxvk, Feature::Vkd3d])
+    });
+    if !sandbox.devices.iter().any(|device| device == "all") {
+        checks.push(
+            Check::new(
+                "flatpak.devices",
+                "Flatpak device access",
+                CheckStatus::Warning,
+                "the Flatpak sandbox only sees the GPU, /dev/ntsync and controllers are hidden",
+            )
+            .hint(format!(
+                "Allow all devices with: flatpak override --user --device=all {}",
+                app_id
+            ))
+            .affects(&[Feature::Ntsync]),
+        );
+    }
+    if !Path::new("/usr/lib/i386-linux-gnu/GL").exists() {
+        checks.push(
+            Check::new(
+                "flatpak.gl32",
+                "Flatpak 32-bit drivers",
+                CheckStatus::Warning,
+                "the 32-bit GL extension of the Flatpak runtime is not installed",
+            )
+            .hint("Install org.freedesktop.Platform.GL32.default matching your runtime version")
+            .affects(&[Feature::ThirtyTwoBit]),
+        );
+    }
+    checks
+}
+
+/// Checks of the 64-bit and 32-bit variants of a host library
+pub(crate) fn library_checks(
+    id: &str,
+    title: &str,
+    library: &str,
+    package: &str,
+    feature: Feature,
+) -> Vec<Check> {
+    let found = |directories: &[&str], is_32_bit: bool| {
+        directories
+            .iter()
+            .map(|directory| Path::new(directory).join(library))
+            .find(|path| path.is_file() && is_elf32(path) == is_32_bit)
+    };
+    let mut checks = vec![match found(LIB64_DIRS, false) {
+        Some(path) => Check::new(
+            id,
+            title,
+            CheckStatus::Ok,
+            format!("{} found at {}", library, path.display()),
+        ),
+        None => Check::new(id, title, CheckStatus::Error, format!("{} is not installed", library))
+            .hint(format!("Install {}", package))
+            .affects(&[feature]),
+    }];
+    if found(LIB32_DIRS, true).is_none() {
+        checks.push(
+            Check::new(
+                &format!("{}.32bit", id),
+                &format!("32-bit {}", title),
+                CheckStatus::Warning,
+                format!("no 32-bit {} is installed, 32-bit programs can't use it", library),
+            )
+            .hint(format!("Install the 32-bit (lib32 or i386) variant of {}", package))
+            .affects(&[feature]),
+        );
+    }
+    checks
+}
+
+fn is_software(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    ["llvmpipe", "lavapipe", "swiftshader", "softpipe"]
+        .iter()
+        .any(|software| name.contains(software))
+}
+
+/// `1.3.278` → `(1, 3)`
+fn vulkan_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+/// Whether a file is a 32-bit ELF object
+fn is_elf32(path: &Path) -> bool {
+    use std::io::Read;
+
+    let mut header = [0u8; 5];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|()| &header[..4] == b"\x7fELF" && header[4] == 1)
+}
@@ -0,0 +1,3 @@
+//! Information about the host the daemon runs on
+
+pub mod diagnostics;
@@ -0,0 +1,291 @@
+//! Helpers for running the daemon as a systemd user service
+//!
+//! Covers both sides of the integration: generating and installing the unit
+//! files, and the `sd_notify` protocol the running daemon uses to report
+//! readiness and keep the service watchdog happy.
+
+use crate::Error;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::fs;
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// First file descriptor passed by the service manager (`SD_LISTEN_FDS_START`)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Whether [`activated_listener`] took the descriptor passed by systemd
+static LISTENER_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Base name of the generated units (`bottles-next.service`, `bottles-next.socket`)
+pub const UNIT_NAME: &str = "bottles-next";
+
+/// Settings for the generated units
+#[derive(Debug, Clone)]
+pub struct UnitOptions {
+    /// Daemon executable
+    pub executable: PathBuf,
+    /// Arguments passed to the daemon
+    pub args: Vec<String>,
+    /// Listen on a unix socket and start the daemon on first connection.
+    /// Specifiers such as `%t` (the user runtime directory) are allowed.
+    pub socket: Option<String>,
+    /// Restart the daemon if it stops sending watchdog pings for this long
+    pub watchdog: Option<Duration>,
+}
+
+impl UnitOptions {
+    pub fn new(executable: impl Into<PathBuf>) -> Self {
+        Self {
+            executable: executable.into(),
+            args: Vec::new(),
+            // Where frontends look for the daemon, see `service::default_socket`
+            socket: Some(format!("%t/{}", crate::service::SOCKET_PATH)),
+            watchdog: Some(Duration::from_secs(30)),
+        }
+    }
+
+    /// Render the `.service` unit
+    pub fn service_unit(&self) -> String {
+        let mut unit = String::from("[Unit]\nDescription=Bottles Next daemon\n");
+        if self.socket.is_some() {
+            let _ = writeln!(unit, "Requires={0}.socket\nAfter={0}.socket", UNIT_NAME);
+        }
+
+        let mut exec = quote(&self.executable.display().to_string());
+        for arg in &self.args {
+            exec.push(' ');
+            exec.push_str(&quote(arg));
+        }
+        let _ = write!(unit, "\n[Service]\nType=notify\nExecStart={}\n", exec);
+        if let Some(watchdog) = self.watchdog {
+            let _ = writeln!(unit, "WatchdogSec={}", watchdog.as_secs().max(1));
+        }
+        unit.push_str("Restart=on-failure\n");
+
+        // Socket-activated services are started by their socket unit
+        if self.socket.is_none() {
+            unit.push_str("\n[Install]\nWantedBy=default.target\n");
+        }
+        unit
+    }
+
+    /// Render the `.socket` unit, if socket activation is enabled
+    pub fn socket_unit(&self) -> Option<String> {
+        let socket = self.socket.as_ref()?;
+        Some(format!(
+            "[Unit]\nDescription=Bottles Next daemon socket\n\n\
+             [Socket]\nListenStream={}\nSocketMode=0600\nDirectoryMode=0700\n\n\
+             [Install]\nWantedBy=sockets.target\n",
+            socket
+        ))
+    }
+}
+
+/// Directory holding the units of the current user
+pub fn user_unit_dir() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("systemd").join("user"))
+}
+
+/// Write the units to [`user_unit_dir`] and reload the user systemd instance
+///
+/// The units are not enabled; run `systemctl --user enable --now` on the
+/// socket (or the service, without socket activation) to start using them.
+///
+/// # Returns
+///
+/// The paths of the written unit files
+pub fn install_user_units(options: &UnitOptions) -> Result<Vec<PathBuf>, Error> {
+    let directory = user_unit_dir().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "cannot determine the systemd user unit directory",
+        )
+    })?;
+    fs::create_dir_all(&directory).map_err(Error::Io)?;
+
+    let mut written = Vec::new();
+    let service = directory.join(format!("{}.service", UNIT_NAME));
+    fs::write(&service, options.service_unit()).map_err(Error::Io)?;
+    written.push(service);
+    if let Some(unit) = options.socket_unit() {
+        let socket = directory.join(format!("{}.socket", UNIT_NAME));
+        fs::write(&socket, unit).map_err(Error::Io)?;
+        written.push(socket);
+    }
+
+    let reload = Command::new("systemctl")
+        .args(["--user", "daemon-reload"])
+        .status();
+    if !reload.is_ok_and(|status| status.success()) {
+        tracing::warn!("Failed to reload the systemd user instance");
+    }
+    Ok(written)
+}
+
+/// Quote an `ExecStart=` word if it contains characters systemd would split on
+fn quote(word: &str) -> String {
+    if !word.is_empty() && !word.contains([' ', '\t', '"', '\'', '\\']) {
+        return word.to_string();
+    }
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Send a state update to the service manager (`sd_notify`)
+///
+/// # Returns
+///
+/// `false` if the process is not running under systemd with notifications enabled
+pub fn notify(state: &str) -> Result<bool, Error> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    let socket = UnixDatagram::unbound().map_err(Error::Io)?;
+    match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => {
+            let address = SocketAddr::from_abstract_name(name).map_err(Error::Io)?;
+            socket.send_to_addr(state.as_bytes(), &address).map_err(Error::Io)?;
+        }
+        None => {
+            socket.send_to(state.as_bytes(), &path).map_err(Error::Io)?;
+        }
+    }
+    Ok(true)
+}
+
+/// Tell the service manager that the daemon finished starting up
+pub fn notify_ready() {
+    if let Err(e) = notify("READY=1") {
+        tracing::warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+/// Interval at which the service manager expects watchdog pings, if enabled
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Some(pid) = std::env::var_os("WATCHDOG_PID") {
+        if pid != OsString::from(std::process::id().to_string()) {
+            return None;
+        }
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Background task sending watchdog pings while the server runs
+///
+/// Pings are sent at half the configured interval, from a task of the async
+/// runtime, until the guard is dropped. The service manager thus restarts a
+/// daemon that died or whose runtime stalled, e.g. with every worker blocked,
+/// but not a server that stopped answering requests while the runtime still
+/// makes progress.
+pub struct Watchdog {
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Start pinging if the service manager enabled the watchdog
+    pub fn start() -> Self {
+        let task = watchdog_interval().map(|interval| {
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(interval / 2);
+                loop {
+                    ticks.tick().await;
+                    if let Err(e) = notify("WATCHDOG=1") {
+                        tracing::warn!("Failed to send watchdog ping: {}", e);
+                    }
+                }
+            })
+        });
+        Self { task }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// A listening socket passed in by the service manager
+#[derive(Debug)]
+pub enum ActivatedListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// Take the listening socket systemd passed to this process, if any
+///
+/// Implements the receiving side of socket activation (`LISTEN_FDS`,
+/// `LISTEN_PID`). Only the first socket is used; the unit files generated by
+/// this module only ever pass one. The descriptor is marked close-on-exec, and
+/// programs launched in bottles don't get the protocol's variables, see
+/// [`crate::launch::isolate`], so they inherit neither.
+///
+/// The descriptor is owned by the listener returned by the first call, later
+/// calls return `None`.
+///
+/// # Returns
+///
+/// `None` if the process was not socket activated, or the listener was
+/// already taken
+pub fn activated_listener() -> Result<Option<ActivatedListener>, Error> {
+    let pid_matches = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<i32>().ok())
+        .unwrap_or(0);
+    if !pid_matches || count < 1 || LISTENER_TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(None);
+    }
+    if count > 1 {
+        tracing::warn!("Received {} sockets from systemd, only the first one is used", count);
+    }
+
+    let fd = LISTEN_FDS_START;
+    // SAFETY: fcntl and getsockname only read and flag the descriptor, whose
+    // validity is guaranteed by the socket activation protocol
+    let family = unsafe {
+        if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let mut storage: libc::sockaddr_storage = std::mem::zeroed();
+        let mut length = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        let address = &mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr;
+        if libc::getsockname(fd, address, &mut length) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        i32::from(storage.ss_family)
+    };
+
+    // SAFETY: systemd hands ownership of the descriptor to this process, and it
+    // is wrapped exactly once, guarded by `LISTENER_TAKEN`
+    let listener = match family {
+        libc::AF_UNIX => ActivatedListener::Unix(unsafe { UnixListener::from_raw_fd(fd) }),
+        libc::AF_INET | libc::AF_INET6 => {
+            ActivatedListener::Tcp(unsafe { TcpListener::from_raw_fd(fd) })
+        }
+        other => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("unsupported socket family {} passed by systemd", other),
+            )
+            .into())
+        }
+    };
+    Ok(Some(listener))
+}
@@ -0,0 +1,246 @@
+//! Waking and suspending the machine behind a remote target
+//!
+//! A desktop used as a game server is usually asleep. [`RemoteHost::connect`]
+//! wakes it with a Wake-on-LAN packet when the daemon can't be reached, then
+//! waits for the daemon to answer its health check. On the server,
+//! [`suspend_when_idle`] puts the machine back to sleep once the last
+//! program launched on it exits.
+//!
+//! The packet is broadcast on the local network, so waking only works when
+//! the frontend is on the same network as the host, or through a relay
+//! forwarding the broadcast.
+
+use super::Remote;
+use crate::flatpak;
+use crate::manager::Manager;
+use crate::proto::bottles::{system_client::SystemClient, HealthRequest};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tonic::transport::Endpoint;
+
+/// Where Wake-on-LAN packets are sent by default, the discard port of the
+/// local broadcast address
+pub const DEFAULT_BROADCAST: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::BROADCAST, 9));
+
+/// How often a waking host is checked for
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often the sessions of an idle server are checked
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Hardware address of a network interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl FromStr for MacAddress {
+    type Err = Error;
+
+    /// Parse an address written as `aa:bb:cc:dd:ee:ff` or `aa-bb-cc-dd-ee-ff`
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let invalid = || {
+            Error::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{}' is not a MAC address", s),
+            ))
+        };
+        let mut bytes = [0; 6];
+        let mut parts = s.trim().split([':', '-']);
+        for byte in &mut bytes {
+            let part = parts.next().filter(|part| part.len() == 2).ok_or_else(invalid)?;
+            *byte = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+    }
+}
+
+impl Serialize for MacAddress {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MacAddress {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let address = String::deserialize(deserializer)?;
+        address.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A machine running the daemon, as configured in a frontend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteHost {
+    /// URL of the daemon, e.g. `http://desktop.local:50051`
+    pub endpoint: String,
+    /// Address of the network interface to wake, waking is disabled when
+    /// unset
+    #[serde(default)]
+    pub mac: Option<MacAddress>,
+    /// Where the Wake-on-LAN packet is sent, see [`DEFAULT_BROADCAST`]
+    #[serde(default = "default_broadcast")]
+    pub broadcast: SocketAddr,
+    /// How long a woken host may take to start the daemon
+    #[serde(default = "default_wake_timeout")]
+    pub wake_timeout: Duration,
+    /// Register launched programs for streaming, see
+    /// [`Remote::with_streaming`]
+    #[serde(default)]
+    pub stream: bool,
+}
+
+impl RemoteHost {
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            mac: None,
+            broadcast: DEFAULT_BROADCAST,
+            wake_timeout: default_wake_timeout(),
+            stream: false,
+        }
+    }
+
+    /// Connect to the daemon, waking the host first if it doesn't answer
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the daemon doesn't answer and the host can't be
+    /// woken, or doesn't answer within [`RemoteHost::wake_timeout`]
+    pub async fn connect(&self) -> Result<Remote, Error> {
+        if !is_healthy(&self.endpoint).await {
+            if let Some(mac) = self.mac {
+                wake(mac, self.broadcast)?;
+                wait_until_healthy(&self.endpoint, self.wake_timeout).await?;
+            }
+        }
+        let remote = Remote::connect(self.endpoint.clone()).await?;
+        Ok(remote.with_streaming(self.stream))
+    }
+}
+
+/// Send a Wake-on-LAN packet for the interface `mac` to `broadcast`
+///
+/// # Errors
+///
+/// Returns an error if the packet can't be sent
+pub fn wake(mac: MacAddress, broadcast: SocketAddr) -> Result<(), Error> {
+    // Six 0xff bytes, then the address sixteen times
+    let mut packet = vec![0xff; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&mac.0);
+    }
+    let local: SocketAddr = match broadcast {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).map_err(Error::Io)?;
+    socket.set_broadcast(true).map_err(Error::Io)?;
+    socket.send_to(&packet, broadcast).map_err(Error::Io)?;
+    tracing::info!("Sent a Wake-on-LAN packet for {} to {}", mac, broadcast);
+    Ok(())
+}
+
+/// Wait for the daemon at `endpoint` to answer its health check
+///
+/// # Errors
+///
+/// Returns an error if it doesn't answer within `timeout`
+pub async fn wait_until_healthy(endpoint: &str, timeout: Duration) -> Result<(), Error> {
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        if is_healthy(endpoint).await {
+            tracing::info!("{} is up after {:?}", endpoint, started.elapsed());
+            return Ok(());
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        format!("{} didn't answer within {:?}", endpoint, timeout),
+    )
+    .into())
+}
+
+async fn is_healthy(endpoint: &str) -> bool {
+    let Ok(endpoint) = Endpoint::from_shared(endpoint.to_string()) else {
+        return false;
+    };
+    let Ok(channel) = endpoint.connect_timeout(POLL_INTERVAL).connect().await else {
+        return false;
+    };
+    SystemClient::new(channel)
+        .health(HealthRequest {})
+        .await
+        .is_ok_and(|response| response.into_inner().ok)
+}
+
+/// Suspend the machine once no program has been running in any bottle for
+/// `grace`, after at least one ran
+///
+/// Meant for daemons serving a remote frontend on a machine woken for it,
+/// see the [module documentation](self). Only programs launched through
+/// `manager` are considered. Runs until the machine was suspended once, or
+/// the returned task is aborted.
+pub fn suspend_when_idle(manager: Arc<Manager>, grace: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut idle_since: Option<Instant> = None;
+        let mut launched = false;
+        loop {
+            tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+            if !manager.sessions().is_empty() {
+                launched = true;
+                idle_since = None;
+                continue;
+            }
+            if !launched {
+                continue;
+            }
+            let since = *idle_since.get_or_insert_with(Instant::now);
+            if since.elapsed() >= grace {
+                tracing::info!("No program ran for {:?}, suspending", grace);
+                match tokio::task::spawn_blocking(suspend).await {
+                    Ok(Ok(())) => break,
+                    Ok(Err(e)) => tracing::warn!("Cannot suspend: {}", e),
+                    Err(e) => tracing::warn!("Cannot suspend: {}", e),
+                }
+                // Tried again after another grace period
+                idle_since = None;
+            }
+        }
+    })
+}
+
+/// Suspend the machine through logind
+fn suspend() -> Result<(), Error> {
+    let mut command = Command::new("systemctl");
+    command.arg("suspend").stdin(Stdio::null());
+    let output = flatpak::adapt(command).output().map_err(Error::Io)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(std::io::Error::other(stderr.trim().to_string()).into());
+    }
+    Ok(())
+}
+
+fn default_broadcast() -> SocketAddr {
+    DEFAULT_BROADCAST
+}
+
+fn default_wake_timeout() -> Duration {
+    Duration::from_secs(120)
+}
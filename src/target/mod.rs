@@ -0,0 +1,373 @@
+//! Driving the bottles of this machine or of another one
+//!
+//! Frontends talk to a [`Target`] instead of a [`Manager`], so the same code
+//! drives the bottles of this machine ([`Local`]) and those of another
+//! machine running the daemon ([`Remote`]), e.g. a laptop launching games on
+//! a desktop. Programs run where the bottle is, their output is streamed
+//! back.
+//!
+//! Remote targets are reached over the gRPC API served by
+//! [`crate::service::serve_with`]. A daemon serving it without
+//! authentication should only be exposed on a trusted network or through a
+//! tunnel, otherwise [`Credentials`] match its [`crate::service::Auth`]. Hosts
+//! that sleep when unused are woken and suspended with [`host`]. Programs
+//! launched on a remote target can be streamed back with Sunshine and
+//! Moonlight, see [`Remote::with_streaming`].
+
+pub mod host;
+
+use crate::bottle::BottleState;
+use crate::logs::{self, LogFilter, LogLine};
+use crate::manager::Manager;
+use crate::proto::bottles::{
+    management_client::ManagementClient, runtime_client::RuntimeClient, LaunchProgramRequest,
+    ListBottlesRequest, StreamLogsRequest, TerminateProgramRequest,
+};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio_stream::{Stream, StreamExt};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+#[cfg(feature = "tls")]
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+use tonic::transport::{Channel, Endpoint};
+use tonic::Status;
+
+/// Output of a program, as streamed by [`Target::logs`]
+pub type LogStream = Pin<Box<dyn Stream<Item = LogLine> + Send>>;
+
+/// A bottle as listed by a [`Target`]
+pub use crate::bottle::BottleSummary;
+
+/// A program started through a [`Target`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Launched {
+    /// Session of the program on the target, see [`crate::session`]
+    pub session: u64,
+    /// Process id of the program on the target
+    pub pid: u32,
+    /// Sunshine application streaming the program, for Moonlight to start,
+    /// see [`crate::integrations::sunshine`]
+    #[serde(default)]
+    pub stream_app: Option<String>,
+}
+
+/// A machine whose bottles can be driven
+#[tonic::async_trait]
+pub trait Target: Send + Sync {
+    /// Name of the machine, for frontends to show
+    fn name(&self) -> &str;
+
+    async fn list_bottles(&self) -> Result<Vec<BottleSummary>, Error>;
+
+    /// Launch `program` in `bottle`, see [`Manager::launch_program_with_env`]
+    async fn launch(
+        &self,
+        bottle: &str,
+        program: &Path,
+        args: &[String],
+        overrides: &HashMap<String, String>,
+    ) -> Result<Launched, Error>;
+
+    /// Stop the program with process id `pid` in `bottle`
+    async fn stop(&self, bottle: &str, pid: u32) -> Result<(), Error>;
+
+    /// Follow the output of a session, see [`logs::tail`]
+    async fn logs(
+        &self,
+        bottle: &str,
+        session: u64,
+        filter: LogFilter,
+    ) -> Result<LogStream, Error>;
+}
+
+/// The bottles of this machine
+pub struct Local {
+    manager: Arc<Manager>,
+}
+
+impl Local {
+    pub fn new(manager: Arc<Manager>) -> Self {
+        Self { manager }
+    }
+}
+
+#[tonic::async_trait]
+impl Target for Local {
+    fn name(&self) -> &str {
+        "localhost"
+    }
+
+    async fn list_bottles(&self) -> Result<Vec<BottleSummary>, Error> {
+        let manager = self.manager.clone();
+        blocking(move || manager.list_bottle_summaries()).await
+    }
+
+    async fn launch(
+        &self,
+        bottle: &str,
+        program: &Path,
+        args: &[String],
+        overrides: &HashMap<String, String>,
+    ) -> Result<Launched, Error> {
+        let manager = self.manager.clone();
+        let (bottle, program) = (bottle.to_string(), program.to_path_buf());
+        let (args, overrides) = (args.to_vec(), overrides.clone());
+        let session = blocking(move || {
+            manager.launch_program_with_env(&bottle, &program, &args, &overrides)
+        })
+        .await?;
+        Ok(Launched {
+            session: session.id,
+            pid: session.pid,
+            stream_app: None,
+        })
+    }
+
+    async fn stop(&self, bottle: &str, pid: u32) -> Result<(), Error> {
+        let session = self
+            .manager
+            .sessions()
+            .into_iter()
+            .find(|session| session.bottle == bottle && session.pid == pid)
+            .ok_or_else(|| no_program(bottle, pid))?;
+        let manager = self.manager.clone();
+        blocking(move || manager.stop_session(session.id)).await?;
+        Ok(())
+    }
+
+    async fn logs(
+        &self,
+        bottle: &str,
+        session: u64,
+        filter: LogFilter,
+    ) -> Result<LogStream, Error> {
+        let lines = logs::tail(self.manager.clone(), bottle, session, filter).await?;
+        Ok(Box::pin(lines))
+    }
+}
+
+/// How a [`Remote`] authenticates to the daemon, as required by its
+/// [`crate::service::Auth`]
+#[derive(Clone, Default)]
+pub enum Credentials {
+    #[default]
+    None,
+    /// Sent as `Bearer <token>` in the `authorization` metadata of requests
+    Token(String),
+    /// A client certificate, the daemon's being checked against `ca`; the
+    /// endpoint has to be an `https` URL
+    #[cfg(feature = "tls")]
+    Tls { identity: Identity, ca: Certificate },
+}
+
+// Keeps the token out of logs
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "None"),
+            Self::Token(_) => write!(f, "Token(..)"),
+            #[cfg(feature = "tls")]
+            Self::Tls { .. } => write!(f, "Tls {{ .. }}"),
+        }
+    }
+}
+
+/// Interceptor adding the token of [`Credentials::Token`] to requests
+#[derive(Debug, Clone)]
+struct BearerToken(Option<MetadataValue<Ascii>>);
+
+impl Interceptor for BearerToken {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        if let Some(token) = &self.0 {
+            request.metadata_mut().insert("authorization", token.clone());
+        }
+        Ok(request)
+    }
+}
+
+type AuthChannel = InterceptedService<Channel, BearerToken>;
+
+/// The bottles of another machine running the daemon
+pub struct Remote {
+    name: String,
+    management: ManagementClient<AuthChannel>,
+    runtime: RuntimeClient<AuthChannel>,
+    stream: bool,
+}
+
+impl Remote {
+    /// Connect to the daemon at `endpoint`, e.g. `http://desktop.local:50051`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `endpoint` is not a URL or can't be reached
+    pub async fn connect(endpoint: impl Into<String>) -> Result<Self, Error> {
+        Self::connect_with(endpoint, Credentials::None).await
+    }
+
+    /// Connect to the daemon at `endpoint`, authenticating with
+    /// `credentials`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `endpoint` is not a URL or can't be reached, or
+    /// if the credentials are invalid
+    pub async fn connect_with(
+        endpoint: impl Into<String>,
+        credentials: Credentials,
+    ) -> Result<Self, Error> {
+        let endpoint = endpoint.into();
+        #[allow(unused_mut)]
+        let mut builder = Endpoint::from_shared(endpoint.clone())?;
+        let token = match &credentials {
+            Credentials::Token(token) => {
+                let value = format!("Bearer {}", token).parse().map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "the token is not printable ASCII",
+                    )
+                })?;
+                Some(value)
+            }
+            _ => None,
+        };
+        #[cfg(feature = "tls")]
+        if let Credentials::Tls { identity, ca } = credentials {
+            let tls = ClientTlsConfig::new().identity(identity).ca_certificate(ca);
+            builder = builder.tls_config(tls)?;
+        }
+        let channel = builder.connect().await?;
+        tracing::info!("Connected to {}", endpoint);
+        let token = BearerToken(token);
+        Ok(Self {
+            name: endpoint,
+            management: ManagementClient::with_interceptor(channel.clone(), token.clone()),
+            runtime: RuntimeClient::with_interceptor(channel, token),
+            stream: false,
+        })
+    }
+
+    /// Register the programs launched on the host with its Sunshine, so they
+    /// can be streamed right away with Moonlight
+    ///
+    /// A host without Sunshine still launches the programs, they are just
+    /// not registered, see [`Launched::stream_app`].
+    pub fn with_streaming(mut self, enabled: bool) -> Self {
+        self.stream = enabled;
+        self
+    }
+}
+
+#[tonic::async_trait]
+impl Target for Remote {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn list_bottles(&self) -> Result<Vec<BottleSummary>, Error> {
+        let request = ListBottlesRequest {
+            summary: true,
+            ..Default::default()
+        };
+        let response = self.management.clone().list_bottles(request).await?;
+        Ok(response
+            .into_inner()
+            .bottles
+            .into_iter()
+            .map(|bottle| BottleSummary {
+                kind: bottle.r#type.parse().unwrap_or_default(),
+                name: bottle.name,
+                path: bottle.path.into(),
+                runner: Some(bottle.runner).filter(|runner| !runner.is_empty()),
+                read_only: bottle.read_only,
+                active: bottle.active,
+                state: BottleState::from_id(&bottle.state, bottle.pids).unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn launch(
+        &self,
+        bottle: &str,
+        program: &Path,
+        args: &[String],
+        overrides: &HashMap<String, String>,
+    ) -> Result<Launched, Error> {
+        let request = LaunchProgramRequest {
+            bottle_name: bottle.to_string(),
+            program_path: program.display().to_string(),
+            arguments: args.to_vec(),
+            env_overrides: overrides.clone(),
+            stream: self.stream,
+            ..Default::default()
+        };
+        let response = self.runtime.clone().launch_program(request).await?.into_inner();
+        Ok(Launched {
+            session: response.session_id,
+            pid: response.pid,
+            stream_app: Some(response.stream_app).filter(|app| !app.is_empty()),
+        })
+    }
+
+    async fn stop(&self, bottle: &str, pid: u32) -> Result<(), Error> {
+        let request = TerminateProgramRequest {
+            bottle_name: bottle.to_string(),
+            pid,
+        };
+        let response = self.runtime.clone().terminate_program(request).await?.into_inner();
+        if !response.success {
+            return Err(std::io::Error::other(response.error_message).into());
+        }
+        Ok(())
+    }
+
+    async fn logs(
+        &self,
+        bottle: &str,
+        session: u64,
+        filter: LogFilter,
+    ) -> Result<LogStream, Error> {
+        let request = StreamLogsRequest {
+            bottle_name: bottle.to_string(),
+            session_id: session,
+        };
+        let lines = self.runtime.clone().stream_logs(request).await?.into_inner();
+        let name = self.name.clone();
+        let lines = lines
+            .filter_map(move |line| match line {
+                Ok(line) => Some(logs::parse_line(&line.raw)),
+                Err(e) => {
+                    tracing::warn!("Lost the output of session {} on {}: {}", session, name, e);
+                    None
+                }
+            })
+            .filter(move |line| filter.matches(line));
+        Ok(Box::pin(lines))
+    }
+}
+
+/// Run a blocking manager operation off the async runtime
+async fn blocking<T, F>(f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+fn no_program(bottle: &str, pid: u32) -> Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("no program with pid {} in '{}'", pid, bottle),
+    )
+    .into()
+}
@@ -0,0 +1,358 @@
+//! Type-specific defaults applied when a bottle is created
+//!
+//! Every [`BottleType`] has a built-in [`Template`]. Frontends can list them
+//! with [`list`], let the user adjust one and pass it along with the
+//! [`BottleManifest`](crate::manifest::BottleManifest) so the tweaked version is
+//! applied instead of the built-in one.
+//!
+//! Software bottles also have curated [`Profile`]s, for the domains the
+//! defaults of desktop applications don't serve: office suites, Adobe's
+//! creative applications and music production. Their [`Template::steps`]
+//! install the components, DLL overrides and registry values each needs, and
+//! run like a [`Recipe`] once the bottle is created.
+
+use crate::audio::AudioOptions;
+use crate::bottle::{BottleConfig, BottleType};
+use crate::components::ComponentKind;
+use crate::installers::{Recipe, Step};
+use crate::registry::RegistryValue;
+use crate::sync::SyncMode;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Component version meaning "the newest one available"
+pub const LATEST: &str = "latest";
+
+/// Host fonts linked into prefixes of templates with [`Template::host_fonts`]
+///
+/// Metric-compatible replacements for the core Windows fonts, which many
+/// applications expect to find in `C:\windows\Fonts`.
+const HOST_FONTS: &[&str] = &[
+    "LiberationSans-Regular.ttf",
+    "LiberationSans-Bold.ttf",
+    "LiberationSans-Italic.ttf",
+    "LiberationSans-BoldItalic.ttf",
+    "LiberationSerif-Regular.ttf",
+    "LiberationSerif-Bold.ttf",
+    "LiberationSerif-Italic.ttf",
+    "LiberationSerif-BoldItalic.ttf",
+    "LiberationMono-Regular.ttf",
+    "LiberationMono-Bold.ttf",
+    "DejaVuSans.ttf",
+    "DejaVuSans-Bold.ttf",
+    "DejaVuSansMono.ttf",
+];
+
+/// Directories searched for [`HOST_FONTS`]
+const HOST_FONT_DIRS: &[&str] = &["/usr/share/fonts", "/usr/local/share/fonts"];
+
+/// A curated template of Software bottles, see [`Template::profile`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Profile {
+    /// Microsoft Office and other office suites
+    Office,
+    /// Photoshop, Illustrator and the other Adobe applications
+    Adobe,
+    /// DAWs and audio plugins, through wineasio
+    MusicProduction,
+}
+
+impl Profile {
+    pub const ALL: [Self; 3] = [Self::Office, Self::Adobe, Self::MusicProduction];
+
+    /// Identifier of the profile, as serialized
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Office => "office",
+            Self::Adobe => "adobe",
+            Self::MusicProduction => "music_production",
+        }
+    }
+}
+
+/// Defaults applied to a new bottle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct Template {
+    pub kind: BottleType,
+    /// The curated profile the template comes from, if any
+    pub profile: Option<Profile>,
+    pub description: String,
+    /// DXVK version to install, or [`LATEST`]
+    pub dxvk_version: Option<String>,
+    /// VKD3D-Proton version to install, or [`LATEST`]
+    pub vkd3d_version: Option<String>,
+    pub sync: SyncMode,
+    /// Link the host's core font replacements into the prefix
+    pub host_fonts: bool,
+    /// Variables added to the bottle environment
+    pub environment: HashMap<String, String>,
+    /// Audio settings, for bottles created without any
+    pub audio: AudioOptions,
+    /// Run in the created bottle, after the components of the manifest
+    pub steps: Vec<Step>,
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Self::builtin(BottleType::Custom)
+    }
+}
+
+impl Template {
+    /// The built-in template of a bottle type
+    pub fn builtin(kind: BottleType) -> Self {
+        let mut template = Self {
+            kind: kind.clone(),
+            profile: None,
+            description: String::new(),
+            dxvk_version: None,
+            vkd3d_version: None,
+            sync: SyncMode::Default,
+            host_fonts: false,
+            environment: HashMap::new(),
+            audio: AudioOptions::default(),
+            steps: Vec::new(),
+        };
+        match kind {
+            BottleType::Gaming => {
+                template.description =
+                    "DirectX translation through Vulkan and fast synchronization for games".into();
+                template.dxvk_version = Some(LATEST.into());
+                template.vkd3d_version = Some(LATEST.into());
+                template.sync = SyncMode::Fsync;
+                template
+                    .environment
+                    .insert("WINEDLLOVERRIDES".into(), "winemenubuilder.exe=d".into());
+                template.steps = vec![
+                    component(ComponentKind::Dxvk),
+                    component(ComponentKind::Vkd3d),
+                ];
+            }
+            BottleType::Software => {
+                template.description =
+                    "A minimal prefix with common fonts for desktop applications".into();
+                template.host_fonts = true;
+                template
+                    .environment
+                    .insert("WINEDLLOVERRIDES".into(), "winemenubuilder.exe=d".into());
+            }
+            BottleType::Custom => {
+                template.description = "An empty prefix, configured by hand".into();
+            }
+        }
+        template
+    }
+
+    /// The curated template of a Software bottle for `profile`
+    ///
+    /// Native DLLs only take over from Wine's builtin ones once a program
+    /// installs them, so the overrides are harmless until then.
+    pub fn profile(profile: Profile) -> Self {
+        let mut template = Self::builtin(BottleType::Software);
+        template.profile = Some(profile);
+        match profile {
+            Profile::Office => {
+                template.description =
+                    "Office suites: native XML and rich text DLLs, no hardware rendering".into();
+                template.steps = vec![
+                    windows_version("win10"),
+                    dll_override("riched20"),
+                    dll_override("msxml6"),
+                    // Office 2016 and later draw garbled windows with it
+                    registry(
+                        "HKCU\\Software\\Microsoft\\Office\\16.0\\Common\\Graphics",
+                        "DisableHardwareAcceleration",
+                        RegistryValue::Dword(1),
+                    ),
+                    // Videos embedded in presentations
+                    component(ComponentKind::MediaFoundation),
+                ];
+            }
+            Profile::Adobe => {
+                template.description =
+                    "Adobe applications: GPU acceleration through Vulkan, smooth fonts".into();
+                template.dxvk_version = Some(LATEST.into());
+                template.steps = vec![
+                    component(ComponentKind::Dxvk),
+                    windows_version("win10"),
+                    dll_override("msxml3"),
+                    dll_override("msxml6"),
+                    dll_override("gdiplus"),
+                    registry(
+                        "HKCU\\Control Panel\\Desktop",
+                        "FontSmoothing",
+                        RegistryValue::String("2".into()),
+                    ),
+                    // Subpixel anti-aliasing
+                    registry(
+                        "HKCU\\Control Panel\\Desktop",
+                        "FontSmoothingType",
+                        RegistryValue::Dword(2),
+                    ),
+                ];
+            }
+            Profile::MusicProduction => {
+                template.description =
+                    "DAWs and audio plugins: ASIO through wineasio, low latency".into();
+                template.sync = SyncMode::Fsync;
+                template.audio = AudioOptions {
+                    buffer_size: Some(256),
+                    sample_rate: Some(48_000),
+                    ..AudioOptions::default()
+                };
+                // Real-time priorities for the wineserver and audio threads,
+                // honoured by Wine Staging
+                template
+                    .environment
+                    .insert("STAGING_RT_PRIORITY_SERVER".into(), "90".into());
+                template
+                    .environment
+                    .insert("STAGING_RT_PRIORITY_BASE".into(), "80".into());
+                template.steps = vec![component(ComponentKind::WineAsio)];
+            }
+        }
+        template
+    }
+
+    /// Fill the unset parts of `config` with the template's defaults
+    ///
+    /// Values already present in `config` always win, so applying a template
+    /// never overrides settings given explicitly in a manifest. A `sync` of
+    /// [`SyncMode::Default`] counts as unset, unlike an explicit
+    /// [`SyncMode::None`].
+    pub fn apply(&self, config: &mut BottleConfig) {
+        if config.dxvk_version.is_none() {
+            config.dxvk_version = self.dxvk_version.clone();
+        }
+        if config.vkd3d_version.is_none() {
+            config.vkd3d_version = self.vkd3d_version.clone();
+        }
+
+        if config.sync == SyncMode::Default {
+            config.sync = self.sync;
+        }
+        if config.audio == AudioOptions::default() {
+            config.audio = self.audio.clone();
+        }
+        for (key, value) in &self.environment {
+            config
+                .environment
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
+
+    /// Prepare an initialized prefix according to the template
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the prefix's `Fonts` directory cannot be created.
+    /// Fonts missing on the host are skipped.
+    pub fn prepare_prefix(&self, prefix: &Path) -> Result<(), Error> {
+        if !self.host_fonts {
+            return Ok(());
+        }
+        let fonts = prefix.join("drive_c").join("windows").join("Fonts");
+        fs::create_dir_all(&fonts).map_err(Error::Io)?;
+
+        let mut linked = 0;
+        for (name, source) in find_host_fonts() {
+            let target = fonts.join(name);
+            if target.exists() {
+                continue;
+            }
+            #[cfg(unix)]
+            let result = std::os::unix::fs::symlink(&source, &target);
+            #[cfg(not(unix))]
+            let result = fs::copy(&source, &target).map(|_| ());
+            match result {
+                Ok(()) => linked += 1,
+                Err(e) => tracing::warn!("Failed to link font '{}': {}", source.display(), e),
+            }
+        }
+        tracing::debug!("Linked {} host font(s) into '{}'", linked, prefix.display());
+        Ok(())
+    }
+
+    /// The steps of the template as a recipe, run once the bottle exists
+    pub(crate) fn recipe(&self) -> Recipe {
+        let name = match self.profile {
+            Some(profile) => profile.id().to_string(),
+            None => self.kind.to_string(),
+        };
+        Recipe {
+            name: format!("{} template", name),
+            description: Some(self.description.clone()),
+            bottle_type: self.kind.clone(),
+            steps: self.steps.clone(),
+        }
+    }
+}
+
+/// The built-in templates of every bottle type, then the curated profiles
+pub fn list() -> Vec<Template> {
+    [BottleType::Gaming, BottleType::Software, BottleType::Custom]
+        .into_iter()
+        .map(Template::builtin)
+        .chain(Profile::ALL.into_iter().map(Template::profile))
+        .collect()
+}
+
+fn windows_version(version: &str) -> Step {
+    registry("HKCU\\Software\\Wine", "Version", RegistryValue::String(version.into()))
+}
+
+/// Prefer the native build of `dll` a program installs
+fn dll_override(dll: &str) -> Step {
+    Step::SetDllOverride {
+        dll: dll.into(),
+        mode: "native,builtin".into(),
+    }
+}
+
+fn registry(key: &str, name: &str, value: RegistryValue) -> Step {
+    Step::SetRegistry {
+        key: key.into(),
+        name: name.into(),
+        value,
+    }
+}
+
+fn component(component: ComponentKind) -> Step {
+    Step::InstallComponent {
+        component,
+        version: None,
+    }
+}
+
+/// Locate the files of [`HOST_FONTS`] on the host
+fn find_host_fonts() -> Vec<(&'static str, PathBuf)> {
+    let mut found: Vec<(&'static str, PathBuf)> = Vec::new();
+    let mut pending: Vec<PathBuf> = HOST_FONT_DIRS.iter().map(PathBuf::from).collect();
+    while let Some(directory) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&directory) else {
+            continue;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let name = entry.file_name();
+            if let Some(font) = HOST_FONTS.iter().find(|font| name == **font) {
+                if !found.iter().any(|(known, _)| known == font) {
+                    found.push((*font, path));
+                }
+            }
+        }
+    }
+    found
+}
@@ -0,0 +1,156 @@
+//! Low-rate previews of the windows of running sessions
+//!
+//! Frontends can show what a running game looks like in their list of running
+//! programs. Capturing is opt-in per bottle through [`ThumbnailOptions`] and
+//! disabled by default, since it records whatever the program displays.
+//!
+//! Only sessions running inside gamescope (see [`crate::launch::gamescope`])
+//! can be captured: gamescope gives the program its own nested X display,
+//! which is grabbed without touching the rest of the desktop. Grabbing uses
+//! ImageMagick's `import`, which also scales the image down.
+
+use crate::session::Session;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::fs;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Thumbnails younger than this are returned from the cache instead of being
+/// captured again
+pub const MIN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Width of thumbnails when the bottle doesn't set one
+pub const DEFAULT_WIDTH: u32 = 320;
+
+/// Per-bottle thumbnail settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ThumbnailOptions {
+    /// Allow frontends to capture the windows of the bottle's sessions
+    pub enabled: bool,
+    /// Width of the thumbnails in pixels, the height keeps the aspect ratio
+    pub width: Option<u32>,
+}
+
+/// A captured preview of a session's window
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub session: u64,
+    pub captured_at: SystemTime,
+    /// PNG encoded image
+    pub png: Vec<u8>,
+}
+
+/// Cache of the latest thumbnail of every session, so frontends polling
+/// faster than [`MIN_INTERVAL`] don't cause extra captures
+#[derive(Debug, Default)]
+pub struct Thumbnails {
+    latest: Mutex<HashMap<u64, Thumbnail>>,
+}
+
+impl Thumbnails {
+    /// Get a recent thumbnail of `session`, capturing a new one if needed
+    ///
+    /// Sessions that are not in `running` are dropped from the cache.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the session doesn't run inside gamescope or the
+    /// capture fails
+    pub fn get(
+        &self,
+        session: &Session,
+        options: &ThumbnailOptions,
+        running: &[u64],
+    ) -> Result<Thumbnail, Error> {
+        {
+            let mut latest = self.latest();
+            latest.retain(|id, _| running.contains(id));
+            if let Some(thumbnail) = latest.get(&session.id) {
+                let age = thumbnail.captured_at.elapsed().unwrap_or(Duration::MAX);
+                if age < MIN_INTERVAL {
+                    return Ok(thumbnail.clone());
+                }
+            }
+        }
+
+        let display = nested_display(session.pid).ok_or_else(|| {
+            unsupported(format!(
+                "session {} does not run inside gamescope, only gamescope sessions can be captured",
+                session.id
+            ))
+        })?;
+        let thumbnail = Thumbnail {
+            session: session.id,
+            captured_at: SystemTime::now(),
+            png: grab(&display, options.width.unwrap_or(DEFAULT_WIDTH))?,
+        };
+        self.latest().insert(session.id, thumbnail.clone());
+        Ok(thumbnail)
+    }
+
+    fn latest(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Thumbnail>> {
+        self.latest
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The X display gamescope created for the processes below `pid`
+///
+/// Gamescope sets `GAMESCOPE_WAYLAND_DISPLAY` and its nested `DISPLAY` for the
+/// program it runs, so the first descendant with both is looked for.
+#[cfg(target_os = "linux")]
+fn nested_display(pid: u32) -> Option<String> {
+    crate::resources::descendants(pid).into_iter().find_map(|pid| {
+        let environ = fs::read(format!("/proc/{}/environ", pid)).ok()?;
+        let variables: Vec<&[u8]> = environ.split(|byte| *byte == 0).collect();
+        let value = |name: &str| {
+            variables.iter().find_map(|variable| {
+                let rest = variable.strip_prefix(name.as_bytes())?.strip_prefix(b"=")?;
+                Some(String::from_utf8_lossy(rest).to_string())
+            })
+        };
+        value("GAMESCOPE_WAYLAND_DISPLAY")?;
+        value("DISPLAY")
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn nested_display(_pid: u32) -> Option<String> {
+    None
+}
+
+/// Capture the root window of an X display as a PNG `width` pixels wide
+fn grab(display: &str, width: u32) -> Result<Vec<u8>, Error> {
+    let output = Command::new("import")
+        .args(["-display", display, "-window", "root", "-resize"])
+        .arg(format!("{}x", width))
+        .arg("png:-")
+        .output()
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                unsupported("capturing thumbnails needs ImageMagick's 'import'".to_string())
+            } else {
+                Error::Io(e)
+            }
+        })?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(std::io::Error::other(format!(
+            "cannot capture display {}: {}",
+            display,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(output.stdout)
+}
+
+fn unsupported(message: String) -> Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported, message).into()
+}
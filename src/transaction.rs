@@ -0,0 +1,170 @@
+//! Undoing multi-step operations that fail half-way
+//!
+//! Creating a bottle, installing a component or switching runners changes
+//! several things in turn: directories, files of the prefix, the registry,
+//! the bottle index. A [`Transaction`] records how to undo each change as it
+//! is made, and undoes them in reverse order unless it is committed, so an
+//! operation that fails, is cancelled (see [`crate::jobs::CancelToken`]) or
+//! panics leaves things as they were before it started.
+//!
+//! Undoing is best effort: a compensating action that fails is logged and
+//! the others still run.
+
+use crate::jobs::CancelToken;
+use crate::Error;
+use std::fs;
+use std::path::Path;
+
+/// A compensating action, with what it undoes for the logs
+type Undo<'a> = (String, Box<dyn FnOnce() -> Result<(), Error> + 'a>);
+
+/// Changes of an operation, undone when dropped without
+/// [`Transaction::commit`]
+#[must_use = "a transaction is rolled back when dropped"]
+pub struct Transaction<'a> {
+    name: String,
+    undo: Vec<Undo<'a>>,
+    token: Option<CancelToken>,
+}
+
+impl<'a> Transaction<'a> {
+    /// Start the transaction of the operation `name`, e.g. `create 'Games'`
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            undo: Vec::new(),
+            token: None,
+        }
+    }
+
+    /// Roll back once `token` is cancelled, at the next
+    /// [`Transaction::check`] or [`Transaction::step`]
+    pub fn with_token(mut self, token: Option<&CancelToken>) -> Self {
+        self.token = token.cloned();
+        self
+    }
+
+    /// Return [`Error::Cancelled`] if the operation was cancelled
+    pub fn check(&self) -> Result<(), Error> {
+        match &self.token {
+            Some(token) => token.check(),
+            None => Ok(()),
+        }
+    }
+
+    /// Record how to undo a change that was just made
+    pub fn on_rollback(
+        &mut self,
+        description: impl Into<String>,
+        undo: impl FnOnce() -> Result<(), Error> + 'a,
+    ) {
+        self.undo.push((description.into(), Box::new(undo)));
+    }
+
+    /// Run `change`, recording `undo` if it succeeds
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Cancelled`] without running `change` if the
+    /// operation was cancelled, otherwise the error of `change`
+    pub fn step<T>(
+        &mut self,
+        description: impl Into<String>,
+        change: impl FnOnce() -> Result<T, Error>,
+        undo: impl FnOnce() -> Result<(), Error> + 'a,
+    ) -> Result<T, Error> {
+        self.check()?;
+        let value = change()?;
+        self.on_rollback(description, undo);
+        Ok(value)
+    }
+
+    /// Create the directory `path` and its parents, removing the ones that
+    /// didn't exist on rollback
+    pub fn create_dir_all(&mut self, path: &Path) -> Result<(), Error> {
+        self.check()?;
+        let created = path
+            .ancestors()
+            .take_while(|ancestor| !ancestor.exists())
+            .last()
+            .map(Path::to_path_buf);
+        fs::create_dir_all(path).map_err(Error::Io)?;
+        if let Some(created) = created {
+            let description = format!("create '{}'", created.display());
+            self.on_rollback(description, move || remove_dir(&created));
+        }
+        Ok(())
+    }
+
+    /// Keep a copy of the file at `path`, put back on rollback, before it is
+    /// changed
+    ///
+    /// A file that doesn't exist yet is removed on rollback instead, a
+    /// symbolic link is recreated.
+    pub fn preserve_file(&mut self, path: &Path) -> Result<(), Error> {
+        self.check()?;
+        let path = path.to_path_buf();
+        let description = format!("change '{}'", path.display());
+        #[cfg(unix)]
+        if let Ok(link) = fs::read_link(&path) {
+            self.on_rollback(description, move || {
+                remove_file(&path)?;
+                std::os::unix::fs::symlink(link, &path).map_err(Error::Io)
+            });
+            return Ok(());
+        }
+        match fs::read(&path) {
+            Ok(content) => self.on_rollback(description, move || {
+                fs::write(&path, content).map_err(Error::Io)
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.on_rollback(description, move || remove_file(&path))
+            }
+            Err(e) => return Err(Error::Io(e)),
+        }
+        Ok(())
+    }
+
+    /// Keep the changes, nothing is undone
+    pub fn commit(mut self) {
+        tracing::debug!("Committed {} ({} changes)", self.name, self.undo.len());
+        self.undo.clear();
+    }
+
+    /// Undo the changes now, in reverse order
+    pub fn rollback(mut self) {
+        self.undo_all();
+    }
+
+    fn undo_all(&mut self) {
+        if self.undo.is_empty() {
+            return;
+        }
+        tracing::warn!("Rolling back {} ({} changes)", self.name, self.undo.len());
+        while let Some((description, undo)) = self.undo.pop() {
+            if let Err(e) = undo() {
+                tracing::warn!("Cannot undo {} of {}: {}", description, self.name, e);
+            }
+        }
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        self.undo_all();
+    }
+}
+
+fn remove_dir(path: &Path) -> Result<(), Error> {
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::Io(e)),
+        _ => Ok(()),
+    }
+}
+
+fn remove_file(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::Io(e)),
+        _ => Ok(()),
+    }
+}
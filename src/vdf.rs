@@ -0,0 +1,191 @@
+//! Parser for Valve's text KeyValues format (`.vdf`, `.acf`)
+//!
+//! Used to read Steam library metadata such as `libraryfolders.vdf`,
+//! `appmanifest_*.acf` and Proton's `toolmanifest.vdf`.
+
+use crate::limits::Limits;
+
+/// A KeyValues node: either a string or a nested list of key/value pairs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Look up a child by key, ignoring case like Steam does
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(entries) => entries
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v),
+            Self::String(_) => None,
+        }
+    }
+
+    /// Follow a path of keys, e.g. `["AppState", "name"]`
+    pub fn path(&self, keys: &[&str]) -> Option<&Value> {
+        keys.iter().try_fold(self, |value, key| value.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            Self::Object(_) => None,
+        }
+    }
+
+    /// Iterate over the children of an object; strings have none
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &Value)> {
+        let entries: &[(String, Value)] = match self {
+            Self::Object(entries) => entries,
+            Self::String(_) => &[],
+        };
+        entries.iter().map(|(k, v)| (k.as_str(), v))
+    }
+}
+
+/// Parse a KeyValues document into its root object
+///
+/// The parser is lenient: conditionals (`[$WIN32]`) are ignored and a missing
+/// closing brace at the end of the input closes the object implicitly.
+///
+/// # Returns
+///
+/// `None` if the document is structurally invalid (a key without a value, or
+/// an unbalanced closing brace), or exceeds the [default limits](Limits)
+pub fn parse(input: &str) -> Option<Value> {
+    parse_with_limits(input, &Limits::DEFAULT)
+}
+
+/// Parse a KeyValues document like [`parse`], within `limits`
+///
+/// Every key counts as an item, and every nested object as a level of depth.
+pub fn parse_with_limits(input: &str, limits: &Limits) -> Option<Value> {
+    if !limits.allows_size(input.len()) {
+        return None;
+    }
+    let mut tokens = Tokenizer {
+        rest: input,
+        items: 0,
+    };
+    let entries = parse_entries(&mut tokens, limits, 0)?;
+    Some(Value::Object(entries))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Text(String),
+    Open,
+    Close,
+}
+
+/// The entries of an object at `depth`, the root object being at 0
+fn parse_entries(
+    tokens: &mut Tokenizer<'_>,
+    limits: &Limits,
+    depth: usize,
+) -> Option<Vec<(String, Value)>> {
+    if depth > limits.max_depth {
+        return None;
+    }
+    let mut entries = Vec::new();
+    loop {
+        let key = match tokens.next() {
+            Some(Token::Text(key)) => key,
+            Some(Token::Close) if depth > 0 => return Some(entries),
+            Some(Token::Close) | Some(Token::Open) => return None,
+            None => return Some(entries),
+        };
+        tokens.items += 1;
+        if tokens.items > limits.max_items {
+            return None;
+        }
+        let value = match tokens.next()? {
+            Token::Text(value) => Value::String(value),
+            Token::Open => Value::Object(parse_entries(tokens, limits, depth + 1)?),
+            Token::Close => return None,
+        };
+        entries.push((key, value));
+    }
+}
+
+struct Tokenizer<'a> {
+    rest: &'a str,
+    /// Keys read so far
+    items: usize,
+}
+
+impl Tokenizer<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let mut rest = self.rest;
+        loop {
+            rest = rest.trim_start();
+            if let Some(comment) = rest.strip_prefix("//") {
+                rest = comment.find('\n').map_or("", |end| &comment[end..]);
+                continue;
+            }
+            if rest.starts_with('[') {
+                // Platform conditionals like [$WIN32] only matter to Steam itself
+                rest = match rest.find(']') {
+                    Some(end) => &rest[end + 1..],
+                    None => "",
+                };
+                continue;
+            }
+            break;
+        }
+
+        let mut chars = rest.chars();
+        let token = match chars.next() {
+            None => {
+                self.rest = rest;
+                return None;
+            }
+            Some('{') => {
+                rest = chars.as_str();
+                Token::Open
+            }
+            Some('}') => {
+                rest = chars.as_str();
+                Token::Close
+            }
+            Some('"') => {
+                let body = chars.as_str();
+                let mut text = String::new();
+                let mut escaped = false;
+                let mut end = body.len();
+                for (index, c) in body.char_indices() {
+                    if escaped {
+                        text.push(match c {
+                            'n' => '\n',
+                            't' => '\t',
+                            other => other,
+                        });
+                        escaped = false;
+                    } else if c == '\\' {
+                        escaped = true;
+                    } else if c == '"' {
+                        end = index + 1;
+                        break;
+                    } else {
+                        text.push(c);
+                    }
+                }
+                rest = &body[end..];
+                Token::Text(text)
+            }
+            Some(_) => {
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || c == '{' || c == '}' || c == '"')
+                    .unwrap_or(rest.len());
+                let text = rest[..end].to_string();
+                rest = &rest[end..];
+                Token::Text(text)
+            }
+        };
+        self.rest = rest;
+        Some(token)
+    }
+}
@@ -0,0 +1,166 @@
+//! Versions of runners and components, as they are named
+//!
+//! Runners and components don't follow semantic versioning: Wine reports
+//! `wine-9.0 (Staging)`, GE builds are named `GE-Proton9-20`, DXVK releases
+//! `2.4`. [`Version::parse`] splits such a name into the flavor of the build,
+//! its numbers and what follows them, so versions of the same flavor compare
+//! by their numbers. Versions of different flavors don't compare at all: a
+//! Wine build is neither older nor newer than a Proton build.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Characters separating the parts of a version
+const SEPARATORS: &[char] = &['-', '_', '.', ' ', '(', ')'];
+
+/// Suffixes of builds released before the version they are named after
+const PRERELEASES: &[&str] = &["alpha", "beta", "pre", "rc"];
+
+/// A parsed version, e.g. `GE-Proton9-20` or `wine-9.0-staging`
+///
+/// Trailing zeros don't count, `9.0` is the same version as `9`. Between
+/// equal numbers, pre-releases (`rc1`, `beta`) come first, then the plain
+/// version, then other suffixes in alphabetical order.
+#[derive(Debug, Clone)]
+pub struct Version {
+    raw: String,
+    flavor: String,
+    numbers: Vec<u64>,
+    suffix: String,
+}
+
+impl Version {
+    /// Parse a version, `None` if it has no numbers
+    pub fn parse(version: &str) -> Option<Self> {
+        let raw = version.trim();
+        let lower = raw.to_lowercase();
+        let start = lower.find(|c: char| c.is_ascii_digit())?;
+        let mut flavor = lower[..start].trim_end_matches(SEPARATORS);
+        if flavor == "v" {
+            flavor = "";
+        }
+
+        let mut numbers = Vec::new();
+        let mut rest = &lower[start..];
+        loop {
+            let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            numbers.push(rest[..end].parse().unwrap_or(u64::MAX));
+            rest = &rest[end..];
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some('.' | '-' | '_'), Some(next)) if next.is_ascii_digit() => rest = &rest[1..],
+                _ => break,
+            }
+        }
+
+        Some(Self {
+            raw: raw.to_string(),
+            flavor: flavor.to_string(),
+            numbers,
+            suffix: rest.trim_matches(SEPARATORS).to_string(),
+        })
+    }
+
+    /// The version as it was given
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    /// What comes before the numbers, lowercase, e.g. `ge-proton` or `wine`,
+    /// empty for plain versions like `2.4`
+    pub fn flavor(&self) -> &str {
+        &self.flavor
+    }
+
+    pub fn numbers(&self) -> &[u64] {
+        &self.numbers
+    }
+
+    /// What follows the numbers, lowercase, e.g. `staging`
+    pub fn suffix(&self) -> &str {
+        &self.suffix
+    }
+
+    /// Whether this is a later version of the same flavor as `other`
+    pub fn is_newer_than(&self, other: &Version) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Greater)
+    }
+
+    /// Rank of the suffix between versions with the same numbers
+    fn suffix_rank(&self) -> u8 {
+        if self.suffix.is_empty() {
+            1
+        } else if PRERELEASES.iter().any(|prefix| self.suffix.starts_with(prefix)) {
+            0
+        } else {
+            2
+        }
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.flavor != other.flavor {
+            return None;
+        }
+        let length = self.numbers.len().max(other.numbers.len());
+        let number = |version: &Self, index| version.numbers.get(index).copied().unwrap_or(0);
+        let numbers = (0..length)
+            .map(|index| number(self, index).cmp(&number(other, index)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal);
+        Some(
+            numbers
+                .then_with(|| self.suffix_rank().cmp(&other.suffix_rank()))
+                .then_with(|| self.suffix.cmp(&other.suffix)),
+        )
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+impl FromStr for Version {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{}' is not a version", s),
+            )
+            .into()
+        })
+    }
+}
+
+/// The newest of `items` by the version `version` gives of each, among those
+/// of the flavor `flavor`
+///
+/// Items without a version or of another flavor are skipped.
+pub fn newest<T>(
+    items: impl IntoIterator<Item = T>,
+    flavor: &str,
+    version: impl Fn(&T) -> Option<Version>,
+) -> Option<T> {
+    let flavor = flavor.to_lowercase();
+    items
+        .into_iter()
+        .filter_map(|item| Some((version(&item)?, item)))
+        .filter(|(version, _)| version.flavor == flavor)
+        .fold(None, |newest: Option<(Version, T)>, (version, item)| match newest {
+            Some(newest) if !version.is_newer_than(&newest.0) => Some(newest),
+            _ => Some((version, item)),
+        })
+        .map(|(_, item)| item)
+}
@@ -0,0 +1,206 @@
+//! Warming up a freshly started daemon
+//!
+//! The first requests of a frontend would otherwise pay for reading the
+//! indexes of catalogs, asking every runner for its version and listing the
+//! runners to find the one of each bottle. [`warm_up`] does it all ahead of
+//! time, reporting its [`Readiness`] as it goes, and
+//! [`Manager::queue_warm_up`] runs it as a job while the daemon already
+//! serves requests. Nothing waits for it: a request arriving first does the
+//! work itself, as without a warm-up.
+//!
+//! Runner directories are checked in parallel, each runner only being
+//! started when the version it left in the cache is stale.
+
+use crate::bottle::BottleFilter;
+use crate::catalog::Catalog;
+use crate::jobs::CancelToken;
+use crate::manager::Manager;
+use crate::runner::{self, Runner, Wine};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+/// What the warm-up is doing, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Reading the cached indexes of the catalogs
+    Catalogs,
+    /// Checking the runner directories
+    Runners,
+    /// Finding the runner of every bottle
+    Bottles,
+    /// Done, the caches are warm
+    Ready,
+}
+
+impl Stage {
+    /// What the stage does, for frontends to show
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Catalogs => "Reading the catalogs",
+            Self::Runners => "Checking the runners",
+            Self::Bottles => "Resolving the runners of the bottles",
+            Self::Ready => "Ready",
+        }
+    }
+}
+
+/// Progress of the warm-up, in items of its current stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Readiness {
+    pub stage: Stage,
+    pub current: u64,
+    /// Items of the stage, unknown for the bottles, read a page at a time
+    pub total: Option<u64>,
+}
+
+/// What the warm-up found
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WarmUpReport {
+    /// Catalogs with a cached index
+    pub catalogs: usize,
+    /// Runners found
+    pub runners: usize,
+    pub bottles: usize,
+    /// Bottles whose runner isn't installed, with the name of the runner
+    pub unresolved: Vec<(String, String)>,
+    pub elapsed: Duration,
+}
+
+/// Warm up the caches of `manager`, see the [module documentation](self)
+///
+/// A catalog whose cache can't be read is only logged, like runner
+/// directories that hold no runner.
+///
+/// # Errors
+///
+/// Returns [`Error::Cancelled`] once `token` is cancelled, or an error if
+/// the bottles can't be listed
+pub fn warm_up(
+    manager: &Manager,
+    catalogs: &[Catalog],
+    token: &CancelToken,
+    mut progress: impl FnMut(&Readiness),
+) -> Result<WarmUpReport, Error> {
+    let _span = tracing::info_span!("warm_up").entered();
+    let started = Instant::now();
+    let mut report = WarmUpReport::default();
+    let mut report_progress = |stage, current: usize, total: Option<usize>| {
+        progress(&Readiness {
+            stage,
+            current: current as u64,
+            total: total.map(|total| total as u64),
+        })
+    };
+
+    report_progress(Stage::Catalogs, 0, Some(catalogs.len()));
+    for (done, catalog) in catalogs.iter().enumerate() {
+        token.check()?;
+        match manager.cached_catalog(catalog) {
+            Ok(Some(_)) => report.catalogs += 1,
+            Ok(None) => tracing::debug!("No cached index for the catalog {}", catalog.url()),
+            Err(e) => tracing::warn!("Cannot read the catalog {}: {}", catalog.url(), e),
+        }
+        report_progress(Stage::Catalogs, done + 1, Some(catalogs.len()));
+    }
+
+    let candidates: Vec<PathBuf> = manager
+        .runner_roots()
+        .iter()
+        .flat_map(|root| runner::candidates(root))
+        .collect();
+    report_progress(Stage::Runners, 0, Some(candidates.len()));
+    let mut found = check_runners(&candidates, token, |done| {
+        report_progress(Stage::Runners, done, Some(candidates.len()))
+    })?;
+    if let Some(wine) = Wine::system_lazy() {
+        found.push((wine.info().name().to_string(), wine.info().directory().to_path_buf()));
+    }
+    manager.remember_runners(found.iter().map(|(name, path)| (name.as_str(), path.as_path())));
+    report.runners = found.len();
+
+    report_progress(Stage::Bottles, 0, None);
+    for summary in manager.bottle_summaries(BottleFilter::default()) {
+        let summary = summary?;
+        report.bottles += 1;
+        if let Some(name) = summary.runner {
+            if !found.iter().any(|(found, _)| *found == name) {
+                report.unresolved.push((summary.name, name));
+            }
+        }
+        report_progress(Stage::Bottles, report.bottles, None);
+    }
+
+    report.elapsed = started.elapsed();
+    report_progress(Stage::Ready, 1, Some(1));
+    tracing::info!(
+        "Warmed up {} catalogs, {} runners and {} bottles in {:?}",
+        report.catalogs,
+        report.runners,
+        report.bottles,
+        report.elapsed
+    );
+    for (bottle, runner) in &report.unresolved {
+        tracing::warn!("The runner {} of '{}' is not installed", runner, bottle);
+    }
+    Ok(report)
+}
+
+/// Check the runner directories `candidates` in parallel, resolving the
+/// versions of their runners
+///
+/// Returns the name and directory of every runner found, in the order of
+/// `candidates`. `progress` is called with the directories checked so far.
+fn check_runners(
+    candidates: &[PathBuf],
+    token: &CancelToken,
+    mut progress: impl FnMut(usize),
+) -> Result<Vec<(String, PathBuf)>, Error> {
+    let workers = std::thread::available_parallelism()
+        .map_or(1, |workers| workers.get())
+        .min(candidates.len());
+    let next = Mutex::new(candidates.iter().enumerate());
+    let (sender, receiver) = mpsc::channel();
+    let mut found = std::thread::scope(|scope| {
+        for _ in 0..workers {
+            let (next, sender) = (&next, sender.clone());
+            scope.spawn(move || loop {
+                let candidate = next
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .next();
+                let Some((index, directory)) = candidate else {
+                    break;
+                };
+                let runner = (!token.is_cancelled())
+                    .then(|| runner::open(directory, true))
+                    .flatten()
+                    .map(|runner| {
+                        let info = runner.info();
+                        tracing::debug!("Found {} {}", info.name(), info.version());
+                        (index, info.name().to_string(), directory.clone())
+                    });
+                if sender.send(runner).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut found = Vec::new();
+        for (done, runner) in receiver.iter().enumerate() {
+            found.extend(runner);
+            progress(done + 1);
+        }
+        found
+    });
+    token.check()?;
+    found.sort_by_key(|(index, _, _)| *index);
+    Ok(found
+        .into_iter()
+        .map(|(_, name, directory)| (name, directory))
+        .collect())
+}
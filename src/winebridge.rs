@@ -0,0 +1,587 @@
+//! The WineBridge agent, running inside prefixes
+//!
+//! WineBridge is a Windows program serving the `WineBridge` gRPC service of
+//! [`crate::proto::winebridge`] from inside a prefix, so its processes,
+//! registry and files are handled as Windows programs see them. It is shipped
+//! apart from the crate, as the PE executable [`Manager::bridge_path`]:
+//! [`deploy`] copies it into a prefix, at [`PREFIX_PATH`], when it is missing
+//! there or of another version, and [`Manager::start_bridge`] runs it with the
+//! runner of the bottle as `winebridge.exe --port 0`. The bridge listens on a
+//! free loopback port and reports it as its first line of output, `port
+//! <port>`. Any local user can reach the port, so each start also gives the
+//! bridge a new [`Token`] in `WINEBRIDGE_TOKEN`, which it expects with every
+//! request.
+//!
+//! A bridge is stopped once no program launched by the manager or by the
+//! bridge has run in its bottle for [`IDLE_TIMEOUT`], and before the bottle is
+//! moved or deleted. It is asked to shut down first: Proton and umu run it
+//! under processes of their own, which killing the runner process would leave
+//! running.
+//!
+//! Programs can be created by the bridge instead of by a `wine` command, see
+//! [`BridgedProcess`] and [`Runner::launch_with`]: the bridge then reports
+//! their output and their Windows exit code, which the `wine` process only
+//! passes on truncated, and not at all for programs it started detached.
+//!
+//! [`Runner::launch_with`]: crate::runner::Runner::launch_with
+//! [`Manager::bridge_path`]: crate::manager::Manager::bridge_path
+//! [`Manager::start_bridge`]: crate::manager::Manager::start_bridge
+
+use crate::drives;
+use crate::pe::PeFile;
+use crate::proto::winebridge::wine_bridge_client::WineBridgeClient;
+use crate::proto::winebridge::{
+    CreateProcessRequest, KillProcessRequest, ProcessOutput, ShutdownRequest, StreamProcessRequest,
+};
+use crate::session::Sessions;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tonic::metadata::MetadataValue;
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Channel, Endpoint};
+use tonic::Status;
+
+/// File name of the bridge executable
+pub const EXECUTABLE: &str = "winebridge.exe";
+
+/// Where the bridge is deployed, relative to the prefix
+pub const PREFIX_PATH: &str = "drive_c/windows/winebridge.exe";
+
+/// How long a bridge keeps running without programs in its bottle
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often bottles with a bridge are checked for running programs
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often [`Bridge::connect`] retries while the bridge starts
+const CONNECT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long [`BridgedProcess::spawn`] waits for a bridge just started
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a bridge asked to shut down has before it is killed
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long [`read_port`] waits for a bridge to report its port, starting
+/// Wine in a prefix that needs an update being slow
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Variable of the bridge's environment holding its [`Token`]
+pub const TOKEN_VARIABLE: &str = "WINEBRIDGE_TOKEN";
+
+/// A client of a bridge, sending its [`Token`]
+pub type Client = WineBridgeClient<InterceptedService<Channel, Token>>;
+
+/// Secret a bridge expects in the `authorization` metadata of requests, as
+/// `Bearer <token>`
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Token(String);
+
+impl Token {
+    /// A new random token
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the system's random source can't be read
+    pub(crate) fn generate() -> Result<Self, Error> {
+        let mut bytes = [0; 16];
+        File::open("/dev/urandom")
+            .and_then(|mut random| random.read_exact(&mut bytes))
+            .map_err(Error::Io)?;
+        Ok(Self(bytes.iter().map(|byte| format!("{:02x}", byte)).collect()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+// Keeps the token out of logs
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Token(..)")
+    }
+}
+
+impl Interceptor for Token {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        let value = MetadataValue::try_from(format!("Bearer {}", self.0))
+            .map_err(|_| Status::internal("the winebridge token is not valid metadata"))?;
+        request.metadata_mut().insert("authorization", value);
+        Ok(request)
+    }
+}
+
+/// A bridge running in a bottle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bridge {
+    pub bottle: String,
+    /// Process id of the runner process running the bridge
+    pub pid: u32,
+    /// Loopback port the bridge listens on
+    pub port: u16,
+    /// Version of the deployed executable, if it has one
+    pub version: Option<String>,
+    /// Secret of this start of the bridge
+    #[serde(skip)]
+    pub token: Token,
+    #[serde(skip)]
+    processes: Processes,
+}
+
+/// Number of [`BridgedProcess`]es of a bridge still running, shared by the
+/// clones of its [`Bridge`]
+#[derive(Debug, Clone, Default)]
+struct Processes(Arc<AtomicUsize>);
+
+impl Processes {
+    fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Count one more process until the returned guard is dropped
+    fn start(&self) -> ProcessGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        ProcessGuard(self.0.clone())
+    }
+}
+
+// The same start of a bridge
+impl PartialEq for Processes {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Processes {}
+
+struct ProcessGuard(Arc<AtomicUsize>);
+
+impl Drop for ProcessGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Bridge {
+    /// A bridge started in `bottle` as the process `pid`, see
+    /// [`Manager::start_bridge`]
+    pub(crate) fn new(
+        bottle: String,
+        pid: u32,
+        port: u16,
+        version: Option<String>,
+        token: Token,
+    ) -> Self {
+        Self {
+            bottle,
+            pid,
+            port,
+            version,
+            token,
+            processes: Processes::default(),
+        }
+    }
+
+    /// URL of the bridge's gRPC service
+    pub fn endpoint(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    /// Connect to the bridge, waiting up to `timeout` for it to listen
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bridge doesn't accept connections in time
+    pub async fn connect(&self, timeout: Duration) -> Result<Client, Error> {
+        let endpoint = Endpoint::from_shared(self.endpoint())?;
+        let started = Instant::now();
+        loop {
+            match endpoint.connect().await {
+                Ok(channel) => {
+                    return Ok(WineBridgeClient::with_interceptor(channel, self.token.clone()))
+                }
+                Err(e) if started.elapsed() >= timeout => return Err(e.into()),
+                Err(_) => tokio::time::sleep(CONNECT_INTERVAL).await,
+            }
+        }
+    }
+}
+
+/// Something a [`BridgedProcess`] did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    /// The process exited with this Windows exit code, e.g. `0xC0000005`
+    /// for an access violation
+    Exited(u32),
+}
+
+impl Output {
+    fn from_message(message: ProcessOutput) -> Option<Self> {
+        match message.stream.as_str() {
+            "stdout" => Some(Self::Stdout(message.data)),
+            "stderr" => Some(Self::Stderr(message.data)),
+            "exit" => Some(Self::Exited(message.exit_code)),
+            _ => None,
+        }
+    }
+}
+
+/// A program created by the bridge of its prefix
+///
+/// Its output and exit are received on a thread of their own, so they are
+/// read without an async runtime: iterating over the process yields its
+/// output as it comes, [`Output::Exited`] last.
+#[derive(Debug)]
+pub struct BridgedProcess {
+    /// Windows process id, as the programs of the prefix see it
+    pub pid: u32,
+    output: mpsc::Receiver<Result<Output, Error>>,
+}
+
+impl BridgedProcess {
+    /// Have `bridge` create a process running `executable` with `args`
+    ///
+    /// `executable` is a host path under a drive of `prefix`, or a Windows
+    /// path or program name as it is, e.g. `cmd`. The process runs in the
+    /// environment of the bridge along with `env`: variables read by Wine as
+    /// it starts, e.g. `WINEDLLOVERRIDES`, have no effect there.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `executable` is on no drive of `prefix`, if the
+    /// bridge can't be reached or can't create the process
+    pub fn spawn(
+        bridge: &Bridge,
+        prefix: &Path,
+        executable: &Path,
+        args: &[String],
+        env: &HashMap<String, String>,
+    ) -> Result<Self, Error> {
+        let command = if executable.is_absolute() {
+            drives::windows_path(prefix, executable).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("'{}' is on no drive of the prefix", executable.display()),
+                )
+            })?
+        } else {
+            executable.to_string_lossy().into_owned()
+        };
+        let request = CreateProcessRequest {
+            command,
+            args: args.to_vec(),
+            env: env.clone(),
+            capture_output: true,
+            ..Default::default()
+        };
+        let connection = bridge.clone();
+        let (started_sender, started) = mpsc::channel();
+        let (sender, output) = mpsc::channel();
+        // Keeps the bridge from stopping while the process may still run
+        let running = bridge.processes.start();
+        std::thread::spawn(move || {
+            let _running = running;
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    let _ = started_sender.send(Err(Error::Io(e)));
+                    return;
+                }
+            };
+            runtime.block_on(async move {
+                let created = async {
+                    let mut client = connection.connect(CONNECT_TIMEOUT).await?;
+                    let pid = client.create_process(request).await?.into_inner().pid;
+                    let output = match client.stream_process(StreamProcessRequest { pid }).await {
+                        Ok(output) => output,
+                        // Older bridges can't stream, nothing would see the
+                        // process exit
+                        Err(status) => {
+                            let _ = client.kill_process(KillProcessRequest { pid }).await;
+                            return Err(status.into());
+                        }
+                    };
+                    Ok::<_, Error>((pid, output.into_inner()))
+                };
+                let mut messages = match created.await {
+                    Ok((pid, messages)) => {
+                        let _ = started_sender.send(Ok(pid));
+                        messages
+                    }
+                    Err(e) => {
+                        let _ = started_sender.send(Err(e));
+                        return;
+                    }
+                };
+                loop {
+                    let output: Result<Output, Error> = match messages.message().await {
+                        Ok(Some(message)) => match Output::from_message(message) {
+                            Some(output) => Ok(output),
+                            None => continue,
+                        },
+                        Ok(None) => Err(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "the bridge stopped before the process exited",
+                        )
+                        .into()),
+                        Err(status) => Err(status.into()),
+                    };
+                    let last = !matches!(output, Ok(Output::Stdout(_) | Output::Stderr(_)));
+                    if sender.send(output).is_err() || last {
+                        return;
+                    }
+                }
+            });
+        });
+        let pid = started
+            .recv()
+            .map_err(|_| std::io::Error::other("the bridge connection was lost"))??;
+        tracing::info!(
+            "Created '{}' through the winebridge of '{}'",
+            executable.display(),
+            bridge.bottle
+        );
+        Ok(Self { pid, output })
+    }
+
+    /// Wait for the process to exit, discarding its output
+    ///
+    /// # Returns
+    ///
+    /// The Windows exit code of the process
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bridge stopped or the connection to it was
+    /// lost before the process exited
+    pub fn wait(&mut self) -> Result<u32, Error> {
+        for output in self.by_ref() {
+            if let Output::Exited(code) = output? {
+                return Ok(code);
+            }
+        }
+        Err(std::io::Error::other("the process already exited").into())
+    }
+}
+
+impl Iterator for BridgedProcess {
+    type Item = Result<Output, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.output.recv().ok()
+    }
+}
+
+/// Version of the bridge executable at `path`, from its version resource
+pub fn version(path: &Path) -> Option<String> {
+    PeFile::open(path)
+        .ok()?
+        .version_info()?
+        .file_version
+        .filter(|version| !version.is_empty())
+}
+
+/// Copy the bridge executable `source` into `prefix`, unless the same
+/// version is already there
+///
+/// Executables without a version are compared by content. The deployed file
+/// is replaced by a rename, so a bridge running from it is left alone.
+///
+/// # Returns
+///
+/// Whether the bridge was copied
+///
+/// # Errors
+///
+/// Returns an error if `source` doesn't exist or can't be copied
+pub fn deploy(source: &Path, prefix: &Path) -> Result<bool, Error> {
+    if !source.is_file() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("winebridge is not installed at '{}'", source.display()),
+        )
+        .into());
+    }
+    let target = prefix.join(PREFIX_PATH);
+    let available = version(source);
+    if target.is_file() {
+        let current = match (version(&target), &available) {
+            (Some(deployed), Some(available)) => &deployed == available,
+            _ => fs::read(&target).ok() == Some(fs::read(source).map_err(Error::Io)?),
+        };
+        if current {
+            return Ok(false);
+        }
+    }
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(Error::Io)?;
+    }
+    let partial = target.with_extension("exe.partial");
+    fs::copy(source, &partial).map_err(Error::Io)?;
+    fs::rename(&partial, &target).map_err(Error::Io)?;
+    tracing::info!(
+        "Deployed winebridge {} into '{}'",
+        available.as_deref().unwrap_or("(unversioned)"),
+        prefix.display()
+    );
+    Ok(true)
+}
+
+/// Bridges running in the bottles of a manager, by bottle name
+#[derive(Debug, Default)]
+pub(crate) struct Bridges {
+    running: Mutex<HashMap<String, (Bridge, Child)>>,
+}
+
+impl Bridges {
+    /// The bridge running in `bottle`, if it didn't exit
+    pub(crate) fn get(&self, bottle: &str) -> Option<Bridge> {
+        let mut running = self.running();
+        let (bridge, child) = running.get_mut(bottle)?;
+        if matches!(child.try_wait(), Ok(None)) {
+            return Some(bridge.clone());
+        }
+        tracing::warn!("The winebridge of '{}' exited", bottle);
+        running.remove(bottle);
+        None
+    }
+
+    /// Track a started bridge, stopping the one it replaces
+    pub(crate) fn insert(&self, bridge: Bridge, child: Child) {
+        let replaced = self.running().insert(bridge.bottle.clone(), (bridge, child));
+        if let Some((bridge, child)) = replaced {
+            terminate(&bridge, child);
+        }
+    }
+
+    /// Stop the bridge running in `bottle`, if any
+    pub(crate) fn stop(&self, bottle: &str) -> Option<Bridge> {
+        let (bridge, child) = self.running().remove(bottle)?;
+        terminate(&bridge, child);
+        tracing::info!("Stopped the winebridge of '{}'", bottle);
+        Some(bridge)
+    }
+
+    fn running(&self) -> MutexGuard<'_, HashMap<String, (Bridge, Child)>> {
+        self.running
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Ask `bridge` to shut down, then kill its runner process `child` if it is
+/// still running after [`SHUTDOWN_TIMEOUT`]
+fn terminate(bridge: &Bridge, mut child: Child) {
+    if matches!(child.try_wait(), Ok(None)) {
+        if let Err(e) = shutdown(bridge) {
+            tracing::warn!("Cannot shut the winebridge of '{}' down: {}", bridge.bottle, e);
+        }
+        let asked = Instant::now();
+        while matches!(child.try_wait(), Ok(None)) && asked.elapsed() < SHUTDOWN_TIMEOUT {
+            std::thread::sleep(CONNECT_INTERVAL);
+        }
+        if matches!(child.try_wait(), Ok(None)) {
+            if let Err(e) = child.kill() {
+                tracing::warn!("Cannot stop the winebridge of '{}': {}", bridge.bottle, e);
+            }
+        }
+    }
+    let _ = child.wait();
+}
+
+/// Send `Shutdown` to `bridge`, from a thread of its own as callers may run
+/// on an async runtime
+fn shutdown(bridge: &Bridge) -> Result<(), Error> {
+    let bridge = bridge.clone();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(Error::Io)?;
+        runtime.block_on(async move {
+            let mut client = bridge.connect(CONNECT_INTERVAL).await?;
+            client.shutdown(ShutdownRequest {}).await?;
+            Ok(())
+        })
+    })
+    .join()
+    .map_err(|_| std::io::Error::other("the shutdown of the bridge panicked"))?
+}
+
+/// Read the port reported by a bridge just started with `--port 0` from its
+/// `stdout`, as `port <port>`, then copy the rest of its output to `log`
+///
+/// # Errors
+///
+/// Returns an error if the bridge exits or reports no port within
+/// [`START_TIMEOUT`]
+pub(crate) fn read_port(stdout: ChildStdout, log: Option<File>) -> Result<u16, Error> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut stdout = BufReader::new(stdout);
+        let mut line = String::new();
+        let read = stdout.read_line(&mut line).map(|_| line);
+        let _ = sender.send(read);
+        // The bridge would block on a full pipe
+        let _ = match log {
+            Some(mut log) => io::copy(&mut stdout, &mut log),
+            None => io::copy(&mut stdout, &mut io::sink()),
+        };
+    });
+    let line = match receiver.recv_timeout(START_TIMEOUT) {
+        Ok(line) => line.map_err(Error::Io)?,
+        Err(_) => {
+            let message = "the bridge reported no port in time";
+            return Err(io::Error::new(io::ErrorKind::TimedOut, message).into());
+        }
+    };
+    line.trim()
+        .strip_prefix("port ")
+        .and_then(|port| port.parse().ok())
+        .filter(|&port| port != 0)
+        .ok_or_else(|| {
+            let message = format!("the bridge reported no port: '{}'", line.trim());
+            io::Error::new(io::ErrorKind::InvalidData, message).into()
+        })
+}
+
+/// Stop `bridge` once its bottle ran no program, launched by the manager or
+/// created by the bridge, for [`IDLE_TIMEOUT`]
+pub(crate) fn stop_when_idle(bridges: Arc<Bridges>, sessions: Arc<Sessions>, bridge: Bridge) {
+    std::thread::spawn(move || {
+        let mut idle_since = Instant::now();
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            match bridges.get(&bridge.bottle) {
+                Some(running) if running.pid == bridge.pid => {}
+                // Stopped, or replaced by another bridge
+                _ => return,
+            }
+            let launched = sessions.list().iter().any(|session| session.bottle == bridge.bottle);
+            if launched || bridge.processes.count() > 0 {
+                idle_since = Instant::now();
+            } else if idle_since.elapsed() >= IDLE_TIMEOUT {
+                tracing::debug!("'{}' is idle", bridge.bottle);
+                bridges.stop(&bridge.bottle);
+                return;
+            }
+        }
+    });
+}
@@ -0,0 +1,308 @@
+//! The settings of a prefix usually changed with winecfg
+//!
+//! [`WineSettings`] covers the toggles of winecfg most programs need: running
+//! in a virtual desktop, the DPI of the screen, the audio and graphics drivers
+//! and how the mouse is captured. They are stored in the bottle's configuration and
+//! written to the prefix's registry when they change (see
+//! [`crate::manager::Manager::set_wine_settings`]), so frontends can change
+//! them without starting winecfg.
+//!
+//! Turning the virtual desktop off, or the audio driver back to
+//! [`AudioDriver::Auto`], removes the value from the registry, so Wine
+//! behaves as in a new prefix.
+//!
+//! [`GraphicsDriver::Wayland`] needs a runner built with Wine's Wayland
+//! driver, see [`crate::runner::Capabilities`]. Programs of such bottles are
+//! launched with the variables finding the compositor, see
+//! [`WineSettings::environment`].
+
+use crate::components;
+use crate::runner::Runner;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// Registry key naming the desktop programs run in
+const EXPLORER_KEY: &str = "HKEY_CURRENT_USER\\Software\\Wine\\Explorer";
+
+/// Registry key holding the resolutions of the virtual desktops
+const DESKTOPS_KEY: &str = "HKEY_CURRENT_USER\\Software\\Wine\\Explorer\\Desktops";
+
+/// Name of the virtual desktop programs run in
+const DESKTOP_NAME: &str = "Default";
+
+/// Registry key holding the DPI of the screen
+const DPI_KEY: &str = "HKEY_CURRENT_USER\\Control Panel\\Desktop";
+
+/// Registry key holding the audio and graphics drivers
+const DRIVERS_KEY: &str = "HKEY_CURRENT_USER\\Software\\Wine\\Drivers";
+
+/// Registry key holding the settings of Wine's X11 driver
+const X11_KEY: &str = "HKEY_CURRENT_USER\\Software\\Wine\\X11 Driver";
+
+/// Registry key holding the settings of DirectInput
+const DIRECTINPUT_KEY: &str = "HKEY_CURRENT_USER\\Software\\Wine\\DirectInput";
+
+/// DPI of a new prefix, 100% scaling
+pub const DEFAULT_DPI: u32 = 96;
+
+/// Smallest DPI accepted by winecfg
+pub const MIN_DPI: u32 = 96;
+
+/// Largest DPI accepted by winecfg, 500% scaling
+pub const MAX_DPI: u32 = 480;
+
+/// Size of a virtual desktop, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct VirtualDesktop {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl fmt::Display for VirtualDesktop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// Audio driver of Wine
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum AudioDriver {
+    /// The first driver that works, PulseAudio on most hosts
+    #[default]
+    Auto,
+    /// PulseAudio, or PipeWire's implementation of it
+    Pulse,
+    Alsa,
+    Oss,
+    /// No audio at all, for programs crashing in their audio code
+    Disabled,
+}
+
+impl AudioDriver {
+    /// Value of the driver in the registry, `None` for [`AudioDriver::Auto`]
+    pub fn id(self) -> Option<&'static str> {
+        match self {
+            Self::Auto => None,
+            Self::Pulse => Some("pulse"),
+            Self::Alsa => Some("alsa"),
+            Self::Oss => Some("oss"),
+            Self::Disabled => Some(""),
+        }
+    }
+}
+
+/// Whether DirectInput keeps the mouse in the window of a program
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum MouseWarp {
+    /// Only when the program asks for exclusive access to the mouse
+    #[default]
+    Enable,
+    /// Never, for programs whose camera spins
+    Disable,
+    /// Always, for programs whose cursor leaves the window
+    Force,
+}
+
+impl MouseWarp {
+    /// Value of the setting in the registry
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Enable => "enable",
+            Self::Disable => "disable",
+            Self::Force => "force",
+        }
+    }
+}
+
+/// Graphics driver of Wine, drawing the windows of programs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum GraphicsDriver {
+    /// X11, or Wayland when no X server is reachable, for runners built with
+    /// both
+    #[default]
+    Auto,
+    /// X11, through XWayland on Wayland desktops
+    X11,
+    /// Wine's Wayland driver, without XWayland
+    Wayland,
+}
+
+impl GraphicsDriver {
+    /// Value of the driver in the registry, `None` for [`GraphicsDriver::Auto`]
+    pub fn id(self) -> Option<&'static str> {
+        match self {
+            Self::Auto => None,
+            Self::X11 => Some("x11"),
+            Self::Wayland => Some("wayland"),
+        }
+    }
+}
+
+/// How the mouse is captured by programs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct MouseCapture {
+    /// Keep the mouse in full-screen windows
+    pub fullscreen: bool,
+    pub warp: MouseWarp,
+}
+
+impl Default for MouseCapture {
+    fn default() -> Self {
+        Self {
+            fullscreen: true,
+            warp: MouseWarp::Enable,
+        }
+    }
+}
+
+/// The winecfg settings of a bottle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct WineSettings {
+    /// Run programs in a virtual desktop of this size instead of the host's
+    /// desktop
+    pub virtual_desktop: Option<VirtualDesktop>,
+    /// Dots per inch of the screen, [`DEFAULT_DPI`] for 100% scaling
+    pub dpi: u32,
+    pub audio_driver: AudioDriver,
+    pub graphics_driver: GraphicsDriver,
+    pub mouse_capture: MouseCapture,
+}
+
+impl Default for WineSettings {
+    fn default() -> Self {
+        Self {
+            virtual_desktop: None,
+            dpi: DEFAULT_DPI,
+            audio_driver: AudioDriver::Auto,
+            graphics_driver: GraphicsDriver::Auto,
+            mouse_capture: MouseCapture::default(),
+        }
+    }
+}
+
+impl WineSettings {
+    /// Check that Wine accepts the settings
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the DPI is out of range or the virtual desktop
+    /// has no area
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(MIN_DPI..=MAX_DPI).contains(&self.dpi) {
+            return Err(invalid(format!(
+                "{} DPI is not between {} and {}",
+                self.dpi, MIN_DPI, MAX_DPI
+            )));
+        }
+        if let Some(desktop) = self.virtual_desktop {
+            if desktop.width == 0 || desktop.height == 0 {
+                return Err(invalid(format!("{} is not a desktop size", desktop)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Variables the graphics driver needs to find the display
+    ///
+    /// With [`GraphicsDriver::Wayland`], `WAYLAND_DISPLAY` defaults to the
+    /// first compositor socket of `XDG_RUNTIME_DIR` when the daemon's own
+    /// environment has none, and `PROTON_ENABLE_WAYLAND` turns the driver on
+    /// in Proton builds ignoring the registry.
+    pub fn environment(&self) -> Vec<(&'static str, String)> {
+        if self.graphics_driver != GraphicsDriver::Wayland {
+            return Vec::new();
+        }
+        let mut variables = vec![("PROTON_ENABLE_WAYLAND", "1".to_string())];
+        if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            match wayland_socket() {
+                Some(socket) => variables.push(("WAYLAND_DISPLAY", socket)),
+                None => tracing::warn!("The Wayland driver is set, but no compositor is running"),
+            }
+        }
+        variables
+    }
+}
+
+/// Name of the first Wayland socket of `XDG_RUNTIME_DIR`
+fn wayland_socket() -> Option<String> {
+    let runtime = std::path::PathBuf::from(std::env::var_os("XDG_RUNTIME_DIR")?);
+    (0..10)
+        .map(|display| format!("wayland-{}", display))
+        .find(|name| runtime.join(name).exists())
+}
+
+/// Write the settings of `settings` differing from `previous` to `prefix`
+pub(crate) fn apply(
+    runner: &dyn Runner,
+    prefix: &Path,
+    settings: &WineSettings,
+    previous: &WineSettings,
+) -> Result<(), Error> {
+    if previous.virtual_desktop != settings.virtual_desktop {
+        match settings.virtual_desktop {
+            Some(desktop) => {
+                set_value(runner, prefix, EXPLORER_KEY, "Desktop", "REG_SZ", DESKTOP_NAME)?;
+                let size = desktop.to_string();
+                set_value(runner, prefix, DESKTOPS_KEY, DESKTOP_NAME, "REG_SZ", &size)?;
+            }
+            None => delete_value(runner, prefix, EXPLORER_KEY, "Desktop"),
+        }
+    }
+    if previous.dpi != settings.dpi {
+        let dpi = settings.dpi.to_string();
+        set_value(runner, prefix, DPI_KEY, "LogPixels", "REG_DWORD", &dpi)?;
+    }
+    if previous.audio_driver != settings.audio_driver {
+        match settings.audio_driver.id() {
+            Some(driver) => set_value(runner, prefix, DRIVERS_KEY, "Audio", "REG_SZ", driver)?,
+            None => delete_value(runner, prefix, DRIVERS_KEY, "Audio"),
+        }
+    }
+    if previous.graphics_driver != settings.graphics_driver {
+        match settings.graphics_driver.id() {
+            Some(driver) => set_value(runner, prefix, DRIVERS_KEY, "Graphics", "REG_SZ", driver)?,
+            None => delete_value(runner, prefix, DRIVERS_KEY, "Graphics"),
+        }
+    }
+    if previous.mouse_capture != settings.mouse_capture {
+        let capture = &settings.mouse_capture;
+        let grab = if capture.fullscreen { "Y" } else { "N" };
+        set_value(runner, prefix, X11_KEY, "GrabFullscreen", "REG_SZ", grab)?;
+        let warp = capture.warp.id();
+        set_value(runner, prefix, DIRECTINPUT_KEY, "MouseWarpOverride", "REG_SZ", warp)?;
+    }
+    Ok(())
+}
+
+fn set_value(
+    runner: &dyn Runner,
+    prefix: &Path,
+    key: &str,
+    name: &str,
+    kind: &str,
+    data: &str,
+) -> Result<(), Error> {
+    let args = ["add", key, "/v", name, "/t", kind, "/d", data, "/f"];
+    components::run(runner, prefix, "reg", &args)
+}
+
+/// Delete a value, which fails when it doesn't exist
+fn delete_value(runner: &dyn Runner, prefix: &Path, key: &str, name: &str) {
+    if components::run(runner, prefix, "reg", &["delete", key, "/v", name, "/f"]).is_err() {
+        tracing::debug!("'{}' had no value '{}'", key, name);
+    }
+}
+
+fn invalid(message: String) -> Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message).into()
+}